@@ -0,0 +1,143 @@
+//! Python bindings for the embeddable carinfo node, built with maturin as the `carinfo_p2p`
+//! module.
+
+use futures::StreamExt;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use rust_car_p2p::{CarP2pClient, ListMode, NodeEvent, NodeEvents};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+/// Converts a library error into a Python `RuntimeError`.
+fn py_err(e: impl std::fmt::Display) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+/// Turns a JSON value into plain Python dicts/lists via `json.loads`.
+fn to_py(py: Python<'_>, value: serde_json::Value) -> PyResult<PyObject> {
+    Ok(py
+        .import("json")?
+        .call_method1("loads", (value.to_string(),))?
+        .unbind())
+}
+
+/// A running node. Calls block until done but release the GIL while waiting.
+#[pyclass]
+struct Node {
+    runtime: Arc<Runtime>,
+    client: CarP2pClient,
+}
+
+#[pymethods]
+impl Node {
+    #[new]
+    fn start(py: Python<'_>) -> PyResult<Node> {
+        let runtime = Arc::new(Runtime::new().map_err(py_err)?);
+        let client = py
+            .allow_threads(|| runtime.block_on(CarP2pClient::start()))
+            .map_err(py_err)?;
+        Ok(Node { runtime, client })
+    }
+
+    #[getter]
+    fn peer_id(&self) -> String {
+        self.client.peer_id().to_string()
+    }
+
+    fn create(&self, py: Python<'_>, make: &str, model: &str, horsepower: u32) -> PyResult<usize> {
+        py.allow_threads(|| {
+            self.runtime
+                .block_on(self.client.create(make, model, horsepower))
+        })
+        .map_err(py_err)
+    }
+
+    fn publish(&self, py: Python<'_>, id: usize) -> PyResult<()> {
+        py.allow_threads(|| self.runtime.block_on(self.client.publish(id)))
+            .map_err(py_err)
+    }
+
+    fn unpublish(&self, py: Python<'_>, id: usize) -> PyResult<()> {
+        py.allow_threads(|| self.runtime.block_on(self.client.unpublish(id)))
+            .map_err(py_err)
+    }
+
+    /// The local catalog as a list of dicts.
+    fn local(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let carinfos = py
+            .allow_threads(|| self.runtime.block_on(self.client.local_carinfos()))
+            .map_err(py_err)?;
+        to_py(py, serde_json::to_value(carinfos).map_err(py_err)?)
+    }
+
+    fn peers(&self, py: Python<'_>) -> PyResult<Vec<String>> {
+        let peers = py
+            .allow_threads(|| self.runtime.block_on(self.client.peers()))
+            .map_err(py_err)?;
+        Ok(peers.iter().map(|p| p.to_string()).collect())
+    }
+
+    /// Asks `peer` (or every peer when omitted) for their public records; answers show up
+    /// as `carinfos` events.
+    #[pyo3(signature = (peer=None))]
+    fn query(&self, peer: Option<String>) -> PyResult<()> {
+        let mode = match peer {
+            Some(peer) => ListMode::One(peer),
+            None => ListMode::ALL,
+        };
+        self.client.query(mode).map_err(py_err)
+    }
+
+    /// Returns an iterator over events from now on.
+    fn events(&self) -> PyResult<Events> {
+        Ok(Events {
+            runtime: self.runtime.clone(),
+            events: self.client.subscribe().map_err(py_err)?,
+        })
+    }
+}
+
+/// Blocking iterator over node events, each a dict with a `type` key.
+#[pyclass]
+struct Events {
+    runtime: Arc<Runtime>,
+    events: NodeEvents,
+}
+
+#[pymethods]
+impl Events {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let runtime = self.runtime.clone();
+        let events = &mut self.events;
+        let event = py.allow_threads(|| runtime.block_on(events.next()));
+        let value = match event {
+            Some(NodeEvent::PeerDiscovered(peer)) => {
+                serde_json::json!({ "type": "peer_discovered", "peer": peer.to_string() })
+            }
+            Some(NodeEvent::PeerExpired(peer)) => {
+                serde_json::json!({ "type": "peer_expired", "peer": peer.to_string() })
+            }
+            Some(NodeEvent::Listing { peer, carinfos }) => serde_json::json!({
+                "type": "carinfos",
+                "peer": peer.to_string(),
+                "carinfos": carinfos,
+            }),
+            Some(NodeEvent::Served { peer }) => {
+                serde_json::json!({ "type": "served", "peer": peer.to_string() })
+            }
+            None => return Ok(None),
+        };
+        to_py(py, value).map(Some)
+    }
+}
+
+#[pymodule]
+fn carinfo_p2p(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Node>()?;
+    m.add_class::<Events>()?;
+    Ok(())
+}
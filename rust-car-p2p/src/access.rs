@@ -0,0 +1,87 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tokio::fs;
+
+use crate::Result;
+
+const ACCESS_FILE_PATH: &str = "./access.json";
+
+/// Which peers may connect to us: never those on the blocklist, and in allowlist-only mode
+/// only those on the allowlist. Persisted so the lists survive restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PeerAccess {
+    #[serde(default)]
+    blocked: BTreeSet<String>,
+    #[serde(default)]
+    allowed: BTreeSet<String>,
+    #[serde(default)]
+    allowlist_only: bool,
+}
+
+impl PeerAccess {
+    pub async fn load() -> Result<PeerAccess> {
+        match fs::read(ACCESS_FILE_PATH).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PeerAccess::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether `peer` may connect and have its messages handled.
+    pub fn permits(&self, peer: &PeerId) -> bool {
+        let peer = peer.to_string();
+        !self.blocked.contains(&peer) && (!self.allowlist_only || self.allowed.contains(&peer))
+    }
+
+    pub fn is_blocked(&self, peer: &PeerId) -> bool {
+        self.blocked.contains(&peer.to_string())
+    }
+
+    pub fn allowlist_only(&self) -> bool {
+        self.allowlist_only
+    }
+
+    pub fn blocked(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.blocked.iter().filter_map(|peer| peer.parse().ok())
+    }
+
+    pub fn allowed(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.allowed.iter().filter_map(|peer| peer.parse().ok())
+    }
+
+    /// Adds `peer` to the blocklist, returning false if it was on it already.
+    pub async fn block(&mut self, peer: &PeerId) -> Result<bool> {
+        let added = self.blocked.insert(peer.to_string());
+        self.save_if(added).await
+    }
+
+    pub async fn unblock(&mut self, peer: &PeerId) -> Result<bool> {
+        let removed = self.blocked.remove(&peer.to_string());
+        self.save_if(removed).await
+    }
+
+    /// Adds `peer` to the allowlist, returning false if it was on it already.
+    pub async fn allow(&mut self, peer: &PeerId) -> Result<bool> {
+        let added = self.allowed.insert(peer.to_string());
+        self.save_if(added).await
+    }
+
+    pub async fn disallow(&mut self, peer: &PeerId) -> Result<bool> {
+        let removed = self.allowed.remove(&peer.to_string());
+        self.save_if(removed).await
+    }
+
+    pub async fn set_allowlist_only(&mut self, on: bool) -> Result<bool> {
+        let changed = self.allowlist_only != on;
+        self.allowlist_only = on;
+        self.save_if(changed).await
+    }
+
+    async fn save_if(&self, changed: bool) -> Result<bool> {
+        if changed {
+            fs::write(ACCESS_FILE_PATH, serde_json::to_string(self)?).await?;
+        }
+        Ok(changed)
+    }
+}
@@ -0,0 +1,105 @@
+use libp2p::PeerId;
+use log::error;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::{fs, io};
+
+use crate::Result;
+
+const ALIASES_FILE_PATH: &str = "./aliases.json";
+
+/// Peer ids by the names given to them with `alias`, read on first use. Commands accept the
+/// names wherever they expect a peer id.
+static ALIASES: Lazy<RwLock<BTreeMap<String, String>>> = Lazy::new(|| RwLock::new(load()));
+
+fn load() -> BTreeMap<String, String> {
+    match fs::read(ALIASES_FILE_PATH) {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+            error!("invalid aliases in {}, {}", ALIASES_FILE_PATH, e);
+            BTreeMap::new()
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => {
+            error!("error reading {}, {}", ALIASES_FILE_PATH, e);
+            BTreeMap::new()
+        }
+    }
+}
+
+fn save(aliases: &BTreeMap<String, String>) -> Result<()> {
+    fs::write(ALIASES_FILE_PATH, serde_json::to_string_pretty(aliases)?)?;
+    Ok(())
+}
+
+/// A peer id, or the peer with that alias.
+pub fn resolve(s: &str) -> Option<PeerId> {
+    s.parse().ok().or_else(|| {
+        ALIASES
+            .read()
+            .expect("aliases lock")
+            .get(s)
+            .and_then(|peer| peer.parse().ok())
+    })
+}
+
+pub fn name(peer: &PeerId) -> Option<String> {
+    let peer = peer.to_string();
+    ALIASES
+        .read()
+        .expect("aliases lock")
+        .iter()
+        .find(|(_, p)| **p == peer)
+        .map(|(name, _)| name.clone())
+}
+
+/// A peer as shown in messages: its alias if it has one, its id otherwise.
+pub fn display(peer: &PeerId) -> String {
+    name(peer).unwrap_or_else(|| peer.to_string())
+}
+
+/// A peer as shown in peer listings: its id, followed by its alias if it has one.
+pub fn label(peer: &PeerId) -> String {
+    match name(peer) {
+        Some(name) => format!("{} ({})", peer, name),
+        None => peer.to_string(),
+    }
+}
+
+/// Names `peer`, replacing what the name meant before and any other name of the peer.
+pub fn set(name: &str, peer: &PeerId) -> Result<()> {
+    if name == "all" || name.parse::<PeerId>().is_ok() {
+        return Err(format!("{} can't be an alias", name).into());
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err("aliases may only contain letters, digits, -, _ and .".into());
+    }
+    let mut aliases = ALIASES.write().expect("aliases lock");
+    let peer = peer.to_string();
+    aliases.retain(|_, p| *p != peer);
+    aliases.insert(name.to_owned(), peer);
+    save(&aliases)
+}
+
+/// Returns false if there was no such alias.
+pub fn remove(name: &str) -> Result<bool> {
+    let mut aliases = ALIASES.write().expect("aliases lock");
+    if aliases.remove(name).is_none() {
+        return Ok(false);
+    }
+    save(&aliases)?;
+    Ok(true)
+}
+
+/// Every alias with its peer id, by name.
+pub fn all() -> Vec<(String, String)> {
+    ALIASES
+        .read()
+        .expect("aliases lock")
+        .iter()
+        .map(|(name, peer)| (name.clone(), peer.clone()))
+        .collect()
+}
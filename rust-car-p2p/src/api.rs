@@ -0,0 +1,277 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use libp2p::{request_response::OutboundFailure, swarm::Swarm, PeerId};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    behaviour::CarinfoBehaviour, create_new_carinfo, find_duplicate, health::Health, metrics,
+    publish_carinfo, read_local_carinfo, read_local_carinfos, storage::find_vin, vehicle::Details,
+    vin, Carinfo, Carinfos, ListMode, ListRequest,
+};
+
+/// Where the records of one peer go once it answers, or why it didn't.
+pub type ListReply = oneshot::Sender<std::result::Result<Carinfos, ListError>>;
+
+#[derive(Debug)]
+pub enum ListError {
+    Outbound(OutboundFailure),
+    /// The answer failed [`crate::ListResponse::verify`].
+    Unverified(String),
+}
+
+/// Something only the event loop can answer, because it needs the swarm.
+pub enum ApiRequest {
+    Peers(oneshot::Sender<Vec<PeerEntry>>),
+    PeerCarinfos(PeerId, ListReply),
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeerEntry {
+    pub peer: String,
+    pub connected: bool,
+}
+
+/// A car to create, sent to `POST /cars` or the gRPC `CreateCar`.
+#[derive(Deserialize)]
+pub struct NewCarinfo {
+    pub make: String,
+    pub model: String,
+    pub horsepower: u32,
+    #[serde(default)]
+    pub vin: Option<String>,
+    #[serde(flatten)]
+    pub details: Details,
+    /// Create the car even if the catalog has it already.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Why [`create`] didn't create a car.
+#[derive(Debug)]
+pub enum CreateError {
+    Invalid(String),
+    /// Another car has the VIN.
+    Conflict(String),
+    /// The catalog has the car already, as the carinfo with this id.
+    Duplicate(usize),
+    Failed(String),
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for CreateError {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        CreateError::Failed(e.to_string())
+    }
+}
+
+/// An error answer, sent as `{"error": "..."}`.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for ApiError {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+type Requests = mpsc::UnboundedSender<ApiRequest>;
+
+/// Serves the REST API on `addr`. Record requests are answered here; requests about peers are
+/// passed to the event loop through `requests`, see [`handle`]. `/metrics` is served from
+/// `health` like in container mode. Once `shutdown` resolves, no
+/// new connections are accepted and the server returns when requests in progress are answered.
+pub async fn serve(
+    addr: SocketAddr,
+    requests: Requests,
+    health: Arc<Health>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let metrics = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(health);
+    let app = Router::new()
+        .route("/cars", get(list_carinfos).post(create_carinfo))
+        .route("/cars/:id/publish", post(publish))
+        .route("/peers", get(list_peers))
+        .route("/peers/:id/cars", get(list_peer_carinfos))
+        .with_state(requests)
+        .merge(metrics);
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("error binding api server to {}, {}", addr, e);
+            return;
+        }
+    };
+    info!("Serving the REST API on {}", addr);
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        error!("api server failed, {}", e);
+    }
+}
+
+/// Answers a request passed on by [`serve`]. Listings of a peer are answered once its response
+/// arrives, in `behaviour::handle_list_event`.
+pub fn handle(request: ApiRequest, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match request {
+        ApiRequest::Peers(reply) => {
+            let mut peers: Vec<PeerId> = swarm.behaviour().discovered_peers().copied().collect();
+            peers.sort();
+            peers.dedup();
+            let peers = peers
+                .into_iter()
+                .map(|peer| PeerEntry {
+                    peer: peer.to_string(),
+                    connected: swarm.is_connected(&peer),
+                })
+                .collect();
+            let _ = reply.send(peers);
+        }
+        ApiRequest::PeerCarinfos(peer, reply) => {
+            let behaviour = swarm.behaviour_mut();
+            let request_id = behaviour.pages.send(
+                &mut behaviour.list,
+                &peer,
+                ListRequest::new(ListMode::One(peer.to_string()), None),
+            );
+            behaviour.api_requests.insert(request_id, reply);
+        }
+    }
+}
+
+async fn metrics(State(health): State<Arc<Health>>) -> String {
+    metrics::render(&health).await
+}
+
+async fn list_carinfos() -> Result<Json<Carinfos>, ApiError> {
+    Ok(Json(read_local_carinfos().await?))
+}
+
+async fn create_carinfo(
+    Json(new): Json<NewCarinfo>,
+) -> Result<(StatusCode, Json<Carinfo>), ApiError> {
+    match create(new).await {
+        Ok(carinfo) => Ok((StatusCode::CREATED, Json(carinfo))),
+        Err(CreateError::Invalid(e)) => Err(ApiError(StatusCode::BAD_REQUEST, e)),
+        Err(CreateError::Conflict(e)) => Err(ApiError(StatusCode::CONFLICT, e)),
+        Err(CreateError::Duplicate(id)) => Err(ApiError(
+            StatusCode::CONFLICT,
+            format!(
+                "carinfo {} is the same car, send \"force\": true to create it anyway",
+                id
+            ),
+        )),
+        Err(CreateError::Failed(e)) => Err(ApiError(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+/// Creates `new` with the checks `create car` makes: a valid VIN no other car has, and unless
+/// `force` is set, a car the catalog doesn't have yet.
+pub async fn create(new: NewCarinfo) -> Result<Carinfo, CreateError> {
+    if new.make.trim().is_empty() || new.model.trim().is_empty() {
+        return Err(CreateError::Invalid(
+            "make and model can't be empty".to_owned(),
+        ));
+    }
+    if let Some(vin) = &new.vin {
+        let vin = vin::validate(vin).map_err(CreateError::Invalid)?;
+        if let Some(other) = find_vin(&vin).await? {
+            return Err(CreateError::Conflict(format!(
+                "VIN {} is already used by carinfo {}",
+                vin, other
+            )));
+        }
+    }
+    if !new.force {
+        if let Some(known) =
+            find_duplicate(&new.make, &new.model, new.horsepower, new.vin.as_deref()).await?
+        {
+            return Err(CreateError::Duplicate(known.id));
+        }
+    }
+    let id = create_new_carinfo(
+        &new.make,
+        &new.model,
+        new.horsepower,
+        new.vin.as_deref(),
+        &new.details,
+    )
+    .await?;
+    read_local_carinfo(id)
+        .await?
+        .ok_or_else(|| CreateError::Failed(format!("carinfo {} is gone after creating it", id)))
+}
+
+async fn publish(Path(id): Path<usize>) -> Result<StatusCode, ApiError> {
+    if read_local_carinfo(id).await?.is_none() {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("no carinfo with id {}", id),
+        ));
+    }
+    publish_carinfo(id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_peers(State(requests): State<Requests>) -> Result<Json<Vec<PeerEntry>>, ApiError> {
+    let (reply, peers) = oneshot::channel();
+    send(&requests, ApiRequest::Peers(reply))?;
+    Ok(Json(peers.await.map_err(|_| stopped())?))
+}
+
+async fn list_peer_carinfos(
+    State(requests): State<Requests>,
+    Path(peer): Path<String>,
+) -> Result<Json<Carinfos>, ApiError> {
+    let peer: PeerId = peer.parse().map_err(|e| {
+        ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("invalid peer id {}, {}", peer, e),
+        )
+    })?;
+    let (reply, carinfos) = oneshot::channel();
+    send(&requests, ApiRequest::PeerCarinfos(peer, reply))?;
+    match carinfos.await.map_err(|_| stopped())? {
+        Ok(carinfos) => Ok(Json(carinfos)),
+        Err(ListError::Outbound(OutboundFailure::Timeout)) => Err(ApiError(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("{} didn't answer in time", peer),
+        )),
+        Err(ListError::Outbound(e)) => Err(ApiError(
+            StatusCode::BAD_GATEWAY,
+            format!("can't reach {}, {}", peer, e),
+        )),
+        Err(ListError::Unverified(e)) => Err(ApiError(
+            StatusCode::BAD_GATEWAY,
+            format!("invalid answer from {}, {}", peer, e),
+        )),
+    }
+}
+
+fn send(requests: &Requests, request: ApiRequest) -> Result<(), ApiError> {
+    requests.send(request).map_err(|_| stopped())
+}
+
+fn stopped() -> ApiError {
+    ApiError(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "the node is shutting down".to_owned(),
+    )
+}
@@ -0,0 +1,244 @@
+use log::error;
+use serde::de::DeserializeOwned;
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::Result;
+
+/// `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    name.into()
+}
+
+/// The previous version of a file written with [`write`].
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+/// Replaces the file at `path` so that a crash at any point leaves either the old or the new
+/// version in place. `contents` go to `<path>.tmp` first and are synced to disk, the old
+/// version is copied to `<path>.bak`, then the temporary file is renamed over `path`.
+pub async fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    stage(path, contents, None).await?;
+    commit(path).await
+}
+
+/// [`write`] for files only the user running the node may read, created with mode 0600.
+pub async fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    stage(path, contents, Some(0o600)).await?;
+    commit(path).await
+}
+
+/// The first half of [`write`]: `contents` go to `<path>.tmp` and are synced to disk, with
+/// `mode` if given. [`commit`] puts them in place, so several files can be written before
+/// any of them is replaced.
+pub async fn stage(path: &Path, contents: &[u8], mode: Option<u32>) -> Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    // the mode only applies to a new file
+    match fs::remove_file(&tmp).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        options.mode(mode);
+    }
+    let mut file = options.open(&tmp).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+/// The second half of [`write`]: the old version is copied to `<path>.bak`, then the file
+/// [`stage`] wrote is renamed over `path`.
+pub async fn commit(path: &Path) -> Result<()> {
+    match fs::copy(path, backup_path(path)).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    fs::rename(with_suffix(path, ".tmp"), path).await?;
+    Ok(())
+}
+
+/// Makes the backup of `path` a copy of its current version, for when the previous version
+/// held data that mustn't stay on disk. Without a current version the backup is removed.
+pub async fn refresh_backup(path: &Path) -> Result<()> {
+    let backup = backup_path(path);
+    let tmp = with_suffix(&backup, ".tmp");
+    match fs::copy(path, &tmp).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return match fs::remove_file(&backup).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        Err(e) => return Err(e.into()),
+    }
+    fs::rename(&tmp, &backup).await?;
+    Ok(())
+}
+
+/// Removes what [`stage`] wrote for `path` without putting it in place.
+pub async fn discard(path: &Path) {
+    let _ = fs::remove_file(with_suffix(path, ".tmp")).await;
+}
+
+/// [`write`] for startup code that can't await.
+pub fn write_blocking(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    let mut file = std::fs::File::create(&tmp)?;
+    std::io::Write::write_all(&mut file, contents)?;
+    file.sync_all()?;
+    drop(file);
+    match std::fs::copy(path, backup_path(path)) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Checked once at startup: if the file at `path` doesn't parse as `T` but its backup does, the
+/// damaged file is kept as `<path>.damaged` and the backup restored. Fails if there is no usable
+/// backup, so the damaged file isn't overwritten. A missing file is left to the caller.
+pub fn recover<T: DeserializeOwned>(path: &Path) -> Result<()> {
+    let problem = match std::fs::read(path) {
+        Ok(content) => match serde_json::from_slice::<T>(&content) {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let backup = backup_path(path);
+    let usable = std::fs::read(&backup)
+        .map_err(|e| e.to_string())
+        .and_then(|content| {
+            serde_json::from_slice::<T>(&content)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = usable {
+        return Err(format!(
+            "{} is damaged, {}, and there is no usable backup in {}, {}",
+            path.display(),
+            problem,
+            backup.display(),
+            e
+        )
+        .into());
+    }
+    let damaged = with_suffix(path, ".damaged");
+    std::fs::rename(path, &damaged)?;
+    std::fs::copy(&backup, path)?;
+    error!(
+        "{} was damaged, {}, restored it from {} and kept the damaged file as {}",
+        path.display(),
+        problem,
+        backup.display(),
+        damaged.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("carinfo-atomic-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn write_keeps_the_previous_version() {
+        let dir = scratch("write");
+        let path = dir.join("list.json");
+        write(&path, b"[1]").await.unwrap();
+        assert!(!backup_path(&path).exists());
+        write(&path, b"[1,2]").await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"[1,2]");
+        assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"[1]");
+        assert!(!with_suffix(&path, ".tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn write_private_is_readable_by_the_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = scratch("private");
+        let path = dir.join("keys.json");
+        write_private(&path, b"{}").await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recover_restores_a_damaged_file_from_its_backup() {
+        let dir = scratch("recover");
+        let path = dir.join("list.json");
+        std::fs::write(&path, b"[1,").unwrap();
+        std::fs::write(backup_path(&path), b"[1]").unwrap();
+        recover::<Vec<u32>>(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"[1]");
+        assert_eq!(
+            std::fs::read(with_suffix(&path, ".damaged")).unwrap(),
+            b"[1,"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recover_leaves_good_and_missing_files_alone() {
+        let dir = scratch("recover-good");
+        let path = dir.join("list.json");
+        recover::<Vec<u32>>(&path).unwrap();
+        assert!(!path.exists());
+        std::fs::write(&path, b"[2]").unwrap();
+        std::fs::write(backup_path(&path), b"[1]").unwrap();
+        recover::<Vec<u32>>(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"[2]");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recover_fails_without_a_usable_backup() {
+        let dir = scratch("recover-none");
+        let path = dir.join("list.json");
+        std::fs::write(&path, b"[1,").unwrap();
+        assert!(recover::<Vec<u32>>(&path).is_err());
+        std::fs::write(backup_path(&path), b"{").unwrap();
+        assert!(recover::<Vec<u32>>(&path).is_err());
+        // the damaged file stays for the user to look at
+        assert_eq!(std::fs::read(&path).unwrap(), b"[1,");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refresh_backup_drops_the_previous_version() {
+        let dir = scratch("refresh");
+        let path = dir.join("list.json");
+        write(&path, b"[1]").await.unwrap();
+        write(&path, b"[2]").await.unwrap();
+        refresh_backup(&path).await.unwrap();
+        assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"[2]");
+        std::fs::remove_file(&path).unwrap();
+        refresh_backup(&path).await.unwrap();
+        assert!(!backup_path(&path).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
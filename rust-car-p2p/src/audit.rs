@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use tokio::fs;
+
+use crate::{chat, Result};
+
+const AUDIT_FILE_PATH: &str = "./audit.json";
+/// Oldest entries are dropped beyond this many.
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccessKind {
+    /// The car was part of a listing sent in answer to `ls car`.
+    Listing,
+    /// Thumbnails of the car's photos were sent.
+    Thumbnails,
+    /// A full-size photo of the car was fetched.
+    Photo,
+}
+
+/// One peer receiving one of our records.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub peer: String,
+    pub car_id: usize,
+    pub kind: AccessKind,
+    /// Unix timestamp of when it was sent.
+    pub at: u64,
+}
+
+/// Who received which of our records, persisted so it can be reviewed later.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+}
+
+impl AuditLog {
+    pub async fn load() -> Result<AuditLog> {
+        match fs::read(AUDIT_FILE_PATH).await {
+            Ok(content) => Ok(AuditLog {
+                entries: serde_json::from_slice(&content)?,
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(AuditLog::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Logs that `peer` was sent the cars `car_ids`.
+    pub async fn record(
+        &mut self,
+        peer: &str,
+        kind: AccessKind,
+        car_ids: impl IntoIterator<Item = usize>,
+    ) -> Result<()> {
+        let at = chat::now();
+        for car_id in car_ids {
+            self.entries.push_back(AuditEntry {
+                peer: peer.to_owned(),
+                car_id,
+                kind,
+                at,
+            });
+        }
+        while self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.save().await
+    }
+
+    /// Accesses to car `id`, newest first.
+    pub fn for_car(&self, id: usize) -> Vec<&AuditEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|e| e.car_id == id)
+            .collect()
+    }
+
+    async fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(&self.entries)?;
+        fs::write(AUDIT_FILE_PATH, &json).await?;
+        Ok(())
+    }
+}
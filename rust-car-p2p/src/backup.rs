@@ -0,0 +1,202 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use libp2p::identity::{ed25519, PublicKey};
+use libp2p::PeerId;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::{
+    atomic, keystore, read_local_carinfos, schema, storage, write_local_carinfos, Config, Result,
+};
+
+const DEFAULT_DIR: &str = "./backups";
+/// Automatic backups kept unless `backup_keep` says otherwise.
+pub const DEFAULT_KEEP: usize = 7;
+/// Version of the snapshot format this build writes.
+const SNAPSHOT_VERSION: u32 = 1;
+const PREFIX: &str = "carinfo-";
+const EXTENSION: &str = ".backup";
+
+/// The configuration file from the config given to [`init`].
+static CONFIG_FILE: OnceCell<PathBuf> = OnceCell::new();
+
+/// A node's state at one point in time, stored gzip-compressed.
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    created_at: DateTime<Utc>,
+    /// Peer id of the identity in the snapshot.
+    peer_id: String,
+    /// The catalog as `export json` writes it, see [`schema::encode`].
+    catalog: Value,
+    /// The identity key file, base64 encoded.
+    identity: String,
+    /// The configuration file, if the node had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config: Option<String>,
+}
+
+/// The compressed snapshot encrypted with the key of the unpublished records, see
+/// [`crate::storage::Storage::encrypt_private`], when storage keeps those encrypted.
+#[derive(Serialize, Deserialize)]
+struct Encrypted {
+    encrypted: String,
+}
+
+/// What `restore` put in place.
+#[derive(Debug)]
+pub struct Restored {
+    pub created_at: DateTime<Utc>,
+    pub peer_id: String,
+    pub cars: usize,
+    pub config: bool,
+}
+
+pub fn init(config: &Config) {
+    let _ = CONFIG_FILE.set(config.config_file.clone());
+}
+
+fn config_file() -> PathBuf {
+    CONFIG_FILE
+        .get()
+        .cloned()
+        .unwrap_or_else(|| Config::default().config_file)
+}
+
+/// Writes a snapshot of the catalog, the identity and the configuration file to
+/// `carinfo-<time>.backup` in `dir`, `./backups` unless given, and returns its path. Only the
+/// user running the node can read it, and it is encrypted if unpublished records are.
+pub async fn create(dir: Option<&Path>) -> Result<PathBuf> {
+    let key_path = keystore::path();
+    let identity = fs::read(&key_path)
+        .await
+        .map_err(|e| format!("can't read identity {}, {}", key_path.display(), e))?;
+    let config = match fs::read_to_string(config_file()).await {
+        Ok(config) => Some(config),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let created_at = Utc::now();
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        created_at,
+        peer_id: peer_id(&identity)?.to_string(),
+        catalog: serde_json::from_slice(&schema::encode(&read_local_carinfos().await?)?)?,
+        identity: STANDARD.encode(&identity),
+        config,
+    };
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    serde_json::to_writer(&mut encoder, &snapshot)?;
+    let mut data = encoder.finish()?;
+    if let Some(encrypted) = storage().encrypt_private(&data)? {
+        data = serde_json::to_vec(&Encrypted { encrypted })?;
+    }
+    let dir = dir.unwrap_or(Path::new(DEFAULT_DIR));
+    fs::create_dir_all(dir).await?;
+    let path = dir.join(format!(
+        "{}{}{}",
+        PREFIX,
+        created_at.format("%Y%m%dT%H%M%SZ"),
+        EXTENSION
+    ));
+    atomic::write_private(&path, &data).await?;
+    Ok(path)
+}
+
+/// [`create`] for the periodic backup, deleting all but the newest `keep` in `./backups`.
+pub async fn periodic(keep: usize) -> Result<PathBuf> {
+    let path = create(None).await?;
+    let mut entries = fs::read_dir(DEFAULT_DIR).await?;
+    let mut backups = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(PREFIX) && name.ends_with(EXTENSION) {
+            backups.push(entry.path());
+        }
+    }
+    // the time in the name sorts them oldest first
+    backups.sort();
+    for old in backups.iter().rev().skip(keep) {
+        fs::remove_file(old).await?;
+    }
+    Ok(path)
+}
+
+/// Checks the snapshot at `path` completely, then replaces the catalog, the identity and the
+/// configuration file with its own. The identity and configuration take effect on the next
+/// start; the old key is kept like on `--new-identity`.
+pub async fn restore(path: &Path) -> Result<Restored> {
+    let mut data = fs::read(path).await?;
+    // a compressed snapshot is never valid JSON
+    if let Ok(Encrypted { encrypted }) = serde_json::from_slice(&data) {
+        data = storage()
+            .decrypt_private(&encrypted)
+            .map_err(|e| format!("can't decrypt {}, {}", path.display(), e))?;
+    }
+    let snapshot: Snapshot = serde_json::from_reader(GzDecoder::new(data.as_slice()))
+        .map_err(|e| format!("{} is not a backup, {}", path.display(), e))?;
+    if snapshot.version > SNAPSHOT_VERSION {
+        return Err(format!(
+            "{} was made by a newer version, snapshot version {}",
+            path.display(),
+            snapshot.version
+        )
+        .into());
+    }
+    let carinfos = schema::decode(&serde_json::to_vec(&snapshot.catalog)?)
+        .map_err(|e| format!("invalid catalog in {}, {}", path.display(), e))?;
+    let identity = STANDARD
+        .decode(&snapshot.identity)
+        .map_err(|e| format!("invalid identity in {}, {}", path.display(), e))?;
+    if peer_id(&identity)?.to_string() != snapshot.peer_id {
+        return Err(format!(
+            "the identity in {} isn't that of {}",
+            path.display(),
+            snapshot.peer_id
+        )
+        .into());
+    }
+    if let Some(config) = &snapshot.config {
+        Config::check_file(&config_file(), config)?;
+    }
+
+    // the key and the configuration are staged next to their files and only renamed into
+    // place once the catalog, which storage replaces at once, is written too
+    let config_path = config_file();
+    let staged: Result<()> = async {
+        keystore::stage(&identity)?;
+        if let Some(config) = &snapshot.config {
+            atomic::stage(&config_path, config.as_bytes(), None).await?;
+        }
+        write_local_carinfos(&carinfos).await
+    }
+    .await;
+    if let Err(e) = staged {
+        keystore::discard();
+        atomic::discard(&config_path).await;
+        return Err(e);
+    }
+    keystore::install()?;
+    if snapshot.config.is_some() {
+        atomic::commit(&config_path).await?;
+    }
+    Ok(Restored {
+        created_at: snapshot.created_at,
+        peer_id: snapshot.peer_id,
+        cars: carinfos.len(),
+        config: snapshot.config.is_some(),
+    })
+}
+
+/// The peer id of an identity key file.
+fn peer_id(identity: &[u8]) -> Result<PeerId> {
+    // decoding wipes the bytes it reads
+    let keypair = ed25519::Keypair::decode(&mut identity.to_vec())
+        .map_err(|e| format!("invalid identity key, {}", e))?;
+    Ok(PeerId::from(PublicKey::Ed25519(keypair.public())))
+}
@@ -0,0 +1,2338 @@
+use libp2p::{
+    core::ConnectedPoint,
+    gossipsub::{Gossipsub, GossipsubEvent, GossipsubMessage, IdentTopic},
+    identify::{Identify, IdentifyEvent},
+    kad::{GetProvidersError, GetProvidersOk, KademliaEvent, QueryId, QueryResult},
+    mdns::{Mdns, MdnsEvent},
+    ping::{Ping, PingConfig, PingEvent, PingSuccess},
+    relay::Relay,
+    request_response::{
+        OutboundFailure, RequestId, RequestResponseEvent, RequestResponseMessage, ResponseChannel,
+    },
+    swarm::{toggle::Toggle, Swarm},
+    Multiaddr, NetworkBehaviour, PeerId,
+};
+use log::{debug, error, info};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{atomic::Ordering, Arc};
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+use crate::{
+    access::PeerAccess,
+    aliases,
+    api::{ListError, ListReply},
+    audit::{AccessKind, AuditLog},
+    cache::ResponseCache,
+    catchup::{CatchUp, CatchUpLog},
+    channels,
+    chat::{self, ChatLine, ChatMessage, ChatResponse, Chats},
+    club::{self, ClubCatalog, ClubRequest, ClubResponse, Members},
+    commands::Verbosity,
+    dht::{self, Bootstrap, Dht, DhtEvent, Providing},
+    digest,
+    filter::Filter,
+    format, geo,
+    health::Health,
+    metrics::METRICS,
+    nat::{self, Reachability},
+    offers::{Offer, OfferEntry, OfferRequest, OfferResponse, OfferState, Offers, Sending},
+    outbox::Outbox,
+    output::{self, Output},
+    ownership,
+    pager::{self, Pager},
+    peers::{self, Identity, PeerBook},
+    photos::{
+        self, Download, PhotoChunk, PhotoQuery, PhotoReply, PhotoRequest, ThumbnailRequest,
+        ThumbnailResponse,
+    },
+    protocol::{
+        self, ChatBehaviour, ChatEvent, ClubBehaviour, ClubEvent, ListBehaviour, ListEvent,
+        ListPages, OfferBehaviour, OfferEvent, PhotoBehaviour, PhotoEvent,
+    },
+    queue::{Purpose, RequestQueue},
+    ratelimit::RateLimiter,
+    read_carinfos_for, read_local_carinfos,
+    replica::{ConflictPolicy, Replica},
+    round::{ListRound, ListRounds},
+    search::{self, SearchIndex},
+    seen::SeenCache,
+    share::{self, SealedShare, SHARE_TOPIC},
+    telemetry::{self, TelemetryUpdate, TelemetryViewer, TELEMETRY_TOPIC},
+    wire::{self, DecodeError, Message},
+    Carinfo, Carinfos, Config, ListMode, ListRequest, ListResponse, SearchRequest, PEER_ID,
+};
+
+/// Everything a node speaks, plus the state its commands and event handlers share.
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "CarinfoEvent", event_process = false)]
+pub struct CarinfoBehaviour {
+    pub(crate) gossipsub: Gossipsub,
+    /// Off when the config disables mDNS.
+    pub(crate) mdns: Toggle<Mdns>,
+    pub(crate) list: ListBehaviour,
+    pub(crate) photo: PhotoBehaviour,
+    pub(crate) offer: OfferBehaviour,
+    pub(crate) chat: ChatBehaviour,
+    pub(crate) club_protocol: ClubBehaviour,
+    pub(crate) kademlia: Dht,
+    pub(crate) relay: Relay,
+    pub(crate) ping: Ping,
+    pub(crate) identify: Identify,
+    #[behaviour(ignore)]
+    pub(crate) pager: Option<Pager>,
+    #[behaviour(ignore)]
+    pub(crate) last_responses: HashMap<PeerId, Instant>,
+    /// Latest listing received from each peer during this session.
+    #[behaviour(ignore)]
+    pub(crate) remote_carinfos: HashMap<PeerId, Carinfos>,
+    /// Listings kept across sessions, answering `ls car` within their TTL or while the peer is
+    /// offline.
+    #[behaviour(ignore)]
+    pub(crate) cache: ResponseCache,
+    #[behaviour(ignore)]
+    pub(crate) verbosity: Verbosity,
+    /// Verbosity and send time of the last remote list request, applied to its responses.
+    #[behaviour(ignore)]
+    pub(crate) request: (Verbosity, Instant),
+    /// Set while subscribed to the telemetry topic.
+    #[behaviour(ignore)]
+    pub(crate) telemetry_viewer: Option<TelemetryViewer>,
+    #[behaviour(ignore)]
+    pub(crate) health: Arc<Health>,
+    #[behaviour(ignore)]
+    pub(crate) club: ClubRole,
+    /// Announcements waiting for a listener, see `broadcast`.
+    #[behaviour(ignore)]
+    pub(crate) outbox: Outbox,
+    /// Chat threads, with the requests carrying our lines.
+    #[behaviour(ignore)]
+    pub(crate) chats: Chats,
+    /// Offers made and received, with the requests carrying them.
+    #[behaviour(ignore)]
+    pub(crate) offers: Offers,
+    /// Announcements missed by known peers, replayed when they subscribe again.
+    #[behaviour(ignore)]
+    pub(crate) catchup: CatchUpLog,
+    /// Connected peers per topic they told us they are subscribed to.
+    #[behaviour(ignore)]
+    pub(crate) topic_peers: HashMap<String, HashSet<PeerId>>,
+    /// Which peers were sent which of our records.
+    #[behaviour(ignore)]
+    pub(crate) audit: AuditLog,
+    /// Full-text index of local and received records, if it could be opened.
+    #[behaviour(ignore)]
+    pub(crate) search: Option<SearchIndex>,
+    /// The catalog topic from the config.
+    #[behaviour(ignore)]
+    pub(crate) topic: IdentTopic,
+    /// Channels we are subscribed to, see [`channels`].
+    #[behaviour(ignore)]
+    pub(crate) channels: BTreeSet<String>,
+    /// List requests made for REST API clients, answered with the response instead of printing it.
+    #[behaviour(ignore)]
+    pub(crate) api_requests: HashMap<RequestId, ListReply>,
+    /// Peers we told the user speak another protocol version, found by their messages or by
+    /// identify.
+    #[behaviour(ignore)]
+    pub(crate) other_versions: HashSet<PeerId>,
+    /// Paged listings being received.
+    #[behaviour(ignore)]
+    pub(crate) pages: ListPages,
+    /// Filters of pending search requests, applied again to their responses.
+    #[behaviour(ignore)]
+    pub(crate) searches: HashMap<RequestId, Filter>,
+    /// Queries of pending `search net` requests, ranked by again on response.
+    #[behaviour(ignore)]
+    pub(crate) text_searches: HashMap<RequestId, String>,
+    /// Pending `sync` requests, whose responses go to `replica`.
+    #[behaviour(ignore)]
+    pub(crate) syncs: HashSet<RequestId>,
+    /// Requests for peers that weren't connected, sent once they are.
+    #[behaviour(ignore)]
+    pub(crate) queue: RequestQueue,
+    /// How many list requests each peer gets answered.
+    #[behaviour(ignore)]
+    pub(crate) rate_limiter: RateLimiter,
+    /// The block and allow lists.
+    #[behaviour(ignore)]
+    pub(crate) access: PeerAccess,
+    /// Peers the swarm refuses connections from because of `access`.
+    #[behaviour(ignore)]
+    pub(crate) banned: HashSet<PeerId>,
+    /// Copies of peers' catalogs made with `sync`.
+    #[behaviour(ignore)]
+    pub(crate) replica: Replica,
+    /// Peers dialed with `dial`, made explicit gossipsub peers once connected.
+    #[behaviour(ignore)]
+    pub(crate) dialing: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    pub(crate) bootstrap: Bootstrap,
+    /// Provider records of the makes and models of our public cars.
+    #[behaviour(ignore)]
+    pub(crate) providing: Providing,
+    /// Pending `find` lookups, by what they look for.
+    #[behaviour(ignore)]
+    pub(crate) finds: HashMap<QueryId, String>,
+    #[behaviour(ignore)]
+    pub(crate) reachability: Reachability,
+    #[behaviour(ignore)]
+    pub(crate) peer_book: PeerBook,
+    /// Whether `watch` is on, showing cars as peers publish them.
+    #[behaviour(ignore)]
+    pub(crate) watching: bool,
+    /// Peers a `ping` command waits for the next round trip time of.
+    #[behaviour(ignore)]
+    pub(crate) pings: HashSet<PeerId>,
+    /// Photos being fetched, by the pending request for their next chunk.
+    #[behaviour(ignore)]
+    pub(crate) downloads: HashMap<RequestId, Download>,
+    /// The `ls car all` waiting for answers, if any.
+    #[behaviour(ignore)]
+    pub(crate) rounds: ListRounds,
+    /// Topic messages and list responses handled recently, to drop copies and replays.
+    #[behaviour(ignore)]
+    pub(crate) seen: SeenCache,
+    /// Relays from the config we listen through.
+    #[behaviour(ignore)]
+    pub(crate) relays: Vec<PeerId>,
+    /// Set while a front end is watching, see [`CarinfoBehaviour::watch`].
+    #[behaviour(ignore)]
+    pub(crate) ui: Option<mpsc::UnboundedSender<UiEvent>>,
+}
+
+/// Changes a live view of the node, such as the dashboard or an embedding application, needs
+/// to know about.
+#[derive(Debug, Clone)]
+pub enum UiEvent {
+    PeerDiscovered(PeerId),
+    PeerExpired(PeerId),
+    /// A peer answered a list request.
+    Listing {
+        peer: PeerId,
+        carinfos: Carinfos,
+    },
+    /// A peer asked for our records and was sent those it may see.
+    Served {
+        peer: PeerId,
+    },
+}
+
+impl CarinfoBehaviour {
+    /// Sets up every protocol and loads persisted state, joining the catalog topic from
+    /// `config` and, with `CARINFO_COORDINATOR=1`, coordinating a club. `relay`
+    /// comes with the transport, see [`protocol::relay_transport`].
+    pub async fn new(config: &Config, relay: Relay) -> CarinfoBehaviour {
+        let mut behaviour = CarinfoBehaviour {
+            gossipsub: crate::gossipsub(),
+            list: protocol::list_behaviour(),
+            photo: protocol::photo_behaviour(),
+            offer: protocol::offer_behaviour(),
+            chat: protocol::chat_behaviour(),
+            club_protocol: protocol::club_behaviour(),
+            kademlia: dht::kademlia(&config.bootstrap),
+            relay,
+            // every connected peer is pinged every 15 seconds
+            ping: Ping::new(PingConfig::new()),
+            identify: protocol::identify_behaviour(),
+            // mDNS can't start on hosts without multicast, such as many cloud VMs
+            mdns: if config.mdns {
+                Mdns::new(Default::default())
+                    .await
+                    .map_err(|e| error!("error starting mDNS, running without it, {}", e))
+                    .ok()
+            } else {
+                None
+            }
+            .into(),
+            pager: None,
+            last_responses: HashMap::new(),
+            remote_carinfos: HashMap::new(),
+            cache: ResponseCache::load(config.cache_ttl)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("error reading cached listings, {}", e);
+                    ResponseCache::default()
+                }),
+            verbosity: Verbosity::Normal,
+            request: (Verbosity::Normal, Instant::now()),
+            telemetry_viewer: None,
+            health: Arc::new(Health::new()),
+            club: ClubRole::None,
+            outbox: Outbox::load().await.unwrap_or_else(|e| {
+                error!("error reading outbox, {}", e);
+                Outbox::default()
+            }),
+            topic_peers: HashMap::new(),
+            chats: Chats::load().await.unwrap_or_else(|e| {
+                error!("error reading chats, {}", e);
+                Chats::default()
+            }),
+            offers: Offers::load().await.unwrap_or_else(|e| {
+                error!("error reading offers, {}", e);
+                Offers::default()
+            }),
+            catchup: CatchUpLog::load().await.unwrap_or_else(|e| {
+                error!("error reading catch-up log, {}", e);
+                CatchUpLog::default()
+            }),
+            audit: AuditLog::load().await.unwrap_or_else(|e| {
+                error!("error reading audit log, {}", e);
+                AuditLog::default()
+            }),
+            search: SearchIndex::open()
+                .map_err(|e| error!("error opening search index, {}", e))
+                .ok(),
+            topic: config.topic(),
+            channels: BTreeSet::new(),
+            api_requests: HashMap::new(),
+            other_versions: HashSet::new(),
+            pages: ListPages::default(),
+            searches: HashMap::new(),
+            text_searches: HashMap::new(),
+            syncs: HashSet::new(),
+            queue: RequestQueue::default(),
+            rate_limiter: RateLimiter::new(config.requests_per_minute, config.request_burst),
+            access: PeerAccess::load().await.unwrap_or_else(|e| {
+                error!("error reading block and allow lists, {}", e);
+                PeerAccess::default()
+            }),
+            banned: HashSet::new(),
+            replica: Replica::load(config.conflict_policy)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("error reading synced carinfos, {}", e);
+                    Replica {
+                        policy: config.conflict_policy,
+                        ..Replica::default()
+                    }
+                }),
+            dialing: HashSet::new(),
+            bootstrap: Bootstrap::new(&config.bootstrap),
+            providing: Providing::default(),
+            finds: HashMap::new(),
+            reachability: Reachability::default(),
+            peer_book: PeerBook::load().unwrap_or_else(|e| {
+                error!("error reading known peers, {}", e);
+                PeerBook::default()
+            }),
+            watching: false,
+            pings: HashSet::new(),
+            rounds: ListRounds::new(config.list_window),
+            seen: SeenCache::default(),
+            downloads: HashMap::new(),
+            relays: config.relays.iter().map(|(peer, _)| *peer).collect(),
+            ui: None,
+        };
+
+        for topic in [&config.topic(), &*SHARE_TOPIC] {
+            behaviour.gossipsub.subscribe(topic).expect("can subscribe");
+        }
+        for name in &config.channels {
+            behaviour.join_channel(name);
+        }
+        if std::env::var("CARINFO_COORDINATOR").is_ok_and(|v| !v.is_empty() && v != "0") {
+            match (club::read_catalog().await, Members::load().await) {
+                (Ok(catalog), Ok(members)) => {
+                    info!(
+                        "Coordinating club catalog (version {}, {} cars, {} members)",
+                        catalog.version,
+                        catalog.entries.len(),
+                        members.0.len()
+                    );
+                    behaviour.club = ClubRole::Coordinator { catalog, members };
+                }
+                (Err(e), _) => error!("error reading club catalog, {}", e),
+                (_, Err(e)) => error!("error reading club members, {}", e),
+            }
+        }
+        behaviour
+    }
+
+    pub fn peer_book(&self) -> &PeerBook {
+        &self.peer_book
+    }
+
+    /// Shared with the health and metrics endpoints.
+    pub fn health(&self) -> Arc<Health> {
+        self.health.clone()
+    }
+
+    /// Peers found on the local network with mDNS, none if mDNS is off. A peer may be listed
+    /// once per address.
+    pub(crate) fn discovered_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.mdns
+            .as_ref()
+            .into_iter()
+            .flat_map(Mdns::discovered_nodes)
+    }
+
+    /// Forgets the topics of a peer we no longer have a connection to, and the rate limits
+    /// of peers that have been quiet long enough.
+    pub fn peer_disconnected(&mut self, peer: &PeerId) {
+        self.peer_book.disconnected(*peer);
+        self.rate_limiter.prune();
+        self.topic_peers.values_mut().for_each(|peers| {
+            peers.remove(peer);
+        });
+    }
+
+    /// Keeps track of bootstrap nodes and of whether peers outside the local network reach us,
+    /// finishes a `dial`, see [`CarinfoBehaviour::dial_succeeded`], and sends the requests
+    /// queued for the peer. Asks a club coordinator we joined for the catalog changes we missed.
+    pub fn peer_connected(&mut self, peer: &PeerId, endpoint: &ConnectedPoint) {
+        self.bootstrap.connected(peer);
+        self.reachability.connected(endpoint);
+        self.peer_book.connected(*peer, endpoint);
+        self.dial_succeeded(peer);
+        if let ClubRole::Member { coordinator, .. } = &self.club {
+            if coordinator == peer {
+                self.club_protocol.send_request(peer, ClubRequest::Sync);
+            }
+        }
+        for queued in self.queue.take(peer) {
+            info!(
+                "Sending queued request #{} ({}) to {}",
+                queued.number,
+                queued.purpose,
+                aliases::display(peer)
+            );
+            self.send_list_request(*peer, ListMode::One(peer.to_string()), queued.purpose);
+        }
+        for (thread, line) in self.chats.pending(&peer.to_string()) {
+            self.send_chat_line(thread, line);
+        }
+    }
+
+    /// Sends our line `line` of chat thread `thread` to the other side of the thread.
+    pub(crate) fn send_chat_line(&mut self, thread: usize, line: usize) {
+        let counterpart = self
+            .chats
+            .threads
+            .get(thread)
+            .map(|t| t.counterpart.parse());
+        let (peer, msg) = match (counterpart, self.chats.message(thread, line)) {
+            (Some(Ok(peer)), Some(msg)) => (peer, msg),
+            _ => return,
+        };
+        let request_id = self.chat.send_request(&peer, msg);
+        self.chats.sending.insert(request_id, (thread, line));
+    }
+
+    /// Asks `peer` for its listing, handling the answer as `purpose` says.
+    pub(crate) fn send_list_request(
+        &mut self,
+        peer: PeerId,
+        mode: ListMode,
+        purpose: Purpose,
+    ) -> RequestId {
+        let search = match &purpose {
+            Purpose::Search(filter) => Some(SearchRequest {
+                filter: filter.clone(),
+                text: None,
+            }),
+            Purpose::TextSearch(text) => Some(SearchRequest {
+                filter: Filter::default(),
+                text: Some(text.clone()),
+            }),
+            _ => None,
+        };
+        let request_id = self
+            .pages
+            .send(&mut self.list, &peer, ListRequest::new(mode, search));
+        match purpose {
+            Purpose::List => {}
+            Purpose::Search(filter) => {
+                self.searches.insert(request_id, filter);
+            }
+            Purpose::TextSearch(text) => {
+                self.text_searches.insert(request_id, text);
+            }
+            Purpose::Sync => {
+                self.syncs.insert(request_id);
+            }
+        }
+        request_id
+    }
+
+    /// When the `ls car all` waiting for answers gives up on the rest.
+    pub fn round_deadline(&self) -> Option<Instant> {
+        self.rounds.deadline()
+    }
+
+    /// Stops waiting for answers to the last `ls car all`, counting the peers that didn't answer
+    /// as timed out.
+    pub fn close_round(&mut self) {
+        if let Some(round) = self.rounds.close() {
+            end_round(&round, self.request.0);
+        }
+    }
+
+    /// Finishes a `dial`: the peer gets every message on our topics from now on, as if it were
+    /// subscribed, and gossipsub reconnects to it when the connection drops.
+    pub fn dial_succeeded(&mut self, peer: &PeerId) {
+        if self.dialing.remove(peer) {
+            self.gossipsub.add_explicit_peer(peer);
+            info!("Connected to {}", aliases::display(peer));
+        }
+    }
+
+    /// Gives up on a `dial` that couldn't reach the peer at any of its addresses.
+    pub fn dial_failed(&mut self, peer: &PeerId, error: &str) {
+        if self.dialing.remove(peer) {
+            error!("error dialing {}, {}", aliases::display(peer), error);
+        }
+    }
+
+    /// Sends peer and listing changes to `sender` from now on.
+    pub fn watch(&mut self, sender: mpsc::UnboundedSender<UiEvent>) {
+        self.ui = Some(sender);
+    }
+
+    pub(crate) fn notify(&mut self, event: UiEvent) {
+        if let Some(ui) = &self.ui {
+            if ui.send(event).is_err() {
+                self.ui = None;
+            }
+        }
+    }
+
+    /// Looks for new peers through the DHT.
+    pub fn refresh_dht(&mut self) {
+        // fails while the routing table is empty, mDNS or bootstrap nodes fill it
+        let _ = self.kademlia.bootstrap();
+    }
+
+    pub fn publish_telemetry(&mut self, update: &TelemetryUpdate) {
+        let message = Message::Telemetry(update.clone());
+        METRICS.published(&message);
+        let data = wire::encode(&message);
+        // senders don't have to watch the telemetry topic themselves, gossipsub
+        // fans out to peers that do; with nobody watching the update is dropped
+        let _ = self.gossipsub.publish(TELEMETRY_TOPIC.clone(), data);
+    }
+
+    /// Subscribes to channel `name`, returning false if we are in it already.
+    pub fn join_channel(&mut self, name: &str) -> bool {
+        if !self.channels.insert(name.to_owned()) {
+            return false;
+        }
+        let topic = channels::topic(&self.topic, name);
+        if let Err(e) = self.gossipsub.subscribe(&topic) {
+            error!("error joining {}, {:?}", topic.hash(), e);
+        }
+        true
+    }
+
+    /// Unsubscribes from channel `name`, returning false if we weren't in it.
+    pub fn leave_channel(&mut self, name: &str) -> bool {
+        if !self.channels.remove(name) {
+            return false;
+        }
+        let topic = channels::topic(&self.topic, name);
+        if let Err(e) = self.gossipsub.unsubscribe(&topic) {
+            error!("error leaving {}, {:?}", topic.hash(), e);
+        }
+        true
+    }
+
+    /// Leaves the catalog topic and the channels so peers stop sending us requests.
+    pub fn leave(&mut self) {
+        if let Err(e) = self.gossipsub.unsubscribe(&self.topic) {
+            error!("error leaving {}, {:?}", self.topic.hash(), e);
+        }
+        for name in std::mem::take(&mut self.channels) {
+            let topic = channels::topic(&self.topic, &name);
+            if let Err(e) = self.gossipsub.unsubscribe(&topic) {
+                error!("error leaving {}, {:?}", topic.hash(), e);
+            }
+        }
+    }
+}
+
+/// Refuses connections from blocked peers from the start.
+pub fn ban_blocked(swarm: &mut Swarm<CarinfoBehaviour>) {
+    let blocked: Vec<PeerId> = swarm.behaviour().access.blocked().collect();
+    for peer in blocked {
+        set_banned(swarm, peer, true);
+    }
+}
+
+/// Checks a new connection against the block and allow lists. A peer they don't permit is
+/// disconnected and its further connections are refused.
+pub fn admit(swarm: &mut Swarm<CarinfoBehaviour>, peer: &PeerId) -> bool {
+    let access = &swarm.behaviour().access;
+    if access.permits(peer) {
+        return true;
+    }
+    info!(
+        "Refused {}, {}",
+        aliases::display(peer),
+        if access.is_blocked(peer) {
+            "it is blocked"
+        } else {
+            "it is not on the allowlist"
+        }
+    );
+    set_banned(swarm, *peer, true);
+    false
+}
+
+/// Bans the known peers the block and allow lists no longer permit, disconnecting them, and
+/// lifts the ban of those they now permit. Called after every change to the lists.
+pub(crate) fn apply_access(swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour();
+    let peers: HashSet<PeerId> = behaviour
+        .peer_book
+        .peers()
+        .chain(behaviour.banned.iter())
+        .copied()
+        .chain(behaviour.access.blocked())
+        .collect();
+    for peer in peers {
+        let permitted = swarm.behaviour().access.permits(&peer);
+        set_banned(swarm, peer, !permitted);
+    }
+}
+
+fn set_banned(swarm: &mut Swarm<CarinfoBehaviour>, peer: PeerId, banned: bool) {
+    if banned {
+        if swarm.behaviour_mut().banned.insert(peer) {
+            swarm.behaviour_mut().gossipsub.blacklist_peer(&peer);
+            swarm.ban_peer_id(peer);
+        }
+    } else if swarm.behaviour_mut().banned.remove(&peer) {
+        swarm
+            .behaviour_mut()
+            .gossipsub
+            .remove_blacklisted_peer(&peer);
+        swarm.unban_peer_id(peer);
+    }
+}
+
+/// Copies the swarm state the health endpoints report into [`Health`].
+pub fn update_health(swarm: &Swarm<CarinfoBehaviour>) {
+    let health = &swarm.behaviour().health;
+    health
+        .listening
+        .store(Swarm::listeners(swarm).next().is_some(), Ordering::Relaxed);
+    health
+        .connected_peers
+        .store(swarm.network_info().num_peers(), Ordering::Relaxed);
+    health.discovered_peers.store(
+        swarm
+            .behaviour()
+            .discovered_peers()
+            .collect::<HashSet<_>>()
+            .len(),
+        Ordering::Relaxed,
+    );
+}
+
+/// Our part in a club's shared catalog (coordinator mode).
+pub(crate) enum ClubRole {
+    None,
+    /// We hold the authoritative catalog, enabled with `CARINFO_COORDINATOR=1`, and take
+    /// changes from `members` only.
+    Coordinator {
+        catalog: ClubCatalog,
+        members: Members,
+    },
+    /// We joined `coordinator` and keep the latest catalog it sent.
+    Member {
+        coordinator: PeerId,
+        catalog: ClubCatalog,
+    },
+}
+
+/// Events emitted by `CarinfoBehaviour`, handled by [`handle_behaviour_event`].
+#[derive(Debug)]
+pub enum CarinfoEvent {
+    Gossipsub(Box<GossipsubEvent>),
+    Mdns(Box<MdnsEvent>),
+    List(Box<ListEvent>),
+    Photo(Box<PhotoEvent>),
+    Offer(Box<OfferEvent>),
+    Chat(Box<ChatEvent>),
+    Club(Box<ClubEvent>),
+    Kademlia(Box<DhtEvent>),
+    /// The relay behaviour has no events of its own, the variant only completes the derive.
+    Relay,
+    Ping(PingEvent),
+    Identify(Box<IdentifyEvent>),
+}
+
+impl From<PingEvent> for CarinfoEvent {
+    fn from(event: PingEvent) -> Self {
+        CarinfoEvent::Ping(event)
+    }
+}
+
+impl From<IdentifyEvent> for CarinfoEvent {
+    fn from(event: IdentifyEvent) -> Self {
+        CarinfoEvent::Identify(Box::new(event))
+    }
+}
+
+impl From<()> for CarinfoEvent {
+    fn from(_: ()) -> Self {
+        CarinfoEvent::Relay
+    }
+}
+
+impl From<DhtEvent> for CarinfoEvent {
+    fn from(event: DhtEvent) -> Self {
+        CarinfoEvent::Kademlia(Box::new(event))
+    }
+}
+
+impl From<ListEvent> for CarinfoEvent {
+    fn from(event: ListEvent) -> Self {
+        CarinfoEvent::List(Box::new(event))
+    }
+}
+
+impl From<PhotoEvent> for CarinfoEvent {
+    fn from(event: PhotoEvent) -> Self {
+        CarinfoEvent::Photo(Box::new(event))
+    }
+}
+
+impl From<OfferEvent> for CarinfoEvent {
+    fn from(event: OfferEvent) -> Self {
+        CarinfoEvent::Offer(Box::new(event))
+    }
+}
+
+impl From<ChatEvent> for CarinfoEvent {
+    fn from(event: ChatEvent) -> Self {
+        CarinfoEvent::Chat(Box::new(event))
+    }
+}
+
+impl From<ClubEvent> for CarinfoEvent {
+    fn from(event: ClubEvent) -> Self {
+        CarinfoEvent::Club(Box::new(event))
+    }
+}
+
+impl From<GossipsubEvent> for CarinfoEvent {
+    fn from(event: GossipsubEvent) -> Self {
+        CarinfoEvent::Gossipsub(Box::new(event))
+    }
+}
+
+impl From<MdnsEvent> for CarinfoEvent {
+    fn from(event: MdnsEvent) -> Self {
+        CarinfoEvent::Mdns(Box::new(event))
+    }
+}
+
+pub async fn handle_behaviour_event(event: CarinfoEvent, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match event {
+        CarinfoEvent::Gossipsub(event) => match *event {
+            // strict validation only lets signed messages through, so the source is known
+            GossipsubEvent::Message {
+                propagation_source,
+                message_id,
+                message,
+            } => {
+                // the id is the source and sequence number, which gossipsub only remembers
+                // for a minute
+                if !swarm.behaviour_mut().seen.first_time(&message_id.0) {
+                    debug!("dropped a copy of a message from {}", propagation_source);
+                    return;
+                }
+                if let Some(source) = message.source {
+                    handle_gossip_message(source, message, swarm).await
+                }
+            }
+            GossipsubEvent::Subscribed { peer_id, topic } => {
+                swarm
+                    .behaviour_mut()
+                    .topic_peers
+                    .entry(topic.as_str().to_owned())
+                    .or_default()
+                    .insert(peer_id);
+                // our topics are identity hashed, so the hash is the topic name
+                let topic = IdentTopic::new(topic.into_string());
+                let flushed = flush_outbox(&topic, swarm).await;
+                replay_missed(&topic, peer_id, &flushed, swarm).await;
+            }
+            GossipsubEvent::Unsubscribed { peer_id, topic } => {
+                if let Some(peers) = swarm.behaviour_mut().topic_peers.get_mut(topic.as_str()) {
+                    peers.remove(&peer_id);
+                }
+            }
+        },
+        CarinfoEvent::Mdns(event) => handle_mdns_event(*event, swarm),
+        CarinfoEvent::List(event) => handle_list_event(*event, swarm).await,
+        CarinfoEvent::Photo(event) => handle_photo_event(*event, swarm).await,
+        CarinfoEvent::Offer(event) => handle_offer_event(*event, swarm).await,
+        CarinfoEvent::Chat(event) => handle_chat_event(*event, swarm).await,
+        CarinfoEvent::Club(event) => handle_club_event(*event, swarm).await,
+        CarinfoEvent::Kademlia(event) => handle_dht_event(*event, swarm),
+        CarinfoEvent::Relay => {}
+        CarinfoEvent::Ping(event) => handle_ping_event(event, swarm.behaviour_mut()),
+        CarinfoEvent::Identify(event) => handle_identify_event(*event, swarm.behaviour_mut()),
+    }
+}
+
+/// Keeps the round trip times for `ls p` and answers `ping` commands waiting for them.
+fn handle_ping_event(event: PingEvent, behaviour: &mut CarinfoBehaviour) {
+    let peer = event.peer;
+    match event.result {
+        Ok(PingSuccess::Ping { rtt }) => {
+            behaviour.peer_book.pinged(peer, rtt);
+            if behaviour.pings.remove(&peer) {
+                info!("Ping {}: {}ms", aliases::display(&peer), rtt.as_millis());
+            }
+        }
+        // the peer pinged us
+        Ok(PingSuccess::Pong) => {}
+        Err(e) => {
+            if behaviour.pings.remove(&peer) {
+                error!("error pinging {}, {}", aliases::display(&peer), e);
+            }
+        }
+    }
+}
+
+/// Keeps what peers tell about themselves for `ls p`, pointing out once those that speak
+/// another protocol.
+fn handle_identify_event(event: IdentifyEvent, behaviour: &mut CarinfoBehaviour) {
+    match event {
+        IdentifyEvent::Received { peer_id, info } => {
+            if info.protocol_version != protocol::protocol_version()
+                && behaviour.other_versions.insert(peer_id)
+            {
+                info!(
+                    "{} runs {} and speaks {}, we speak {}",
+                    aliases::display(&peer_id),
+                    info.agent_version,
+                    info.protocol_version,
+                    protocol::protocol_version()
+                );
+            }
+            behaviour.peer_book.identified(
+                peer_id,
+                Identity {
+                    agent: info.agent_version,
+                    protocol_version: info.protocol_version,
+                    listen_addrs: info.listen_addrs.into_iter().collect(),
+                },
+            );
+        }
+        IdentifyEvent::Error { peer_id, error } => {
+            debug!("error identifying {}, {:?}", peer_id, error)
+        }
+        IdentifyEvent::Sent { .. } | IdentifyEvent::Pushed { .. } => {}
+    }
+}
+
+pub(crate) fn handle_dht_event(event: DhtEvent, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match event {
+        // connect to peers found beyond the LAN so they join our topics and can be asked
+        KademliaEvent::RoutingUpdated {
+            peer,
+            is_new_peer: true,
+            ..
+        } => {
+            info!("Found peer {} through the DHT", peer);
+            if !swarm.is_connected(&peer) && swarm.behaviour().access.permits(&peer) {
+                if let Err(e) = swarm.dial(&peer) {
+                    error!("error dialing routed peer {}, {:?}", peer, e);
+                }
+            }
+        }
+        KademliaEvent::OutboundQueryCompleted {
+            result: QueryResult::Bootstrap(Err(e)),
+            ..
+        } => error!("error bootstrapping the DHT, {:?}", e),
+        // without peers to store it with, e.g. before the DHT is joined
+        KademliaEvent::OutboundQueryCompleted {
+            result: QueryResult::StartProviding(Err(e)),
+            ..
+        } => {
+            debug!("error announcing a provider record, {:?}", e);
+            swarm.behaviour_mut().providing.failed(e.key().clone());
+        }
+        KademliaEvent::OutboundQueryCompleted {
+            id,
+            result: QueryResult::GetProviders(result),
+            ..
+        } => {
+            let behaviour = swarm.behaviour_mut();
+            let query = match behaviour.finds.remove(&id) {
+                Some(query) => query,
+                None => return,
+            };
+            let (key, providers) = match result {
+                Ok(GetProvidersOk { key, providers, .. }) => (key, providers),
+                // what was found before the timeout is as good
+                Err(GetProvidersError::Timeout { key, providers, .. }) => (key, providers),
+            };
+            let mut providers: BTreeSet<PeerId> = providers.into_iter().collect();
+            providers.extend(dht::stored_providers(&mut behaviour.kademlia, &key));
+            providers.remove(&PEER_ID);
+            show_providers(&query, &providers);
+        }
+        _ => {}
+    }
+}
+
+/// The answer to `find`.
+fn show_providers(query: &str, providers: &BTreeSet<PeerId>) {
+    if output::json() {
+        output::emit(&Output::Providers {
+            query: query.to_owned(),
+            peers: providers.iter().map(PeerId::to_string).collect(),
+        });
+        return;
+    }
+    if providers.is_empty() {
+        info!("No peer announced public {} cars", query);
+        return;
+    }
+    info!(
+        "Peers with public {} cars, ask them with ls car <peer id>: {}",
+        query,
+        providers
+            .iter()
+            .map(aliases::display)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+/// Updates our provider records to the makes and models of the public cars in the catalog.
+pub async fn announce_catalog(swarm: &mut Swarm<CarinfoBehaviour>) {
+    match read_local_carinfos().await {
+        Ok(carinfos) => {
+            let behaviour = swarm.behaviour_mut();
+            behaviour
+                .providing
+                .update(&mut behaviour.kademlia, &carinfos);
+        }
+        Err(e) => error!("error fetching local carinfos to announce, {}", e),
+    }
+}
+
+pub(crate) async fn handle_gossip_message(
+    source: PeerId,
+    msg: GossipsubMessage,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    // messages of blocked peers still reach us through others
+    if !swarm.behaviour().access.permits(&source) {
+        return;
+    }
+    let message = match decode_gossip(source, &msg.data, swarm.behaviour_mut()) {
+        Some(message) => message,
+        None => return,
+    };
+    METRICS.received(&message);
+    // replayed announcements are handled like the original, if they are meant for us
+    let message = match message {
+        Message::CatchUp(catch_up) if catch_up.replay_to == PEER_ID.to_string() => {
+            match decode_gossip(source, &catch_up.data, swarm.behaviour_mut()) {
+                Some(message) => message,
+                None => return,
+            }
+        }
+        Message::CatchUp(_) => return,
+        message => message,
+    };
+    match message {
+        Message::Share(sealed) if sealed.to == PEER_ID.to_string() => {
+            receive_share(source, sealed).await
+        }
+        Message::Telemetry(update) => {
+            if let Some(viewer) = swarm.behaviour_mut().telemetry_viewer.as_mut() {
+                if viewer.should_show(source) {
+                    info!(
+                        "Telemetry from {}: {}",
+                        source,
+                        telemetry::describe(&update)
+                    );
+                }
+            }
+        }
+        Message::CarPublished(published) => {
+            receive_published(source, *published.carinfo, swarm.behaviour_mut()).await
+        }
+        Message::CarinfoDeleted(tombstone) => {
+            let behaviour = swarm.behaviour_mut();
+            if let Some(carinfos) = behaviour.remote_carinfos.get_mut(&source) {
+                carinfos.retain(|r| r.id != tombstone.deleted);
+                if let Some(search) = behaviour.search.as_mut() {
+                    if let Err(e) = search.index_carinfos(&source.to_string(), carinfos) {
+                        error!("error indexing carinfos of {}, {}", source, e);
+                    }
+                }
+            }
+            if let Err(e) = behaviour.replica.remove(&source, tombstone.deleted).await {
+                error!("error removing synced carinfo, {}", e);
+            }
+            if let Err(e) = behaviour.cache.withdrawn(&source, tombstone.deleted).await {
+                error!("error updating cached listing, {}", e);
+            }
+            info!(
+                "{} withdrew carinfo {}",
+                aliases::display(&source),
+                tombstone.deleted
+            );
+        }
+        // meant for someone else, or only sent in direct requests
+        _ => {}
+    }
+}
+
+/// Adds a car a peer just published to what we know of its catalog, and shows it if `watch` is
+/// on.
+async fn receive_published(source: PeerId, mut carinfo: Carinfo, behaviour: &mut CarinfoBehaviour) {
+    let checked = digest::verify(&carinfo).and_then(|()| {
+        carinfo.origin.get_or_insert_with(|| source.to_string());
+        ownership::verify(&mut carinfo, &source)
+    });
+    if let Err(e) = checked {
+        error!(
+            "dropped carinfo {} published by {}, {}",
+            carinfo.id,
+            aliases::display(&source),
+            e
+        );
+        return;
+    }
+    if behaviour.watching {
+        let details = format::details(&carinfo);
+        info!(
+            "{} just published {} {}, {}{}",
+            aliases::display(&source),
+            carinfo.make.trim(),
+            carinfo.model.trim(),
+            format::NUMBER_FORMAT.power(f64::from(carinfo.horsepower)),
+            if details.is_empty() {
+                String::new()
+            } else {
+                format!(", {}", details)
+            }
+        );
+    }
+    if let Err(e) = behaviour.cache.published(&source, &carinfo).await {
+        error!("error updating cached listing, {}", e);
+    }
+    let carinfos = behaviour.remote_carinfos.entry(source).or_default();
+    match carinfos.iter_mut().find(|r| r.id == carinfo.id) {
+        Some(known) => *known = carinfo,
+        None => carinfos.push(carinfo),
+    }
+    if let Some(search) = behaviour.search.as_mut() {
+        if let Err(e) = search.index_carinfos(&source.to_string(), carinfos) {
+            error!("error indexing carinfos of {}, {}", source, e);
+        }
+    }
+}
+
+/// Decodes a topic message, telling the user once per peer about messages from another
+/// protocol version.
+fn decode_gossip(source: PeerId, data: &[u8], behaviour: &mut CarinfoBehaviour) -> Option<Message> {
+    match wire::decode(data) {
+        Ok(message) => Some(message),
+        Err(DecodeError::Version(version)) => {
+            if behaviour.other_versions.insert(source) {
+                info!(
+                    "Ignoring messages from {}, it speaks protocol version {} and we speak {}",
+                    source,
+                    version,
+                    wire::PROTOCOL_VERSION
+                );
+            }
+            None
+        }
+        Err(e) => {
+            error!("message from {} dropped, {}", source, e);
+            None
+        }
+    }
+}
+
+pub(crate) async fn handle_list_event(event: ListEvent, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match event {
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                },
+        } => {
+            // dropping the channel fails the request on the peer's side
+            if !swarm.behaviour_mut().rate_limiter.allow(&peer) {
+                return;
+            }
+            info!("Received req: {:?} from {:?}", request, peer);
+            respond_with_public_carinfos(peer, &request, channel, swarm).await;
+        }
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Response {
+                    request_id,
+                    response,
+                },
+        } => {
+            let behaviour = swarm.behaviour_mut();
+            let checked = response
+                .verify(&peer, &PEER_ID)
+                .and_then(|()| behaviour.pages.answers(request_id, &response));
+            if let Err(e) = checked {
+                list_failed(peer, request_id, ListError::Unverified(e), behaviour);
+                return;
+            }
+            // with a nonce the signature is unique, so a response seen before is a copy or a
+            // replay; older peers send the same signed listing for an unchanged catalog
+            if response.nonce.is_some() && !behaviour.seen.first_time(&response.signature) {
+                debug!("dropped a response of {} seen before", peer);
+                let request_id = behaviour.pages.fail(request_id);
+                round_answered(request_id, None, false, behaviour);
+                return;
+            }
+            let (request_id, mut response) =
+                match behaviour
+                    .pages
+                    .receive(&mut behaviour.list, &peer, request_id, response)
+                {
+                    Some(listing) => listing,
+                    None => return,
+                };
+            digest::drop_corrupted(&peer, &mut response.data);
+            response.complete(&peer);
+            ownership::drop_forged(&peer, &mut response.data);
+            let cars = response.data.len();
+            if let Some(reply) = behaviour.api_requests.remove(&request_id) {
+                let _ = reply.send(Ok(response.data));
+            } else if behaviour.syncs.remove(&request_id) {
+                handle_sync_response(peer, response, swarm).await;
+            } else if let Some(filter) = behaviour.searches.remove(&request_id) {
+                handle_search_response(peer, &filter, response, swarm);
+            } else if let Some(text) = behaviour.text_searches.remove(&request_id) {
+                handle_text_search_response(peer, &text, response, swarm);
+            } else {
+                handle_list_response(peer, response, swarm).await;
+            }
+            round_answered(request_id, Some(cars), false, swarm.behaviour_mut());
+        }
+        RequestResponseEvent::OutboundFailure {
+            peer,
+            request_id,
+            error,
+        } => list_failed(
+            peer,
+            request_id,
+            ListError::Outbound(error),
+            swarm.behaviour_mut(),
+        ),
+        RequestResponseEvent::InboundFailure { peer, error, .. } => {
+            error!("list request from {} failed, {:?}", peer, error)
+        }
+        RequestResponseEvent::ResponseSent { .. } => {}
+    }
+}
+
+/// Gives up on a listing, telling the REST API client waiting for it or the user why.
+fn list_failed(
+    peer: PeerId,
+    request_id: RequestId,
+    error: ListError,
+    behaviour: &mut CarinfoBehaviour,
+) {
+    let request_id = behaviour.pages.fail(request_id);
+    behaviour.searches.remove(&request_id);
+    behaviour.text_searches.remove(&request_id);
+    behaviour.syncs.remove(&request_id);
+    let timed_out = matches!(error, ListError::Outbound(OutboundFailure::Timeout));
+    round_answered(request_id, None, timed_out, behaviour);
+    match behaviour.api_requests.remove(&request_id) {
+        Some(reply) => {
+            let _ = reply.send(Err(error));
+        }
+        None => match error {
+            ListError::Outbound(e) => error!("list request to {} failed, {:?}", peer, e),
+            ListError::Unverified(e) => error!("dropped the answer of {}, {}", peer, e),
+        },
+    }
+}
+
+/// Counts an answer, or the lack of one, towards the `ls car all` it was asked for, showing the
+/// summary once every peer answered.
+fn round_answered(
+    request_id: RequestId,
+    cars: Option<usize>,
+    timed_out: bool,
+    behaviour: &mut CarinfoBehaviour,
+) {
+    if let Some(round) = behaviour.rounds.answered(request_id, cars, timed_out) {
+        end_round(&round, behaviour.request.0);
+    }
+}
+
+/// Shows how an `ls car all` went, which marks the end of its answers.
+pub(crate) fn end_round(round: &ListRound, verbosity: Verbosity) {
+    if output::json() {
+        output::emit(&Output::ListSummary {
+            number: round.number,
+            answered: round.answered,
+            cached: round.cached,
+            cars: round.cars,
+            timed_out: round.timed_out.iter().map(PeerId::to_string).collect(),
+            failed: round.failed,
+        });
+        return;
+    }
+    info!("ls car all #{} done: {}", round.number, round.summary());
+    if verbosity == Verbosity::Verbose && !round.timed_out.is_empty() {
+        let peers: Vec<String> = round.timed_out.iter().map(aliases::display).collect();
+        info!("Timed out: {}", peers.join(", "));
+    }
+}
+
+pub(crate) async fn handle_list_response(
+    source: PeerId,
+    resp: ListResponse,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let behaviour = swarm.behaviour_mut();
+    let (verbosity, sent_at) = behaviour.request;
+    match verbosity {
+        _ if output::json() => output::emit(&Output::RemoteCars {
+            peer: source.to_string(),
+            cars: &resp.data,
+            cached_at: None,
+        }),
+        Verbosity::Quiet => {}
+        Verbosity::Normal => info!("Response from {}:", aliases::display(&source)),
+        Verbosity::Verbose => {
+            let json = serde_json::to_string(&resp).expect("can jsonify response");
+            info!(
+                "Response from {} ({} cars, {} bytes, {}ms after request):",
+                aliases::label(&source),
+                resp.data.len(),
+                json.len(),
+                sent_at.elapsed().as_millis()
+            );
+            info!("Raw: {}", json);
+        }
+    }
+    behaviour.last_responses.insert(source, Instant::now());
+    behaviour
+        .health
+        .responses_received
+        .fetch_add(1, Ordering::Relaxed);
+    if !output::json() {
+        pager::page(&mut behaviour.pager, verbosity.carinfo_lines(&resp.data));
+    }
+    if let Some(search) = behaviour.search.as_mut() {
+        if let Err(e) = search.index_carinfos(&source.to_string(), &resp.data) {
+            error!("error indexing carinfos of {}, {}", source, e);
+        }
+    }
+    behaviour.notify(UiEvent::Listing {
+        peer: source,
+        carinfos: resp.data.clone(),
+    });
+    if let Err(e) = behaviour.cache.store(&source, &resp.data).await {
+        error!("error caching listing of {}, {}", source, e);
+    }
+    behaviour.remote_carinfos.insert(source, resp.data);
+}
+
+/// Stores a peer's full listing, requested with `sync`, as our copy of its catalog.
+pub(crate) async fn handle_sync_response(
+    source: PeerId,
+    resp: ListResponse,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let behaviour = swarm.behaviour_mut();
+    behaviour.last_responses.insert(source, Instant::now());
+    behaviour
+        .health
+        .responses_received
+        .fetch_add(1, Ordering::Relaxed);
+    behaviour.remote_carinfos.insert(source, resp.data.clone());
+    match behaviour.replica.sync(&source, resp.data).await {
+        Ok(summary) => {
+            info!(
+                "Synced {}: {} added, {} updated, {} removed",
+                aliases::display(&source),
+                summary.added,
+                summary.updated,
+                summary.removed
+            );
+            if summary.conflicts > 0 {
+                info!(
+                    "{} of them disagreed with our copy, {}",
+                    summary.conflicts,
+                    match behaviour.replica.policy {
+                        ConflictPolicy::Manual => "see conflicts",
+                        ConflictPolicy::NewestWins => "kept the newest fields",
+                        ConflictPolicy::OriginWins => "took the origin's copy",
+                    }
+                );
+            }
+        }
+        Err(e) => error!("error storing carinfos synced from {}, {}", source, e),
+    }
+}
+
+/// Shows the records a peer sent for a search. Peers that don't know about searches send all
+/// their public records, so the filter is applied again. Search results are partial listings and
+/// don't replace what we know of the peer's catalog.
+pub(crate) fn handle_search_response(
+    source: PeerId,
+    filter: &Filter,
+    mut resp: ListResponse,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let behaviour = swarm.behaviour_mut();
+    resp.data.retain(|r| filter.matches(r));
+    if output::json() {
+        output::emit(&Output::Matches {
+            peer: Some(source.to_string()),
+            filter: filter.to_string(),
+            cars: &resp.data,
+        });
+    }
+    let (verbosity, _) = behaviour.request;
+    show_matches(
+        source,
+        &filter.to_string(),
+        resp.data.len(),
+        behaviour,
+        || verbosity.carinfo_lines(&resp.data),
+    );
+}
+
+/// Shows the records a peer sent for `search net`, best first. Peers that don't know about
+/// text searches send all their public records, so they are ranked again.
+pub(crate) fn handle_text_search_response(
+    source: PeerId,
+    text: &str,
+    resp: ListResponse,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let behaviour = swarm.behaviour_mut();
+    let ranked = match search::rank(text, resp.data) {
+        Ok(ranked) => ranked,
+        Err(e) => {
+            error!("error ranking carinfos of {}, {}", source, e);
+            return;
+        }
+    };
+    let owner = source.to_string();
+    if output::json() {
+        output::emit(&Output::SearchHits {
+            query: text.to_owned(),
+            hits: ranked
+                .iter()
+                .map(|(score, carinfo)| output::Hit {
+                    score: *score,
+                    owner: &owner,
+                    car: carinfo,
+                })
+                .collect(),
+        });
+    }
+    show_matches(source, text, ranked.len(), behaviour, || {
+        let peer = aliases::display(&source);
+        ranked
+            .iter()
+            .map(|(score, carinfo)| format!("{:.1} {}: {}", score, peer, format::carinfo(carinfo)))
+            .collect()
+    });
+}
+
+/// Notes that `source` answered a search and, unless the output is JSON, pages the `lines`
+/// showing its `matches` for `query`.
+fn show_matches(
+    source: PeerId,
+    query: &str,
+    matches: usize,
+    behaviour: &mut CarinfoBehaviour,
+    lines: impl FnOnce() -> Vec<String>,
+) {
+    behaviour.last_responses.insert(source, Instant::now());
+    behaviour
+        .health
+        .responses_received
+        .fetch_add(1, Ordering::Relaxed);
+    if output::json() {
+        return;
+    }
+    let (verbosity, _) = behaviour.request;
+    if verbosity != Verbosity::Quiet {
+        info!(
+            "{} matches from {} for {}",
+            matches,
+            aliases::display(&source),
+            query
+        );
+    }
+    pager::page(&mut behaviour.pager, lines());
+}
+
+/// Listens at a circuit address of every relay, so peers that can't dial us directly reach us
+/// through it.
+pub fn listen_through_relays(swarm: &mut Swarm<CarinfoBehaviour>, relays: &[(PeerId, Multiaddr)]) {
+    for (relay, addr) in relays {
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(relay, addr.clone());
+        if let Err(e) = swarm.listen_on(nat::circuit_address(relay, addr)) {
+            error!("error listening through relay {}, {:?}", relay, e);
+        }
+    }
+}
+
+/// Dials the peers we had a connection to recently, so the network forms again after a
+/// restart without waiting for mDNS or the bootstrap nodes.
+pub fn reconnect_known(swarm: &mut Swarm<CarinfoBehaviour>) {
+    let recent = swarm.behaviour().peer_book.recent(peers::RECONNECT_WITHIN);
+    let mut dialed = 0;
+    for (peer, addrs) in recent {
+        if swarm.is_connected(&peer) || !swarm.behaviour().access.permits(&peer) {
+            continue;
+        }
+        for addr in addrs {
+            swarm.behaviour_mut().kademlia.add_address(&peer, addr);
+        }
+        match swarm.dial(&peer) {
+            Ok(()) => dialed += 1,
+            Err(e) => debug!("error dialing known peer {}, {:?}", peer, e),
+        }
+    }
+    if dialed > 0 {
+        info!("Reconnecting to {} known peer(s)", dialed);
+    }
+}
+
+/// Dials the bootstrap nodes we aren't connected to that are due for another attempt.
+pub fn redial_bootstrap(swarm: &mut Swarm<CarinfoBehaviour>) {
+    let connected: Vec<PeerId> = swarm
+        .behaviour()
+        .bootstrap
+        .peers()
+        .filter(|peer| swarm.is_connected(peer))
+        .copied()
+        .collect();
+    for peer in swarm.behaviour_mut().bootstrap.due(&connected) {
+        info!("Dialing bootstrap node {}", aliases::display(&peer));
+        if let Err(e) = swarm.dial(&peer) {
+            error!("error dialing bootstrap node {}, {:?}", peer, e);
+        }
+    }
+}
+
+/// Answers with the requested page of our public records and those granted to the receiver,
+/// only those matching the request's filter if it has one, best first for a text search.
+pub(crate) async fn respond_with_public_carinfos(
+    receiver: PeerId,
+    request: &ListRequest,
+    channel: ResponseChannel<ListResponse>,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    match read_carinfos_for(&receiver).await {
+        Ok(mut data) => {
+            if let Some(search) = &request.search {
+                data.retain(|r| search.filter.matches(r));
+                if let Some(text) = &search.text {
+                    data = match search::rank(text, data) {
+                        Ok(ranked) => ranked.into_iter().map(|(_, carinfo)| carinfo).collect(),
+                        Err(e) => {
+                            error!("error ranking carinfos to answer request, {}", e);
+                            return;
+                        }
+                    };
+                }
+            }
+            let resp = ListResponse::page(request, &receiver, data, geo::HOME.clone());
+            let car_ids: Vec<usize> = resp.data.iter().map(|r| r.id).collect();
+            let behaviour = swarm.behaviour_mut();
+            if behaviour.list.send_response(channel, resp).is_err() {
+                error!("{} stopped waiting for our carinfos", receiver);
+                return;
+            }
+            behaviour
+                .health
+                .requests_served
+                .fetch_add(1, Ordering::Relaxed);
+            behaviour.notify(UiEvent::Served { peer: receiver });
+            if let Err(e) = behaviour
+                .audit
+                .record(&receiver.to_string(), AccessKind::Listing, car_ids)
+                .await
+            {
+                error!("error writing audit log, {}", e);
+            }
+        }
+        Err(e) => error!("error fetching local carinfos to answer request, {}", e),
+    }
+}
+
+pub(crate) fn handle_mdns_event(event: MdnsEvent, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match event {
+        MdnsEvent::Discovered(discovered_list) => {
+            // gossipsub only talks to connected peers, the mesh is built from there
+            for (peer, addr) in discovered_list {
+                swarm
+                    .behaviour_mut()
+                    .peer_book
+                    .discovered(peer, addr.clone());
+                // LAN peers also seed the routing table, so remote peers can be found through them
+                swarm.behaviour_mut().kademlia.add_address(&peer, addr);
+                if !swarm.is_connected(&peer) && swarm.behaviour().access.permits(&peer) {
+                    if let Err(e) = swarm.dial(&peer) {
+                        error!("error dialing discovered peer {}, {:?}", peer, e);
+                    }
+                }
+                swarm.behaviour_mut().notify(UiEvent::PeerDiscovered(peer));
+            }
+        }
+        MdnsEvent::Expired(expired_list) => {
+            for (peer, _addr) in expired_list {
+                if !swarm.behaviour().discovered_peers().any(|p| *p == peer) {
+                    swarm.behaviour_mut().notify(UiEvent::PeerExpired(peer));
+                }
+            }
+        }
+    }
+}
+
+pub(crate) async fn receive_share(source: PeerId, sealed: SealedShare) {
+    let share = match share::open(&source, &sealed) {
+        Ok(share) => share,
+        Err(e) => {
+            error!("error opening share from {}, {}", source, e);
+            return;
+        }
+    };
+    for (hash, data) in share.thumbnails {
+        if let Err(e) = photos::store_thumbnail(&hash, &data).await {
+            error!("error storing thumbnail from {}, {}", source, e);
+        }
+    }
+    info!(
+        "{} shared a car with you: {}",
+        source,
+        format::carinfo(&share.carinfo)
+    );
+    if let Err(e) = share::store_received(&source, share.carinfo).await {
+        error!("error storing shared carinfo, {}", e);
+    }
+}
+
+pub(crate) async fn handle_club_event(event: ClubEvent, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match event {
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                },
+        } => {
+            let response = receive_club_request(peer, request, swarm).await;
+            if swarm
+                .behaviour_mut()
+                .club_protocol
+                .send_response(channel, response)
+                .is_err()
+            {
+                error!("{} stopped waiting for our club response", peer);
+            }
+        }
+        RequestResponseEvent::Message {
+            peer,
+            message: RequestResponseMessage::Response { response, .. },
+        } => match response {
+            ClubResponse::Catalog(update) => {
+                update_club_catalog(&peer, update, swarm.behaviour_mut());
+            }
+            ClubResponse::Received => {}
+            ClubResponse::Refused(reason) => info!(
+                "{} refused our club request: {}",
+                aliases::display(&peer),
+                reason
+            ),
+        },
+        RequestResponseEvent::OutboundFailure { peer, error, .. } => error!(
+            "error sending club request to {}, {:?}",
+            aliases::display(&peer),
+            error
+        ),
+        RequestResponseEvent::InboundFailure { peer, error, .. } => {
+            error!("club request from {} failed, {:?}", peer, error)
+        }
+        RequestResponseEvent::ResponseSent { .. } => {}
+    }
+}
+
+/// Applies a change of one of our club's members and answers with the catalog, or takes the
+/// catalog of the coordinator we joined.
+async fn receive_club_request(
+    peer: PeerId,
+    request: ClubRequest,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) -> ClubResponse {
+    let behaviour = swarm.behaviour_mut();
+    let member = peer.to_string();
+    let (catalog, members, request) = match (&mut behaviour.club, request) {
+        (ClubRole::Coordinator { catalog, members }, request) => (catalog, members, request),
+        (ClubRole::Member { .. }, ClubRequest::Catalog(update)) => {
+            return if update_club_catalog(&peer, update, behaviour) {
+                ClubResponse::Received
+            } else {
+                ClubResponse::Refused("not our club coordinator".to_owned())
+            };
+        }
+        _ => return ClubResponse::Refused("not a club coordinator".to_owned()),
+    };
+    if !members.0.contains(&member) {
+        info!(
+            "Refused club request of {}, not a member",
+            aliases::display(&peer)
+        );
+        return ClubResponse::Refused("not a member of this club".to_owned());
+    }
+    let changed = match request {
+        ClubRequest::Submit(carinfo) => {
+            info!(
+                "Club member {} submitted carinfo {}",
+                aliases::display(&peer),
+                carinfo.id
+            );
+            catalog.submit(member, *carinfo);
+            true
+        }
+        ClubRequest::Withdraw(id) => {
+            let removed = catalog.withdraw(&member, id);
+            if removed {
+                info!(
+                    "Club member {} withdrew carinfo {}",
+                    aliases::display(&peer),
+                    id
+                );
+            }
+            removed
+        }
+        ClubRequest::Sync => false,
+        ClubRequest::Catalog(_) => {
+            return ClubResponse::Refused("this node coordinates the club".to_owned())
+        }
+    };
+    let response = ClubResponse::Catalog(catalog.clone());
+    if changed {
+        commit_club_catalog(Some(&peer), swarm).await;
+    }
+    response
+}
+
+/// Takes `update` if it comes from the coordinator we joined, returning whether it did.
+/// Versions older than ours are ignored.
+fn update_club_catalog(
+    peer: &PeerId,
+    update: ClubCatalog,
+    behaviour: &mut CarinfoBehaviour,
+) -> bool {
+    match &mut behaviour.club {
+        ClubRole::Member {
+            coordinator,
+            catalog,
+        } if coordinator == peer => {
+            if update.version > catalog.version {
+                info!(
+                    "Club catalog updated to version {} ({} cars)",
+                    update.version,
+                    update.entries.len()
+                );
+                *catalog = update;
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Persists the coordinator's catalog after a change and sends it to the members connected
+/// now, except `requester`, which gets it in the response to its request. The others ask for
+/// it when they next connect.
+pub(crate) async fn commit_club_catalog(
+    requester: Option<&PeerId>,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let (catalog, members) = match &swarm.behaviour().club {
+        ClubRole::Coordinator { catalog, members } => (catalog.clone(), members),
+        _ => return,
+    };
+    let online: Vec<PeerId> = members
+        .0
+        .iter()
+        .filter_map(|member| member.parse().ok())
+        .filter(|member| Some(member) != requester && swarm.is_connected(member))
+        .collect();
+    if let Err(e) = club::write_catalog(&catalog).await {
+        error!("error writing club catalog, {}", e);
+    }
+    for member in online {
+        swarm
+            .behaviour_mut()
+            .club_protocol
+            .send_request(&member, ClubRequest::Catalog(catalog.clone()));
+    }
+}
+
+/// Broadcasts a change other peers should not miss: known peers that are offline get it
+/// replayed from the catch-up log when they return.
+pub(crate) async fn announce(
+    topic: IdentTopic,
+    message: Message,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    METRICS.published(&message);
+    let data = wire::encode(&message);
+    let behaviour = swarm.behaviour_mut();
+    let online: HashSet<String> = behaviour
+        .topic_peers
+        .get(topic.hash().as_str())
+        .map(|peers| peers.iter().map(|p| p.to_string()).collect())
+        .unwrap_or_default();
+    if behaviour
+        .catchup
+        .record(topic.hash().as_str(), &data, &online)
+        > 0
+    {
+        if let Err(e) = behaviour.catchup.save().await {
+            error!("error writing catch-up log, {}", e);
+        }
+    }
+    publish_or_queue(topic, data, swarm).await;
+}
+
+/// Remembers `peer` as a listener of `topic` and sends it what it missed while offline,
+/// except messages it just got from the outbox.
+pub(crate) async fn replay_missed(
+    topic: &IdentTopic,
+    peer: PeerId,
+    flushed: &[Vec<u8>],
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let behaviour = swarm.behaviour_mut();
+    let new_peer = behaviour
+        .catchup
+        .remember(topic.hash().as_str(), &peer.to_string());
+    let missed = behaviour
+        .catchup
+        .take(topic.hash().as_str(), &peer.to_string());
+    if new_peer || !missed.is_empty() {
+        if let Err(e) = behaviour.catchup.save().await {
+            error!("error writing catch-up log, {}", e);
+        }
+    }
+    let missed: Vec<Vec<u8>> = missed
+        .into_iter()
+        .filter(|data| !flushed.contains(data))
+        .collect();
+    if missed.is_empty() {
+        return;
+    }
+    info!(
+        "Replaying {} missed announcements to {}",
+        missed.len(),
+        peer
+    );
+    for data in missed {
+        let message = Message::CatchUp(CatchUp {
+            replay_to: peer.to_string(),
+            data,
+        });
+        METRICS.published(&message);
+        publish(behaviour, topic.clone(), wire::encode(&message));
+    }
+}
+
+/// Publishes on gossipsub, logging failures.
+pub(crate) fn publish(
+    behaviour: &mut CarinfoBehaviour,
+    topic: IdentTopic,
+    data: impl Into<Vec<u8>>,
+) {
+    if let Err(e) = behaviour.gossipsub.publish(topic.clone(), data) {
+        error!("error publishing on {}, {:?}", topic.hash(), e);
+    }
+}
+
+async fn publish_or_queue(topic: IdentTopic, data: Vec<u8>, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    if behaviour
+        .topic_peers
+        .get(topic.hash().as_str())
+        .is_some_and(|peers| !peers.is_empty())
+    {
+        publish(behaviour, topic, data);
+        return;
+    }
+    match behaviour.outbox.push(topic.hash().as_str(), data).await {
+        Ok(()) => info!(
+            "No peers on {} yet, message queued ({} waiting)",
+            topic.hash().as_str(),
+            behaviour.outbox.len()
+        ),
+        Err(e) => error!("error queueing message, {}", e),
+    }
+}
+
+/// Sends the queued messages for `topic` once a peer subscribed to it, returning what was sent.
+pub(crate) async fn flush_outbox(
+    topic: &IdentTopic,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) -> Vec<Vec<u8>> {
+    let behaviour = swarm.behaviour_mut();
+    if behaviour.outbox.is_empty() {
+        return Vec::new();
+    }
+    match behaviour.outbox.take(topic.hash().as_str()).await {
+        Ok(messages) => {
+            if !messages.is_empty() {
+                info!(
+                    "Sending {} queued messages on {}",
+                    messages.len(),
+                    topic.hash().as_str()
+                );
+            }
+            messages
+                .into_iter()
+                .map(|msg| {
+                    publish(behaviour, topic.clone(), msg.data.clone());
+                    msg.data
+                })
+                .collect()
+        }
+        Err(e) => {
+            error!("error flushing outbox, {}", e);
+            Vec::new()
+        }
+    }
+}
+
+pub(crate) async fn handle_chat_event(event: ChatEvent, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match event {
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                },
+        } => {
+            let response = receive_chat(peer, request, swarm).await;
+            if swarm
+                .behaviour_mut()
+                .chat
+                .send_response(channel, response)
+                .is_err()
+            {
+                error!("{} stopped waiting for our chat response", peer);
+            }
+        }
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Response {
+                    request_id,
+                    response,
+                },
+        } => {
+            let chats = &mut swarm.behaviour_mut().chats;
+            let (thread, line) = match chats.sending.remove(&request_id) {
+                Some(sent) => sent,
+                None => return,
+            };
+            // a refused line would be refused again, so it isn't sent again either
+            if let ChatResponse::Refused(reason) = response {
+                info!(
+                    "{} didn't take your message in chat #{}: {}",
+                    aliases::display(&peer),
+                    thread,
+                    reason
+                );
+            }
+            if let Some(line) = chats
+                .threads
+                .get_mut(thread)
+                .and_then(|t| t.lines.get_mut(line))
+            {
+                line.pending = false;
+            }
+            if let Err(e) = chats.save().await {
+                error!("error storing chat message, {}", e);
+            }
+        }
+        RequestResponseEvent::OutboundFailure {
+            peer,
+            request_id,
+            error,
+        } => {
+            if let Some((thread, _)) = swarm.behaviour_mut().chats.sending.remove(&request_id) {
+                info!(
+                    "couldn't reach {} with your message in chat #{}, {:?}; it is sent again \
+                     when they connect",
+                    aliases::display(&peer),
+                    thread,
+                    error
+                );
+            }
+        }
+        RequestResponseEvent::InboundFailure { peer, error, .. } => {
+            error!("chat message from {} failed, {:?}", peer, error)
+        }
+        RequestResponseEvent::ResponseSent { .. } => {}
+    }
+}
+
+/// Stores a chat line from `source` about its car or ours.
+async fn receive_chat(
+    source: PeerId,
+    msg: ChatMessage,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) -> ChatResponse {
+    let counterpart = source.to_string();
+    if msg.owner != counterpart && msg.owner != PEER_ID.to_string() {
+        return ChatResponse::Refused(format!(
+            "chats are about a car of yours or mine, car {} is {}'s",
+            msg.car_id, msg.owner
+        ));
+    }
+    let line = ChatLine {
+        from_me: false,
+        text: msg.text,
+        at: msg.sent_at,
+        pending: false,
+    };
+    let text = line.text.clone();
+    let chats = &mut swarm.behaviour_mut().chats;
+    match chats.add(&msg.owner, msg.car_id, &counterpart, line).await {
+        Ok(thread) => {
+            info!(
+                "Chat #{} from {} about car {}: {}",
+                thread,
+                aliases::display(&source),
+                msg.car_id,
+                text
+            );
+            ChatResponse::Received
+        }
+        Err(e) => {
+            error!("error storing chat message, {}", e);
+            ChatResponse::Refused("the message couldn't be stored".to_owned())
+        }
+    }
+}
+
+/// Reads the requested thumbnails of cars the peer may list, recording them in the audit log.
+async fn read_requested_thumbnails(
+    source: PeerId,
+    req: ThumbnailRequest,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) -> ThumbnailResponse {
+    // only thumbnails of cars the peer may list are handed out
+    let public_thumbnails: HashMap<String, usize> = match read_carinfos_for(&source).await {
+        Ok(carinfos) => carinfos
+            .iter()
+            .flat_map(|r| r.photos.iter().map(|p| (p.thumbnail.clone(), r.id)))
+            .collect(),
+        Err(e) => {
+            error!(
+                "error fetching local carinfos to answer thumbnail request, {}",
+                e
+            );
+            HashMap::new()
+        }
+    };
+    let hashes: Vec<String> = req
+        .hashes
+        .into_iter()
+        .filter(|hash| public_thumbnails.contains_key(hash))
+        .collect();
+    let resp = ThumbnailResponse {
+        thumbnails: photos::read_thumbnails(&hashes).await,
+    };
+    let car_ids: BTreeSet<usize> = resp
+        .thumbnails
+        .iter()
+        .filter_map(|(hash, _)| public_thumbnails.get(hash).copied())
+        .collect();
+    if let Err(e) = swarm
+        .behaviour_mut()
+        .audit
+        .record(&source.to_string(), AccessKind::Thumbnails, car_ids)
+        .await
+    {
+        error!("error writing audit log, {}", e);
+    }
+    resp
+}
+
+async fn receive_thumbnails(source: PeerId, resp: ThumbnailResponse) {
+    for (hash, data) in resp.thumbnails {
+        match photos::store_thumbnail(&hash, &data).await {
+            Ok(path) => info!("Thumbnail from {}: {}", source, path.display()),
+            Err(e) => error!("error storing thumbnail from {}, {}", source, e),
+        }
+    }
+}
+
+pub(crate) async fn handle_photo_event(event: PhotoEvent, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match event {
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                },
+        } => {
+            let (reply, what) = match request {
+                PhotoQuery::Chunk(request) => (
+                    PhotoReply::Chunk(read_photo_chunk(peer, &request, swarm).await),
+                    format!("photo {}", request.hash),
+                ),
+                PhotoQuery::Thumbnails(request) => (
+                    PhotoReply::Thumbnails(read_requested_thumbnails(peer, request, swarm).await),
+                    "thumbnails".to_owned(),
+                ),
+            };
+            if swarm
+                .behaviour_mut()
+                .photo
+                .send_response(channel, reply)
+                .is_err()
+            {
+                error!("{} stopped waiting for {}", peer, what);
+            }
+        }
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Response {
+                    request_id,
+                    response,
+                },
+        } => match response {
+            PhotoReply::Chunk(chunk) => {
+                let download = match swarm.behaviour_mut().downloads.remove(&request_id) {
+                    Some(download) => download,
+                    None => return,
+                };
+                receive_photo_chunk(peer, download, chunk, swarm).await;
+            }
+            PhotoReply::Thumbnails(resp) => receive_thumbnails(peer, resp).await,
+        },
+        RequestResponseEvent::OutboundFailure {
+            peer,
+            request_id,
+            error,
+        } => {
+            if let Some(download) = swarm.behaviour_mut().downloads.remove(&request_id) {
+                error!(
+                    "error fetching photo {} from {}, {:?}; fetch photo {} again to resume",
+                    download.photo.file_name,
+                    aliases::display(&peer),
+                    error,
+                    download.car_id
+                );
+            } else {
+                error!(
+                    "error fetching thumbnails from {}, {:?}",
+                    aliases::display(&peer),
+                    error
+                );
+            }
+        }
+        RequestResponseEvent::InboundFailure { peer, error, .. } => {
+            error!("photo request from {} failed, {:?}", peer, error)
+        }
+        RequestResponseEvent::ResponseSent { .. } => {}
+    }
+}
+
+/// Reads the requested chunk if the photo belongs to a car the peer may list, recording the
+/// download in the audit log when it starts.
+async fn read_photo_chunk(
+    peer: PeerId,
+    request: &PhotoRequest,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) -> PhotoChunk {
+    let car_id = match read_carinfos_for(&peer).await {
+        Ok(carinfos) => carinfos
+            .iter()
+            .find(|r| r.photos.iter().any(|p| p.hash == request.hash))
+            .map(|r| r.id),
+        Err(e) => {
+            error!(
+                "error fetching local carinfos to answer photo request, {}",
+                e
+            );
+            None
+        }
+    };
+    let car_id = match car_id {
+        Some(car_id) => car_id,
+        None => return PhotoChunk::Unavailable,
+    };
+    let chunk = match photos::read_chunk(&request.hash, request.offset).await {
+        Ok(chunk) => chunk,
+        Err(e) => {
+            error!("error reading photo {}, {}", request.hash, e);
+            return PhotoChunk::Unavailable;
+        }
+    };
+    if request.offset == 0 {
+        if let Err(e) = swarm
+            .behaviour_mut()
+            .audit
+            .record(&peer.to_string(), AccessKind::Photo, [car_id])
+            .await
+        {
+            error!("error writing audit log, {}", e);
+        }
+    }
+    chunk
+}
+
+/// Stores a chunk of a photo being fetched and asks for the next one, until the photo is
+/// complete.
+async fn receive_photo_chunk(
+    peer: PeerId,
+    download: Download,
+    chunk: PhotoChunk,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let (offset, size, data) = match chunk {
+        PhotoChunk::Data { offset, size, data } => (offset, size, data),
+        PhotoChunk::Unavailable => {
+            error!(
+                "{} doesn't share photo {} of car {}",
+                aliases::display(&peer),
+                download.photo.file_name,
+                download.car_id
+            );
+            return;
+        }
+    };
+    match photos::store_chunk(&download.photo.hash, offset, size, &data).await {
+        Ok(Some(path)) => info!(
+            "Fetched photo {} of car {} from {}: {}",
+            download.photo.file_name,
+            download.car_id,
+            aliases::display(&peer),
+            path.display()
+        ),
+        Ok(None) => {
+            let behaviour = swarm.behaviour_mut();
+            let request_id = behaviour.photo.send_request(
+                &peer,
+                PhotoQuery::Chunk(PhotoRequest {
+                    hash: download.photo.hash.clone(),
+                    offset: offset + data.len() as u64,
+                }),
+            );
+            behaviour.downloads.insert(request_id, download);
+        }
+        Err(e) => error!(
+            "error storing photo {} from {}, {}",
+            download.photo.file_name,
+            aliases::display(&peer),
+            e
+        ),
+    }
+}
+
+pub(crate) async fn handle_offer_event(event: OfferEvent, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match event {
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                },
+        } => {
+            let response = match request {
+                OfferRequest::Offer(offer) => receive_offer(peer, offer, swarm).await,
+                OfferRequest::Answer { id, accepted } => {
+                    receive_answer(peer, id, accepted, swarm).await
+                }
+            };
+            if swarm
+                .behaviour_mut()
+                .offer
+                .send_response(channel, response)
+                .is_err()
+            {
+                error!("{} stopped waiting for our offer response", peer);
+            }
+        }
+        RequestResponseEvent::Message {
+            peer,
+            message:
+                RequestResponseMessage::Response {
+                    request_id,
+                    response,
+                },
+        } => {
+            let behaviour = swarm.behaviour_mut();
+            let offers = &mut behaviour.offers;
+            match (offers.sending.remove(&request_id), response) {
+                (Some(Sending::Offer(offer)), OfferResponse::Received) => {
+                    info!(
+                        "{} received your offer for car {}",
+                        aliases::display(&peer),
+                        offer.car_id
+                    );
+                    offers.outgoing.push(OfferEntry {
+                        peer: peer.to_string(),
+                        offer,
+                        state: OfferState::Pending,
+                        told: true,
+                        at: chat::now(),
+                    });
+                }
+                (Some(Sending::Answer(n)), OfferResponse::Received) => {
+                    if let Some(entry) = offers.incoming.get_mut(n) {
+                        entry.told = true;
+                    }
+                }
+                (Some(Sending::Offer(offer)), OfferResponse::Refused(reason)) => {
+                    info!(
+                        "{} refused your offer for car {}: {}",
+                        aliases::display(&peer),
+                        offer.car_id,
+                        reason
+                    );
+                    return;
+                }
+                (Some(Sending::Answer(n)), OfferResponse::Refused(reason)) => {
+                    info!(
+                        "{} didn't take your answer to offer #{}: {}",
+                        aliases::display(&peer),
+                        n,
+                        reason
+                    );
+                    return;
+                }
+                (None, _) => return,
+            }
+            if let Err(e) = offers.save().await {
+                error!("error storing offers, {}", e);
+            }
+        }
+        RequestResponseEvent::OutboundFailure {
+            peer,
+            request_id,
+            error,
+        } => match swarm.behaviour_mut().offers.sending.remove(&request_id) {
+            Some(Sending::Offer(offer)) => error!(
+                "error sending offer for car {} to {}, {:?}",
+                offer.car_id,
+                aliases::display(&peer),
+                error
+            ),
+            Some(Sending::Answer(n)) => error!(
+                "error telling {} about offer #{}, {:?}; answer it again once they are online",
+                aliases::display(&peer),
+                n,
+                error
+            ),
+            None => {}
+        },
+        RequestResponseEvent::InboundFailure { peer, error, .. } => {
+            error!("offer request from {} failed, {:?}", peer, error)
+        }
+        RequestResponseEvent::ResponseSent { .. } => {}
+    }
+}
+
+/// Records an offer for one of our cars the peer may list, unless it expired already.
+async fn receive_offer(
+    peer: PeerId,
+    offer: Offer,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) -> OfferResponse {
+    let listed = match read_carinfos_for(&peer).await {
+        Ok(carinfos) => carinfos.iter().any(|r| r.id == offer.car_id),
+        Err(e) => {
+            error!("error fetching local carinfos to answer offer, {}", e);
+            return OfferResponse::Refused("the owner can't read their cars".to_owned());
+        }
+    };
+    if !listed {
+        return OfferResponse::Refused(format!("no car {}", offer.car_id));
+    }
+    if offer.expiry <= chat::now() {
+        return OfferResponse::Refused("the offer expired".to_owned());
+    }
+    let offers = &mut swarm.behaviour_mut().offers;
+    let sender = peer.to_string();
+    if let Some(n) = offers
+        .incoming
+        .iter()
+        .position(|entry| entry.peer == sender && entry.offer.id == offer.id)
+    {
+        // a repeated request, e.g. after a lost response
+        debug!("offer #{} from {} received again", n, peer);
+        return OfferResponse::Received;
+    }
+    let entry = OfferEntry {
+        peer: sender,
+        offer,
+        state: OfferState::Pending,
+        told: false,
+        at: chat::now(),
+    };
+    info!(
+        "Offer #{}: {}",
+        offers.incoming.len(),
+        entry.incoming_summary()
+    );
+    offers.incoming.push(entry);
+    if let Err(e) = offers.save().await {
+        error!("error storing offers, {}", e);
+    }
+    OfferResponse::Received
+}
+
+/// Records the owner's answer to one of our offers.
+async fn receive_answer(
+    peer: PeerId,
+    id: u64,
+    accepted: bool,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) -> OfferResponse {
+    let offers = &mut swarm.behaviour_mut().offers;
+    let entry = match offers.outgoing_mut(&peer, id) {
+        Some(entry) => entry,
+        None => return OfferResponse::Refused("no such offer".to_owned()),
+    };
+    entry.state = if accepted {
+        OfferState::Accepted
+    } else {
+        OfferState::Rejected
+    };
+    info!(
+        "{} {} your offer of {} for car {}",
+        aliases::display(&peer),
+        if accepted { "accepted" } else { "rejected" },
+        entry.price(),
+        entry.offer.car_id
+    );
+    if let Err(e) = offers.save().await {
+        error!("error storing offers, {}", e);
+    }
+    OfferResponse::Received
+}
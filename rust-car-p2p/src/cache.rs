@@ -0,0 +1,111 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::fs;
+
+use crate::{chat, Carinfo, Carinfos, Result};
+
+const CACHE_FILE_PATH: &str = "./cache.json";
+/// How long a listing is answered from the cache unless the config says otherwise.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The last full listing a peer sent us.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedListing {
+    pub peer: String,
+    /// Unix time the listing arrived.
+    pub received_at: u64,
+    pub carinfos: Carinfos,
+}
+
+impl CachedListing {
+    /// Whether the listing is younger than `ttl`, so listing the peer again can wait.
+    pub fn is_fresh(&self, ttl: Duration) -> bool {
+        chat::now().saturating_sub(self.received_at) < ttl.as_secs()
+    }
+}
+
+/// Peers' listings kept across restarts, so `ls car` doesn't ask a peer again within the TTL
+/// and can still show what an offline peer listed last.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    pub ttl: Duration,
+    listings: Vec<CachedListing>,
+}
+
+impl ResponseCache {
+    pub async fn load(ttl: Duration) -> Result<ResponseCache> {
+        let listings = match fs::read(CACHE_FILE_PATH).await {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(ResponseCache { ttl, listings })
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&CachedListing> {
+        let peer = peer.to_string();
+        self.listings.iter().find(|l| l.peer == peer)
+    }
+
+    /// The cached listing of `peer` if it is still fresh.
+    pub fn fresh(&self, peer: &PeerId) -> Option<&CachedListing> {
+        self.get(peer).filter(|l| l.is_fresh(self.ttl))
+    }
+
+    /// Every cached listing, most recent first.
+    pub fn listings(&self) -> impl Iterator<Item = &CachedListing> {
+        self.listings.iter()
+    }
+
+    /// Replaces the cached listing of `peer` with one that just arrived.
+    pub async fn store(&mut self, peer: &PeerId, carinfos: &Carinfos) -> Result<()> {
+        let peer = peer.to_string();
+        self.listings.retain(|l| l.peer != peer);
+        self.listings.insert(
+            0,
+            CachedListing {
+                peer,
+                received_at: chat::now(),
+                carinfos: carinfos.clone(),
+            },
+        );
+        self.save().await
+    }
+
+    /// Adds or replaces a car the peer just published in its cached listing, if we have one.
+    /// The listing doesn't get any fresher from it.
+    pub async fn published(&mut self, peer: &PeerId, carinfo: &Carinfo) -> Result<()> {
+        let peer = peer.to_string();
+        let listing = match self.listings.iter_mut().find(|l| l.peer == peer) {
+            Some(listing) => listing,
+            None => return Ok(()),
+        };
+        match listing.carinfos.iter_mut().find(|r| r.id == carinfo.id) {
+            Some(known) => *known = carinfo.clone(),
+            None => listing.carinfos.push(carinfo.clone()),
+        }
+        self.save().await
+    }
+
+    /// Drops a car the peer withdrew from its cached listing.
+    pub async fn withdrawn(&mut self, peer: &PeerId, id: usize) -> Result<()> {
+        let peer = peer.to_string();
+        let listing = match self.listings.iter_mut().find(|l| l.peer == peer) {
+            Some(listing) => listing,
+            None => return Ok(()),
+        };
+        let before = listing.carinfos.len();
+        listing.carinfos.retain(|r| r.id != id);
+        if listing.carinfos.len() == before {
+            return Ok(());
+        }
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(&self.listings)?;
+        fs::write(CACHE_FILE_PATH, &json).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Result;
+
+/// DBC ids of extended frames carry this flag on top of the 29-bit identifier.
+const DBC_EXTENDED_FLAG: u32 = 0x8000_0000;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+/// Bits in the largest frame, a 64-byte CAN FD one.
+const MAX_FRAME_BITS: u32 = 512;
+
+/// Signal definitions from a DBC file, keyed by CAN id.
+pub struct Dbc {
+    messages: HashMap<u32, Vec<Signal>>,
+}
+
+struct Signal {
+    name: String,
+    start: u32,
+    len: u32,
+    little_endian: bool,
+    signed: bool,
+    factor: f64,
+    offset: f64,
+    unit: String,
+}
+
+/// Observed range of one decoded signal over a log.
+#[derive(Debug, Clone)]
+pub struct SignalStats {
+    pub unit: String,
+    pub min: f64,
+    pub max: f64,
+    pub last: f64,
+    pub samples: usize,
+}
+
+/// Values derived from a decoded CAN log and attached to a local record.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CanSummary {
+    pub confirmed_horsepower: Option<f64>,
+    pub odometer_km: Option<f64>,
+    /// Unix timestamp of the import.
+    pub read_at: u64,
+}
+
+impl Dbc {
+    /// Parses the `BO_`/`SG_` lines of a DBC file; everything else is ignored.
+    pub fn parse(content: &str) -> Result<Dbc> {
+        let mut messages: HashMap<u32, Vec<Signal>> = HashMap::new();
+        let mut current = None;
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if let Some(rest) = line.strip_prefix("BO_ ") {
+                let id: u32 = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|id| id.parse().ok())
+                    .ok_or_else(|| format!("line {}: invalid message definition", n + 1))?;
+                let id = if id & DBC_EXTENDED_FLAG != 0 {
+                    id & CAN_EFF_MASK
+                } else {
+                    id
+                };
+                messages.entry(id).or_default();
+                current = Some(id);
+            } else if let Some(rest) = line.strip_prefix("SG_ ") {
+                let id =
+                    current.ok_or_else(|| format!("line {}: signal outside message", n + 1))?;
+                let signal = parse_signal(rest)
+                    .ok_or_else(|| format!("line {}: invalid signal definition", n + 1))?;
+                messages.entry(id).or_default().push(signal);
+            } else if line.is_empty() {
+                current = None;
+            }
+        }
+        Ok(Dbc { messages })
+    }
+}
+
+/// Parses `<name> [mux] : <start>|<len>@<order><sign> (<factor>,<offset>) [<min>|<max>] "<unit>" ...`.
+/// Signals of more than 64 bits, or ones that don't fit a CAN FD frame, are invalid.
+fn parse_signal(def: &str) -> Option<Signal> {
+    let (head, layout) = def.split_once(':')?;
+    let name = head.split_whitespace().next()?.to_owned();
+    let mut parts = layout.split_whitespace();
+
+    let (start, rest) = parts.next()?.split_once('|')?;
+    let (len, order) = rest.split_once('@')?;
+    let scaling = parts.next()?;
+    let (factor, offset) = scaling
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .split_once(',')?;
+    let unit = layout
+        .split('"')
+        .nth(1)
+        .map(str::to_owned)
+        .unwrap_or_default();
+    let start: u32 = start.parse().ok()?;
+    let len: u32 = len.parse().ok()?;
+    if !(1..=64).contains(&len) || start.checked_add(len)? > MAX_FRAME_BITS {
+        return None;
+    }
+
+    Some(Signal {
+        name,
+        start,
+        len,
+        little_endian: order.starts_with('1'),
+        signed: order.ends_with('-'),
+        factor: factor.parse().ok()?,
+        offset: offset.parse().ok()?,
+        unit,
+    })
+}
+
+impl Signal {
+    fn decode(&self, data: &[u8]) -> Option<f64> {
+        let mut raw: u64 = 0;
+        if self.little_endian {
+            for i in 0..self.len {
+                let bit = self.start + i;
+                raw |= u64::from(bit_at(data, bit)?) << i;
+            }
+        } else {
+            // Motorola order: start is the MSB, counting down within a byte and then
+            // continuing at bit 7 of the next byte.
+            let mut bit = self.start;
+            for _ in 0..self.len {
+                raw = (raw << 1) | u64::from(bit_at(data, bit)?);
+                bit = if bit.is_multiple_of(8) {
+                    bit + 15
+                } else {
+                    bit - 1
+                };
+            }
+        }
+        let value = if self.signed && self.len < 64 && (raw >> (self.len - 1)) & 1 == 1 {
+            (raw as i64 - (1i64 << self.len)) as f64
+        } else {
+            raw as f64
+        };
+        Some(value * self.factor + self.offset)
+    }
+}
+
+fn bit_at(data: &[u8], bit: u32) -> Option<u8> {
+    data.get((bit / 8) as usize).map(|b| (b >> (bit % 8)) & 1)
+}
+
+/// Decodes every frame of a candump log (`candump -l` or the default console format) that
+/// has a definition in `dbc`, returning per-signal statistics.
+pub fn decode_log(log: &str, dbc: &Dbc) -> BTreeMap<String, SignalStats> {
+    let mut stats: BTreeMap<String, SignalStats> = BTreeMap::new();
+    for (id, data) in log.lines().filter_map(parse_frame) {
+        let signals = match dbc.messages.get(&id) {
+            Some(signals) => signals,
+            None => continue,
+        };
+        for signal in signals {
+            let value = match signal.decode(&data) {
+                Some(v) => v,
+                None => continue,
+            };
+            stats
+                .entry(signal.name.clone())
+                .and_modify(|s| {
+                    s.min = s.min.min(value);
+                    s.max = s.max.max(value);
+                    s.last = value;
+                    s.samples += 1;
+                })
+                .or_insert_with(|| SignalStats {
+                    unit: signal.unit.clone(),
+                    min: value,
+                    max: value,
+                    last: value,
+                    samples: 1,
+                });
+        }
+    }
+    stats
+}
+
+/// Parses `(ts) can0 123#DEADBEEF` or `can0  123   [4]  DE AD BE EF`.
+fn parse_frame(line: &str) -> Option<(u32, Vec<u8>)> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if let Some(frame) = parts.iter().find(|p| p.contains('#')) {
+        let (id, data) = frame.split_once('#')?;
+        let data = data.trim_start_matches('R');
+        let bytes = (0..data.len())
+            .step_by(2)
+            .map(|i| {
+                data.get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()?;
+        return Some((u32::from_str_radix(id, 16).ok()?, bytes));
+    }
+    let dlc_pos = parts.iter().position(|p| p.starts_with('['))?;
+    let id = u32::from_str_radix(parts.get(dlc_pos.checked_sub(1)?)?, 16).ok()?;
+    let bytes = parts[dlc_pos + 1..]
+        .iter()
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some((id, bytes))
+}
+
+/// Builds the record summary from the signals mapped to horsepower and odometer, taking the
+/// peak observed power and the highest odometer reading.
+pub fn summarize(
+    stats: &BTreeMap<String, SignalStats>,
+    hp_signal: Option<&str>,
+    odometer_signal: Option<&str>,
+) -> Result<CanSummary> {
+    let lookup = |name: &str| {
+        stats
+            .get(name)
+            .ok_or_else(|| format!("signal {} not found in log", name))
+    };
+    let confirmed_horsepower = match hp_signal {
+        Some(name) => {
+            let s = lookup(name)?;
+            Some(match s.unit.as_str() {
+                "kW" | "kw" => s.max / 0.745_699_872,
+                "PS" | "ps" => s.max * 0.986_320_07,
+                _ => s.max,
+            })
+        }
+        None => None,
+    };
+    let odometer_km = match odometer_signal {
+        Some(name) => {
+            let s = lookup(name)?;
+            Some(match s.unit.as_str() {
+                "mi" | "miles" => s.max * 1.609_344,
+                _ => s.max,
+            })
+        }
+        None => None,
+    };
+    Ok(CanSummary {
+        confirmed_horsepower,
+        odometer_km,
+        read_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBC: &str = r#"VERSION ""
+
+BO_ 256 Engine: 8 ECU
+ SG_ Power : 0|16@1+ (0.1,0) [0|6553.5] "kW" Vector__XXX
+ SG_ Temp : 16|8@1- (1,0) [-128|127] "C" Vector__XXX
+
+BO_ 2147484672 Cluster: 8 ECU
+ SG_ Odometer : 7|24@0+ (1,0) [0|16777215] "mi" Vector__XXX
+"#;
+
+    const LOG: &str = "(1600000000.000000) can0 100#E803F6\n\
+                       (1600000000.100000) can0 100#D007FF\n\
+                       (1600000000.200000) can0 200#FFFF\n  \
+                       can0  400   [3]  01 E2 40\n";
+
+    #[test]
+    fn decodes_signals_of_known_frames() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        let stats = decode_log(LOG, &dbc);
+        assert_eq!(stats.len(), 3);
+
+        let power = &stats["Power"];
+        assert_eq!(power.unit, "kW");
+        assert_eq!((power.min, power.max, power.last), (100.0, 200.0, 200.0));
+        assert_eq!(power.samples, 2);
+
+        let temp = &stats["Temp"];
+        assert_eq!((temp.min, temp.max), (-10.0, -1.0));
+
+        // Motorola byte order, on an extended frame
+        assert_eq!(stats["Odometer"].last, 123456.0);
+    }
+
+    #[test]
+    fn summarizes_in_horsepower_and_kilometres() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        let stats = decode_log(LOG, &dbc);
+        let summary = summarize(&stats, Some("Power"), Some("Odometer")).unwrap();
+        let hp = summary.confirmed_horsepower.unwrap();
+        assert!((hp - 268.2).abs() < 0.1, "{}", hp);
+        let km = summary.odometer_km.unwrap();
+        assert!((km - 198_683.2).abs() < 0.1, "{}", km);
+
+        let none = summarize(&stats, None, None).unwrap();
+        assert!(none.confirmed_horsepower.is_none() && none.odometer_km.is_none());
+        assert_eq!(
+            summarize(&stats, Some("Boost"), None)
+                .unwrap_err()
+                .to_string(),
+            "signal Boost not found in log"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_signals() {
+        let error = |dbc: &str| Dbc::parse(dbc).err().map(|e| e.to_string());
+        assert_eq!(
+            error("BO_ 256 Engine: 8 ECU\n SG_ Wide : 0|65@1+ (1,0) [0|1] \"\" X\n"),
+            Some("line 2: invalid signal definition".to_owned())
+        );
+        assert_eq!(
+            error("BO_ 256 Engine: 8 ECU\n SG_ Late : 500|16@1+ (1,0) [0|1] \"\" X\n"),
+            Some("line 2: invalid signal definition".to_owned())
+        );
+        assert_eq!(
+            error(" SG_ Power : 0|16@1+ (0.1,0) [0|1] \"kW\" X\n"),
+            Some("line 1: signal outside message".to_owned())
+        );
+        assert_eq!(
+            error("BO_ Engine: 8 ECU\n"),
+            Some("line 1: invalid message definition".to_owned())
+        );
+    }
+}
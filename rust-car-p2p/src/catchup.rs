@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+
+use crate::Result;
+
+const CATCHUP_FILE_PATH: &str = "./catchup.json";
+/// Oldest entries are dropped beyond this many.
+const MAX_ENTRIES: usize = 500;
+
+/// Wraps an announcement replayed to one peer; everyone else on the topic ignores it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CatchUp {
+    pub replay_to: String,
+    /// The encoded [`crate::wire::Message`] that was missed.
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    peer: String,
+    topic: String,
+    data: Vec<u8>,
+    /// Unix timestamp of the original announcement.
+    at: u64,
+}
+
+/// Announcements missed by known peers that were offline when they went out, persisted so
+/// they can be replayed when those peers come back.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CatchUpLog {
+    /// Peers that have subscribed to each topic at some point.
+    known: BTreeMap<String, BTreeSet<String>>,
+    entries: VecDeque<Entry>,
+}
+
+impl CatchUpLog {
+    pub async fn load() -> Result<CatchUpLog> {
+        match fs::read(CATCHUP_FILE_PATH).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CatchUpLog::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Notes that `peer` listens on `topic`, returning whether it was new.
+    pub fn remember(&mut self, topic: &str, peer: &str) -> bool {
+        self.known
+            .entry(topic.to_owned())
+            .or_default()
+            .insert(peer.to_owned())
+    }
+
+    /// Logs `data` for every known peer of `topic` that is not in `online`, returning how many
+    /// peers missed it.
+    pub fn record(&mut self, topic: &str, data: &[u8], online: &HashSet<String>) -> usize {
+        let missing: Vec<String> = match self.known.get(topic) {
+            Some(peers) => peers
+                .iter()
+                .filter(|p| !online.contains(*p))
+                .cloned()
+                .collect(),
+            None => return 0,
+        };
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        for peer in missing.iter() {
+            self.entries.push_back(Entry {
+                peer: peer.clone(),
+                topic: topic.to_owned(),
+                data: data.to_owned(),
+                at,
+            });
+        }
+        while self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        missing.len()
+    }
+
+    /// Removes and returns, oldest first, what `peer` missed on `topic`.
+    pub fn take(&mut self, topic: &str, peer: &str) -> Vec<Vec<u8>> {
+        let (taken, kept): (VecDeque<Entry>, VecDeque<Entry>) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|e| e.topic == topic && e.peer == peer);
+        self.entries = kept;
+        taken.into_iter().map(|e| e.data).collect()
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        fs::write(CATCHUP_FILE_PATH, &json).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+use libp2p::gossipsub::IdentTopic;
+
+/// Lower-cases a channel name, which must be letters, digits and `-` since it becomes part of
+/// a topic name.
+pub fn normalize(name: &str) -> Result<String, String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '-') {
+        return Err(format!(
+            "invalid channel {}, expected letters, digits and -",
+            name
+        ));
+    }
+    Ok(name)
+}
+
+/// Topic of channel `name` under the catalog topic, e.g. `carinfos.bmw`, so networks with
+/// different catalog topics keep their channels apart too.
+pub fn topic(catalog: &IdentTopic, name: &str) -> IdentTopic {
+    IdentTopic::new(format!("{}.{}", catalog.hash().as_str(), name))
+}
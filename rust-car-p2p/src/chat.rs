@@ -0,0 +1,170 @@
+use libp2p::request_response::RequestId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+
+use crate::{atomic, format, Result};
+
+const CHATS_FILE_PATH: &str = "./chats.json";
+
+/// One chat message about a listed car, sent to the other side of the thread alone in a
+/// [`ChatBehaviour`](crate::protocol::ChatBehaviour) request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// Peer that owns the car the thread is about: the sender or the recipient.
+    pub owner: String,
+    pub car_id: usize,
+    pub text: String,
+    pub sent_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChatResponse {
+    Received,
+    /// Why the message wasn't taken, e.g. a thread about neither side's car.
+    Refused(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatLine {
+    pub from_me: bool,
+    pub text: String,
+    pub at: u64,
+    /// Set on our lines the counterpart hasn't confirmed yet; they are sent again when it
+    /// connects.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pending: bool,
+}
+
+/// A conversation with one peer about one car.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatThread {
+    pub owner: String,
+    pub car_id: usize,
+    pub counterpart: String,
+    pub lines: Vec<ChatLine>,
+}
+
+impl ChatThread {
+    pub fn summary(&self, me: &str) -> String {
+        let about = if self.owner == me {
+            format!("my car {}", self.car_id)
+        } else {
+            format!("car {} of {}", self.car_id, self.owner)
+        };
+        let last = self
+            .lines
+            .last()
+            .map(|l| format!(", last {}", format::age(l.at)))
+            .unwrap_or_default();
+        format!(
+            "{} with {} ({} messages{})",
+            about,
+            self.counterpart,
+            self.lines.len(),
+            last
+        )
+    }
+}
+
+/// All chat threads, persisted locally.
+#[derive(Debug, Default)]
+pub struct Chats {
+    pub threads: Vec<ChatThread>,
+    /// Our lines in flight, by thread and line number.
+    pub sending: HashMap<RequestId, (usize, usize)>,
+}
+
+impl Chats {
+    pub async fn load() -> Result<Chats> {
+        match fs::read(CHATS_FILE_PATH).await {
+            Ok(content) => Ok(Chats {
+                threads: serde_json::from_slice(&content)?,
+                sending: HashMap::new(),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Chats::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Appends a line to the thread about `owner`'s car `car_id` with `counterpart`, starting
+    /// the thread if needed. Returns the thread's index. A line the counterpart sends again,
+    /// because our confirmation didn't reach it, is only kept once.
+    pub async fn add(
+        &mut self,
+        owner: &str,
+        car_id: usize,
+        counterpart: &str,
+        line: ChatLine,
+    ) -> Result<usize> {
+        let existing = self
+            .threads
+            .iter()
+            .position(|t| t.owner == owner && t.car_id == car_id && t.counterpart == counterpart);
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                self.threads.push(ChatThread {
+                    owner: owner.to_owned(),
+                    car_id,
+                    counterpart: counterpart.to_owned(),
+                    lines: Vec::new(),
+                });
+                self.threads.len() - 1
+            }
+        };
+        let lines = &mut self.threads[index].lines;
+        let repeated = !line.from_me
+            && lines
+                .iter()
+                .any(|l| !l.from_me && l.at == line.at && l.text == line.text);
+        if !repeated {
+            lines.push(line);
+            self.save().await?;
+        }
+        Ok(index)
+    }
+
+    /// The message carrying our line `line` of thread `thread`.
+    pub fn message(&self, thread: usize, line: usize) -> Option<ChatMessage> {
+        let t = self.threads.get(thread)?;
+        let l = t.lines.get(line)?;
+        Some(ChatMessage {
+            owner: t.owner.clone(),
+            car_id: t.car_id,
+            text: l.text.clone(),
+            sent_at: l.at,
+        })
+    }
+
+    /// Our unconfirmed lines to `counterpart` that aren't in flight, as thread and line
+    /// numbers, oldest first within each thread.
+    pub fn pending(&self, counterpart: &str) -> Vec<(usize, usize)> {
+        let mut pending = Vec::new();
+        for (n, thread) in self.threads.iter().enumerate() {
+            if thread.counterpart != counterpart {
+                continue;
+            }
+            for (i, line) in thread.lines.iter().enumerate() {
+                if line.pending && !self.sending.values().any(|sent| *sent == (n, i)) {
+                    pending.push((n, i));
+                }
+            }
+        }
+        pending
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let json = serde_json::to_vec(&self.threads)?;
+        atomic::write_private(Path::new(CHATS_FILE_PATH), &json).await
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
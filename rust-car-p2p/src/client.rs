@@ -0,0 +1,248 @@
+use libp2p::{
+    futures::{Stream, StreamExt},
+    swarm::{Swarm, SwarmBuilder, SwarmEvent},
+    PeerId,
+};
+use log::error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    behaviour::{self, CarinfoBehaviour},
+    commands, create_new_carinfo, dht, pager, publish_carinfo,
+    queue::Purpose,
+    read_local_carinfo, read_local_carinfos, unpublish_carinfo,
+    vehicle::Details,
+    Carinfos, Config, ListMode, Result, PEER_ID,
+};
+
+/// Something that happened on the network, as seen by an embedded node.
+pub use crate::behaviour::UiEvent as NodeEvent;
+
+/// Typed event stream returned by [`CarP2pClient::subscribe`]. Ends when the node stops.
+pub struct NodeEvents(mpsc::UnboundedReceiver<NodeEvent>);
+
+impl Stream for NodeEvents {
+    type Item = NodeEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<NodeEvent>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+enum Command {
+    Query(ListMode),
+    Publish(usize, oneshot::Sender<Result<()>>),
+    Unpublish(usize, oneshot::Sender<Result<()>>),
+    Peers(oneshot::Sender<Vec<PeerId>>),
+    Subscribe(mpsc::UnboundedSender<NodeEvent>),
+}
+
+/// High-level handle to a carinfo node running in the background, for applications that
+/// want to take part in the network without dealing with the swarm. The node is the one the
+/// terminal front end runs, block and allow lists, rate limits, the DHT and audit log
+/// included. Cheap to clone; the node stops once every handle is dropped.
+#[derive(Clone)]
+pub struct CarP2pClient {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl CarP2pClient {
+    /// Starts a node on the current tokio runtime, listening on all interfaces and joining the
+    /// catalog topic.
+    pub async fn start() -> Result<CarP2pClient> {
+        Self::start_node(Config::default()).await
+    }
+
+    /// Like [`start`](Self::start), with the storage, network and node settings from
+    /// `config`. Storage can only be set up once per process.
+    pub async fn start_with_config(config: Config) -> Result<CarP2pClient> {
+        crate::init_storage(&config)?;
+        Self::start_node(config).await
+    }
+
+    async fn start_node(config: Config) -> Result<CarP2pClient> {
+        // listings go to subscribers and the log, nobody reads pages
+        pager::disable();
+        let (transport, relay) = crate::relay_transport(config.swarm_key);
+        let behaviour = CarinfoBehaviour::new(&config, relay).await;
+        let mut swarm = SwarmBuilder::new(transport, behaviour, *PEER_ID)
+            .executor(Box::new(|fut| {
+                tokio::spawn(fut);
+            }))
+            .build();
+        swarm.listen_on(config.listen.clone())?;
+        if let Some(addr) = config.ws_listen.clone() {
+            swarm.listen_on(addr)?;
+        }
+        behaviour::listen_through_relays(&mut swarm, &config.relays);
+        behaviour::ban_blocked(&mut swarm);
+        behaviour::reconnect_known(&mut swarm);
+
+        let (commands, command_rcv) = mpsc::unbounded_channel();
+        tokio::spawn(run(swarm, command_rcv));
+        Ok(CarP2pClient { commands })
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        *PEER_ID
+    }
+
+    /// Stores a new, unpublished record and returns its id.
+    pub async fn create(&self, make: &str, model: &str, horsepower: u32) -> Result<usize> {
+        create_new_carinfo(make, model, horsepower, None, &Details::default()).await
+    }
+
+    /// Makes a local record visible to other peers and announces it.
+    pub async fn publish(&self, id: usize) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Publish(id, sender))?;
+        receiver.await?
+    }
+
+    /// Hides a published record again and tells peers to drop their copy.
+    pub async fn unpublish(&self, id: usize) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Unpublish(id, sender))?;
+        receiver.await?
+    }
+
+    pub async fn local_carinfos(&self) -> Result<Carinfos> {
+        read_local_carinfos().await
+    }
+
+    /// Asks one peer (or all on the catalog topic with `ListMode::ALL`) for their public
+    /// records. Answers arrive as [`NodeEvent::Listing`] on subscribed streams.
+    pub fn query(&self, mode: ListMode) -> Result<()> {
+        self.send(Command::Query(mode))
+    }
+
+    /// Peers currently discovered via mDNS.
+    pub async fn peers(&self) -> Result<Vec<PeerId>> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Peers(sender))?;
+        Ok(receiver.await?)
+    }
+
+    /// Returns a stream of all events from now on.
+    pub fn subscribe(&self) -> Result<NodeEvents> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.send(Command::Subscribe(sender))?;
+        Ok(NodeEvents(receiver))
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| "carinfo node has stopped".into())
+    }
+}
+
+/// Drives the node like the terminal front end's main loop, minus the terminal: swarm events
+/// go to the behaviour's handlers, the DHT is refreshed and bootstrap nodes redialed.
+async fn run(mut swarm: Swarm<CarinfoBehaviour>, mut commands: mpsc::UnboundedReceiver<Command>) {
+    let (events, mut event_rcv) = mpsc::unbounded_channel();
+    swarm.behaviour_mut().watch(events);
+    let mut subscribers: Vec<mpsc::UnboundedSender<NodeEvent>> = Vec::new();
+    let mut dht_refresh = tokio::time::interval(dht::REFRESH_INTERVAL);
+    let mut bootstrap_redial = tokio::time::interval(dht::REDIAL_INTERVAL);
+    loop {
+        behaviour::update_health(&swarm);
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => handle_command(command, &mut swarm, &mut subscribers).await,
+                None => break,
+            },
+            Some(event) = event_rcv.recv() => {
+                subscribers.retain(|s| s.send(event.clone()).is_ok());
+            }
+            _ = dht_refresh.tick() => {
+                swarm.behaviour_mut().refresh_dht();
+                behaviour::announce_catalog(&mut swarm).await;
+            }
+            _ = bootstrap_redial.tick() => behaviour::redial_bootstrap(&mut swarm),
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::Behaviour(event) => {
+                    behaviour::handle_behaviour_event(event, &mut swarm).await
+                }
+                // refused by the block or allow list otherwise
+                SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. }
+                    if behaviour::admit(&mut swarm, &peer_id) =>
+                {
+                    swarm.behaviour_mut().peer_connected(&peer_id, &endpoint)
+                }
+                SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                    swarm.behaviour_mut().peer_disconnected(&peer_id)
+                }
+                SwarmEvent::UnreachableAddr { peer_id, error, attempts_remaining: 0, .. } => {
+                    swarm.behaviour_mut().dial_failed(&peer_id, &error.to_string())
+                }
+                _ => {}
+            },
+        }
+    }
+    swarm.behaviour_mut().leave();
+    // give the swarm a moment to tell peers we left
+    let _ = tokio::time::timeout(Duration::from_millis(500), async {
+        loop {
+            swarm.select_next_some().await;
+        }
+    })
+    .await;
+}
+
+async fn handle_command(
+    command: Command,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+    subscribers: &mut Vec<mpsc::UnboundedSender<NodeEvent>>,
+) {
+    match command {
+        Command::Query(mode) => {
+            let behaviour = swarm.behaviour_mut();
+            let peers: Vec<PeerId> = match &mode {
+                ListMode::ALL => behaviour
+                    .topic_peers
+                    .get(behaviour.topic.hash().as_str())
+                    .map(|peers| peers.iter().copied().collect())
+                    .unwrap_or_default(),
+                ListMode::One(peer) => match peer.parse() {
+                    Ok(peer) => vec![peer],
+                    Err(e) => {
+                        error!("invalid peer id: {}, {}", peer, e);
+                        Vec::new()
+                    }
+                },
+            };
+            for peer in peers {
+                behaviour.send_list_request(peer, mode.clone(), Purpose::List);
+            }
+        }
+        Command::Publish(id, reply) => {
+            let result = publish_carinfo(id).await;
+            if result.is_ok() {
+                match read_local_carinfo(id).await {
+                    Ok(Some(carinfo)) => commands::announce_published(carinfo, swarm).await,
+                    Ok(None) => {}
+                    Err(e) => error!("error reading carinfo with id {}, {}", id, e),
+                }
+            }
+            let _ = reply.send(result);
+        }
+        Command::Unpublish(id, reply) => {
+            let result = unpublish_carinfo(id).await;
+            if result.is_ok() {
+                commands::announce_deleted(id, swarm).await;
+            }
+            let _ = reply.send(result);
+        }
+        Command::Peers(reply) => {
+            let mut peers: Vec<PeerId> = swarm.behaviour().discovered_peers().copied().collect();
+            peers.sort();
+            peers.dedup();
+            let _ = reply.send(peers);
+        }
+        Command::Subscribe(sender) => subscribers.push(sender),
+    }
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tokio::fs;
+
+use crate::{Carinfo, Result};
+
+const CLUB_FILE_PATH: &str = "./club.json";
+const MEMBERS_FILE_PATH: &str = "./club_members.json";
+
+/// Requests of the club protocol, see [`crate::protocol::ClubCodec`]. Members send the first
+/// three to the coordinator they joined; the coordinator sends its catalog to its members.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ClubRequest {
+    /// Adds or replaces a member's car in the shared catalog.
+    Submit(Box<Carinfo>),
+    /// Removes a member's car from the shared catalog.
+    Withdraw(usize),
+    /// Asks the coordinator for its current catalog.
+    Sync,
+    /// The coordinator's merged catalog after a change.
+    Catalog(ClubCatalog),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ClubResponse {
+    /// The coordinator's catalog once a member's request is applied.
+    Catalog(ClubCatalog),
+    /// A member took the catalog it was sent.
+    Received,
+    Refused(String),
+}
+
+/// A car in the shared catalog, owned by the member that submitted it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClubEntry {
+    pub member: String,
+    pub carinfo: Carinfo,
+}
+
+/// The authoritative club catalog. `version` grows with every accepted change so members can
+/// ignore stale copies.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClubCatalog {
+    pub version: u64,
+    pub entries: Vec<ClubEntry>,
+}
+
+impl ClubCatalog {
+    pub fn submit(&mut self, member: String, carinfo: Carinfo) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| e.member == member && e.carinfo.id == carinfo.id)
+        {
+            Some(entry) => entry.carinfo = carinfo,
+            None => self.entries.push(ClubEntry { member, carinfo }),
+        }
+        self.version += 1;
+    }
+
+    /// Removes every car of `member`, returning whether it had any.
+    pub fn remove_member(&mut self, member: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.member != member);
+        let removed = self.entries.len() != before;
+        if removed {
+            self.version += 1;
+        }
+        removed
+    }
+
+    /// Returns whether the car was in the catalog.
+    pub fn withdraw(&mut self, member: &str, id: usize) -> bool {
+        let before = self.entries.len();
+        self.entries
+            .retain(|e| !(e.member == member && e.carinfo.id == id));
+        let removed = self.entries.len() != before;
+        if removed {
+            self.version += 1;
+        }
+        removed
+    }
+}
+
+/// Loads the coordinator's catalog, starting empty if there is none yet.
+pub async fn read_catalog() -> Result<ClubCatalog> {
+    match fs::read(CLUB_FILE_PATH).await {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ClubCatalog::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn write_catalog(catalog: &ClubCatalog) -> Result<()> {
+    let json = serde_json::to_string(catalog)?;
+    fs::write(CLUB_FILE_PATH, &json).await?;
+    Ok(())
+}
+
+/// The peers a coordinator takes requests from and sends its catalog to, kept in
+/// `./club_members.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Members(pub BTreeSet<String>);
+
+impl Members {
+    pub async fn load() -> Result<Members> {
+        match fs::read(MEMBERS_FILE_PATH).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Members::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        fs::write(MEMBERS_FILE_PATH, &json).await?;
+        Ok(())
+    }
+}
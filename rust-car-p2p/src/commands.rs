@@ -0,0 +1,2609 @@
+use libp2p::{kad::record::Key, swarm::Swarm, Multiaddr, PeerId};
+use log::{error, info};
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::{fs, sync::mpsc};
+
+use crate::{
+    aliases, attach_can_summary, attach_obd_stats, attach_photo,
+    audit::AccessKind,
+    backup,
+    behaviour::{
+        announce, announce_catalog, apply_access, commit_club_catalog, end_round, CarinfoBehaviour,
+        ClubRole,
+    },
+    can, channels,
+    chat::{self, ChatLine},
+    club::{ClubCatalog, ClubRequest},
+    create_new_carinfo, dedupe, delete_carinfo, dht, edit_carinfo, export,
+    filter::Filter,
+    find_duplicate,
+    format::{self, Column},
+    geo, grant_carinfo,
+    homeassistant::HomeAssistant,
+    import::{self, CsvImport, ImportReport, JsonImport},
+    journal,
+    listing::ListOptions,
+    lookup::Enricher,
+    merge_attributes, merge_duplicates, nat,
+    obd::{self, ObdStats},
+    offers::{Offer, OfferRequest, OfferState, Sending},
+    output::{self, Output},
+    pager,
+    parser::{
+        self, AccessCommand, ChannelCommand, ChatTarget, ClubCommand, Command, Grouping,
+        ListTarget, QueueCommand, TelemetryCommand,
+    },
+    photos::{self, Download, PhotoQuery, PhotoRequest, ThumbnailRequest},
+    price::{self, PriceProviders},
+    protocol, publish_carinfo,
+    queue::Purpose,
+    read_local_carinfo, read_local_carinfos, record_prices,
+    replica::Resolution,
+    revoke_carinfo,
+    search::{self, LOCAL_OWNER},
+    set_channels, set_location,
+    share::{self, SharedCarinfo, SHARE_TOPIC},
+    storage::{self, Reverted},
+    tag_carinfo,
+    telemetry::{self, TelemetryUpdate, TelemetryViewer, TELEMETRY_TOPIC},
+    unpublish_carinfo, untag_carinfo,
+    vehicle::{Details, Price},
+    wire::Message,
+    CarPublished, Carinfo, CarinfoDeleted, ListMode, PEER_ID,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+impl Verbosity {
+    /// Splits a trailing `-v`/`-q` off an input line, falling back to `default`.
+    fn split(line: &str, default: Verbosity) -> (&str, Verbosity) {
+        let line = line.trim_end();
+        if let Some(cmd) = line.strip_suffix(" -v") {
+            (cmd, Verbosity::Verbose)
+        } else if let Some(cmd) = line.strip_suffix(" -q") {
+            (cmd, Verbosity::Quiet)
+        } else {
+            (line, default)
+        }
+    }
+
+    pub(crate) fn carinfo_lines(self, carinfos: &[Carinfo]) -> Vec<String> {
+        match self {
+            Verbosity::Verbose => carinfos
+                .iter()
+                .map(|r| serde_json::to_string(r).expect("can jsonify carinfo"))
+                .collect(),
+            _ => carinfos.iter().map(format::carinfo).collect(),
+        }
+    }
+}
+
+/// State commands keep between lines that isn't part of the network behaviour.
+pub struct Session {
+    enricher: Enricher,
+    price_providers: PriceProviders,
+    home_assistant: Option<HomeAssistant>,
+    /// Where a running telemetry task sends its updates, see [`CarinfoBehaviour::publish_telemetry`].
+    telemetry_sender: mpsc::UnboundedSender<TelemetryUpdate>,
+    telemetry_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Session {
+    /// Sets up lookups, price feeds and Home Assistant from the environment.
+    pub async fn from_env(telemetry_sender: mpsc::UnboundedSender<TelemetryUpdate>) -> Session {
+        let mut home_assistant = HomeAssistant::from_env();
+        if let Some(ha) = home_assistant.as_mut() {
+            ha.sync().await;
+        }
+        Session {
+            enricher: Enricher::from_env(),
+            price_providers: PriceProviders::from_env(),
+            home_assistant,
+            telemetry_sender,
+            telemetry_task: None,
+        }
+    }
+
+    /// Stops background work started by commands.
+    pub fn stop(&mut self) {
+        if let Some(task) = self.telemetry_task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Runs one line of user input, or feeds it to the pager while one is open.
+pub async fn execute(line: &str, swarm: &mut Swarm<CarinfoBehaviour>, session: &mut Session) {
+    if swarm.behaviour().pager.is_some() {
+        handle_pager_input(line, swarm);
+        return;
+    }
+    let (cmd, verbosity) = Verbosity::split(line, swarm.behaviour().verbosity);
+    let command = match parser::parse(cmd) {
+        Ok(command) => command,
+        Err(e) if output::json() => {
+            output::emit(&Output::Error {
+                message: e.to_string(),
+            });
+            return;
+        }
+        Err(e) => {
+            error!("{}", e.problem);
+            if let Some(usage) = e.usage {
+                info!("usage: {}", usage);
+            }
+            return;
+        }
+    };
+    // Home Assistant mirrors the catalog, so it is synced after every change to it
+    let changes_catalog = matches!(
+        command,
+        Command::Create { .. }
+            | Command::Edit { .. }
+            | Command::Delete(_)
+            | Command::Unpublish(_)
+            | Command::Publish(_)
+            | Command::Grant { .. }
+            | Command::Revoke { .. }
+            | Command::ImportCsv { .. }
+            | Command::ImportJson { .. }
+            | Command::Dedupe { merge: true }
+            | Command::Undo
+            | Command::Redo
+            | Command::Restore(_)
+            | Command::Tag { .. }
+            | Command::Untag { .. }
+            | Command::Geo { .. }
+            | Command::ChannelCar { .. }
+            | Command::Enrich(_)
+            | Command::Price(_)
+            | Command::Obd { .. }
+            | Command::Can { .. }
+            | Command::Photo { .. }
+    );
+    match command {
+        Command::Help(topic) => handle_help(topic.as_deref(), swarm),
+        Command::ListPeers => handle_list_peers(verbosity, swarm).await,
+        Command::ListRoutedPeers => handle_list_routed_peers(verbosity, swarm),
+        Command::Status => handle_status(swarm).await,
+        Command::Dial { peer, addr } => handle_dial(peer, addr, swarm),
+        Command::NetStatus => handle_net_status(swarm),
+        Command::Ping(peer) => handle_ping(peer, swarm),
+        Command::Alias { name, peer } => handle_alias(&name, &peer),
+        Command::ListAliases => handle_list_aliases(swarm),
+        Command::Unalias(name) => handle_unalias(&name),
+        Command::Access(command) => handle_access(command, swarm).await,
+        Command::ListMarket { radius_km } => handle_list_market(radius_km, swarm),
+        Command::Audits(id) => handle_audits(id, swarm),
+        Command::History(id) => handle_history(id, swarm).await,
+        Command::Undo => handle_undo(false, swarm).await,
+        Command::Redo => handle_undo(true, swarm).await,
+        Command::Share { id, peer } => handle_share(id, peer, swarm).await,
+        Command::ListShared => handle_list_shared(swarm).await,
+        Command::Sync(peer) => handle_sync(peer, swarm),
+        Command::ListSynced(origin) => handle_list_synced(origin.as_ref(), swarm),
+        Command::Grant { id, peer } => handle_grant(id, peer).await,
+        Command::Revoke { id, peer } => handle_revoke(id, peer, swarm).await,
+        Command::Geo { id, at } => handle_geo_carinfo(id, at).await,
+        Command::ChannelCar { id, channels } => handle_channel_car(id, channels, swarm).await,
+        Command::Channels(command) => handle_channels(command, swarm),
+        Command::Tag { id, tag } => handle_tag(id, &tag).await,
+        Command::Untag { id, tag } => handle_untag(id, &tag).await,
+        Command::ListCarinfos(target) => handle_list_carinfos(target, verbosity, swarm).await,
+        Command::ListLocal(options) => handle_list_local(&options, verbosity, swarm).await,
+        Command::ListCached => handle_list_cached(swarm),
+        Command::ListTable(grouping) => handle_list_table(grouping, swarm),
+        Command::Queue(command) => handle_queue(command, swarm),
+        Command::Create {
+            make,
+            model,
+            horsepower,
+            vin,
+            details,
+            force,
+        } => {
+            handle_create_carinfo(&make, &model, horsepower, vin.as_deref(), &details, force).await
+        }
+        Command::Dedupe { merge } => handle_dedupe(merge, swarm).await,
+        Command::Edit {
+            id,
+            make,
+            model,
+            horsepower,
+            vin,
+            details,
+        } => {
+            handle_edit_carinfo(
+                id,
+                make.as_deref(),
+                model.as_deref(),
+                horsepower,
+                vin.as_deref(),
+                &details,
+            )
+            .await
+        }
+        Command::Delete(id) => handle_delete_carinfo(id, swarm).await,
+        Command::Unpublish(id) => handle_unpublish_carinfo(id, swarm).await,
+        Command::Publish(id) => handle_publish_carinfo(id, swarm).await,
+        Command::Watch(on) => handle_watch(on, swarm),
+        Command::ExportParquet(path) => handle_export_parquet(&path, swarm).await,
+        Command::ExportCsv(path) => handle_export_csv(&path).await,
+        Command::ImportCsv { path, options } => handle_import_csv(&path, &options).await,
+        Command::ExportJson(path) => handle_export_json(&path).await,
+        Command::ImportJson { path, options } => handle_import_json(&path, &options).await,
+        Command::Backup(dir) => handle_backup(dir.as_deref()).await,
+        Command::Restore(path) => handle_restore(&path).await,
+        Command::Enrich(id) => handle_enrich_carinfo(id, &mut session.enricher).await,
+        Command::Price(id) => handle_price_carinfo(id, &session.price_providers, swarm).await,
+        Command::Can {
+            id,
+            log,
+            dbc,
+            hp_signal,
+            odometer_signal,
+        } => {
+            handle_can_carinfo(
+                id,
+                &log,
+                &dbc,
+                hp_signal.as_deref(),
+                odometer_signal.as_deref(),
+                swarm,
+            )
+            .await
+        }
+        Command::Obd { id, source, share } => handle_obd_carinfo(id, &source, share).await,
+        Command::Telemetry(command) => {
+            handle_telemetry(
+                command,
+                &session.telemetry_sender,
+                &mut session.telemetry_task,
+                swarm,
+            )
+            .await
+        }
+        Command::Club(command) => handle_club(command, swarm).await,
+        Command::Photo { id, path } => handle_photo_carinfo(id, &path).await,
+        Command::Thumbnails { peer, id } => handle_thumbnails(peer, id, swarm).await,
+        Command::FetchPhoto { peer, id } => handle_fetch_photo(peer, id, swarm).await,
+        Command::Search(query) => handle_search(&query, swarm).await,
+        Command::SearchNet(text) => handle_search_net(text, verbosity, swarm),
+        Command::FindProviders { make, model } => {
+            handle_find_providers(&make, model.as_deref(), swarm)
+        }
+        Command::Find { target, filter } => handle_find(target, filter, verbosity, swarm).await,
+        Command::Chats(thread) => handle_list_chats(thread, swarm),
+        Command::Chat { to, text } => handle_chat(to, &text, swarm).await,
+        Command::MakeOffer {
+            peer,
+            car_id,
+            price,
+            valid_for,
+        } => handle_make_offer(peer, car_id, price, valid_for, swarm),
+        Command::Offers => handle_list_offers(swarm),
+        Command::AnswerOffer { n, accept } => handle_answer_offer(n, accept, swarm).await,
+        Command::Conflicts => handle_list_conflicts(swarm),
+        Command::ResolveConflict { n, resolution } => {
+            handle_resolve_conflict(n, resolution, swarm).await
+        }
+        Command::SetVerbosity(verbosity) => handle_set_verbosity(verbosity, swarm),
+    }
+    if changes_catalog {
+        announce_catalog(swarm).await;
+        if let Some(ha) = session.home_assistant.as_mut() {
+            ha.sync().await;
+        }
+    }
+}
+
+pub(crate) fn handle_pager_input(line: &str, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    if let Some(pager) = behaviour.pager.as_mut() {
+        if !pager.handle_input(line) {
+            behaviour.pager = None;
+        }
+    }
+}
+
+pub(crate) fn handle_help(topic: Option<&str>, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let lines = parser::help(topic);
+    if lines.is_empty() {
+        info!("no command starts with {}", topic.unwrap_or_default());
+        return;
+    }
+    info!("Append -v or -q to a command to change its verbosity for that command only.");
+    pager::page(&mut swarm.behaviour_mut().pager, lines);
+}
+
+pub(crate) fn handle_set_verbosity(verbosity: Verbosity, swarm: &mut Swarm<CarinfoBehaviour>) {
+    swarm.behaviour_mut().verbosity = verbosity;
+    info!("Verbosity set to {:?}", verbosity);
+}
+
+/// `ls p` shows every peer found or connected to since the node started, and those connected
+/// to in earlier sessions: whether it is connected, still found by mDNS or expired, whether it
+/// listens on the catalog topic, when it was last seen, what it told about itself with
+/// identify, and where it was found or says it listens.
+pub(crate) async fn handle_list_peers(verbosity: Verbosity, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour();
+    let discovered: HashSet<PeerId> = behaviour.discovered_peers().copied().collect();
+    let subscribed = behaviour.topic_peers.get(behaviour.topic.hash().as_str());
+    let mut peers: BTreeSet<PeerId> = behaviour.peer_book.peers().copied().collect();
+    peers.extend(discovered.iter().copied());
+    let state = |p: &PeerId| {
+        if swarm.is_connected(p) {
+            "connected"
+        } else if discovered.contains(p) {
+            "discovered"
+        } else {
+            "expired"
+        }
+    };
+    let is_subscribed = |p: &PeerId| subscribed.is_some_and(|peers| peers.contains(p));
+    if output::json() {
+        let peers = peers
+            .iter()
+            .map(|p| {
+                let info = behaviour.peer_book.get(p);
+                let identity = info.and_then(|info| info.identity.as_ref());
+                output::Peer {
+                    peer_id: p.to_string(),
+                    alias: aliases::name(p),
+                    state: state(p),
+                    subscribed: is_subscribed(p),
+                    last_seen: info.map(|info| info.last_seen),
+                    rtt_ms: info
+                        .and_then(|info| info.rtt)
+                        .map(|(rtt, _)| rtt.as_millis() as u64),
+                    addrs: info
+                        .map(|info| info.addrs.iter().map(Multiaddr::to_string).collect())
+                        .unwrap_or_default(),
+                    agent: identity.map(|identity| identity.agent.clone()),
+                    protocol_version: identity.map(|identity| identity.protocol_version.clone()),
+                    listen_addrs: identity
+                        .map(|identity| {
+                            identity
+                                .listen_addrs
+                                .iter()
+                                .map(Multiaddr::to_string)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+        output::emit(&Output::Peers { peers });
+        return;
+    }
+    let lines = peers
+        .iter()
+        .map(|p| {
+            if verbosity == Verbosity::Quiet {
+                return aliases::label(p);
+            }
+            let connected = swarm.is_connected(p);
+            let state = state(p);
+            let topic = if is_subscribed(p) {
+                "subscribed"
+            } else {
+                "not subscribed"
+            };
+            let info = behaviour.peer_book.get(p);
+            let latency = match info.and_then(|info| info.rtt) {
+                Some((rtt, _)) => format!(", {}ms", rtt.as_millis()),
+                None => String::new(),
+            };
+            let seen = match info {
+                _ if connected => "seen now".to_owned(),
+                Some(info) => format!("seen {}", format::age(info.last_seen)),
+                None => "not seen yet".to_owned(),
+            };
+            // the agent, and the protocol only when it isn't ours
+            let agent = match info.and_then(|info| info.identity.as_ref()) {
+                Some(identity) if identity.protocol_version != protocol::protocol_version() => {
+                    format!(
+                        ", {} speaking {}",
+                        identity.agent, identity.protocol_version
+                    )
+                }
+                Some(identity) => format!(", {}", identity.agent),
+                None => String::new(),
+            };
+            let addrs: Vec<String> = info
+                .map(|info| {
+                    info.reachable_at()
+                        .iter()
+                        .map(Multiaddr::to_string)
+                        .collect()
+                })
+                .unwrap_or_default();
+            format!(
+                "{} ({}, {}, {}{}{}) {}",
+                aliases::label(p),
+                state,
+                topic,
+                seen,
+                latency,
+                agent,
+                addrs.join(" ")
+            )
+            .trim_end()
+            .to_owned()
+        })
+        .collect();
+    if verbosity != Verbosity::Quiet {
+        info!("Peers:");
+    }
+    pager::page(&mut swarm.behaviour_mut().pager, lines);
+}
+
+/// `ping <peer id>` shows the round trip time to a peer. Connected peers are pinged every 15
+/// seconds anyway, so the last result is shown right away if there is one; otherwise the
+/// peer is dialed if need be and the result shown once it comes in.
+pub(crate) fn handle_ping(peer: PeerId, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let last = swarm.behaviour().peer_book.get(&peer).and_then(|i| i.rtt);
+    match last {
+        Some((rtt, at)) if swarm.is_connected(&peer) => info!(
+            "Ping {}: {}ms, measured {}",
+            aliases::display(&peer),
+            rtt.as_millis(),
+            format::age(at)
+        ),
+        _ => {
+            swarm.behaviour_mut().pings.insert(peer);
+            if swarm.is_connected(&peer) {
+                info!("Waiting for the next ping to {}", aliases::display(&peer));
+            } else if let Err(e) = swarm.dial(&peer) {
+                swarm.behaviour_mut().pings.remove(&peer);
+                error!("error dialing {}, {:?}", aliases::display(&peer), e);
+            } else {
+                info!("Dialing {} to ping it", aliases::display(&peer));
+            }
+        }
+    }
+}
+
+/// `dial <multiaddr>/p2p/<peer id>` connects to a peer mDNS can't find, see
+/// [`CarinfoBehaviour::dial_succeeded`].
+pub(crate) fn handle_dial(peer: PeerId, addr: Multiaddr, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    behaviour.kademlia.add_address(&peer, addr.clone());
+    behaviour.dialing.insert(peer);
+    if swarm.is_connected(&peer) {
+        swarm.behaviour_mut().dial_succeeded(&peer);
+        return;
+    }
+    match swarm.dial(&peer) {
+        Ok(()) => info!("Dialing {} at {}", aliases::display(&peer), addr),
+        Err(e) => swarm
+            .behaviour_mut()
+            .dial_failed(&peer, &format!("{:?}", e)),
+    }
+}
+
+/// `net status` shows how peers outside the local network get to us.
+pub(crate) fn handle_net_status(swarm: &mut Swarm<CarinfoBehaviour>) {
+    info!(
+        "Reachability: {}",
+        swarm.behaviour().reachability.describe()
+    );
+    let mut lines: Vec<String> = swarm
+        .listeners()
+        .map(|addr| match nat::relay_of(addr) {
+            Some(relay) => format!("{} (through relay {})", addr, aliases::display(&relay)),
+            None => addr.to_string(),
+        })
+        .collect();
+    lines.extend(swarm.behaviour().relays.iter().map(|relay| {
+        let state = if swarm.is_connected(relay) {
+            "connected"
+        } else {
+            "not connected"
+        };
+        format!("relay {} ({})", aliases::label(relay), state)
+    }));
+    info!("Listen addresses and relays:");
+    pager::page(&mut swarm.behaviour_mut().pager, lines);
+}
+
+pub(crate) fn handle_alias(name: &str, peer: &PeerId) {
+    match aliases::set(name, peer) {
+        Ok(()) => info!("{} is now known as {}", peer, name),
+        Err(e) => error!("error setting alias {}, {}", name, e),
+    }
+}
+
+pub(crate) fn handle_list_aliases(swarm: &mut Swarm<CarinfoBehaviour>) {
+    let lines: Vec<String> = aliases::all()
+        .into_iter()
+        .map(|(name, peer)| format!("{} {}", name, peer))
+        .collect();
+    info!("Aliases ({})", lines.len());
+    pager::page(&mut swarm.behaviour_mut().pager, lines);
+}
+
+pub(crate) fn handle_unalias(name: &str) {
+    match aliases::remove(name) {
+        Ok(true) => info!("Forgot alias {}", name),
+        Ok(false) => error!("no alias {}", name),
+        Err(e) => error!("error removing alias {}, {}", name, e),
+    }
+}
+
+/// `block`, `unblock`, `allow`, `disallow` and `allowlist on|off` change who may connect,
+/// `access` shows it. Connected peers the change no longer permits are disconnected.
+pub(crate) async fn handle_access(command: AccessCommand, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let access = &mut swarm.behaviour_mut().access;
+    let (changed, done, unchanged) = match command {
+        AccessCommand::Show => {
+            let mut lines: Vec<String> = access
+                .blocked()
+                .map(|p| format!("blocked {}", aliases::label(&p)))
+                .collect();
+            lines.extend(
+                access
+                    .allowed()
+                    .map(|p| format!("allowed {}", aliases::label(&p))),
+            );
+            info!(
+                "Allowlist-only mode is {}",
+                if access.allowlist_only() { "on" } else { "off" }
+            );
+            if lines.is_empty() {
+                info!("No blocked or allowed peers");
+                return;
+            }
+            pager::page(&mut swarm.behaviour_mut().pager, lines);
+            return;
+        }
+        AccessCommand::Block(peer) => (
+            access.block(&peer).await,
+            format!("Blocked {}", aliases::display(&peer)),
+            format!("{} is blocked already", aliases::display(&peer)),
+        ),
+        AccessCommand::Unblock(peer) => (
+            access.unblock(&peer).await,
+            format!("Unblocked {}", aliases::display(&peer)),
+            format!("{} is not blocked", aliases::display(&peer)),
+        ),
+        AccessCommand::Allow(peer) => (
+            access.allow(&peer).await,
+            format!("Allowed {}", aliases::display(&peer)),
+            format!("{} is allowed already", aliases::display(&peer)),
+        ),
+        AccessCommand::Disallow(peer) => (
+            access.disallow(&peer).await,
+            format!("Took {} off the allowlist", aliases::display(&peer)),
+            format!("{} is not on the allowlist", aliases::display(&peer)),
+        ),
+        AccessCommand::AllowlistOnly(on) => (
+            access.set_allowlist_only(on).await,
+            format!("Allowlist-only mode is {}", if on { "on" } else { "off" }),
+            format!(
+                "Allowlist-only mode is {} already",
+                if on { "on" } else { "off" }
+            ),
+        ),
+    };
+    match changed {
+        Ok(true) => {
+            info!("{}", done);
+            apply_access(swarm);
+        }
+        Ok(false) => info!("{}", unchanged),
+        Err(e) => error!("error saving the block and allow lists, {}", e),
+    }
+}
+
+pub(crate) fn handle_list_routed_peers(verbosity: Verbosity, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let lines = dht::routed_peers(&mut swarm.behaviour_mut().kademlia)
+        .into_iter()
+        .map(|p| match verbosity {
+            Verbosity::Verbose => format!(
+                "{} ({}) {}",
+                aliases::label(&p.peer),
+                if p.connected {
+                    "connected"
+                } else {
+                    "disconnected"
+                },
+                p.addresses
+                    .iter()
+                    .map(|a| a.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            _ => aliases::label(&p.peer),
+        })
+        .collect();
+    if verbosity != Verbosity::Quiet {
+        info!("DHT Peers:");
+    }
+    pager::page(&mut swarm.behaviour_mut().pager, lines);
+}
+
+pub(crate) async fn handle_status(swarm: &mut Swarm<CarinfoBehaviour>) {
+    info!("Peer Id: {}", *PEER_ID);
+    info!("Listening on:");
+    Swarm::listeners(swarm).for_each(|addr| info!("  {}", addr));
+
+    let discovered: HashSet<&PeerId> = swarm.behaviour().discovered_peers().collect();
+    info!(
+        "Peers: {} connected, {} discovered",
+        swarm.network_info().num_peers(),
+        discovered.len()
+    );
+    for peer in discovered {
+        let state = if swarm.is_connected(peer) {
+            "connected"
+        } else {
+            "discovered"
+        };
+        let last_response = match swarm.behaviour().last_responses.get(peer) {
+            Some(at) => format!("last response {}s ago", at.elapsed().as_secs()),
+            None => "no response yet".to_owned(),
+        };
+        info!("  {} ({}, {})", peer, state, last_response);
+    }
+
+    let topics: Vec<&str> = swarm
+        .behaviour()
+        .gossipsub
+        .topics()
+        .map(|t| t.as_str())
+        .collect();
+    info!("Topics: {}", topics.join(", "));
+    if !swarm.behaviour().outbox.is_empty() {
+        info!(
+            "Outbox: {} messages waiting for peers",
+            swarm.behaviour().outbox.len()
+        );
+    }
+    if !swarm.behaviour().catchup.is_empty() {
+        info!(
+            "Catch-up log: {} announcements waiting for offline peers",
+            swarm.behaviour().catchup.len()
+        );
+    }
+    match read_local_carinfos().await {
+        Ok(v) => info!(
+            "Catalog: {} cars ({} public)",
+            v.len(),
+            v.iter().filter(|r| r.public).count()
+        ),
+        Err(e) => error!("error fetching local carinfos: {}", e),
+    };
+}
+
+pub(crate) async fn handle_list_carinfos(
+    target: ListTarget,
+    verbosity: Verbosity,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    if target != ListTarget::Local {
+        swarm.behaviour_mut().request = (verbosity, Instant::now());
+    }
+    match target {
+        ListTarget::All | ListTarget::Peer(_) => {
+            send_list_requests(&target, None, verbosity, swarm)
+        }
+        ListTarget::Local => handle_list_local(&ListOptions::default(), verbosity, swarm).await,
+    };
+}
+
+/// `ls car [--sort <field>] [--desc] [--fields <field>,...]` lists the local records, sorted
+/// and as a table if asked.
+pub(crate) async fn handle_list_local(
+    options: &ListOptions,
+    verbosity: Verbosity,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let started = Instant::now();
+    let mut v = match read_local_carinfos().await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("error fetching local carinfos: {}", e);
+            return;
+        }
+    };
+    options.sort(&mut v);
+    if output::json() {
+        output::emit(&Output::LocalCars { cars: &v });
+        return;
+    }
+    match verbosity {
+        Verbosity::Quiet => {}
+        Verbosity::Normal => info!("Local Carinfos ({})", v.len()),
+        Verbosity::Verbose => info!(
+            "Local Carinfos ({}, read from {} in {}ms)",
+            v.len(),
+            crate::storage().describe(),
+            started.elapsed().as_millis()
+        ),
+    }
+    let lines = options
+        .table(&v)
+        .unwrap_or_else(|| verbosity.carinfo_lines(&v));
+    pager::page(&mut swarm.behaviour_mut().pager, lines);
+}
+
+/// Sends a list request to the targeted peer, or to every peer we know to be on the catalog
+/// topic. With a filter the peers only answer with matching records, and the responses are
+/// shown as search results. Without one, peers with a fresh cached listing are answered from
+/// the cache, and so are offline peers with any cached listing. `ls car all` waits for answers
+/// for the configured window, then shows a summary.
+fn send_list_requests(
+    target: &ListTarget,
+    filter: Option<&Filter>,
+    verbosity: Verbosity,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let behaviour = swarm.behaviour();
+    let mut peers: Vec<PeerId> = match target {
+        ListTarget::Local => return,
+        ListTarget::Peer(peer) => vec![*peer],
+        ListTarget::All => behaviour
+            .topic_peers
+            .get(behaviour.topic.hash().as_str())
+            .map(|peers| peers.iter().copied().collect())
+            .unwrap_or_default(),
+    };
+    if filter.is_none() && *target == ListTarget::All {
+        let cached: Vec<PeerId> = behaviour
+            .cache
+            .listings()
+            .filter_map(|listing| listing.peer.parse().ok())
+            .filter(|peer| !peers.contains(peer))
+            .collect();
+        peers.extend(cached);
+    }
+    let offline: HashSet<PeerId> = peers
+        .iter()
+        .filter(|peer| !swarm.is_connected(peer))
+        .copied()
+        .collect();
+    let behaviour = swarm.behaviour_mut();
+    if peers.is_empty() {
+        info!("No peers to ask yet");
+        return;
+    }
+    let tracked = filter.is_none() && *target == ListTarget::All;
+    if tracked {
+        if let Some(closed) = behaviour.rounds.start() {
+            end_round(&closed, verbosity);
+        }
+    }
+    let mut queued = Vec::new();
+    for peer in peers {
+        let offline = offline.contains(&peer);
+        let cached = match filter {
+            None => show_cached(peer, offline, verbosity, behaviour),
+            Some(_) => None,
+        };
+        if let (true, Some(cars), Some(round)) = (tracked, cached, behaviour.rounds.open()) {
+            round.from_cache(cars);
+        }
+        if cached.is_some() && !offline {
+            continue;
+        }
+        let purpose = match filter {
+            Some(filter) => Purpose::Search(filter.clone()),
+            None => Purpose::List,
+        };
+        let mode = match target {
+            ListTarget::Peer(peer) => ListMode::One(peer.to_string()),
+            _ => ListMode::ALL,
+        };
+        // only a peer asked for by name waits for it to come back
+        if offline {
+            if let ListTarget::Peer(_) = target {
+                queued.push((peer, purpose));
+            }
+            continue;
+        }
+        if verbosity == Verbosity::Verbose {
+            info!("Sending request to {}: {:?} for {}", peer, mode, purpose);
+        }
+        let request_id = behaviour.send_list_request(peer, mode, purpose);
+        if let (true, Some(round)) = (tracked, behaviour.rounds.open()) {
+            round.expect(request_id, peer);
+        }
+    }
+    if tracked {
+        let window = behaviour.rounds.window;
+        if let Some(done) = behaviour.rounds.finished() {
+            end_round(&done, verbosity);
+        } else if let Some(open) = behaviour.rounds.open() {
+            if verbosity != Verbosity::Quiet && !output::json() {
+                info!(
+                    "ls car all #{}: waiting up to {}s for {} peer{}",
+                    open.number,
+                    window.as_secs(),
+                    open.waiting_for(),
+                    if open.waiting_for() == 1 { "" } else { "s" }
+                );
+            }
+        }
+    }
+    for (peer, purpose) in queued {
+        queue_request(peer, purpose, swarm);
+    }
+}
+
+/// Queues a request for a peer that isn't connected and tries to reach it; the request is sent
+/// when it connects, now or later.
+fn queue_request(peer: PeerId, purpose: Purpose, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let description = purpose.to_string();
+    let number = swarm.behaviour_mut().queue.push(peer, purpose);
+    info!(
+        "{} is not connected, request #{} ({}) is queued until it is; see queue",
+        aliases::display(&peer),
+        number,
+        description
+    );
+    // mDNS, the DHT or an earlier connection may know where the peer is
+    if let Err(e) = swarm.dial(&peer) {
+        info!("Can't dial {} yet, {:?}", aliases::display(&peer), e);
+    }
+}
+
+/// `queue` shows the requests waiting for their peer, `queue cancel <n>|all` drops them.
+pub(crate) fn handle_queue(command: QueueCommand, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let queue = &mut swarm.behaviour_mut().queue;
+    match command {
+        QueueCommand::List => {
+            let lines: Vec<String> = queue
+                .iter()
+                .map(|r| {
+                    format!(
+                        "#{} {} {}, queued {}",
+                        r.number,
+                        r.purpose,
+                        aliases::display(&r.peer),
+                        format::age(r.queued_at)
+                    )
+                })
+                .collect();
+            if lines.is_empty() {
+                info!("No queued requests");
+                return;
+            }
+            info!("Queued requests ({})", lines.len());
+            pager::page(&mut swarm.behaviour_mut().pager, lines);
+        }
+        QueueCommand::Cancel(number) => match queue.cancel(number) {
+            Some(r) => info!(
+                "Cancelled request #{} ({}) to {}",
+                r.number,
+                r.purpose,
+                aliases::display(&r.peer)
+            ),
+            None => info!("No queued request #{}", number),
+        },
+        QueueCommand::CancelAll => info!("Cancelled {} queued requests", queue.clear()),
+    }
+}
+
+/// Shows the cached listing of `peer` like a response to a list request, if it is fresh or the
+/// peer is offline, in which case a listing past its TTL is marked stale. Returns how many cars
+/// it showed.
+fn show_cached(
+    peer: PeerId,
+    offline: bool,
+    verbosity: Verbosity,
+    behaviour: &mut CarinfoBehaviour,
+) -> Option<usize> {
+    let ttl = behaviour.cache.ttl;
+    let listing = match behaviour.cache.get(&peer) {
+        Some(listing) if offline || listing.is_fresh(ttl) => listing.clone(),
+        _ => return None,
+    };
+    let age = format::age(listing.received_at);
+    match verbosity {
+        _ if output::json() => output::emit(&Output::RemoteCars {
+            peer: peer.to_string(),
+            cars: &listing.carinfos,
+            cached_at: Some(listing.received_at),
+        }),
+        Verbosity::Quiet => {}
+        _ if listing.is_fresh(ttl) => {
+            info!(
+                "Response from {} (cached {}):",
+                aliases::display(&peer),
+                age
+            )
+        }
+        _ => info!(
+            "Response from {} (STALE, offline, cached {}):",
+            aliases::display(&peer),
+            age
+        ),
+    }
+    if !output::json() {
+        pager::page(
+            &mut behaviour.pager,
+            verbosity.carinfo_lines(&listing.carinfos),
+        );
+    }
+    let cars = listing.carinfos.len();
+    behaviour
+        .remote_carinfos
+        .entry(peer)
+        .or_insert(listing.carinfos);
+    Some(cars)
+}
+
+/// `ls car cached` shows every cached listing with its age, without asking any peer.
+pub(crate) fn handle_list_cached(swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour();
+    let mut lines = Vec::new();
+    for listing in behaviour.cache.listings() {
+        let peer = listing.peer.parse::<PeerId>().ok();
+        let name = peer.as_ref().map_or(listing.peer.clone(), aliases::display);
+        let online = peer.is_some_and(|peer| swarm.is_connected(&peer));
+        lines.push(format!(
+            "{}: {} cars, cached {}{}{}",
+            name,
+            listing.carinfos.len(),
+            format::age(listing.received_at),
+            if listing.is_fresh(behaviour.cache.ttl) {
+                ""
+            } else {
+                ", stale"
+            },
+            if online { "" } else { ", offline" }
+        ));
+        lines.extend(
+            listing
+                .carinfos
+                .iter()
+                .map(|carinfo| format!("  {}", format::carinfo(carinfo))),
+        );
+    }
+    if lines.is_empty() {
+        info!("No cached listings yet");
+        return;
+    }
+    info!(
+        "Cached listings (fresh for {}s)",
+        behaviour.cache.ttl.as_secs()
+    );
+    pager::page(&mut swarm.behaviour_mut().pager, lines);
+}
+
+/// `ls car table [by peer | by make]` shows the cars received from peers in one table sorted by
+/// make, model and peer, or under a line per peer or make.
+pub(crate) fn handle_list_table(grouping: Option<Grouping>, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let mut cars: Vec<(String, &Carinfo)> = behaviour
+        .remote_carinfos
+        .iter()
+        .flat_map(|(peer, carinfos)| {
+            let name = aliases::display(peer);
+            carinfos.iter().map(move |c| (name.clone(), c))
+        })
+        .collect();
+    if cars.is_empty() {
+        info!("No cars from peers yet, run ls car all first");
+        return;
+    }
+    let make = |c: &Carinfo| c.make.trim().to_lowercase();
+    let group = |(name, c): &(String, &Carinfo)| match grouping {
+        Some(Grouping::Peer) => name.clone(),
+        Some(Grouping::Make) => make(c),
+        None => String::new(),
+    };
+    cars.sort_by(|a, b| {
+        group(a)
+            .cmp(&group(b))
+            .then_with(|| make(a.1).cmp(&make(b.1)))
+            .then_with(|| a.1.model.trim().cmp(b.1.model.trim()))
+            .then_with(|| a.0.cmp(&b.0))
+            .then_with(|| a.1.id.cmp(&b.1.id))
+    });
+    if output::json() {
+        let mut peers: Vec<&PeerId> = behaviour.remote_carinfos.keys().collect();
+        peers.sort_by_key(|peer| aliases::display(peer));
+        for peer in peers {
+            output::emit(&Output::RemoteCars {
+                peer: peer.to_string(),
+                cars: &behaviour.remote_carinfos[peer],
+                cached_at: None,
+            });
+        }
+        return;
+    }
+    // the column grouped by is in the group's title instead
+    let skipped = match grouping {
+        Some(Grouping::Peer) => Some(0),
+        Some(Grouping::Make) => Some(2),
+        None => None,
+    };
+    let columns: Vec<Column> = [
+        ("peer", false),
+        ("id", true),
+        ("make", false),
+        ("model", false),
+        ("power", true),
+    ]
+    .into_iter()
+    .enumerate()
+    .filter(|(i, _)| Some(*i) != skipped)
+    .map(|(_, (name, right))| Column { name, right })
+    .collect();
+    let rows: Vec<Vec<String>> = cars
+        .iter()
+        .map(|(name, c)| {
+            [
+                name.clone(),
+                c.id.to_string(),
+                c.make.trim().to_owned(),
+                c.model.trim().to_owned(),
+                format::NUMBER_FORMAT.power(f64::from(c.horsepower)),
+            ]
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != skipped)
+            .map(|(_, cell)| cell)
+            .collect()
+        })
+        .collect();
+    let mut table = format::table(&columns, &rows).into_iter();
+    let mut lines: Vec<String> = table.next().into_iter().collect();
+    let mut last_group = None;
+    for (car, line) in cars.iter().zip(table) {
+        let key = group(car);
+        if grouping.is_some() && last_group.as_ref() != Some(&key) {
+            let count = cars.iter().filter(|other| group(other) == key).count();
+            let title = match grouping {
+                Some(Grouping::Make) => car.1.make.trim().to_owned(),
+                _ => key.clone(),
+            };
+            lines.push(format!("{} ({})", title, cars_count(count)));
+            last_group = Some(key);
+        }
+        lines.push(line);
+    }
+    info!(
+        "{} from {} peers, from the last `ls car all`",
+        cars_count(cars.len()),
+        behaviour.remote_carinfos.len()
+    );
+    pager::page(&mut behaviour.pager, lines);
+}
+
+fn cars_count(count: usize) -> String {
+    format!("{} car{}", count, if count == 1 { "" } else { "s" })
+}
+
+/// `ls market [near <n>km]` lists the cars received from peers, optionally only those within
+/// reach of our `CARINFO_LOCATION`.
+pub(crate) fn handle_list_market(radius_km: Option<f64>, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let home = geo::HOME.as_deref();
+    if radius_km.is_some() && home.is_none() {
+        error!("set CARINFO_LOCATION=<lat>,<lon> to filter by distance");
+        return;
+    }
+    let behaviour = swarm.behaviour_mut();
+    let mut lines = Vec::new();
+    for (peer, carinfos) in behaviour.remote_carinfos.iter() {
+        for carinfo in carinfos {
+            let distance = home
+                .zip(carinfo.location.as_deref())
+                .and_then(|(home, at)| geo::distance_km(home, at));
+            if let Some(radius) = radius_km {
+                // cars without a location can't be shown to be in reach
+                if !distance.is_some_and(|d| d <= radius) {
+                    continue;
+                }
+            }
+            let away = distance
+                .map(|d| format!(", ~{} away", format::NUMBER_FORMAT.distance(d)))
+                .unwrap_or_default();
+            lines.push(format!(
+                "{}: {}{}",
+                aliases::display(peer),
+                format::carinfo(carinfo),
+                away
+            ));
+        }
+    }
+    info!("Market ({} cars, from the last `ls car all`)", lines.len());
+    pager::page(&mut behaviour.pager, lines);
+}
+
+/// `audits car <id>` shows which peers were sent a car, newest first.
+pub(crate) fn handle_audits(id: usize, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let lines: Vec<String> = behaviour
+        .audit
+        .for_car(id)
+        .iter()
+        .map(|e| {
+            let kind = match e.kind {
+                AccessKind::Listing => "listing",
+                AccessKind::Thumbnails => "thumbnails",
+                AccessKind::Photo => "photo",
+            };
+            format!("{} {} ({})", format::age(e.at), e.peer, kind)
+        })
+        .collect();
+    info!("Carinfo {} was sent {} times", id, lines.len());
+    pager::page(&mut behaviour.pager, lines);
+}
+
+pub(crate) async fn handle_history(id: usize, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let entries = match journal::history(id).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("error reading the journal, {}", e);
+            return;
+        }
+    };
+    let lines: Vec<String> = entries
+        .iter()
+        .map(|e| {
+            let actor = e
+                .actor
+                .parse()
+                .map_or(e.actor.clone(), |peer| aliases::display(&peer));
+            format!("{} {} by {}", format::age(e.at), e.change, actor)
+        })
+        .collect();
+    info!("Carinfo {} changed {} times", id, lines.len());
+    pager::page(&mut swarm.behaviour_mut().pager, lines);
+}
+
+/// `undo` reverts the latest change of the catalog, `redo` makes the change undone last again.
+/// Peers are told about cars that became public or left the network.
+pub(crate) async fn handle_undo(redo: bool, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let reverted = if redo {
+        storage::redo_change().await
+    } else {
+        storage::undo_change().await
+    };
+    let Reverted { entry, was, now } = match reverted {
+        Ok(Some(reverted)) => reverted,
+        Ok(None) => {
+            info!("nothing to {}", if redo { "redo" } else { "undo" });
+            return;
+        }
+        Err(e) => {
+            error!("error reverting the last change, {}", e);
+            return;
+        }
+    };
+    info!(
+        "{} {}",
+        if redo { "Redid" } else { "Undid" },
+        entry.summary()
+    );
+    match now {
+        Some(carinfo) if carinfo.public => announce_published(carinfo, swarm).await,
+        _ if was.is_some_and(|carinfo| carinfo.public) => {
+            announce_deleted(entry.car_id, swarm).await
+        }
+        _ => {}
+    }
+}
+
+/// `share car <id> with <peer id>` sends the full record, private fields included, encrypted so
+/// only that peer can read it.
+pub(crate) async fn handle_share(id: usize, peer: PeerId, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let carinfo = match read_local_carinfo(id).await {
+        Ok(Some(carinfo)) => carinfo,
+        Ok(None) => {
+            error!("no carinfo with id {}", id);
+            return;
+        }
+        Err(e) => {
+            error!("error fetching local carinfos: {}", e);
+            return;
+        }
+    };
+    let hashes: Vec<String> = carinfo.photos.iter().map(|p| p.thumbnail.clone()).collect();
+    let share = SharedCarinfo {
+        carinfo,
+        thumbnails: photos::read_thumbnails(&hashes).await,
+    };
+    match share::seal(&peer, &share) {
+        Ok(sealed) => {
+            announce(SHARE_TOPIC.clone(), Message::Share(sealed), swarm).await;
+            info!("Shared carinfo {} with {}", id, peer);
+        }
+        Err(e) => error!("error encrypting carinfo {} for {}, {}", id, peer, e),
+    }
+}
+
+/// `shared` lists the records peers shared with us privately.
+pub(crate) async fn handle_list_shared(swarm: &mut Swarm<CarinfoBehaviour>) {
+    match share::read_received().await {
+        Ok(received) => {
+            info!("Shared with me ({})", received.len());
+            let lines = received
+                .iter()
+                .map(|r| {
+                    format!(
+                        "{} {}: {}",
+                        format::age(r.at),
+                        r.from,
+                        format::carinfo(&r.carinfo)
+                    )
+                })
+                .collect();
+            pager::page(&mut swarm.behaviour_mut().pager, lines);
+        }
+        Err(e) => error!("error reading shared carinfos, {}", e),
+    }
+}
+
+/// `sync <peer id>` asks a peer for its whole public catalog, see
+/// [`handle_sync_response`](crate::behaviour::handle_sync_response).
+pub(crate) fn handle_sync(peer: PeerId, swarm: &mut Swarm<CarinfoBehaviour>) {
+    if !swarm.is_connected(&peer) {
+        queue_request(peer, Purpose::Sync, swarm);
+        return;
+    }
+    swarm
+        .behaviour_mut()
+        .send_list_request(peer, ListMode::One(peer.to_string()), Purpose::Sync);
+    info!("Syncing the catalog of {}", peer);
+}
+
+pub(crate) fn handle_list_synced(origin: Option<&PeerId>, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let lines: Vec<String> = behaviour
+        .replica
+        .of(origin)
+        .map(|r| {
+            format!(
+                "{} {}: {}",
+                format::age(r.synced_at),
+                r.origin,
+                format::carinfo(&r.carinfo)
+            )
+        })
+        .collect();
+    info!("Synced ({})", lines.len());
+    pager::page(&mut behaviour.pager, lines);
+}
+
+pub(crate) fn handle_list_conflicts(swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    if behaviour.replica.conflicts.is_empty() {
+        info!("no conflicts");
+        return;
+    }
+    let lines = behaviour
+        .replica
+        .conflicts
+        .iter()
+        .enumerate()
+        .map(|(n, conflict)| format!("#{} {}", n, conflict.summary()))
+        .collect();
+    pager::page(&mut behaviour.pager, lines);
+}
+
+pub(crate) async fn handle_resolve_conflict(
+    n: usize,
+    resolution: Resolution,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    match swarm.behaviour_mut().replica.resolve(n, resolution).await {
+        Ok(Some(conflict)) => info!(
+            "Resolved the conflict over car {} of {}",
+            conflict.ours.id,
+            conflict
+                .origin
+                .parse()
+                .map_or(conflict.origin.clone(), |peer| aliases::display(&peer))
+        ),
+        Ok(None) => info!("no conflict #{}", n),
+        Err(e) => error!("error storing synced carinfos, {}", e),
+    }
+}
+
+/// `geo car <id> <lat>,<lon>` tags a car with a coarse location, `geo car <id> clear` removes it.
+pub(crate) async fn handle_geo_carinfo(id: usize, at: Option<(f64, f64)>) {
+    let location = at.map(|(lat, lon)| geo::encode(lat, lon, geo::PRECISION));
+    match set_location(id, location.clone()).await {
+        Ok(()) => match location {
+            Some(hash) => info!("Carinfo {} is now located in geohash cell {}", id, hash),
+            None => info!("Cleared the location of carinfo {}", id),
+        },
+        Err(e) => error!("error setting location of carinfo {}, {}", id, e),
+    }
+}
+
+pub(crate) async fn handle_create_carinfo(
+    make: &str,
+    model: &str,
+    horsepower: u32,
+    vin: Option<&str>,
+    details: &Details,
+    force: bool,
+) {
+    if !force {
+        match find_duplicate(make, model, horsepower, vin).await {
+            Ok(Some(known)) => {
+                error!(
+                    "not creating carinfo, the catalog has it already: {}; add --force to \
+                     create it anyway",
+                    format::carinfo(&known)
+                );
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("error looking for duplicates: {}", e);
+                return;
+            }
+        }
+    }
+    match create_new_carinfo(make, model, horsepower, vin, details).await {
+        Ok(id) if output::json() => match read_local_carinfo(id).await {
+            Ok(Some(carinfo)) => output::emit(&Output::Created { car: &carinfo }),
+            Ok(None) => error!("carinfo {} is gone after creating it", id),
+            Err(e) => error!("error reading carinfo with id {}, {}", id, e),
+        },
+        Ok(id) => {
+            info!("Created carinfo:");
+            info!("Make: {}", make);
+            info!("Model: {}", model);
+            info!("Horsepower:: {}", horsepower);
+            if let Some(vin) = vin {
+                info!("VIN: {}", vin);
+            }
+            if !details.is_empty() {
+                if let Ok(Some(carinfo)) = read_local_carinfo(id).await {
+                    info!("Details: {}", format::details(&carinfo));
+                }
+            }
+        }
+        Err(e) => error!("error creating carinfo: {}", e),
+    };
+}
+
+/// `dedupe` lists the groups of local records that are the same car, see
+/// [`storage::duplicate_key`], and `dedupe merge` merges each group into its oldest record.
+/// Peers are told to drop the published records merged away.
+pub(crate) async fn handle_dedupe(merge: bool, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let key = storage::duplicate_key();
+    let groups = match read_local_carinfos().await {
+        Ok(carinfos) => dedupe::groups(carinfos, key),
+        Err(e) => {
+            error!("error fetching local carinfos: {}", e);
+            return;
+        }
+    };
+    if groups.is_empty() {
+        info!("No duplicates by {}", key);
+        return;
+    }
+    if !merge {
+        let lines: Vec<String> = groups
+            .iter()
+            .flat_map(|group| {
+                group.iter().enumerate().map(|(i, r)| {
+                    format!("{}{}", if i == 0 { "" } else { "  " }, format::carinfo(r))
+                })
+            })
+            .collect();
+        info!(
+            "{} cars have duplicates by {}, merge them with dedupe merge",
+            groups.len(),
+            key
+        );
+        pager::page(&mut swarm.behaviour_mut().pager, lines);
+        return;
+    }
+    for group in groups {
+        let merged = match merge_duplicates(&group).await {
+            Ok(merged) => merged,
+            Err(e) => {
+                error!("error merging duplicates of carinfo {}, {}", group[0].id, e);
+                continue;
+            }
+        };
+        let removed: Vec<&Carinfo> = group.iter().filter(|r| r.id != merged.id).collect();
+        info!(
+            "Merged {} into {}",
+            removed
+                .iter()
+                .map(|r| format!("#{}", r.id))
+                .collect::<Vec<_>>()
+                .join(", "),
+            format::carinfo(&merged)
+        );
+        for r in removed.iter().filter(|r| r.public) {
+            announce_deleted(r.id, swarm).await;
+        }
+        if merged.public {
+            announce_published(merged, swarm).await;
+        }
+    }
+}
+
+/// Publishes a record and announces it, so peers running `watch` see it right away.
+pub(crate) async fn handle_publish_carinfo(id: usize, swarm: &mut Swarm<CarinfoBehaviour>) {
+    if let Err(e) = publish_carinfo(id).await {
+        info!("error publishing carinfo with id {}, {}", id, e);
+        return;
+    }
+    if output::json() {
+        output::emit(&Output::Published { id });
+    } else {
+        info!("Published Carinfo with id: {}", id);
+    }
+    match read_local_carinfo(id).await {
+        Ok(Some(carinfo)) => announce_published(carinfo, swarm).await,
+        Ok(None) => {}
+        Err(e) => error!("error reading carinfo with id {}, {}", id, e),
+    }
+}
+
+/// Announces a published record on its channels, or on the catalog topic if it is in none.
+/// Peers that are offline get it from the catch-up log when they return.
+pub(crate) async fn announce_published(carinfo: Carinfo, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let mut carinfo = storage::outgoing(carinfo);
+    if carinfo.location.is_none() {
+        carinfo.location = geo::HOME.clone();
+    }
+    let catalog = &swarm.behaviour().topic;
+    let topics = if carinfo.channels.is_empty() {
+        vec![catalog.clone()]
+    } else {
+        carinfo
+            .channels
+            .iter()
+            .map(|name| channels::topic(catalog, name))
+            .collect()
+    };
+    for topic in topics {
+        let published = Message::CarPublished(CarPublished {
+            carinfo: Box::new(carinfo.clone()),
+        });
+        announce(topic, published, swarm).await;
+    }
+}
+
+/// `channel car <id> [<channel>...]` replaces the channels of a car. A published car is
+/// announced again, so the members of its new channels see it.
+pub(crate) async fn handle_channel_car(
+    id: usize,
+    names: BTreeSet<String>,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    match set_channels(id, names).await {
+        Ok(carinfo) => {
+            if carinfo.channels.is_empty() {
+                info!("Carinfo {} is in no channel", id);
+            } else {
+                info!(
+                    "Carinfo {} is in {}",
+                    id,
+                    format::channels(&carinfo.channels)
+                );
+            }
+            if carinfo.public {
+                announce_published(carinfo, swarm).await;
+            }
+        }
+        Err(e) => error!("error setting channels of carinfo {}, {}", id, e),
+    }
+}
+
+/// `channels` lists the channels we are in with their connected peers, `channels join|leave
+/// <name>` changes them until the node stops.
+pub(crate) fn handle_channels(command: ChannelCommand, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    match command {
+        ChannelCommand::List => {
+            if behaviour.channels.is_empty() {
+                info!("Not in any channel");
+                return;
+            }
+            let lines: Vec<String> = behaviour
+                .channels
+                .iter()
+                .map(|name| {
+                    let topic = channels::topic(&behaviour.topic, name);
+                    let peers = behaviour
+                        .topic_peers
+                        .get(topic.hash().as_str())
+                        .map_or(0, HashSet::len);
+                    format!("#{} ({}), {} peers", name, topic.hash().as_str(), peers)
+                })
+                .collect();
+            info!("Channels ({})", lines.len());
+            pager::page(&mut behaviour.pager, lines);
+        }
+        ChannelCommand::Join(name) => {
+            if behaviour.join_channel(&name) {
+                info!("Joined #{}", name);
+            } else {
+                info!("Already in #{}", name);
+            }
+        }
+        ChannelCommand::Leave(name) => {
+            if behaviour.leave_channel(&name) {
+                info!("Left #{}", name);
+            } else {
+                info!("Not in #{}", name);
+            }
+        }
+    }
+}
+
+/// `watch` shows cars as peers publish them, `unwatch` stops.
+pub(crate) fn handle_watch(on: bool, swarm: &mut Swarm<CarinfoBehaviour>) {
+    swarm.behaviour_mut().watching = on;
+    if on {
+        info!("Watching for newly published cars");
+    } else {
+        info!("Stopped watching for newly published cars");
+    }
+}
+
+/// Changes the given fields of a record; `None` keeps the current value.
+pub(crate) async fn handle_edit_carinfo(
+    id: usize,
+    make: Option<&str>,
+    model: Option<&str>,
+    horsepower: Option<u32>,
+    vin: Option<&str>,
+    details: &Details,
+) {
+    match edit_carinfo(id, make, model, horsepower, vin, details).await {
+        Ok(carinfo) => info!("Edited carinfo: {}", format::carinfo(&carinfo)),
+        Err(e) => error!("error editing carinfo with id {}, {}", id, e),
+    }
+}
+
+/// Deletes a record; if it was published, peers are told to forget it too.
+pub(crate) async fn handle_delete_carinfo(id: usize, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match delete_carinfo(id).await {
+        Ok(removed) => {
+            info!("Deleted carinfo: {}", format::carinfo(&removed));
+            if removed.public {
+                announce_deleted(id, swarm).await;
+            }
+        }
+        Err(e) => error!("error deleting carinfo with id {}, {}", id, e),
+    }
+}
+
+/// Tells peers to drop their copy of our record `id`, on the catalog topic. Peers that are
+/// offline get it from the catch-up log when they return.
+pub(crate) async fn announce_deleted(id: usize, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let tombstone = Message::CarinfoDeleted(CarinfoDeleted { deleted: id });
+    announce(swarm.behaviour().topic.clone(), tombstone, swarm).await;
+}
+
+/// Takes a record off the network and tells peers to drop their copy.
+pub(crate) async fn handle_unpublish_carinfo(id: usize, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match unpublish_carinfo(id).await {
+        Ok(()) => {
+            info!("Unpublished Carinfo with id: {}", id);
+            announce_deleted(id, swarm).await;
+        }
+        Err(e) => error!("error unpublishing carinfo with id {}, {}", id, e),
+    }
+}
+
+pub(crate) async fn handle_grant(id: usize, peer: PeerId) {
+    match grant_carinfo(id, &peer).await {
+        Ok(true) => info!("{} now gets carinfo {} in its listings", peer, id),
+        Ok(false) => info!("{} already gets carinfo {} in its listings", peer, id),
+        Err(e) => error!("error granting carinfo {} to {}, {}", id, peer, e),
+    }
+}
+
+pub(crate) async fn handle_tag(id: usize, tag: &str) {
+    match tag_carinfo(id, tag).await {
+        Ok(true) => info!("Tagged carinfo {} {}", id, tag),
+        Ok(false) => info!("Carinfo {} is already tagged {}", id, tag),
+        Err(e) => error!("error tagging carinfo {}, {}", id, e),
+    }
+}
+
+pub(crate) async fn handle_untag(id: usize, tag: &str) {
+    match untag_carinfo(id, tag).await {
+        Ok(true) => info!("Carinfo {} is no longer tagged {}", id, tag),
+        Ok(false) => info!("Carinfo {} isn't tagged {}", id, tag),
+        Err(e) => error!("error untagging carinfo {}, {}", id, e),
+    }
+}
+
+/// Takes back a grant. Unless the car is published the peer no longer may have it, so a
+/// tombstone is announced like on `unpublish car`.
+pub(crate) async fn handle_revoke(id: usize, peer: PeerId, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match revoke_carinfo(id, &peer).await {
+        Ok(true) => {
+            info!("{} no longer gets carinfo {} in its listings", peer, id);
+            match read_local_carinfo(id).await {
+                Ok(Some(carinfo)) if carinfo.public => {}
+                Ok(_) => {
+                    announce_deleted(id, swarm).await;
+                }
+                Err(e) => error!("error reading carinfo with id {}, {}", id, e),
+            }
+        }
+        Ok(false) => info!("{} had no grant for carinfo {}", peer, id),
+        Err(e) => error!("error revoking carinfo {} from {}, {}", id, peer, e),
+    }
+}
+
+pub(crate) async fn handle_obd_carinfo(id: usize, path: &str, shared: bool) {
+    match obd::read(path).await {
+        Ok(stats) => {
+            let stats = ObdStats { shared, ..stats };
+            info!(
+                "Read OBD-II stats: odometer {:?} km, fuel {:?}%, trouble codes {:?}",
+                stats.odometer_km, stats.fuel_level_percent, stats.dtc_codes
+            );
+            if let Err(e) = attach_obd_stats(id, stats).await {
+                error!("error attaching OBD-II stats to carinfo {}, {}", id, e);
+            } else {
+                info!("Attached OBD-II stats to carinfo with id: {}", id);
+            }
+        }
+        Err(e) => error!("error reading OBD-II data from {}, {}", path, e),
+    }
+}
+
+pub(crate) async fn handle_export_parquet(path: &str, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let local_carinfos = match read_local_carinfos().await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("error fetching local carinfos: {}", e);
+            return;
+        }
+    };
+    let mut records: Vec<(String, Carinfo)> = local_carinfos
+        .into_iter()
+        .map(|r| (PEER_ID.to_string(), r))
+        .collect();
+    for (peer, carinfos) in swarm.behaviour().remote_carinfos.iter() {
+        records.extend(carinfos.iter().map(|r| (peer.to_string(), r.clone())));
+    }
+    match export::parquet(path, records).await {
+        Ok(count) => info!("Exported {} carinfos to {}", count, path),
+        Err(e) => error!("error exporting to {}, {}", path, e),
+    }
+}
+
+pub(crate) async fn handle_export_csv(path: &str) {
+    let local_carinfos = match read_local_carinfos().await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("error fetching local carinfos: {}", e);
+            return;
+        }
+    };
+    match export::csv(path, local_carinfos).await {
+        Ok(count) => info!("Exported {} carinfos to {}", count, path),
+        Err(e) => error!("error exporting to {}, {}", path, e),
+    }
+}
+
+/// `import csv <path>` adds a spreadsheet's rows to the catalog, listing the rows it left out.
+pub(crate) async fn handle_import_csv(path: &str, options: &CsvImport) {
+    let result = import::csv(path, options).await;
+    show_import(path, options.dry_run, "line", result);
+}
+
+pub(crate) async fn handle_export_json(path: &str) {
+    let local_carinfos = match read_local_carinfos().await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("error fetching local carinfos: {}", e);
+            return;
+        }
+    };
+    match export::json(path, &local_carinfos).await {
+        Ok(count) => info!("Exported {} carinfos to {}", count, path),
+        Err(e) => error!("error exporting to {}, {}", path, e),
+    }
+}
+
+/// `import json <path>` adds another node's catalog to ours, listing the cars it left out.
+pub(crate) async fn handle_import_json(path: &str, options: &JsonImport) {
+    let result = import::json(path, options).await;
+    show_import(path, options.dry_run, "car", result);
+}
+
+/// Summary of an import, with the `unit` (line or car) and reason of every skipped entry.
+fn show_import(path: &str, dry_run: bool, unit: &str, result: crate::Result<ImportReport>) {
+    match result {
+        Ok(report) => {
+            if dry_run {
+                info!(
+                    "Dry run, nothing imported. Importing {} would give: {}",
+                    path, report
+                );
+            } else {
+                info!("Imported {}: {}", path, report);
+            }
+            for (position, reason) in &report.skipped {
+                info!("  skipped {} {}: {}", unit, position, reason);
+            }
+        }
+        Err(e) => error!("error importing {}, {}", path, e),
+    }
+}
+
+pub(crate) async fn handle_backup(dir: Option<&str>) {
+    match backup::create(dir.map(Path::new)).await {
+        Ok(path) => info!("Backed up to {}", path.display()),
+        Err(e) => error!("error backing up, {}", e),
+    }
+}
+
+/// `restore <path>` swaps in a backup; the catalog at once, the identity on the next start.
+pub(crate) async fn handle_restore(path: &str) {
+    let restored = match backup::restore(Path::new(path)).await {
+        Ok(restored) => restored,
+        Err(e) => {
+            error!("error restoring {}, {}", path, e);
+            return;
+        }
+    };
+    info!(
+        "Restored {} cars from the backup of {} made {}{}",
+        restored.cars,
+        restored.peer_id,
+        restored.created_at.format("%Y-%m-%d %H:%M UTC"),
+        if restored.config {
+            ", and its config file"
+        } else {
+            ""
+        }
+    );
+    if restored.peer_id != PEER_ID.to_string() {
+        info!("Restart to take on its identity, the current key was kept as .old");
+    } else if restored.config {
+        info!("Restart to apply its config file");
+    }
+}
+
+pub(crate) async fn handle_telemetry(
+    command: TelemetryCommand,
+    sender: &mpsc::UnboundedSender<TelemetryUpdate>,
+    task: &mut Option<tokio::task::JoinHandle<()>>,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    match command {
+        TelemetryCommand::Start {
+            id,
+            source,
+            interval,
+            region,
+        } => {
+            if interval < telemetry::MIN_INTERVAL {
+                info!(
+                    "interval raised to the minimum of {}s",
+                    telemetry::MIN_INTERVAL.as_secs()
+                );
+            }
+            let carinfo = match read_local_carinfo(id).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("error fetching local carinfos: {}", e);
+                    return;
+                }
+            };
+            match carinfo {
+                Some(carinfo) if carinfo.public => {
+                    if let Some(previous) = task.take() {
+                        previous.abort();
+                    }
+                    *task = Some(telemetry::start(
+                        &carinfo,
+                        source,
+                        region,
+                        interval,
+                        sender.clone(),
+                    ));
+                    info!("Streaming telemetry for carinfo with id: {}", id);
+                }
+                Some(_) => info!(
+                    "carinfo {} must be published before streaming telemetry",
+                    id
+                ),
+                None => error!("no carinfo with id {}", id),
+            }
+        }
+        TelemetryCommand::Stop => match task.take() {
+            Some(t) => {
+                t.abort();
+                info!("Stopped streaming telemetry");
+            }
+            None => info!("telemetry is not being streamed"),
+        },
+        TelemetryCommand::Watch(min_gap) => {
+            let behaviour = swarm.behaviour_mut();
+            if let Err(e) = behaviour.gossipsub.subscribe(&TELEMETRY_TOPIC) {
+                error!("error subscribing to telemetry, {:?}", e);
+                return;
+            }
+            behaviour.telemetry_viewer = Some(TelemetryViewer::new(min_gap));
+            info!(
+                "Watching telemetry, at most one update per peer every {}s",
+                min_gap.as_secs()
+            );
+        }
+        TelemetryCommand::Unwatch => {
+            let behaviour = swarm.behaviour_mut();
+            if let Err(e) = behaviour.gossipsub.unsubscribe(&TELEMETRY_TOPIC) {
+                error!("error unsubscribing from telemetry, {:?}", e);
+            }
+            behaviour.telemetry_viewer = None;
+            info!("Stopped watching telemetry");
+        }
+    }
+}
+
+/// Decodes a candump log with a DBC file and, if signals are named, attaches the confirmed
+/// horsepower and odometer to the car.
+pub(crate) async fn handle_can_carinfo(
+    id: usize,
+    log_path: &str,
+    dbc_path: &str,
+    hp_signal: Option<&str>,
+    odometer_signal: Option<&str>,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let (log, dbc) = match (
+        fs::read_to_string(log_path).await,
+        fs::read_to_string(dbc_path).await,
+    ) {
+        (Ok(log), Ok(dbc)) => (log, dbc),
+        (Err(e), _) => {
+            error!("error reading {}, {}", log_path, e);
+            return;
+        }
+        (_, Err(e)) => {
+            error!("error reading {}, {}", dbc_path, e);
+            return;
+        }
+    };
+    let dbc = match can::Dbc::parse(&dbc) {
+        Ok(dbc) => dbc,
+        Err(e) => {
+            error!("error parsing {}, {}", dbc_path, e);
+            return;
+        }
+    };
+    let stats = can::decode_log(&log, &dbc);
+    info!("Decoded {} signals:", stats.len());
+    pager::page(
+        &mut swarm.behaviour_mut().pager,
+        stats
+            .iter()
+            .map(|(name, s)| {
+                format!(
+                    "{}: min {} max {} last {} {} ({} samples)",
+                    name, s.min, s.max, s.last, s.unit, s.samples
+                )
+            })
+            .collect(),
+    );
+
+    if hp_signal.is_none() && odometer_signal.is_none() {
+        return;
+    }
+    match can::summarize(&stats, hp_signal, odometer_signal) {
+        Ok(summary) => {
+            if let Err(e) = attach_can_summary(id, summary).await {
+                error!("error attaching CAN summary to carinfo {}, {}", id, e);
+            } else {
+                info!("Attached CAN summary to carinfo with id: {}", id);
+            }
+        }
+        Err(e) => error!("error summarizing CAN log, {}", e),
+    }
+}
+
+pub(crate) async fn handle_enrich_carinfo(id: usize, enricher: &mut Enricher) {
+    if enricher.is_empty() {
+        info!("no lookups enabled, set CARINFO_LOOKUPS (e.g. nhtsa,http)");
+        return;
+    }
+    let carinfo = match read_local_carinfo(id).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("error fetching local carinfos: {}", e);
+            return;
+        }
+    };
+    let carinfo = match carinfo {
+        Some(carinfo) => carinfo,
+        None => {
+            error!("no carinfo with id {}", id);
+            return;
+        }
+    };
+    let attributes = enricher.enrich(&carinfo).await;
+    attributes.iter().for_each(|(k, v)| info!("{}: {}", k, v));
+    if let Err(e) = merge_attributes(id, attributes).await {
+        error!("error enriching carinfo {}, {}", id, e);
+    } else {
+        info!("Enriched carinfo with id: {}", id);
+    }
+}
+
+pub(crate) async fn handle_price_carinfo(
+    id: usize,
+    providers: &PriceProviders,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    if providers.is_empty() {
+        info!("no price providers enabled, set CARINFO_PRICE_PROVIDERS (e.g. http)");
+        return;
+    }
+    let carinfo = match read_local_carinfo(id).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("error fetching local carinfos: {}", e);
+            return;
+        }
+    };
+    let carinfo = match carinfo {
+        Some(carinfo) => carinfo,
+        None => {
+            error!("no carinfo with id {}", id);
+            return;
+        }
+    };
+    let points = providers.quote(&carinfo).await;
+    match record_prices(id, points).await {
+        Ok(history) => {
+            info!("Price history for carinfo {}:", id);
+            pager::page(&mut swarm.behaviour_mut().pager, price::trend(&history));
+        }
+        Err(e) => error!("error recording prices for carinfo {}, {}", id, e),
+    }
+}
+
+pub(crate) async fn handle_club(command: ClubCommand, swarm: &mut Swarm<CarinfoBehaviour>) {
+    match command {
+        ClubCommand::Join(coordinator) => {
+            let behaviour = swarm.behaviour_mut();
+            if let ClubRole::Coordinator { .. } = behaviour.club {
+                info!("this node is a club coordinator");
+                return;
+            }
+            behaviour.club = ClubRole::Member {
+                coordinator,
+                catalog: ClubCatalog::default(),
+            };
+            behaviour
+                .club_protocol
+                .send_request(&coordinator, ClubRequest::Sync);
+            info!("Joined club coordinated by {}", coordinator);
+        }
+        ClubCommand::Leave => {
+            let behaviour = swarm.behaviour_mut();
+            if let ClubRole::Member { .. } = behaviour.club {
+                behaviour.club = ClubRole::None;
+                info!("Left club");
+            } else {
+                info!("not a club member");
+            }
+        }
+        ClubCommand::Submit(id) | ClubCommand::Withdraw(id) => {
+            let action = match command {
+                ClubCommand::Submit(_) => "submit",
+                _ => "withdraw",
+            };
+            let carinfo = match read_local_carinfo(id).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("error fetching local carinfos: {}", e);
+                    return;
+                }
+            };
+            let coordinator = match &mut swarm.behaviour_mut().club {
+                ClubRole::None => {
+                    info!("join a club first: club join <coordinator peer id>");
+                    return;
+                }
+                ClubRole::Coordinator { catalog, .. } => {
+                    match (action, carinfo) {
+                        ("submit", Some(carinfo)) => catalog.submit(PEER_ID.to_string(), carinfo),
+                        ("submit", None) => {
+                            error!("no carinfo with id {}", id);
+                            return;
+                        }
+                        _ => {
+                            if !catalog.withdraw(&PEER_ID.to_string(), id) {
+                                info!("carinfo {} is not in the club catalog", id);
+                                return;
+                            }
+                        }
+                    }
+                    commit_club_catalog(None, swarm).await;
+                    info!("Club catalog updated");
+                    return;
+                }
+                ClubRole::Member { coordinator, .. } => *coordinator,
+            };
+            let request = match (action, carinfo) {
+                ("submit", Some(carinfo)) => ClubRequest::Submit(Box::new(carinfo)),
+                ("submit", None) => {
+                    error!("no carinfo with id {}", id);
+                    return;
+                }
+                _ => ClubRequest::Withdraw(id),
+            };
+            swarm
+                .behaviour_mut()
+                .club_protocol
+                .send_request(&coordinator, request);
+            info!("Sent {} of carinfo {} to the coordinator", action, id);
+        }
+        ClubCommand::List => {
+            let behaviour = swarm.behaviour_mut();
+            let catalog = match &behaviour.club {
+                ClubRole::None => {
+                    info!("not in a club");
+                    return;
+                }
+                ClubRole::Coordinator { catalog, .. } | ClubRole::Member { catalog, .. } => catalog,
+            };
+            info!(
+                "Club catalog version {} ({} cars)",
+                catalog.version,
+                catalog.entries.len()
+            );
+            let lines = catalog
+                .entries
+                .iter()
+                .map(|e| format!("{}: {}", e.member, format::carinfo(&e.carinfo)))
+                .collect();
+            pager::page(&mut behaviour.pager, lines);
+        }
+        ClubCommand::Add(peer) | ClubCommand::Remove(peer) => {
+            let members = match &mut swarm.behaviour_mut().club {
+                ClubRole::Coordinator { members, .. } => members,
+                _ => {
+                    info!("only the club coordinator manages members");
+                    return;
+                }
+            };
+            let added = matches!(command, ClubCommand::Add(_));
+            let changed = if added {
+                members.0.insert(peer.to_string())
+            } else {
+                members.0.remove(&peer.to_string())
+            };
+            if !changed {
+                info!(
+                    "{} is {} a member",
+                    aliases::display(&peer),
+                    if added { "already" } else { "not" }
+                );
+                return;
+            }
+            if let Err(e) = members.save().await {
+                error!("error storing club members, {}", e);
+            }
+            if added {
+                info!("{} is a club member now", aliases::display(&peer));
+                return;
+            }
+            info!("{} is no longer a club member", aliases::display(&peer));
+            if let ClubRole::Coordinator { catalog, .. } = &mut swarm.behaviour_mut().club {
+                if catalog.remove_member(&peer.to_string()) {
+                    commit_club_catalog(None, swarm).await;
+                }
+            }
+        }
+        ClubCommand::Members => {
+            let behaviour = swarm.behaviour_mut();
+            let members = match &behaviour.club {
+                ClubRole::Coordinator { members, .. } => members,
+                _ => {
+                    info!("only the club coordinator keeps a member list");
+                    return;
+                }
+            };
+            info!("{} club members", members.0.len());
+            let lines = members
+                .0
+                .iter()
+                .map(|member| {
+                    member
+                        .parse()
+                        .map_or(member.clone(), |peer| aliases::display(&peer))
+                })
+                .collect();
+            pager::page(&mut behaviour.pager, lines);
+        }
+    }
+}
+
+/// `filter [all|<peer id>] <field><op><value>...` lists the local records matching every
+/// condition, or asks peers for their matching public records.
+pub(crate) async fn handle_find(
+    target: ListTarget,
+    filter: Filter,
+    verbosity: Verbosity,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    if target != ListTarget::Local {
+        swarm.behaviour_mut().request = (verbosity, Instant::now());
+        send_list_requests(&target, Some(&filter), verbosity, swarm);
+        return;
+    }
+    match read_local_carinfos().await {
+        Ok(local) => {
+            let matches: Vec<Carinfo> = local.into_iter().filter(|r| filter.matches(r)).collect();
+            if output::json() {
+                output::emit(&Output::Matches {
+                    peer: None,
+                    filter: filter.to_string(),
+                    cars: &matches,
+                });
+                return;
+            }
+            if verbosity != Verbosity::Quiet {
+                info!("{} local matches for {}", matches.len(), filter);
+            }
+            pager::page(
+                &mut swarm.behaviour_mut().pager,
+                verbosity.carinfo_lines(&matches),
+            );
+        }
+        Err(e) => error!("error fetching local carinfos: {}", e),
+    }
+}
+
+/// `search net <words>` asks every peer on the catalog topic for its public records best
+/// matching the words. Answers are shown ranked as they arrive, see
+/// [`crate::behaviour::handle_text_search_response`].
+pub(crate) fn handle_search_net(
+    text: String,
+    verbosity: Verbosity,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    if search::words(&text).is_empty() {
+        error!("nothing to search for in {}", text);
+        return;
+    }
+    let behaviour = swarm.behaviour();
+    let peers: Vec<PeerId> = behaviour
+        .topic_peers
+        .get(behaviour.topic.hash().as_str())
+        .map(|peers| peers.iter().copied().collect())
+        .unwrap_or_default();
+    if peers.is_empty() {
+        info!("No peers to ask yet");
+        return;
+    }
+    let behaviour = swarm.behaviour_mut();
+    behaviour.request = (verbosity, Instant::now());
+    if verbosity != Verbosity::Quiet && !output::json() {
+        info!("Searching {} peer(s) for {}", peers.len(), text);
+    }
+    for peer in peers {
+        behaviour.send_list_request(peer, ListMode::ALL, Purpose::TextSearch(text.clone()));
+    }
+}
+
+/// `search <query>` ranks local records and every listing received so far.
+pub(crate) async fn handle_search(query: &str, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let search = match behaviour.search.as_mut() {
+        Some(search) => search,
+        None => {
+            error!("search index is not available");
+            return;
+        }
+    };
+    // local records change through many commands, so they are reindexed on every search
+    match read_local_carinfos().await {
+        Ok(mut local) => {
+            // the index is plaintext on disk, encrypted records stay out of it
+            if crate::storage().encrypts_private() {
+                local.retain(|r| r.public);
+            }
+            if let Err(e) = search.index_carinfos(LOCAL_OWNER, &local) {
+                error!("error indexing local carinfos, {}", e);
+            }
+        }
+        Err(e) => error!("error fetching local carinfos: {}", e),
+    }
+    match search.search(query, 50) {
+        Ok(hits) if output::json() => output::emit(&Output::SearchHits {
+            query: query.to_owned(),
+            hits: hits
+                .iter()
+                .map(|h| output::Hit {
+                    score: h.score,
+                    owner: &h.owner,
+                    car: &h.carinfo,
+                })
+                .collect(),
+        }),
+        Ok(hits) => {
+            info!("{} matches for {}", hits.len(), query);
+            let lines = hits
+                .iter()
+                .map(|h| {
+                    format!(
+                        "{:.2} {}: {}",
+                        h.score,
+                        h.owner,
+                        format::carinfo(&h.carinfo)
+                    )
+                })
+                .collect();
+            pager::page(&mut behaviour.pager, lines);
+        }
+        Err(e) => error!("invalid search query, {}", e),
+    }
+}
+
+/// `find <make> [<model>]` looks the make or model up in the DHT; the peers that announced
+/// it are shown once the lookup completes.
+pub(crate) fn handle_find_providers(
+    make: &str,
+    model: Option<&str>,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let key = dht::provider_key(make, model);
+    let query = match model {
+        Some(model) => format!("{} {}", make, model),
+        None => make.to_owned(),
+    };
+    let behaviour = swarm.behaviour_mut();
+    let id = behaviour.kademlia.get_providers(Key::new(&key));
+    behaviour.finds.insert(id, query);
+    info!("Looking up {} in the DHT", key);
+}
+
+pub(crate) fn handle_list_chats(thread: Option<usize>, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let lines = match thread {
+        None => behaviour
+            .chats
+            .threads
+            .iter()
+            .enumerate()
+            .map(|(i, t)| format!("#{} {}", i, t.summary(&PEER_ID.to_string())))
+            .collect(),
+        Some(n) => match behaviour.chats.threads.get(n) {
+            Some(thread) => thread
+                .lines
+                .iter()
+                .map(|l| {
+                    let who = if l.from_me { "me" } else { "them" };
+                    let pending = if l.pending {
+                        " (not delivered yet)"
+                    } else {
+                        ""
+                    };
+                    format!("{} {}: {}{}", format::age(l.at), who, l.text, pending)
+                })
+                .collect(),
+            None => {
+                info!("no chat #{}", n);
+                return;
+            }
+        },
+    };
+    pager::page(&mut behaviour.pager, lines);
+}
+
+/// Replies in a thread, or writes to the owner of a car, opening a thread. The line is sent to
+/// the other side alone, now or once it connects.
+pub(crate) async fn handle_chat(to: ChatTarget, text: &str, swarm: &mut Swarm<CarinfoBehaviour>) {
+    let (to, owner, car_id) = match to {
+        ChatTarget::Thread(n) => match swarm.behaviour().chats.threads.get(n) {
+            Some(t) => (t.counterpart.clone(), t.owner.clone(), t.car_id),
+            None => {
+                info!("no chat #{}", n);
+                return;
+            }
+        },
+        ChatTarget::Car { peer, .. } if peer == *PEER_ID => {
+            info!("can't chat with ourselves");
+            return;
+        }
+        ChatTarget::Car { peer, car_id } => (peer.to_string(), peer.to_string(), car_id),
+    };
+
+    let line = ChatLine {
+        from_me: true,
+        text: text.to_owned(),
+        at: chat::now(),
+        pending: true,
+    };
+    let behaviour = swarm.behaviour_mut();
+    match behaviour.chats.add(&owner, car_id, &to, line).await {
+        Ok(thread) => {
+            let line = behaviour.chats.threads[thread].lines.len() - 1;
+            behaviour.send_chat_line(thread, line);
+            info!("Sending in chat #{}", thread);
+        }
+        Err(e) => error!("error storing chat message, {}", e),
+    }
+}
+
+/// Sends an offer for a peer's car; it is kept once the owner confirms receiving it.
+pub(crate) fn handle_make_offer(
+    peer: PeerId,
+    car_id: usize,
+    price: Price,
+    valid_for: Duration,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    if peer == *PEER_ID {
+        info!("car {} is ours", car_id);
+        return;
+    }
+    let offer = Offer {
+        id: rand::random(),
+        car_id,
+        price,
+        expiry: chat::now() + valid_for.as_secs(),
+    };
+    let behaviour = swarm.behaviour_mut();
+    let request_id = behaviour
+        .offer
+        .send_request(&peer, OfferRequest::Offer(offer.clone()));
+    behaviour
+        .offers
+        .sending
+        .insert(request_id, Sending::Offer(offer));
+    info!(
+        "Sending offer for car {} to {}",
+        car_id,
+        aliases::display(&peer)
+    );
+}
+
+pub(crate) fn handle_list_offers(swarm: &mut Swarm<CarinfoBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let offers = &behaviour.offers;
+    if offers.incoming.is_empty() && offers.outgoing.is_empty() {
+        info!("no offers");
+        return;
+    }
+    let lines = offers
+        .incoming
+        .iter()
+        .enumerate()
+        .map(|(n, entry)| format!("#{} {}", n, entry.incoming_summary()))
+        .chain(offers.outgoing.iter().map(|entry| entry.outgoing_summary()))
+        .collect();
+    pager::page(&mut behaviour.pager, lines);
+}
+
+/// Decides on a received offer and tells the buyer. Answering the same way again only tells
+/// a buyer who didn't get the answer.
+pub(crate) async fn handle_answer_offer(
+    n: usize,
+    accept: bool,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let state = if accept {
+        OfferState::Accepted
+    } else {
+        OfferState::Rejected
+    };
+    let behaviour = swarm.behaviour_mut();
+    let entry = match behaviour.offers.incoming.get_mut(n) {
+        Some(entry) => entry,
+        None => {
+            info!("no offer #{}", n);
+            return;
+        }
+    };
+    if entry.expired() {
+        info!("offer #{} expired", n);
+        return;
+    }
+    match entry.state {
+        OfferState::Pending => {
+            entry.state = state;
+            entry.told = false;
+        }
+        decided if decided != state || entry.told => {
+            info!(
+                "offer #{} is {} already",
+                n,
+                if decided == OfferState::Accepted {
+                    "accepted"
+                } else {
+                    "rejected"
+                }
+            );
+            return;
+        }
+        _ => {}
+    }
+    let peer: PeerId = match entry.peer.parse() {
+        Ok(peer) => peer,
+        Err(e) => {
+            error!("invalid peer id {} of offer #{}, {}", entry.peer, n, e);
+            return;
+        }
+    };
+    let request = OfferRequest::Answer {
+        id: entry.offer.id,
+        accepted: accept,
+    };
+    if let Err(e) = behaviour.offers.save().await {
+        error!("error storing offers, {}", e);
+    }
+    let request_id = behaviour.offer.send_request(&peer, request);
+    behaviour
+        .offers
+        .sending
+        .insert(request_id, Sending::Answer(n));
+    info!(
+        "{} offer #{}, telling {}",
+        if accept { "Accepted" } else { "Rejected" },
+        n,
+        aliases::display(&peer)
+    );
+}
+
+pub(crate) async fn handle_photo_carinfo(id: usize, path: &str) {
+    match photos::import(path).await {
+        Ok(photo) => {
+            let thumbnail = photos::thumbnail_path(&photo.thumbnail).unwrap_or_default();
+            if let Err(e) = attach_photo(id, photo).await {
+                error!("error attaching photo to carinfo {}, {}", id, e);
+            } else {
+                info!(
+                    "Attached {} to carinfo with id: {}, thumbnail {}",
+                    path,
+                    id,
+                    thumbnail.display()
+                );
+            }
+        }
+        Err(e) => error!("error importing photo {}, {}", path, e),
+    }
+}
+
+/// `thumbs <peer id> <car id>` fetches the thumbnails of a car from that peer's last listing.
+pub(crate) async fn handle_thumbnails(
+    peer: PeerId,
+    id: usize,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let carinfo = swarm
+        .behaviour()
+        .remote_carinfos
+        .get(&peer)
+        .and_then(|carinfos| carinfos.iter().find(|r| r.id == id));
+    let hashes: Vec<String> = match carinfo {
+        Some(carinfo) => carinfo.photos.iter().map(|p| p.thumbnail.clone()).collect(),
+        None => {
+            info!(
+                "car {} of {} not listed yet, run ls car {} first",
+                id, peer, peer
+            );
+            return;
+        }
+    };
+    let mut cached = 0;
+    let mut missing = Vec::new();
+    for hash in hashes {
+        match photos::thumbnail_path(&hash) {
+            Ok(path) if path.exists() => {
+                info!("Thumbnail {}", path.display());
+                cached += 1;
+            }
+            Ok(_) => missing.push(hash),
+            Err(e) => error!("skipping thumbnail {:?} of car {}, {}", hash, id, e),
+        }
+    }
+    if missing.is_empty() {
+        if cached == 0 {
+            info!("car {} has no photos", id);
+        }
+        return;
+    }
+    swarm.behaviour_mut().photo.send_request(
+        &peer,
+        PhotoQuery::Thumbnails(ThumbnailRequest { hashes: missing }),
+    );
+}
+
+/// `fetch photo [<peer id>] <car id>` downloads the photos of a listed car that aren't stored
+/// yet, continuing interrupted downloads.
+pub(crate) async fn handle_fetch_photo(
+    peer: Option<PeerId>,
+    id: usize,
+    swarm: &mut Swarm<CarinfoBehaviour>,
+) {
+    let behaviour = swarm.behaviour_mut();
+    let mut listed = behaviour
+        .remote_carinfos
+        .iter()
+        .filter(|(owner, _)| peer.is_none_or(|peer| **owner == peer))
+        .filter_map(|(owner, carinfos)| {
+            carinfos
+                .iter()
+                .find(|r| r.id == id)
+                .map(|carinfo| (*owner, carinfo.photos.clone()))
+        });
+    let (owner, car_photos) = match (listed.next(), listed.next()) {
+        (Some(car), None) => car,
+        (Some(_), Some(_)) => {
+            info!(
+                "several peers list a car {}, use fetch photo <peer id> {}",
+                id, id
+            );
+            return;
+        }
+        (None, _) => {
+            match peer {
+                Some(peer) => info!(
+                    "car {} of {} not listed yet, run ls car {} first",
+                    id, peer, peer
+                ),
+                None => info!("no peer listed a car {} yet, run ls car all first", id),
+            }
+            return;
+        }
+    };
+    if car_photos.is_empty() {
+        info!("car {} has no photos", id);
+        return;
+    }
+    for photo in car_photos {
+        let path = match photos::photo_path(&photo.hash) {
+            Ok(path) => path,
+            Err(e) => {
+                error!("skipping photo {}, {}", photo.file_name, e);
+                continue;
+            }
+        };
+        if path.exists() {
+            info!("Photo {}: {}", photo.file_name, path.display());
+            continue;
+        }
+        if behaviour
+            .downloads
+            .values()
+            .any(|download| download.photo.hash == photo.hash)
+        {
+            info!("Photo {} is already being fetched", photo.file_name);
+            continue;
+        }
+        let offset = photos::fetched_bytes(&photo.hash).await;
+        if offset > 0 {
+            info!(
+                "Resuming photo {} at {} of {} bytes",
+                photo.file_name, offset, photo.size
+            );
+        } else {
+            info!("Fetching photo {} ({} bytes)", photo.file_name, photo.size);
+        }
+        let request_id = behaviour.photo.send_request(
+            &owner,
+            PhotoQuery::Chunk(PhotoRequest {
+                hash: photo.hash.clone(),
+                offset,
+            }),
+        );
+        behaviour
+            .downloads
+            .insert(request_id, Download { car_id: id, photo });
+    }
+}
@@ -0,0 +1,277 @@
+use rust_car_p2p::{
+    config::{self, Flag},
+    init_storage, parser, profile, storage, Config,
+};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::daemon;
+
+const BIN_NAME: &str = env!("CARGO_PKG_NAME");
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+/// Startup subcommands, handled in `main` before anything else.
+const SUBCOMMANDS: &[&str] = &["ctl", "completions"];
+
+/// Flags `main` and the front ends read themselves rather than [`Config::load`]. Keep in sync
+/// with them.
+const FRONT_END_FLAGS: &[Flag] = &[
+    ("--profile", Some(&[])),
+    ("--output", Some(&["text", "json"])),
+    ("--daemon", None),
+    ("--tui", None),
+    ("--container", None),
+    ("--new-identity", None),
+];
+
+/// Returns the completion script for `shell`, or `None` if the shell isn't supported. The
+/// scripts ask `completions complete` for the candidates, see [`complete`], and fall back to
+/// file names when there are none.
+pub fn script(shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash()),
+        "zsh" => Some(zsh()),
+        "fish" => Some(fish()),
+        "powershell" => Some(powershell()),
+        _ => None,
+    }
+}
+
+/// `completions complete <n> <word>...`: the candidates for the word after the first `n`
+/// words of a command line, which starts with that word if it is given. Record ids are read
+/// from the storage the command line would use.
+pub async fn complete(args: &[String]) -> Vec<String> {
+    let words = args.get(1..).unwrap_or_default();
+    let done = match args.first().and_then(|n| n.parse().ok()) {
+        Some(n) if n <= words.len() => n,
+        _ => return Vec::new(),
+    };
+    let current = words.get(done).map_or("", String::as_str);
+    candidates(&words[..done])
+        .await
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(current))
+        .collect()
+}
+
+async fn candidates(done: &[String]) -> Vec<String> {
+    match done.first().map(String::as_str) {
+        Some("completions") if done.len() == 1 => strings(SHELLS),
+        Some("completions") => Vec::new(),
+        Some("ctl") => ctl(done).await,
+        _ => {
+            let flags = || config::FLAGS.iter().chain(FRONT_END_FLAGS);
+            let previous = done.last().map(String::as_str);
+            if let Some((_, Some(values))) = flags().find(|(name, _)| Some(*name) == previous) {
+                return strings(values);
+            }
+            let mut candidates: Vec<String> = flags().map(|(name, _)| name.to_string()).collect();
+            if done.is_empty() {
+                candidates.extend(strings(SUBCOMMANDS));
+            }
+            candidates
+        }
+    }
+}
+
+/// Candidates after `ctl`: its flags, then the commands of [`parser::COMMANDS`].
+async fn ctl(done: &[String]) -> Vec<String> {
+    let mut i = 1;
+    while done
+        .get(i)
+        .is_some_and(|word| daemon::FLAGS.contains(&word.as_str()))
+    {
+        i += 2;
+    }
+    match done.get(i..) {
+        // a flag's value
+        None => Vec::new(),
+        Some([]) => {
+            let mut candidates = strings(daemon::FLAGS);
+            candidates.extend(command(&[]));
+            candidates
+        }
+        Some(words) => {
+            let mut candidates = command(words);
+            if takes_id(words) {
+                candidates.extend(record_ids(done).await);
+            }
+            candidates
+        }
+    }
+}
+
+/// The words that can follow `words` in a command, from the usages of [`parser::COMMANDS`].
+fn command(words: &[String]) -> Vec<String> {
+    let mut candidates = BTreeSet::new();
+    for tokens in usages() {
+        if let Some(next) = next_token(&tokens, words).filter(|token| literal(token)) {
+            candidates.extend(next.split('|').map(str::to_owned));
+        }
+    }
+    candidates.into_iter().collect()
+}
+
+/// Whether a usage of [`parser::COMMANDS`] expects a record id after `words`.
+fn takes_id(words: &[String]) -> bool {
+    usages().any(|tokens| next_token(&tokens, words) == Some("<id>"))
+}
+
+/// The token of a usage after `words`, if they match its leading words.
+fn next_token<'a>(tokens: &[&'a str], words: &[String]) -> Option<&'a str> {
+    let matches = words.iter().zip(tokens).all(|(word, token)| {
+        literal(token) && token.split('|').any(|choice| choice == word.as_str())
+    });
+    tokens.get(words.len()).copied().filter(|_| matches)
+}
+
+/// Words typed as they are, like `car` or `on|off`, unlike placeholders and optional parts.
+fn literal(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '|')
+}
+
+/// The tokens of every alternative usage of every command. Alternatives are separated by ` | `
+/// outside brackets.
+fn usages() -> impl Iterator<Item = Vec<&'static str>> {
+    parser::COMMANDS.iter().flat_map(|spec| {
+        let mut alternatives = Vec::new();
+        let (mut depth, mut start) = (0usize, 0);
+        for (i, c) in spec.usage.char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth = depth.saturating_sub(1),
+                '|' if depth == 0 && spec.usage[i..].starts_with("| ") => {
+                    alternatives.push(spec.usage[start..i].split_whitespace().collect());
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        alternatives.push(spec.usage[start..].split_whitespace().collect());
+        alternatives
+    })
+}
+
+/// The ids of the local records, in the profile and storage `args` select. None if the storage
+/// can't be read, e.g. an encrypted one without its passphrase.
+async fn record_ids(args: &[String]) -> Vec<String> {
+    let mut args = args.to_vec();
+    if profile::select(&mut args).is_err() {
+        return Vec::new();
+    }
+    let opened = Config::load(&args).and_then(|config| init_storage(&config));
+    match opened {
+        // not read_local_carinfos, which would create an identity to fill in origins
+        Ok(()) => storage::storage()
+            .all()
+            .await
+            .map(|carinfos| carinfos.iter().map(|c| c.id.to_string()).collect())
+            .unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+fn function_name() -> String {
+    format!("_{}", BIN_NAME.replace('-', "_"))
+}
+
+fn bash() -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "{}() {{", function_name());
+    let _ = writeln!(s, "    local IFS=$'\\n'");
+    let _ = writeln!(
+        s,
+        "    COMPREPLY=($({} completions complete \"$((COMP_CWORD-1))\" \"${{COMP_WORDS[@]:1:COMP_CWORD}}\" 2>/dev/null))",
+        BIN_NAME
+    );
+    let _ = writeln!(s, "}}");
+    let _ = writeln!(s, "complete -o default -F {} {}", function_name(), BIN_NAME);
+    s
+}
+
+fn zsh() -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "#compdef {}", BIN_NAME);
+    let _ = writeln!(s, "{}() {{", function_name());
+    let _ = writeln!(s, "    local -a candidates");
+    let _ = writeln!(
+        s,
+        "    candidates=(${{(f)\"$({} completions complete $((CURRENT-2)) \"${{(@)words[2,CURRENT]}}\" 2>/dev/null)\"}})",
+        BIN_NAME
+    );
+    let _ = writeln!(s, "    if (( ${{#candidates}} )); then");
+    let _ = writeln!(s, "        compadd -a candidates");
+    let _ = writeln!(s, "    else");
+    let _ = writeln!(s, "        _files");
+    let _ = writeln!(s, "    fi");
+    let _ = writeln!(s, "}}");
+    let _ = writeln!(s, "compdef {} {}", function_name(), BIN_NAME);
+    s
+}
+
+fn fish() -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "function _{}_complete", function_name());
+    let _ = writeln!(s, "    set -l words (commandline -opc)");
+    let _ = writeln!(s, "    set -e words[1]");
+    let _ = writeln!(s, "    set -l current (commandline -ct)");
+    let _ = writeln!(
+        s,
+        "    set -l candidates ({} completions complete (count $words) $words \"$current\" 2>/dev/null)",
+        BIN_NAME
+    );
+    let _ = writeln!(s, "    if set -q candidates[1]");
+    let _ = writeln!(s, "        printf '%s\\n' $candidates");
+    let _ = writeln!(s, "    else");
+    let _ = writeln!(s, "        __fish_complete_path \"$current\"");
+    let _ = writeln!(s, "    end");
+    let _ = writeln!(s, "end");
+    let _ = writeln!(
+        s,
+        "complete -c {} -f -a \"(_{}_complete)\"",
+        BIN_NAME,
+        function_name()
+    );
+    s
+}
+
+fn powershell() -> String {
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        "Register-ArgumentCompleter -Native -CommandName '{}' -ScriptBlock {{",
+        BIN_NAME
+    );
+    let _ = writeln!(
+        s,
+        "    param($wordToComplete, $commandAst, $cursorPosition)"
+    );
+    let _ = writeln!(
+        s,
+        "    $words = @($commandAst.CommandElements | Select-Object -Skip 1 | ForEach-Object {{ $_.ToString() }})"
+    );
+    let _ = writeln!(
+        s,
+        "    $done = if ($wordToComplete) {{ $words.Count - 1 }} else {{ $words.Count }}"
+    );
+    let _ = writeln!(
+        s,
+        "    & '{}' completions complete $done @words 2>$null | ForEach-Object {{",
+        BIN_NAME
+    );
+    let _ = writeln!(
+        s,
+        "        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)"
+    );
+    let _ = writeln!(s, "    }}");
+    let _ = writeln!(s, "}}");
+    s
+}
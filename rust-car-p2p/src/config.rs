@@ -0,0 +1,558 @@
+use libp2p::{gossipsub::IdentTopic, multiaddr::Protocol, pnet::PreSharedKey, Multiaddr, PeerId};
+use log::LevelFilter;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs, io, slice};
+
+use crate::{
+    backup, cache, channels, dedupe::DuplicateKey, dht, ratelimit, replica::ConflictPolicy, round,
+    Result,
+};
+
+const DEFAULT_TOPIC: &str = "carinfos";
+const DEFAULT_LISTEN: &str = "/ip4/0.0.0.0/tcp/0";
+const CONFIG_FILE_PATH: &str = "./config.toml";
+const DEFAULT_CONTROL_SOCKET: &str = "./carinfo.sock";
+
+/// A command line flag and the values it takes: `None` for switches, no values for flags that
+/// take any value, such as paths.
+pub type Flag = (&'static str, Option<&'static [&'static str]>);
+
+/// The flags [`Config::load`] reads, for shell completion. Keep in sync with it.
+pub const FLAGS: &[Flag] = &[
+    ("--config", Some(&[])),
+    ("--storage", Some(&[])),
+    ("--topic", Some(&[])),
+    ("--channel", Some(&[])),
+    ("--listen", Some(&[])),
+    ("--ws-listen", Some(&[])),
+    ("--http", Some(&[])),
+    ("--grpc", Some(&[])),
+    ("--no-mdns", None),
+    ("--headless", None),
+    ("--log-file", Some(&[])),
+    ("--cache-ttl", Some(&[])),
+    ("--list-window", Some(&[])),
+    ("--swarm-key", Some(&[])),
+    (
+        "--conflict-policy",
+        Some(&["newest-wins", "origin-wins", "manual"]),
+    ),
+    ("--control-socket", Some(&[])),
+    ("--backup-interval", Some(&[])),
+    ("--backup-keep", Some(&[])),
+    ("--bootstrap", Some(&[])),
+    ("--relay", Some(&[])),
+];
+
+/// Node settings resolved at startup, so several nodes can share a machine or keep to their own
+/// network.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Where local records are kept, `None` for the storage backend's default file.
+    pub storage_path: Option<PathBuf>,
+    /// Name of the catalog topic. Nodes only see each other's listings if it matches.
+    pub topic: String,
+    /// Channels to join at startup, see [`channels`].
+    pub channels: Vec<String>,
+    pub listen: Multiaddr,
+    /// Second, WebSocket listen address for browser peers, e.g. `/ip4/0.0.0.0/tcp/4002/ws`.
+    pub ws_listen: Option<Multiaddr>,
+    /// Where to serve the REST API, if at all.
+    pub http: Option<SocketAddr>,
+    /// Where to serve the gRPC API, if at all.
+    pub grpc: Option<SocketAddr>,
+    /// Nodes to dial at startup and to join the DHT through.
+    pub bootstrap: Vec<(PeerId, Multiaddr)>,
+    /// Circuit relays to listen through, for nodes peers can't dial directly.
+    pub relays: Vec<(PeerId, Multiaddr)>,
+    /// Whether to find peers on the local network with mDNS.
+    pub mdns: bool,
+    /// Runs without reading the terminal, taking commands only over the REST and gRPC APIs,
+    /// e.g. as a systemd service.
+    pub headless: bool,
+    /// `RUST_LOG` style filter used when `RUST_LOG` isn't set, e.g. `info` or
+    /// `info,libp2p_gossipsub=debug`.
+    pub log_level: Option<String>,
+    /// Where to write JSON logs, rotated daily, instead of mixing them with command output.
+    pub log_file: Option<PathBuf>,
+    /// How long `ls car` answers from a peer's cached listing instead of asking it again.
+    pub cache_ttl: Duration,
+    /// How long `ls car all` waits for answers before showing how many peers answered.
+    pub list_window: Duration,
+    /// List requests each peer may make a minute, 0 for no limit, and how many at once.
+    pub requests_per_minute: u32,
+    pub request_burst: u32,
+    /// Pre-shared key of a private network. Only nodes with the same key can connect.
+    pub swarm_key: Option<PreSharedKey>,
+    /// What makes `create car` refuse a car as a duplicate and `dedupe` group records.
+    pub duplicate_key: DuplicateKey,
+    /// What `sync` does when a peer's copy of a record disagrees with ours.
+    pub conflict_policy: ConflictPolicy,
+    /// Unix socket a daemon takes commands on, and `ctl` sends them to.
+    pub control_socket: PathBuf,
+    /// How often to make a backup, see [`backup`], if at all.
+    pub backup_interval: Option<Duration>,
+    /// How many automatic backups to keep; older ones are deleted.
+    pub backup_keep: usize,
+    /// The configuration file settings were read from, `./config.toml` or `--config <path>`,
+    /// whether it exists or not. Backups include it.
+    pub config_file: PathBuf,
+}
+
+/// The keys `config.toml` may set, all optional. Values are checked like the matching
+/// environment variables.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    storage: Option<PathBuf>,
+    topic: Option<String>,
+    #[serde(default)]
+    channels: Vec<String>,
+    listen: Option<String>,
+    ws_listen: Option<String>,
+    http: Option<String>,
+    grpc: Option<String>,
+    #[serde(default)]
+    bootstrap: Vec<String>,
+    #[serde(default)]
+    relays: Vec<String>,
+    mdns: Option<bool>,
+    headless: Option<bool>,
+    log_level: Option<String>,
+    log_file: Option<PathBuf>,
+    /// Seconds.
+    cache_ttl: Option<u64>,
+    /// Seconds.
+    list_window: Option<u64>,
+    requests_per_minute: Option<u32>,
+    request_burst: Option<u32>,
+    /// Path of the swarm key file.
+    swarm_key: Option<PathBuf>,
+    duplicate_key: Option<String>,
+    conflict_policy: Option<String>,
+    control_socket: Option<PathBuf>,
+    /// Seconds, 0 for none.
+    backup_interval: Option<u64>,
+    backup_keep: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            storage_path: None,
+            topic: DEFAULT_TOPIC.to_owned(),
+            channels: Vec::new(),
+            listen: DEFAULT_LISTEN
+                .parse()
+                .expect("valid default listen address"),
+            ws_listen: None,
+            http: None,
+            grpc: None,
+            bootstrap: Vec::new(),
+            relays: Vec::new(),
+            mdns: true,
+            headless: false,
+            log_level: None,
+            log_file: None,
+            cache_ttl: cache::DEFAULT_TTL,
+            list_window: round::DEFAULT_WINDOW,
+            requests_per_minute: ratelimit::DEFAULT_PER_MINUTE,
+            request_burst: ratelimit::DEFAULT_BURST,
+            swarm_key: None,
+            duplicate_key: DuplicateKey::default(),
+            conflict_policy: ConflictPolicy::default(),
+            control_socket: PathBuf::from(DEFAULT_CONTROL_SOCKET),
+            backup_interval: None,
+            backup_keep: backup::DEFAULT_KEEP,
+            config_file: PathBuf::from(CONFIG_FILE_PATH),
+        }
+    }
+}
+
+impl Config {
+    /// Starts from `./config.toml`, or the file given with `--config <path>`, then reads
+    /// `CARINFO_STORAGE_PATH`, `CARINFO_TOPIC`, `CARINFO_CHANNELS`, `CARINFO_LISTEN`,
+    /// `CARINFO_WS_LISTEN`, `CARINFO_API`, `CARINFO_GRPC`, `CARINFO_BOOTSTRAP`, `CARINFO_RELAY`,
+    /// `CARINFO_MDNS`, `CARINFO_HEADLESS`, `CARINFO_LOG_FILE`, `CARINFO_CACHE_TTL`,
+    /// `CARINFO_LIST_WINDOW`, `CARINFO_SWARM_KEY`, `CARINFO_CONFLICT_POLICY`,
+    /// `CARINFO_CONTROL_SOCKET`, `CARINFO_BACKUP_INTERVAL` and `CARINFO_BACKUP_KEEP`, then lets
+    /// `--storage <path>`, `--topic <name>`, `--listen <multiaddr>`, `--ws-listen <multiaddr>`,
+    /// `--http <addr>`, `--grpc <addr>`, `--no-mdns`, `--headless`, `--log-file <path>`,
+    /// `--cache-ttl <seconds>`, `--list-window <seconds>`, `--swarm-key <path>`,
+    /// `--conflict-policy <policy>`, `--control-socket <path>`, `--backup-interval <seconds>`
+    /// and `--backup-keep <n>` in `args` override them. Every
+    /// `--bootstrap <multiaddr>` and `--relay <multiaddr>` adds a node, and every
+    /// `--channel <name>` a channel.
+    pub fn load(args: &[String]) -> Result<Config> {
+        let mut config = Config::default();
+        let path = args
+            .iter()
+            .position(|a| a == "--config")
+            .map(|i| {
+                args.get(i + 1)
+                    .map(PathBuf::from)
+                    .ok_or_else(|| "--config needs a value".to_owned())
+            })
+            .transpose()?;
+        match path {
+            Some(path) => {
+                config.read_file(&path, true)?;
+                config.config_file = path;
+            }
+            None => config.read_file(Path::new(CONFIG_FILE_PATH), false)?,
+        }
+        if let Some(path) = non_empty_var("CARINFO_STORAGE_PATH") {
+            config.storage_path = Some(path.into());
+        }
+        if let Some(topic) = non_empty_var("CARINFO_TOPIC") {
+            config.topic = topic;
+        }
+        if let Some(names) = non_empty_var("CARINFO_CHANNELS") {
+            config.channels = names
+                .split(',')
+                .filter(|name| !name.trim().is_empty())
+                .map(channels::normalize)
+                .collect::<std::result::Result<_, _>>()?;
+        }
+        if let Some(listen) = non_empty_var("CARINFO_LISTEN") {
+            config.listen = parse_listen(&listen)?;
+        }
+        if let Some(listen) = non_empty_var("CARINFO_WS_LISTEN") {
+            config.ws_listen = Some(parse_ws_listen(&listen)?);
+        }
+        if let Some(http) = non_empty_var("CARINFO_API") {
+            config.http = Some(parse_http(&http)?);
+        }
+        if let Some(grpc) = non_empty_var("CARINFO_GRPC") {
+            config.grpc = Some(parse_http(&grpc)?);
+        }
+        if let Some(nodes) = non_empty_var("CARINFO_BOOTSTRAP") {
+            config.bootstrap = parse_nodes(&nodes, "bootstrap")?;
+        }
+        if let Some(nodes) = non_empty_var("CARINFO_RELAY") {
+            config.relays = parse_nodes(&nodes, "relay")?;
+        }
+        if let Some(mdns) = non_empty_var("CARINFO_MDNS") {
+            config.mdns = parse_switch(&mdns, "CARINFO_MDNS")?;
+        }
+        if let Some(headless) = non_empty_var("CARINFO_HEADLESS") {
+            config.headless = parse_switch(&headless, "CARINFO_HEADLESS")?;
+        }
+        if let Some(path) = non_empty_var("CARINFO_LOG_FILE") {
+            config.log_file = Some(path.into());
+        }
+        if let Some(ttl) = non_empty_var("CARINFO_CACHE_TTL") {
+            config.cache_ttl = parse_ttl(&ttl)?;
+        }
+        if let Some(window) = non_empty_var("CARINFO_LIST_WINDOW") {
+            config.list_window = parse_window(&window)?;
+        }
+        if let Some(path) = non_empty_var("CARINFO_SWARM_KEY") {
+            config.swarm_key = Some(read_swarm_key(Path::new(&path))?);
+        }
+        if let Some(policy) = non_empty_var("CARINFO_CONFLICT_POLICY") {
+            config.conflict_policy = policy.parse()?;
+        }
+        if let Some(path) = non_empty_var("CARINFO_CONTROL_SOCKET") {
+            config.control_socket = path.into();
+        }
+        if let Some(secs) = non_empty_var("CARINFO_BACKUP_INTERVAL") {
+            config.backup_interval = parse_backup_interval(&secs)?;
+        }
+        if let Some(keep) = non_empty_var("CARINFO_BACKUP_KEEP") {
+            config.backup_keep = parse_backup_keep(&keep)?;
+        }
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--storage" => config.storage_path = Some(flag_value(&mut args, arg)?.into()),
+                "--topic" => config.topic = flag_value(&mut args, arg)?,
+                "--channel" => config
+                    .channels
+                    .push(channels::normalize(&flag_value(&mut args, arg)?)?),
+                "--listen" => config.listen = parse_listen(&flag_value(&mut args, arg)?)?,
+                "--ws-listen" => {
+                    config.ws_listen = Some(parse_ws_listen(&flag_value(&mut args, arg)?)?)
+                }
+                "--http" => config.http = Some(parse_http(&flag_value(&mut args, arg)?)?),
+                "--grpc" => config.grpc = Some(parse_http(&flag_value(&mut args, arg)?)?),
+                "--no-mdns" => config.mdns = false,
+                "--headless" => config.headless = true,
+                "--log-file" => config.log_file = Some(flag_value(&mut args, arg)?.into()),
+                "--cache-ttl" => config.cache_ttl = parse_ttl(&flag_value(&mut args, arg)?)?,
+                "--list-window" => config.list_window = parse_window(&flag_value(&mut args, arg)?)?,
+                "--swarm-key" => {
+                    let path = flag_value(&mut args, arg)?;
+                    config.swarm_key = Some(read_swarm_key(Path::new(&path))?);
+                }
+                "--conflict-policy" => {
+                    config.conflict_policy = flag_value(&mut args, arg)?.parse()?
+                }
+                "--control-socket" => config.control_socket = flag_value(&mut args, arg)?.into(),
+                "--backup-interval" => {
+                    config.backup_interval = parse_backup_interval(&flag_value(&mut args, arg)?)?
+                }
+                "--backup-keep" => {
+                    config.backup_keep = parse_backup_keep(&flag_value(&mut args, arg)?)?
+                }
+                "--bootstrap" => config
+                    .bootstrap
+                    .push(parse_node(&flag_value(&mut args, arg)?, "bootstrap")?),
+                "--relay" => config
+                    .relays
+                    .push(parse_node(&flag_value(&mut args, arg)?, "relay")?),
+                _ => {}
+            }
+        }
+        if config.topic.trim().is_empty() {
+            return Err("the topic name can't be empty".into());
+        }
+        Ok(config)
+    }
+
+    /// Applies the settings in the TOML file at `path`. A missing file is only an error if it
+    /// was asked for. Errors name the file and the key.
+    fn read_file(&mut self, path: &Path, required: bool) -> Result<()> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(()),
+            Err(e) => return Err(format!("can't read {}, {}", path.display(), e).into()),
+        };
+        self.apply_file(path, &content)
+    }
+
+    /// Checks that `content` would do as the configuration file at `path`, e.g. one about to
+    /// be restored from a backup.
+    pub fn check_file(path: &Path, content: &str) -> Result<()> {
+        Config::default().apply_file(path, content)
+    }
+
+    fn apply_file(&mut self, path: &Path, content: &str) -> Result<()> {
+        let file: ConfigFile =
+            toml::from_str(content).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let at = |key: &'static str| move |e| invalid_key(path, key, e);
+        if let Some(storage) = file.storage {
+            self.storage_path = Some(storage);
+        }
+        if let Some(topic) = file.topic {
+            self.topic = topic;
+        }
+        for name in file.channels {
+            let name = channels::normalize(&name).map_err(|e| at("channels")(e.into()))?;
+            self.channels.push(name);
+        }
+        if let Some(listen) = file.listen {
+            self.listen = parse_listen(&listen).map_err(at("listen"))?;
+        }
+        if let Some(listen) = file.ws_listen {
+            self.ws_listen = Some(parse_ws_listen(&listen).map_err(at("ws_listen"))?);
+        }
+        if let Some(http) = file.http {
+            self.http = Some(parse_http(&http).map_err(at("http"))?);
+        }
+        if let Some(grpc) = file.grpc {
+            self.grpc = Some(parse_http(&grpc).map_err(at("grpc"))?);
+        }
+        for node in file.bootstrap {
+            let node = parse_node(&node, "bootstrap").map_err(at("bootstrap"))?;
+            self.bootstrap.push(node);
+        }
+        for node in file.relays {
+            let node = parse_node(&node, "relay").map_err(at("relays"))?;
+            self.relays.push(node);
+        }
+        if let Some(mdns) = file.mdns {
+            self.mdns = mdns;
+        }
+        if let Some(headless) = file.headless {
+            self.headless = headless;
+        }
+        if let Some(level) = file.log_level {
+            check_log_level(&level).map_err(at("log_level"))?;
+            self.log_level = Some(level);
+        }
+        if let Some(path) = file.log_file {
+            self.log_file = Some(path);
+        }
+        if let Some(secs) = file.cache_ttl {
+            self.cache_ttl = Duration::from_secs(secs);
+        }
+        if let Some(secs) = file.list_window {
+            self.list_window = parse_window(&secs.to_string()).map_err(at("list_window"))?;
+        }
+        if let Some(rate) = file.requests_per_minute {
+            self.requests_per_minute = rate;
+        }
+        if let Some(burst) = file.request_burst {
+            if burst == 0 {
+                return Err(
+                    invalid_key(path, "request_burst", "it must be at least 1".into()).into(),
+                );
+            }
+            self.request_burst = burst;
+        }
+        if let Some(key) = file.duplicate_key {
+            self.duplicate_key = key
+                .parse()
+                .map_err(|e: String| at("duplicate_key")(e.into()))?;
+        }
+        if let Some(policy) = file.conflict_policy {
+            self.conflict_policy = policy
+                .parse()
+                .map_err(|e: String| at("conflict_policy")(e.into()))?;
+        }
+        if let Some(key) = file.swarm_key {
+            self.swarm_key = Some(read_swarm_key(&key).map_err(at("swarm_key"))?);
+        }
+        if let Some(path) = file.control_socket {
+            self.control_socket = path;
+        }
+        if let Some(secs) = file.backup_interval {
+            self.backup_interval = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        if let Some(keep) = file.backup_keep {
+            self.backup_keep = parse_backup_keep(&keep.to_string()).map_err(at("backup_keep"))?;
+        }
+        if self.topic.trim().is_empty() {
+            return Err(invalid_key(path, "topic", "it can't be empty".into()).into());
+        }
+        Ok(())
+    }
+
+    /// The catalog topic to subscribe to and announce on.
+    pub fn topic(&self) -> IdentTopic {
+        IdentTopic::new(self.topic.clone())
+    }
+}
+
+fn non_empty_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn flag_value(args: &mut slice::Iter<String>, flag: &str) -> Result<String> {
+    args.next()
+        .cloned()
+        .ok_or_else(|| format!("{} needs a value", flag).into())
+}
+
+fn parse_listen(addr: &str) -> Result<Multiaddr> {
+    let addr: Multiaddr = addr
+        .parse()
+        .map_err(|e| format!("invalid listen address {}, {}", addr, e))?;
+    // libp2p 0.39 has no QUIC transport
+    if addr.iter().any(|p| matches!(p, Protocol::Quic)) {
+        return Err(format!(
+            "can't listen on {}, QUIC isn't supported; use a /tcp address",
+            addr
+        )
+        .into());
+    }
+    Ok(addr)
+}
+
+fn parse_ws_listen(addr: &str) -> Result<Multiaddr> {
+    let addr = parse_listen(addr)?;
+    match addr.iter().last() {
+        Some(Protocol::Ws(_)) => Ok(addr),
+        _ => Err(format!("websocket listen address {} must end in /ws", addr).into()),
+    }
+}
+
+/// Comma separated `<multiaddr>/p2p/<peer id>` entries.
+fn parse_nodes(nodes: &str, kind: &str) -> Result<Vec<(PeerId, Multiaddr)>> {
+    nodes
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|node| parse_node(node, kind))
+        .collect()
+}
+
+fn parse_node(node: &str, kind: &str) -> Result<(PeerId, Multiaddr)> {
+    dht::parse_node(node).ok_or_else(|| {
+        format!(
+            "invalid {} node {}, expected <multiaddr>/p2p/<peer id>",
+            kind, node
+        )
+        .into()
+    })
+}
+
+fn invalid_key(path: &Path, key: &str, e: Box<dyn std::error::Error + Send + Sync>) -> String {
+    format!("{}: invalid `{}`, {}", path.display(), key, e)
+}
+
+/// Accepts what `RUST_LOG` does: comma separated levels, each optionally after `<target>=`.
+fn check_log_level(filter: &str) -> Result<()> {
+    for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let level = directive.rsplit('=').next().unwrap_or(directive);
+        if level.parse::<LevelFilter>().is_err() {
+            return Err(format!(
+                "unknown level {}, expected off, error, warn, info, debug or trace",
+                level
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Reads a key in the `swarm.key` format of IPFS private networks:
+/// `/key/swarm/psk/1.0.0/`, `/base16/` and 64 hex digits on three lines.
+fn read_swarm_key(path: &Path) -> Result<PreSharedKey> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("can't read swarm key {}, {}", path.display(), e))?;
+    content
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid swarm key {}, {}", path.display(), e).into())
+}
+
+fn parse_ttl(secs: &str) -> Result<Duration> {
+    secs.parse()
+        .map(Duration::from_secs)
+        .map_err(|e| format!("invalid cache ttl {}, expected seconds, {}", secs, e).into())
+}
+
+fn parse_window(secs: &str) -> Result<Duration> {
+    match secs.parse() {
+        Ok(0) => Err("the list window must be at least 1 second".into()),
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(e) => Err(format!("invalid list window {}, expected seconds, {}", secs, e).into()),
+    }
+}
+
+/// Seconds between automatic backups, 0 for none.
+fn parse_backup_interval(secs: &str) -> Result<Option<Duration>> {
+    match secs.parse() {
+        Ok(0) => Ok(None),
+        Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+        Err(e) => Err(format!("invalid backup interval {}, expected seconds, {}", secs, e).into()),
+    }
+}
+
+fn parse_backup_keep(keep: &str) -> Result<usize> {
+    match keep.parse() {
+        Ok(0) => Err("at least 1 backup must be kept".into()),
+        Ok(keep) => Ok(keep),
+        Err(e) => Err(format!("invalid backup count {}, {}", keep, e).into()),
+    }
+}
+
+/// `1`, `true` or `on`, and `0`, `false` or `off`.
+fn parse_switch(value: &str, name: &str) -> Result<bool> {
+    match value {
+        "1" | "true" | "on" => Ok(true),
+        "0" | "false" | "off" => Ok(false),
+        _ => Err(format!("invalid {} {}, expected 1 or 0", name, value).into()),
+    }
+}
+
+fn parse_http(addr: &str) -> Result<SocketAddr> {
+    addr.parse()
+        .map_err(|e| format!("invalid http address {}, {}", addr, e).into())
+}
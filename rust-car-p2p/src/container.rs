@@ -0,0 +1,79 @@
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use log::{error, info};
+use rust_car_p2p::health::Health;
+use serde_json::json;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_HTTP_ADDR: &str = "0.0.0.0:8080";
+
+/// Whether `--container` was passed or `CARINFO_CONTAINER` is set.
+pub fn enabled(args: &[String]) -> bool {
+    args.iter().any(|a| a == "--container")
+        || std::env::var("CARINFO_CONTAINER").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Logs one JSON object per line to stdout, honouring `RUST_LOG` (default `info`).
+pub fn init_json_logger() {
+    let mut builder = pretty_env_logger::formatted_builder();
+    builder
+        .target(env_logger::Target::Stdout)
+        .format(|buf, record| {
+            let ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or_default();
+            let line = json!({
+                "ts": ts,
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "msg": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        })
+        .parse_filters(&std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_owned()));
+    builder.init();
+}
+
+/// Serves `/healthz` and `/metrics` on `CARINFO_HTTP` (default `0.0.0.0:8080`).
+pub async fn serve(health: Arc<Health>) {
+    let addr = std::env::var("CARINFO_HTTP").unwrap_or_else(|_| DEFAULT_HTTP_ADDR.to_owned());
+    let addr: SocketAddr = match addr.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("invalid CARINFO_HTTP address {}, {}", addr, e);
+            return;
+        }
+    };
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(metrics))
+        .with_state(health);
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("error binding http server to {}, {}", addr, e);
+            return;
+        }
+    };
+    info!("Serving /healthz and /metrics on {}", addr);
+    if let Err(e) = axum::serve(listener, app).await {
+        error!("http server failed, {}", e);
+    }
+}
+
+async fn healthz(State(health): State<Arc<Health>>) -> (StatusCode, &'static str) {
+    if health.listening.load(Ordering::Relaxed) {
+        (StatusCode::OK, "ok\n")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not listening\n")
+    }
+}
+
+/// Prometheus text exposition of the node counters.
+async fn metrics(State(health): State<Arc<Health>>) -> String {
+    rust_car_p2p::metrics::render(&health).await
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// One command sent to a daemon by `ctl`, as a JSON line.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ControlRequest {
+    /// The command as it would be typed at the prompt.
+    pub line: String,
+    /// How long to keep sending output once the command returned, for answers from peers.
+    #[serde(default)]
+    pub wait_secs: u64,
+}
+
+/// A line the node printed while a control command ran, sent back as a JSON line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlOutput {
+    pub level: String,
+    pub message: String,
+}
+
+/// A command passed on to the event loop, since running it needs the swarm. `done` is sent
+/// once it returned.
+pub struct ControlCommand {
+    pub line: String,
+    pub done: oneshot::Sender<()>,
+}
+
+/// What the node prints, copied to every control connection waiting for a command's output.
+#[derive(Clone, Default)]
+pub struct Output(Arc<Mutex<Vec<mpsc::UnboundedSender<ControlOutput>>>>);
+
+impl Output {
+    pub fn send(&self, level: &str, message: String) {
+        let mut subscribers = self.0.lock().expect("output lock");
+        if subscribers.is_empty() {
+            return;
+        }
+        let output = ControlOutput {
+            level: level.to_owned(),
+            message,
+        };
+        subscribers.retain(|s| s.send(output.clone()).is_ok());
+    }
+
+    /// Receives what the node prints from now on, until the receiver is dropped.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<ControlOutput> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.0.lock().expect("output lock").push(sender);
+        receiver
+    }
+}
+
+/// Longest wait a request may ask for.
+const MAX_WAIT: Duration = Duration::from_secs(300);
+
+#[cfg(unix)]
+pub use unix::{request, serve};
+
+#[cfg(unix)]
+mod unix {
+    use log::{error, info};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{unix::OwnedWriteHalf, UnixListener, UnixStream};
+    use tokio::sync::{mpsc, oneshot};
+
+    use super::{ControlCommand, ControlOutput, ControlRequest, Output, MAX_WAIT};
+    use crate::Result;
+
+    /// Serves control connections on the Unix socket at `path`, readable by this user only,
+    /// passing their commands to `commands` and sending back what the node prints meanwhile.
+    /// A socket left behind by a node that crashed is replaced, one in use is an error.
+    pub async fn serve(
+        path: PathBuf,
+        commands: mpsc::UnboundedSender<ControlCommand>,
+        output: Output,
+    ) {
+        if path.exists() {
+            if UnixStream::connect(&path).await.is_ok() {
+                error!(
+                    "error serving control socket, another node serves {} already",
+                    path.display()
+                );
+                return;
+            }
+            let _ = std::fs::remove_file(&path);
+        }
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("error binding control socket {}, {}", path.display(), e);
+                return;
+            }
+        };
+        if let Err(e) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)) {
+            error!("error restricting control socket {}, {}", path.display(), e);
+            return;
+        }
+        info!("Serving the control socket on {}", path.display());
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle(stream, commands.clone(), output.clone()));
+                }
+                Err(e) => error!("error accepting control connection, {}", e),
+            }
+        }
+    }
+
+    async fn handle(
+        stream: UnixStream,
+        commands: mpsc::UnboundedSender<ControlCommand>,
+        output: Output,
+    ) {
+        let (reader, mut writer) = stream.into_split();
+        let request = match BufReader::new(reader).lines().next_line().await {
+            Ok(Some(line)) => match serde_json::from_str::<ControlRequest>(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    error!("error reading control request, {}", e);
+                    return;
+                }
+            },
+            Ok(None) => return,
+            Err(e) => {
+                error!("error reading control request, {}", e);
+                return;
+            }
+        };
+        let mut lines = output.subscribe();
+        let (done, mut finished) = oneshot::channel();
+        if commands
+            .send(ControlCommand {
+                line: request.line,
+                done,
+            })
+            .is_err()
+        {
+            return;
+        }
+        // the command's output, then what the node prints during the wait asked for
+        loop {
+            tokio::select! {
+                Some(line) = lines.recv() => {
+                    if !send(&mut writer, &line).await {
+                        return;
+                    }
+                }
+                _ = &mut finished => break,
+            }
+        }
+        let deadline = tokio::time::sleep(Duration::from_secs(request.wait_secs).min(MAX_WAIT));
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                Some(line) = lines.recv() => {
+                    if !send(&mut writer, &line).await {
+                        return;
+                    }
+                }
+                _ = &mut deadline => break,
+            }
+        }
+        while let Ok(line) = lines.try_recv() {
+            if !send(&mut writer, &line).await {
+                return;
+            }
+        }
+    }
+
+    /// Writes `line` as a JSON line, returning false once the client went away.
+    async fn send(writer: &mut OwnedWriteHalf, line: &ControlOutput) -> bool {
+        let mut json = serde_json::to_vec(line).expect("output serializes");
+        json.push(b'\n');
+        writer.write_all(&json).await.is_ok()
+    }
+
+    /// Sends `request` to the daemon serving `path` and passes each line of its output to
+    /// `print` until the daemon closes the connection.
+    pub async fn request(
+        path: &Path,
+        request: &ControlRequest,
+        mut print: impl FnMut(ControlOutput),
+    ) -> Result<()> {
+        let stream = UnixStream::connect(path)
+            .await
+            .map_err(|e| format!("can't reach a node at {}, {}", path.display(), e))?;
+        let (reader, mut writer) = stream.into_split();
+        let mut json = serde_json::to_vec(request)?;
+        json.push(b'\n');
+        writer.write_all(&json).await?;
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            print(serde_json::from_str(&line)?);
+        }
+        Ok(())
+    }
+}
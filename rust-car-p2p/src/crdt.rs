@@ -0,0 +1,275 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Carinfo, PEER_ID};
+
+/// Hybrid logical clock time of a change: wall-clock milliseconds, a counter for changes
+/// within the same millisecond or behind a peer's clock, and the node that made it, which
+/// breaks ties. Stamps order every change the same way on every node.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Stamp {
+    pub millis: u64,
+    pub counter: u32,
+    pub node: String,
+}
+
+/// The fields of a record that are merged one by one. Records written before stamps existed
+/// have none, and lose against any stamped change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Make,
+    Model,
+    Horsepower,
+    Public,
+    Location,
+    Tags,
+}
+
+impl Field {
+    pub const ALL: [Field; 6] = [
+        Field::Make,
+        Field::Model,
+        Field::Horsepower,
+        Field::Public,
+        Field::Location,
+        Field::Tags,
+    ];
+
+    fn copy(self, from: &Carinfo, to: &mut Carinfo) {
+        match self {
+            Field::Make => to.make = from.make.clone(),
+            Field::Model => to.model = from.model.clone(),
+            Field::Horsepower => to.horsepower = from.horsepower,
+            Field::Public => to.public = from.public,
+            Field::Location => to.location = from.location.clone(),
+            Field::Tags => to.tags = from.tags.clone(),
+        }
+    }
+}
+
+/// When each field of a record was last changed, making every field a last-writer-wins
+/// register.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stamps(BTreeMap<Field, Stamp>);
+
+impl Stamps {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, field: Field) -> Option<&Stamp> {
+        self.0.get(&field)
+    }
+
+    /// Records a local change of `fields`, all at the same time.
+    pub fn touch(&mut self, fields: &[Field]) {
+        let stamp = tick();
+        for field in fields {
+            self.0.insert(*field, stamp.clone());
+        }
+    }
+
+    /// The latest change to any field.
+    pub fn latest(&self) -> Option<&Stamp> {
+        self.0.values().max()
+    }
+}
+
+#[derive(Default)]
+struct Clock {
+    millis: u64,
+    counter: u32,
+}
+
+/// How far ahead of our wall clock a peer's stamp may be and still move our clock; stamps
+/// further ahead are ignored, so one peer can't push every later change into the future.
+const MAX_DRIFT_MILLIS: u64 = 60 * 1000;
+
+static CLOCK: Lazy<Mutex<Clock>> = Lazy::new(Default::default);
+
+fn wall_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A stamp for a local change, later than any stamp made or seen before.
+pub fn tick() -> Stamp {
+    let mut clock = CLOCK.lock().expect("clock lock");
+    let now = wall_millis();
+    if now > clock.millis {
+        clock.millis = now;
+        clock.counter = 0;
+    } else {
+        clock.counter = clock.counter.saturating_add(1);
+    }
+    Stamp {
+        millis: clock.millis,
+        counter: clock.counter,
+        node: PEER_ID.to_string(),
+    }
+}
+
+/// Moves the clock past a stamp received from a peer, so our next changes order after it even
+/// if the peer's wall clock is ahead of ours, by at most [`MAX_DRIFT_MILLIS`].
+pub fn observe(stamp: &Stamp) {
+    let mut clock = CLOCK.lock().expect("clock lock");
+    let now = wall_millis();
+    if stamp.millis > now.saturating_add(MAX_DRIFT_MILLIS) {
+        return;
+    }
+    let millis = now.max(clock.millis).max(stamp.millis);
+    clock.counter = match (millis == clock.millis, millis == stamp.millis) {
+        (true, true) => clock.counter.max(stamp.counter).saturating_add(1),
+        (true, false) => clock.counter.saturating_add(1),
+        (false, true) => stamp.counter.saturating_add(1),
+        (false, false) => 0,
+    };
+    clock.millis = millis;
+}
+
+/// Merges another copy of the same record into `local`. Every field takes the value with the
+/// later stamp, the fields that aren't stamped, like photos and OBD-II data, come from the copy
+/// with the latest change. Between equal stamps `other` wins, so unstamped copies from older
+/// peers replace what we had. For stamped copies the result doesn't depend on the order they
+/// are merged in.
+pub fn merge(local: &mut Carinfo, other: &Carinfo) {
+    for stamp in other.stamps.0.values() {
+        observe(stamp);
+    }
+    let mut merged = if other.stamps.latest() >= local.stamps.latest() {
+        other.clone()
+    } else {
+        local.clone()
+    };
+    for field in Field::ALL {
+        let (from, stamp) = if other.stamps.get(field) >= local.stamps.get(field) {
+            (other, other.stamps.get(field))
+        } else {
+            (&*local, local.stamps.get(field))
+        };
+        field.copy(from, &mut merged);
+        if let Some(stamp) = stamp {
+            merged.stamps.0.insert(field, stamp.clone());
+        }
+    }
+    *local = merged;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stamp(millis: u64, node: &str) -> Stamp {
+        Stamp {
+            millis,
+            counter: 0,
+            node: node.to_owned(),
+        }
+    }
+
+    fn carinfo(make: &str, model: &str, horsepower: u32, stamps: &[(Field, Stamp)]) -> Carinfo {
+        let mut carinfo: Carinfo = serde_json::from_value(json!({
+            "id": 1,
+            "make": make,
+            "model": model,
+            "horsepower": horsepower,
+            "public": false,
+        }))
+        .expect("valid record");
+        carinfo.stamps = Stamps(stamps.iter().cloned().collect());
+        carinfo
+    }
+
+    #[test]
+    fn every_field_takes_the_later_change() {
+        let mut local = carinfo(
+            "BMW",
+            "M3",
+            420,
+            &[
+                (Field::Make, stamp(2000, "a")),
+                (Field::Model, stamp(1000, "a")),
+            ],
+        );
+        let other = carinfo(
+            "Audi",
+            "M5",
+            600,
+            &[
+                (Field::Make, stamp(1000, "b")),
+                (Field::Model, stamp(3000, "b")),
+                (Field::Horsepower, stamp(3000, "b")),
+            ],
+        );
+        merge(&mut local, &other);
+        assert_eq!(
+            (local.make.as_str(), local.model.as_str(), local.horsepower),
+            ("BMW", "M5", 600)
+        );
+        assert_eq!(local.stamps.get(Field::Make), Some(&stamp(2000, "a")));
+        assert_eq!(local.stamps.get(Field::Model), Some(&stamp(3000, "b")));
+        assert_eq!(local.stamps.latest(), Some(&stamp(3000, "b")));
+    }
+
+    #[test]
+    fn the_node_breaks_ties() {
+        let mut local = carinfo("BMW", "M3", 420, &[(Field::Make, stamp(1000, "b"))]);
+        let other = carinfo("Audi", "M3", 420, &[(Field::Make, stamp(1000, "a"))]);
+        merge(&mut local, &other);
+        assert_eq!(local.make, "BMW");
+    }
+
+    #[test]
+    fn merging_in_either_order_gives_the_same_record() {
+        let a = carinfo(
+            "BMW",
+            "M3",
+            420,
+            &[
+                (Field::Make, stamp(2000, "a")),
+                (Field::Horsepower, stamp(1000, "a")),
+            ],
+        );
+        let b = carinfo(
+            "Audi",
+            "RS4",
+            450,
+            &[
+                (Field::Model, stamp(2000, "b")),
+                (Field::Horsepower, stamp(1500, "b")),
+            ],
+        );
+        let (mut ab, mut ba) = (a.clone(), b.clone());
+        merge(&mut ab, &b);
+        merge(&mut ba, &a);
+        for merged in [&ab, &ba] {
+            assert_eq!(
+                (
+                    merged.make.as_str(),
+                    merged.model.as_str(),
+                    merged.horsepower
+                ),
+                ("BMW", "RS4", 450)
+            );
+        }
+        assert_eq!(ab.stamps, ba.stamps);
+    }
+
+    #[test]
+    fn unstamped_copies_replace_unstamped_ones_but_not_stamped_ones() {
+        let mut local = carinfo("BMW", "M3", 420, &[]);
+        merge(&mut local, &carinfo("BMW", "M3 CS", 460, &[]));
+        assert_eq!((local.model.as_str(), local.horsepower), ("M3 CS", 460));
+
+        let mut local = carinfo("BMW", "M3", 420, &[(Field::Model, stamp(1000, "a"))]);
+        merge(&mut local, &carinfo("BMW", "M3 CS", 460, &[]));
+        assert_eq!((local.model.as_str(), local.horsepower), ("M3", 460));
+    }
+}
@@ -0,0 +1,100 @@
+use rust_car_p2p::{
+    control::{self, ControlRequest},
+    Config,
+};
+
+const USAGE: &str =
+    "usage: ctl [--config <path>] [--control-socket <path>] [--wait <secs>] <command>";
+
+/// The flags `ctl` reads before the command, each followed by a value.
+pub const FLAGS: &[&str] = &["--config", "--control-socket", "--profile", "--wait"];
+
+/// Whether `--daemon` was passed.
+pub fn enabled(args: &[String]) -> bool {
+    args.iter().any(|a| a == "--daemon")
+}
+
+/// `ctl`: runs one command on the daemon and prints its output, errors on stderr. Returns the
+/// exit status, 1 if the daemon can't be reached or the command printed an error.
+pub async fn ctl(args: &[String]) -> i32 {
+    let mut config_args = Vec::new();
+    let mut wait_secs = 0;
+    let mut args = args.iter();
+    let mut words = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "--control-socket" if words.is_empty() => match args.next() {
+                Some(value) => config_args.extend([arg.clone(), value.clone()]),
+                None => return usage(&format!("{} needs a value", arg)),
+            },
+            // already entered, see profile::select
+            "--profile" if words.is_empty() => {
+                args.next();
+            }
+            "--wait" if words.is_empty() => match args.next().map(|v| v.parse()) {
+                Some(Ok(secs)) => wait_secs = secs,
+                _ => return usage("--wait needs a number of seconds"),
+            },
+            word => words.push(quote(word)),
+        }
+    }
+    if words.is_empty() {
+        return usage("no command given");
+    }
+    let config = match Config::load(&config_args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid configuration, {}", e);
+            return 1;
+        }
+    };
+    let request = ControlRequest {
+        line: words.join(" "),
+        wait_secs,
+    };
+    send(&config, &request).await
+}
+
+#[cfg(unix)]
+async fn send(config: &Config, request: &ControlRequest) -> i32 {
+    let mut failed = false;
+    let result = control::request(&config.control_socket, request, |output| {
+        match output.level.as_str() {
+            "ERROR" => {
+                failed = true;
+                eprintln!("{}", output.message);
+            }
+            "WARN" => eprintln!("{}", output.message),
+            _ => println!("{}", output.message),
+        }
+    })
+    .await;
+    match result {
+        Ok(()) if failed => 1,
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}; is the node running with --daemon?", e);
+            1
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn send(_: &Config, _: &ControlRequest) -> i32 {
+    eprintln!("ctl needs unix sockets, which this platform doesn't have");
+    1
+}
+
+fn usage(problem: &str) -> i32 {
+    eprintln!("{}\n{}", problem, USAGE);
+    1
+}
+
+/// Quotes an argument the shell already split off, so the node's parser keeps it one word.
+fn quote(word: &str) -> String {
+    if word.is_empty() || word.contains(char::is_whitespace) {
+        format!("\"{}\"", word)
+    } else {
+        word.to_owned()
+    }
+}
@@ -0,0 +1,144 @@
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Carinfo;
+
+/// What makes two local records the same car.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKey {
+    /// Same make and model, ignoring case, and horsepower.
+    #[default]
+    MakeModelHorsepower,
+    /// Same VIN. Records without one are never duplicates.
+    Vin,
+}
+
+impl FromStr for DuplicateKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "make-model-hp" => Ok(DuplicateKey::MakeModelHorsepower),
+            "vin" => Ok(DuplicateKey::Vin),
+            other => Err(format!(
+                "unknown duplicate key {}, expected make-model-hp or vin",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DuplicateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DuplicateKey::MakeModelHorsepower => "make, model and horsepower",
+            DuplicateKey::Vin => "VIN",
+        })
+    }
+}
+
+impl DuplicateKey {
+    /// Whether `a` and `b` are the same car. Records with different VINs never are.
+    pub fn same_car(&self, a: &Carinfo, b: &Carinfo) -> bool {
+        self.of(a).is_some() && self.of(a) == self.of(b) && !different_vins(a, b)
+    }
+
+    /// What records with the same car share, `None` if `carinfo` can't have duplicates.
+    pub fn of(&self, carinfo: &Carinfo) -> Option<String> {
+        match self {
+            DuplicateKey::MakeModelHorsepower => Some(format!(
+                "{}\n{}\n{}",
+                carinfo.make.trim().to_lowercase(),
+                carinfo.model.trim().to_lowercase(),
+                carinfo.horsepower
+            )),
+            DuplicateKey::Vin => carinfo.vin.as_ref().map(|vin| vin.to_uppercase()),
+        }
+    }
+}
+
+fn different_vins(a: &Carinfo, b: &Carinfo) -> bool {
+    match (&a.vin, &b.vin) {
+        (Some(a), Some(b)) => !a.eq_ignore_ascii_case(b),
+        _ => false,
+    }
+}
+
+/// Records with the same `key`, each group ordered by id. Records without duplicates are left
+/// out, and so are groups with several VINs, which hold different cars of the same model.
+pub fn groups(carinfos: Vec<Carinfo>, key: DuplicateKey) -> Vec<Vec<Carinfo>> {
+    let mut by_key: BTreeMap<String, Vec<Carinfo>> = BTreeMap::new();
+    for carinfo in carinfos {
+        if let Some(k) = key.of(&carinfo) {
+            by_key.entry(k).or_default().push(carinfo);
+        }
+    }
+    let mut groups: Vec<Vec<Carinfo>> = by_key
+        .into_values()
+        .filter(|group| {
+            group.len() > 1
+                && group
+                    .iter()
+                    .all(|a| group.iter().all(|b| !different_vins(a, b)))
+        })
+        .map(|mut group| {
+            group.sort_by_key(|r| r.id);
+            group
+        })
+        .collect();
+    groups.sort_by_key(|group| group[0].id);
+    groups
+}
+
+/// Merges a group of duplicates into its oldest record, the one with the lowest id. A field
+/// set on several records takes the value of the most recently updated one, gaps are filled
+/// from the others. Photos, price history, attributes, grants and channels are combined, and
+/// the car is public if any of them was.
+pub fn merge(group: &[Carinfo]) -> Carinfo {
+    let mut newest_first: Vec<&Carinfo> = group.iter().collect();
+    newest_first.sort_by_key(|r| std::cmp::Reverse(r.updated_at));
+    let oldest = group
+        .iter()
+        .min_by_key(|r| r.id)
+        .expect("a group has records");
+    let mut merged = newest_first[0].clone();
+    merged.id = oldest.id;
+    merged.origin = oldest.origin.clone();
+    merged.created_at = oldest.created_at;
+    merged.updated_at = Some(Utc::now());
+    for other in &newest_first[1..] {
+        merged.vin = merged.vin.take().or_else(|| other.vin.clone());
+        merged.year = merged.year.or(other.year);
+        merged.mileage_km = merged.mileage_km.or(other.mileage_km);
+        merged.price = merged.price.take().or_else(|| other.price.clone());
+        merged.fuel = merged.fuel.or(other.fuel);
+        merged.transmission = merged.transmission.or(other.transmission);
+        merged.location = merged.location.take().or_else(|| other.location.clone());
+        merged.obd = merged.obd.take().or_else(|| other.obd.clone());
+        merged.can = merged.can.take().or_else(|| other.can.clone());
+        merged.public |= other.public;
+        for photo in &other.photos {
+            if !merged.photos.iter().any(|p| p.hash == photo.hash) {
+                merged.photos.push(photo.clone());
+            }
+        }
+        for point in &other.price_history {
+            if !merged.price_history.contains(point) {
+                merged.price_history.push(point.clone());
+            }
+        }
+        for (name, value) in &other.attributes {
+            merged
+                .attributes
+                .entry(name.clone())
+                .or_insert_with(|| value.clone());
+        }
+        merged
+            .allowed_peers
+            .extend(other.allowed_peers.iter().cloned());
+        merged.channels.extend(other.channels.iter().cloned());
+    }
+    merged
+}
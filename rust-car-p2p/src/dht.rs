@@ -0,0 +1,213 @@
+use libp2p::{
+    kad::{
+        kbucket::NodeStatus,
+        record::{
+            store::{MemoryStore, RecordStore},
+            Key,
+        },
+        Kademlia, KademliaConfig, KademliaEvent,
+    },
+    multiaddr::Protocol,
+    Multiaddr, PeerId,
+};
+use log::error;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::{Carinfo, PEER_ID};
+
+pub type Dht = Kademlia<MemoryStore>;
+pub type DhtEvent = KademliaEvent;
+
+/// Our own protocol name keeps the routing table to carinfo nodes instead of the IPFS network.
+const PROTOCOL_NAME: &[u8] = b"/carinfo/kad/1.0.0";
+/// How often to look for new peers through the DHT, starting right away.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// A peer in the routing table.
+#[derive(Debug, Clone)]
+pub struct RoutedPeer {
+    pub peer: PeerId,
+    pub addresses: Vec<Multiaddr>,
+    pub connected: bool,
+}
+
+/// Kademlia seeded with the bootstrap nodes from the config.
+pub fn kademlia(bootstrap: &[(PeerId, Multiaddr)]) -> Dht {
+    let mut config = KademliaConfig::default();
+    config.set_protocol_name(PROTOCOL_NAME);
+    let mut dht = Kademlia::with_config(*PEER_ID, MemoryStore::new(*PEER_ID), config);
+    for (peer, addr) in bootstrap {
+        dht.add_address(peer, addr.clone());
+    }
+    dht
+}
+
+/// Splits `<multiaddr>/p2p/<peer id>` into the peer and its address.
+pub fn parse_node(s: &str) -> Option<(PeerId, Multiaddr)> {
+    let mut addr: Multiaddr = s.parse().ok()?;
+    match addr.pop()? {
+        Protocol::P2p(hash) => Some((PeerId::from_multihash(hash).ok()?, addr)),
+        _ => None,
+    }
+}
+
+/// Bootstrap nodes we aren't connected to are dialed again after this, twice as long after
+/// every further attempt, up to [`MAX_RETRY_DELAY`].
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+/// How often to look for bootstrap nodes due for another dial.
+pub const REDIAL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The bootstrap nodes from the config, dialed at startup and again with exponential backoff
+/// whenever we aren't connected to them.
+#[derive(Debug, Default)]
+pub struct Bootstrap {
+    nodes: HashMap<PeerId, Retry>,
+}
+
+#[derive(Debug)]
+struct Retry {
+    at: Instant,
+    delay: Duration,
+}
+
+impl Bootstrap {
+    pub fn new(nodes: &[(PeerId, Multiaddr)]) -> Bootstrap {
+        let now = Instant::now();
+        Bootstrap {
+            nodes: nodes
+                .iter()
+                .map(|(peer, _)| {
+                    (
+                        *peer,
+                        Retry {
+                            at: now,
+                            delay: RETRY_DELAY,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.nodes.keys()
+    }
+
+    /// The nodes due for a dial, except the `connected` ones, with their next attempt pushed
+    /// back.
+    pub fn due(&mut self, connected: &[PeerId]) -> Vec<PeerId> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for (peer, retry) in self.nodes.iter_mut() {
+            if retry.at > now || connected.contains(peer) {
+                continue;
+            }
+            due.push(*peer);
+            retry.at = now + retry.delay;
+            retry.delay = (retry.delay * 2).min(MAX_RETRY_DELAY);
+        }
+        due
+    }
+
+    /// Starts the backoff over, so a node that drops later is dialed again right away.
+    pub fn connected(&mut self, peer: &PeerId) {
+        if let Some(retry) = self.nodes.get_mut(peer) {
+            retry.at = Instant::now();
+            retry.delay = RETRY_DELAY;
+        }
+    }
+}
+
+/// Everyone in the routing table, whether found through the DHT or added locally.
+pub fn routed_peers(dht: &mut Dht) -> Vec<RoutedPeer> {
+    let mut peers = Vec::new();
+    for bucket in dht.kbuckets() {
+        for entry in bucket.iter() {
+            peers.push(RoutedPeer {
+                peer: *entry.node.key.preimage(),
+                addresses: entry.node.value.iter().cloned().collect(),
+                connected: entry.status == NodeStatus::Connected,
+            });
+        }
+    }
+    peers
+}
+
+/// The provider record key of a make, or of a model of it, e.g. `carinfo:bmw:m3`. Case and
+/// spacing don't matter, `Alfa  Romeo` is `alfa-romeo`.
+pub fn provider_key(make: &str, model: Option<&str>) -> String {
+    let normalize = |s: &str| {
+        s.split_whitespace()
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>()
+            .join("-")
+    };
+    match model {
+        Some(model) => format!("carinfo:{}:{}", normalize(make), normalize(model)),
+        None => format!("carinfo:{}", normalize(make)),
+    }
+}
+
+/// The keys we provide for our public cars: each make, and each model of it.
+pub fn provider_keys(carinfos: &[Carinfo]) -> BTreeSet<String> {
+    let us = PEER_ID.to_string();
+    carinfos
+        .iter()
+        .filter(|c| c.public && c.origin.as_ref().is_none_or(|origin| *origin == us))
+        .filter(|c| !c.make.trim().is_empty())
+        .flat_map(|c| {
+            [
+                provider_key(&c.make, None),
+                provider_key(&c.make, Some(&c.model)),
+            ]
+        })
+        .collect()
+}
+
+/// Provider records announcing the makes and models we have public cars of, so `find` only
+/// has to ask the peers that have them. Kademlia republishes them every 12 hours.
+#[derive(Debug, Default)]
+pub struct Providing {
+    announced: BTreeSet<String>,
+    /// Keys whose announcement reached no peer, tried again on the next update.
+    failed: HashSet<Key>,
+}
+
+impl Providing {
+    /// Announces the keys of `carinfos` not announced yet and those that failed, and withdraws
+    /// those no public car has any more.
+    pub fn update(&mut self, dht: &mut Dht, carinfos: &[Carinfo]) {
+        let keys = provider_keys(carinfos);
+        for gone in self.announced.difference(&keys) {
+            let key = Key::new(gone);
+            dht.stop_providing(&key);
+            self.failed.remove(&key);
+        }
+        for key in &keys {
+            let key_bytes = Key::new(key);
+            if self.announced.contains(key) && !self.failed.remove(&key_bytes) {
+                continue;
+            }
+            if let Err(e) = dht.start_providing(key_bytes) {
+                error!("error announcing {}, {:?}", key, e);
+            }
+        }
+        self.announced = keys;
+    }
+
+    pub fn failed(&mut self, key: Key) {
+        self.failed.insert(key);
+    }
+}
+
+/// Providers of `key` we know without asking, from records other peers stored with us.
+pub fn stored_providers(dht: &mut Dht, key: &Key) -> Vec<PeerId> {
+    dht.store_mut()
+        .providers(key)
+        .into_iter()
+        .map(|record| record.provider)
+        .filter(|provider| provider != &*PEER_ID)
+        .collect()
+}
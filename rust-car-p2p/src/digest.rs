@@ -0,0 +1,129 @@
+use libp2p::PeerId;
+use log::error;
+use sha2::{Digest, Sha256};
+
+use crate::{aliases, Carinfo, Carinfos};
+
+/// The hash of what a record holds: SHA-256 of its JSON with keys in order, in hex. The hash
+/// itself, the origin's signature, whether it was verified and the peers it's granted to are
+/// left out, so two copies of a record have the same hash wherever they are kept.
+pub fn of(carinfo: &Carinfo) -> String {
+    let mut content = carinfo.clone();
+    content.content_hash = None;
+    content.origin_signature = None;
+    content.unverified = false;
+    content.allowed_peers.clear();
+    // without preserve_order, maps in a `Value` keep their keys sorted
+    let value = serde_json::to_value(&content).expect("can encode record");
+    let json = serde_json::to_vec(&value).expect("can encode record");
+    Sha256::digest(&json)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Stores the hash of the record's current content with it.
+pub fn seal(carinfo: &mut Carinfo) {
+    carinfo.content_hash = Some(of(carinfo));
+}
+
+/// Whether two records hold the same, by their hashes if both have one.
+pub fn same(a: &Carinfo, b: &Carinfo) -> bool {
+    match (&a.content_hash, &b.content_hash) {
+        (Some(a), Some(b)) => a == b,
+        _ => of(a) == of(b),
+    }
+}
+
+/// Checks that a record holds what its hash says. Records without a hash, from peers that
+/// predate hashes, pass.
+pub fn verify(carinfo: &Carinfo) -> Result<(), String> {
+    match &carinfo.content_hash {
+        Some(hash) if *hash != of(carinfo) => {
+            Err(format!("its content doesn't match hash {}", hash))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Drops the records from `holder` that were changed on the way, or by a peer passing them on.
+pub fn drop_corrupted(holder: &PeerId, carinfos: &mut Carinfos) {
+    carinfos.retain(|carinfo| match verify(carinfo) {
+        Ok(()) => true,
+        Err(e) => {
+            error!(
+                "dropped carinfo {} from {}, {}",
+                carinfo.id,
+                aliases::display(holder),
+                e
+            );
+            false
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn golf() -> Carinfo {
+        serde_json::from_value(json!({
+            "id": 1,
+            "make": "VW",
+            "model": "Golf",
+            "horsepower": 150,
+            "public": true,
+        }))
+        .expect("valid record")
+    }
+
+    #[test]
+    fn hashes_the_content_only() {
+        let golf = golf();
+        let hash = of(&golf);
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let mut passed_on = golf.clone();
+        passed_on.content_hash = Some("stale".to_owned());
+        passed_on.origin_signature = Some("signature".to_owned());
+        passed_on.unverified = true;
+        passed_on.allowed_peers.insert(PeerId::random().to_string());
+        assert_eq!(of(&passed_on), hash);
+
+        let mut edited = golf;
+        edited.horsepower = 245;
+        assert_ne!(of(&edited), hash);
+    }
+
+    #[test]
+    fn verifies_sealed_records() {
+        let mut golf = golf();
+        assert_eq!(verify(&golf), Ok(()));
+        seal(&mut golf);
+        assert_eq!(verify(&golf), Ok(()));
+
+        let mut tampered = golf.clone();
+        tampered.horsepower = 300;
+        assert!(verify(&tampered)
+            .unwrap_err()
+            .contains("doesn't match hash"));
+        tampered.content_hash = None;
+        assert!(!same(&golf, &tampered));
+        tampered.horsepower = golf.horsepower;
+        assert!(same(&golf, &tampered));
+    }
+
+    #[test]
+    fn drops_corrupted_records() {
+        let mut sealed = golf();
+        seal(&mut sealed);
+        let mut tampered = sealed.clone();
+        tampered.id = 2;
+        let mut carinfos = vec![sealed, tampered, golf()];
+        drop_corrupted(&PeerId::random(), &mut carinfos);
+        let ids: Vec<usize> = carinfos.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1, 1]);
+    }
+}
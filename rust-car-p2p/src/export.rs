@@ -0,0 +1,149 @@
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::ByteArray;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::sync::Arc;
+
+use crate::{import::Column, schema, Carinfo, Result};
+
+/// Flat Parquet layout of exported records, one row per car. Columns are written in this order.
+const PARQUET_SCHEMA: &str = "
+    message carinfo {
+        required binary origin_peer (UTF8);
+        required int64 id;
+        required binary make (UTF8);
+        required binary model (UTF8);
+        optional double horsepower;
+        required boolean public;
+        optional double odometer_km;
+        optional double fuel_level_percent;
+        optional binary dtc_codes (UTF8);
+    }
+";
+
+/// Writes `records`, each tagged with the peer it originates from, to a Parquet file at `path`.
+pub async fn parquet(path: &str, records: Vec<(String, Carinfo)>) -> Result<usize> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || write_parquet(&path, &records)).await?
+}
+
+fn write_parquet(path: &str, records: &[(String, Carinfo)]) -> Result<usize> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let props = Arc::new(WriterProperties::builder().build());
+    let file = std::fs::File::create(path)?;
+    let mut writer = SerializedFileWriter::new(file, schema, props)?;
+    let mut row_group = writer.next_row_group()?;
+
+    let mut column = 0;
+    while let Some(mut col) = row_group.next_column()? {
+        match (column, col.untyped()) {
+            (0, ColumnWriter::ByteArrayColumnWriter(w)) => {
+                let values = strings(records.iter().map(|(origin, _)| origin.as_str()));
+                w.write_batch(&values, None, None)?;
+            }
+            (1, ColumnWriter::Int64ColumnWriter(w)) => {
+                let values: Vec<i64> = records.iter().map(|(_, r)| r.id as i64).collect();
+                w.write_batch(&values, None, None)?;
+            }
+            (2, ColumnWriter::ByteArrayColumnWriter(w)) => {
+                let values = strings(records.iter().map(|(_, r)| r.make.trim()));
+                w.write_batch(&values, None, None)?;
+            }
+            (3, ColumnWriter::ByteArrayColumnWriter(w)) => {
+                let values = strings(records.iter().map(|(_, r)| r.model.trim()));
+                w.write_batch(&values, None, None)?;
+            }
+            (4, ColumnWriter::DoubleColumnWriter(w)) => {
+                let (values, levels) =
+                    optional(records.iter().map(|(_, r)| Some(f64::from(r.horsepower))));
+                w.write_batch(&values, Some(&levels), None)?;
+            }
+            (5, ColumnWriter::BoolColumnWriter(w)) => {
+                let values: Vec<bool> = records.iter().map(|(_, r)| r.public).collect();
+                w.write_batch(&values, None, None)?;
+            }
+            (6, ColumnWriter::DoubleColumnWriter(w)) => {
+                let (values, levels) = optional(
+                    records
+                        .iter()
+                        .map(|(_, r)| r.obd.as_ref().and_then(|o| o.odometer_km)),
+                );
+                w.write_batch(&values, Some(&levels), None)?;
+            }
+            (7, ColumnWriter::DoubleColumnWriter(w)) => {
+                let (values, levels) = optional(
+                    records
+                        .iter()
+                        .map(|(_, r)| r.obd.as_ref().and_then(|o| o.fuel_level_percent)),
+                );
+                w.write_batch(&values, Some(&levels), None)?;
+            }
+            (8, ColumnWriter::ByteArrayColumnWriter(w)) => {
+                let (values, levels) = optional(records.iter().map(|(_, r)| {
+                    r.obd
+                        .as_ref()
+                        .map(|o| ByteArray::from(o.dtc_codes.join(" ").as_str()))
+                }));
+                w.write_batch(&values, Some(&levels), None)?;
+            }
+            _ => return Err(format!("unexpected parquet column {}", column).into()),
+        }
+        col.close()?;
+        column += 1;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+    Ok(records.len())
+}
+
+fn strings<'a>(values: impl Iterator<Item = &'a str>) -> Vec<ByteArray> {
+    values.map(ByteArray::from).collect()
+}
+
+/// Splits optional values into the present values plus definition levels (1 = present).
+fn optional<T>(values: impl Iterator<Item = Option<T>>) -> (Vec<T>, Vec<i16>) {
+    let mut present = Vec::new();
+    let mut levels = Vec::new();
+    for value in values {
+        match value {
+            Some(v) => {
+                present.push(v);
+                levels.push(1);
+            }
+            None => levels.push(0),
+        }
+    }
+    (present, levels)
+}
+
+/// Writes our own records to a CSV file at `path`, in the columns `import csv` reads back.
+pub async fn csv(path: &str, records: Vec<Carinfo>) -> Result<usize> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || write_csv(&path, &records)).await?
+}
+
+fn write_csv(path: &str, records: &[Carinfo]) -> Result<usize> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(Column::ALL.map(Column::header))?;
+    for r in records {
+        writer.write_record([
+            r.id.to_string(),
+            r.make.trim().to_owned(),
+            r.model.trim().to_owned(),
+            r.horsepower.to_string(),
+            r.vin.clone().unwrap_or_default(),
+            if r.public { "yes" } else { "no" }.to_owned(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(records.len())
+}
+
+/// Writes our own records, every field included, to a JSON file at `path` that `import json`
+/// reads on another node.
+pub async fn json(path: &str, records: &[Carinfo]) -> Result<usize> {
+    tokio::fs::write(path, schema::encode_pretty(records)?).await?;
+    Ok(records.len())
+}
@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::Carinfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    /// Longest symbols first, so `>=` isn't read as `>`.
+    const SYMBOLS: [(&'static str, Op); 6] = [
+        ("!=", Op::Ne),
+        ("<=", Op::Le),
+        (">=", Op::Ge),
+        ("=", Op::Eq),
+        ("<", Op::Lt),
+        (">", Op::Gt),
+    ];
+
+    fn symbol(self) -> &'static str {
+        Op::SYMBOLS
+            .iter()
+            .find(|(_, op)| *op == self)
+            .map(|(symbol, _)| *symbol)
+            .expect("every op has a symbol")
+    }
+
+    fn compare<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+        }
+    }
+}
+
+/// One comparison against a field of a record. Make, model and VIN compare case-insensitively
+/// and only for (in)equality.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    Make(Op, String),
+    Model(Op, String),
+    Horsepower(Op, u32),
+    Vin(Op, String),
+    /// Whether the record is in a channel, see [`channels`](crate::channels).
+    Channel(Op, String),
+    /// Whether the record has a tag, see [`tags`](crate::tags).
+    Tag(Op, String),
+}
+
+impl Condition {
+    /// Parses `make=Toyota`, `model!=Yaris`, `hp>300` (also `horsepower>300`), `channel=bmw` or
+    /// `tag=classic`.
+    pub fn parse(s: &str) -> Result<Condition, String> {
+        let (field, op, value) = split(s).ok_or_else(|| {
+            format!(
+                "invalid filter {}, expected <field><op><value> like make=Toyota or hp>300",
+                s
+            )
+        })?;
+        let text = |op: Op| match op {
+            Op::Eq | Op::Ne => Ok(op),
+            _ => Err(format!("{} can only be compared with = or !=", field)),
+        };
+        match field {
+            "make" => Ok(Condition::Make(text(op)?, value.to_owned())),
+            "model" => Ok(Condition::Model(text(op)?, value.to_owned())),
+            "vin" => Ok(Condition::Vin(text(op)?, value.to_owned())),
+            "channel" => Ok(Condition::Channel(text(op)?, value.to_lowercase())),
+            "tag" => Ok(Condition::Tag(text(op)?, value.to_lowercase())),
+            "hp" | "horsepower" => value
+                .parse()
+                .map(|hp| Condition::Horsepower(op, hp))
+                .map_err(|e| format!("invalid horsepower {}, {}", value, e)),
+            _ => Err(format!(
+                "unknown filter field {}, expected make, model, vin, hp, channel or tag",
+                field
+            )),
+        }
+    }
+
+    /// Whether `s` looks like a condition rather than free text.
+    pub fn is_condition(s: &str) -> bool {
+        split(s).is_some_and(|(field, _, _)| {
+            matches!(
+                field,
+                "make" | "model" | "vin" | "hp" | "horsepower" | "channel" | "tag"
+            )
+        })
+    }
+
+    pub fn matches(&self, carinfo: &Carinfo) -> bool {
+        match self {
+            Condition::Make(op, make) => text_matches(*op, &carinfo.make, make),
+            Condition::Model(op, model) => text_matches(*op, &carinfo.model, model),
+            Condition::Horsepower(op, hp) => op.compare(carinfo.horsepower, *hp),
+            Condition::Vin(op, vin) => {
+                text_matches(*op, carinfo.vin.as_deref().unwrap_or_default(), vin)
+            }
+            Condition::Channel(op, name) => member(*op, carinfo.channels.contains(name)),
+            Condition::Tag(op, tag) => member(*op, carinfo.tags.contains(tag)),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Make(op, make) => write!(f, "make{}{}", op.symbol(), make),
+            Condition::Model(op, model) => write!(f, "model{}{}", op.symbol(), model),
+            Condition::Horsepower(op, hp) => write!(f, "hp{}{}", op.symbol(), hp),
+            Condition::Vin(op, vin) => write!(f, "vin{}{}", op.symbol(), vin),
+            Condition::Channel(op, name) => write!(f, "channel{}{}", op.symbol(), name),
+            Condition::Tag(op, tag) => write!(f, "tag{}{}", op.symbol(), tag),
+        }
+    }
+}
+
+fn text_matches(op: Op, field: &str, value: &str) -> bool {
+    let equal = field.trim().eq_ignore_ascii_case(value);
+    if op == Op::Ne {
+        !equal
+    } else {
+        equal
+    }
+}
+
+fn member(op: Op, member: bool) -> bool {
+    if op == Op::Ne {
+        !member
+    } else {
+        member
+    }
+}
+
+/// Splits `<field><op><value>` at the first operator.
+fn split(s: &str) -> Option<(&str, Op, &str)> {
+    let at = s.find(['!', '<', '>', '='])?;
+    let (field, rest) = s.split_at(at);
+    let (symbol, op) = Op::SYMBOLS
+        .iter()
+        .find(|(symbol, _)| rest.starts_with(symbol))?;
+    let value = rest[symbol.len()..].trim();
+    if field.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some((field, *op, value))
+}
+
+/// Conditions a record must all meet, used for local searches and sent to peers in a
+/// [`SearchRequest`](crate::SearchRequest) so they only answer with matching records.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    pub conditions: Vec<Condition>,
+}
+
+impl Filter {
+    pub fn parse<'a>(words: impl IntoIterator<Item = &'a str>) -> Result<Filter, String> {
+        Ok(Filter {
+            conditions: words
+                .into_iter()
+                .map(Condition::parse)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn matches(&self, carinfo: &Carinfo) -> bool {
+        self.conditions.iter().all(|c| c.matches(carinfo))
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let conditions: Vec<String> = self.conditions.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}", conditions.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn supra() -> Carinfo {
+        serde_json::from_value(json!({
+            "id": 1,
+            "make": "Toyota ",
+            "model": "Supra",
+            "horsepower": 340,
+            "public": true,
+            "tags": ["classic"],
+            "channels": ["jdm"],
+        }))
+        .expect("valid record")
+    }
+
+    #[test]
+    fn parses_conditions_and_prints_them_back() {
+        let filter = Filter::parse(["make=Toyota", "horsepower>=300", "tag=Classic"]).unwrap();
+        assert_eq!(
+            filter.conditions,
+            vec![
+                Condition::Make(Op::Eq, "Toyota".to_owned()),
+                Condition::Horsepower(Op::Ge, 300),
+                Condition::Tag(Op::Eq, "classic".to_owned()),
+            ]
+        );
+        assert_eq!(filter.to_string(), "make=Toyota hp>=300 tag=classic");
+        assert_eq!(
+            Condition::parse("model!=Yaris").unwrap(),
+            Condition::Model(Op::Ne, "Yaris".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_conditions() {
+        assert!(Condition::parse("make>Toyota")
+            .unwrap_err()
+            .contains("can only be compared with = or !="));
+        assert!(Condition::parse("colour=red")
+            .unwrap_err()
+            .contains("unknown filter field colour"));
+        assert!(Condition::parse("hp>fast")
+            .unwrap_err()
+            .starts_with("invalid horsepower fast"));
+        assert!(Condition::parse("turbo").is_err());
+        assert!(Filter::parse(["make=Toyota", "hp=lots"]).is_err());
+    }
+
+    #[test]
+    fn tells_conditions_from_free_text() {
+        assert!(Condition::is_condition("hp>300"));
+        assert!(Condition::is_condition("make=VW"));
+        assert!(!Condition::is_condition("turbo"));
+        assert!(!Condition::is_condition("colour=red"));
+    }
+
+    #[test]
+    fn matches_records() {
+        let supra = supra();
+        let matches = |s: &str| Condition::parse(s).unwrap().matches(&supra);
+        assert!(matches("make=toyota"));
+        assert!(!matches("make!=Toyota"));
+        assert!(matches("model=SUPRA"));
+        assert!(matches("hp>300"));
+        assert!(matches("hp<=340"));
+        assert!(!matches("hp<340"));
+        assert!(matches("tag=classic"));
+        assert!(matches("tag!=modern"));
+        assert!(matches("channel=JDM"));
+        assert!(!matches("vin=1M8GDM9AXKP042788"));
+
+        assert!(Filter::parse(["make=Toyota", "hp>300"])
+            .unwrap()
+            .matches(&supra));
+        assert!(!Filter::parse(["make=Toyota", "hp>400"])
+            .unwrap()
+            .matches(&supra));
+        assert!(Filter::default().matches(&supra));
+    }
+}
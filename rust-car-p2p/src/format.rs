@@ -0,0 +1,308 @@
+use once_cell::sync::Lazy;
+use std::collections::BTreeSet;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{aliases, photos, Carinfo, PEER_ID};
+
+/// Mechanical horsepower to kilowatts.
+const KW_PER_HP: f64 = 0.745_699_872;
+const MILES_PER_KM: f64 = 0.621_371_192;
+
+/// Formatting preferences detected once from the environment at startup.
+pub static NUMBER_FORMAT: Lazy<NumberFormat> = Lazy::new(NumberFormat::from_env);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitSystem {
+    Metric,
+    Imperial,
+}
+
+/// Locale-dependent number formatting plus the preferred unit system.
+#[derive(Debug, Clone)]
+pub struct NumberFormat {
+    decimal_sep: char,
+    group_sep: Option<char>,
+    units: UnitSystem,
+}
+
+impl NumberFormat {
+    /// Derives separators from `LC_ALL`/`LC_NUMERIC`/`LANG` (e.g. `de_DE.UTF-8`) and the unit
+    /// system from the locale's territory, overridable with `CARINFO_UNITS=metric|imperial`.
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|k| env::var(k).ok())
+            .find(|v| !v.is_empty())
+            .unwrap_or_else(|| "C".to_owned());
+        let mut format = NumberFormat::for_locale(&locale);
+        match env::var("CARINFO_UNITS").as_deref() {
+            Ok("metric") => format.units = UnitSystem::Metric,
+            Ok("imperial") => format.units = UnitSystem::Imperial,
+            _ => {}
+        }
+        format
+    }
+
+    pub fn for_locale(locale: &str) -> Self {
+        let name = locale.split(['.', '@']).next().unwrap_or("C");
+        let mut parts = name.split('_');
+        let language = parts.next().unwrap_or("C");
+        let territory = parts.next().unwrap_or("");
+
+        let (decimal_sep, group_sep) = match language {
+            "C" | "POSIX" => ('.', None),
+            "de" | "it" if territory == "CH" => ('.', Some('\'')),
+            "de" | "nl" | "it" | "es" | "pt" | "da" | "id" | "tr" | "el" => (',', Some('.')),
+            "fr" | "sv" | "fi" | "nb" | "no" | "cs" | "pl" | "ru" | "uk" | "sk" | "hu" | "bg" => {
+                (',', Some(' '))
+            }
+            _ => ('.', Some(',')),
+        };
+        let units = match territory {
+            "US" | "GB" | "LR" | "MM" => UnitSystem::Imperial,
+            _ => UnitSystem::Metric,
+        };
+        NumberFormat {
+            decimal_sep,
+            group_sep,
+            units,
+        }
+    }
+
+    /// Formats `value` with `decimals` fraction digits and locale separators.
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        let raw = format!("{:.*}", decimals, value.abs());
+        let (int_part, frac_part) = match raw.split_once('.') {
+            Some((i, f)) => (i, Some(f)),
+            None => (raw.as_str(), None),
+        };
+
+        let mut out = String::new();
+        if value < 0.0 {
+            out.push('-');
+        }
+        for (i, c) in int_part.chars().enumerate() {
+            if i > 0 && (int_part.len() - i) % 3 == 0 {
+                if let Some(sep) = self.group_sep {
+                    out.push(sep);
+                }
+            }
+            out.push(c);
+        }
+        if let Some(frac) = frac_part {
+            out.push(self.decimal_sep);
+            out.push_str(frac);
+        }
+        out
+    }
+
+    /// Formats an engine output given in horsepower, as kW for metric users.
+    pub fn power(&self, hp: f64) -> String {
+        match self.units {
+            UnitSystem::Imperial => format!("{} hp", self.number(hp, 0)),
+            UnitSystem::Metric => format!("{} kW", self.number(hp * KW_PER_HP, 0)),
+        }
+    }
+
+    /// Formats a distance given in kilometres, as miles for imperial users.
+    pub fn distance(&self, km: f64) -> String {
+        match self.units {
+            UnitSystem::Imperial => format!("{} mi", self.number(km * MILES_PER_KM, 0)),
+            UnitSystem::Metric => format!("{} km", self.number(km, 0)),
+        }
+    }
+
+    /// Formats a speed given in km/h, as mph for imperial users.
+    pub fn speed(&self, kmh: f64) -> String {
+        match self.units {
+            UnitSystem::Imperial => format!("{} mph", self.number(kmh * MILES_PER_KM, 0)),
+            UnitSystem::Metric => format!("{} km/h", self.number(kmh, 0)),
+        }
+    }
+
+    /// Formats a temperature given in °C, as °F for imperial users.
+    pub fn temperature(&self, celsius: f64) -> String {
+        match self.units {
+            UnitSystem::Imperial => format!("{} °F", self.number(celsius * 1.8 + 32.0, 0)),
+            UnitSystem::Metric => format!("{} °C", self.number(celsius, 0)),
+        }
+    }
+
+    /// Formats a price, with cents only when the amount has any.
+    pub fn price(&self, amount: f64, currency: &str) -> String {
+        let decimals = if amount.fract() == 0.0 { 0 } else { 2 };
+        format!("{} {}", self.number(amount, decimals), currency)
+            .trim_end()
+            .to_owned()
+    }
+}
+
+/// A column of a [`table`].
+pub struct Column {
+    pub name: &'static str,
+    /// Numbers line up on the right.
+    pub right: bool,
+}
+
+/// Lines of a table under a header, each column as wide as its widest cell.
+pub fn table(columns: &[Column], rows: &[Vec<String>]) -> Vec<String> {
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(|cell| cell.chars().count())
+                .chain([column.name.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(columns.iter().zip(&widths))
+            .map(|(cell, (column, width))| {
+                if column.right {
+                    format!("{:>width$}", cell, width = width)
+                } else {
+                    format!("{:<width$}", cell, width = width)
+                }
+            })
+            .collect();
+        cells.join("  ").trim_end().to_owned()
+    };
+    let mut lines = vec![line(columns.iter().map(|c| c.name).collect())];
+    lines.extend(
+        rows.iter()
+            .map(|row| line(row.iter().map(String::as_str).collect())),
+    );
+    lines
+}
+
+/// Describes how long ago a unix timestamp was, e.g. "3d ago".
+pub fn age(at: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    match now.saturating_sub(at) {
+        s if s < 60 => "just now".to_owned(),
+        s if s < 60 * 60 => format!("{}m ago", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h ago", s / (60 * 60)),
+        s => format!("{}d ago", s / (24 * 60 * 60)),
+    }
+}
+
+/// One-line listing representation of a record, using the detected number format.
+/// `#bmw #eu-west`.
+pub fn channels(names: &BTreeSet<String>) -> String {
+    names
+        .iter()
+        .map(|name| format!("#{}", name))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn carinfo(c: &Carinfo) -> String {
+    let power = NUMBER_FORMAT.power(f64::from(c.horsepower));
+    let mut line = format!(
+        "#{} {} {}, {}{}",
+        c.id,
+        c.make.trim(),
+        c.model.trim(),
+        power,
+        if c.public { " [public]" } else { "" }
+    );
+    let details = details(c);
+    if !details.is_empty() {
+        line.push_str(&format!(", {}", details));
+    }
+    if let Some(vin) = &c.vin {
+        line.push_str(&format!(", VIN {}", vin));
+    }
+    if !c.channels.is_empty() {
+        line.push_str(&format!(", in {}", channels(&c.channels)));
+    }
+    if !c.tags.is_empty() {
+        line.push_str(&format!(", tagged {}", c.tags.join(", ")));
+    }
+    // only records whose signature checked out are kept
+    if let (Some(origin), Some(_)) = (&c.origin, &c.origin_signature) {
+        if *origin != PEER_ID.to_string() {
+            let origin = origin
+                .parse()
+                .map_or(origin.clone(), |peer| aliases::display(&peer));
+            line.push_str(&format!(" [signed by {}]", origin));
+        }
+    }
+    if let (Some(origin), true) = (&c.origin, c.unverified) {
+        let origin = origin
+            .parse()
+            .map_or(origin.clone(), |peer| aliases::display(&peer));
+        line.push_str(&format!(" [unverified, says it's from {}]", origin));
+    }
+    if !c.public && !c.allowed_peers.is_empty() {
+        line.push_str(&match c.allowed_peers.len() {
+            1 => " [granted to 1 peer]".to_owned(),
+            n => format!(" [granted to {} peers]", n),
+        });
+    }
+    if let Some(can) = &c.can {
+        if let Some(hp) = can.confirmed_horsepower {
+            line.push_str(&format!(", CAN-confirmed {}", NUMBER_FORMAT.power(hp)));
+        }
+        if let Some(km) = can.odometer_km {
+            line.push_str(&format!(", CAN odometer {}", NUMBER_FORMAT.distance(km)));
+        }
+    }
+    if let Some(obd) = &c.obd {
+        if let Some(km) = obd.odometer_km {
+            line.push_str(&format!(", odometer {}", NUMBER_FORMAT.distance(km)));
+        }
+        if let Some(fuel) = obd.fuel_level_percent {
+            line.push_str(&format!(", fuel {}%", NUMBER_FORMAT.number(fuel, 0)));
+        }
+        if !obd.dtc_codes.is_empty() {
+            line.push_str(&format!(", trouble codes {}", obd.dtc_codes.join(" ")));
+        }
+    }
+    match c.photos.len() {
+        0 => {}
+        1 => line.push_str(", 1 photo"),
+        n => line.push_str(&format!(", {} photos", n)),
+    }
+    // photos of peers' cars stay with them until fetched
+    let stored = c
+        .photos
+        .iter()
+        .filter(|p| photos::is_stored(&p.hash))
+        .count();
+    if stored < c.photos.len() {
+        line.push_str(&format!(" ({} fetched)", stored));
+    }
+    line
+}
+
+/// Year, mileage, fuel, transmission and price of a record, whichever are known, e.g.
+/// "2019, 45.000 km, petrol, automatic, 89.000 EUR"; empty for records without details.
+pub fn details(c: &Carinfo) -> String {
+    let mut parts = Vec::new();
+    if let Some(year) = c.year {
+        parts.push(year.to_string());
+    }
+    if let Some(km) = c.mileage_km {
+        parts.push(NUMBER_FORMAT.distance(f64::from(km)));
+    }
+    if let Some(fuel) = c.fuel {
+        parts.push(fuel.to_string());
+    }
+    if let Some(transmission) = c.transmission {
+        parts.push(transmission.to_string());
+    }
+    if let Some(price) = &c.price {
+        parts.push(NUMBER_FORMAT.price(price.amount as f64, &price.currency));
+    }
+    parts.join(", ")
+}
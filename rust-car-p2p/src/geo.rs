@@ -0,0 +1,138 @@
+use once_cell::sync::Lazy;
+use std::env;
+
+/// Geohash length used for anything shared: 4 characters is a cell of roughly 39 x 20 km, close
+/// enough to judge a drive without revealing an address.
+pub const PRECISION: usize = 4;
+
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// This node's coarse location from `CARINFO_LOCATION=<lat>,<lon>`, sent along with listings.
+pub static HOME: Lazy<Option<String>> = Lazy::new(|| {
+    env::var("CARINFO_LOCATION")
+        .ok()
+        .and_then(|v| parse_lat_lon(&v))
+        .map(|(lat, lon)| encode(lat, lon, PRECISION))
+});
+
+/// Parses `<lat>,<lon>` in decimal degrees.
+pub fn parse_lat_lon(s: &str) -> Option<(f64, f64)> {
+    let (lat, lon) = s.split_once(',')?;
+    let lat = lat.trim().parse::<f64>().ok()?;
+    let lon = lon.trim().parse::<f64>().ok()?;
+    if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) {
+        Some((lat, lon))
+    } else {
+        None
+    }
+}
+
+pub fn encode(lat: f64, lon: f64, precision: usize) -> String {
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(precision);
+    let mut bits = 0;
+    let mut bit_count = 0;
+    let mut even = true;
+    while hash.len() < precision {
+        let (range, value) = if even {
+            (&mut lon_range, lon)
+        } else {
+            (&mut lat_range, lat)
+        };
+        let mid = (range.0 + range.1) / 2.0;
+        bits <<= 1;
+        if value >= mid {
+            bits |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        even = !even;
+        bit_count += 1;
+        if bit_count == 5 {
+            hash.push(BASE32[bits] as char);
+            bits = 0;
+            bit_count = 0;
+        }
+    }
+    hash
+}
+
+/// Returns the centre of a geohash cell, or `None` for an invalid hash.
+pub fn decode(hash: &str) -> Option<(f64, f64)> {
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut even = true;
+    for c in hash.bytes() {
+        let value = BASE32.iter().position(|b| *b == c.to_ascii_lowercase())?;
+        for shift in (0..5).rev() {
+            let range = if even { &mut lon_range } else { &mut lat_range };
+            let mid = (range.0 + range.1) / 2.0;
+            if value >> shift & 1 == 1 {
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+    }
+    if hash.is_empty() {
+        return None;
+    }
+    Some((
+        (lat_range.0 + lat_range.1) / 2.0,
+        (lon_range.0 + lon_range.1) / 2.0,
+    ))
+}
+
+/// Great-circle distance in kilometres between the centres of two geohash cells.
+pub fn distance_km(a: &str, b: &str) -> Option<f64> {
+    let (lat1, lon1) = decode(a)?;
+    let (lat2, lon2) = decode(b)?;
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    Some(2.0 * EARTH_RADIUS_KM * h.sqrt().asin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_known_geohashes() {
+        assert_eq!(encode(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(encode(57.64911, 10.40744, PRECISION), "u4pr");
+        assert_eq!(encode(-90.0, -180.0, 4), "0000");
+    }
+
+    #[test]
+    fn decodes_to_the_centre_of_the_cell() {
+        let (lat, lon) = decode("u4pruydqqvj").unwrap();
+        assert!((lat - 57.64911).abs() < 1e-4, "{}", lat);
+        assert!((lon - 10.40744).abs() < 1e-4, "{}", lon);
+        assert_eq!(decode("U4PR"), decode("u4pr"));
+        assert_eq!(decode(""), None);
+        assert_eq!(decode("u4pa"), None);
+    }
+
+    #[test]
+    fn measures_distances_between_cells() {
+        let paris = encode(48.8566, 2.3522, PRECISION);
+        let berlin = encode(52.52, 13.405, PRECISION);
+        assert_eq!(distance_km(&paris, &paris), Some(0.0));
+        // 878 km between the cities, give or take the size of a cell
+        let distance = distance_km(&paris, &berlin).unwrap();
+        assert!((840.0..920.0).contains(&distance), "{}", distance);
+        assert_eq!(distance_km(&paris, "ai"), None);
+    }
+
+    #[test]
+    fn parses_latitude_and_longitude() {
+        assert_eq!(parse_lat_lon("48.8566, 2.3522"), Some((48.8566, 2.3522)));
+        assert_eq!(parse_lat_lon("91,0"), None);
+        assert_eq!(parse_lat_lon("0,-181"), None);
+        assert_eq!(parse_lat_lon("48.8566"), None);
+    }
+}
@@ -0,0 +1,218 @@
+use libp2p::{
+    futures::{stream::FuturesUnordered, Stream},
+    request_response::OutboundFailure,
+    PeerId,
+};
+use log::{error, info};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio::sync::{mpsc, oneshot};
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::{
+    api::{self, ApiRequest, CreateError, ListError, NewCarinfo},
+    publish_carinfo, read_local_carinfo, read_local_carinfos,
+    vehicle::Details,
+    Carinfo,
+};
+
+/// Messages and service generated from `proto/carinfo.proto`.
+pub mod proto {
+    tonic::include_proto!("carinfo.v1");
+}
+
+use proto::carinfo_server::{Carinfo as CarinfoService, CarinfoServer};
+
+/// Serves the gRPC API on `addr`, answering like the REST API: records here, peers through
+/// `requests` to the event loop, see [`api::handle`]. Returns once `shutdown` resolves and
+/// calls in progress are answered.
+pub async fn serve(
+    addr: SocketAddr,
+    requests: mpsc::UnboundedSender<ApiRequest>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    info!("Serving the gRPC API on {}", addr);
+    if let Err(e) = Server::builder()
+        .add_service(CarinfoServer::new(Node { requests }))
+        .serve_with_shutdown(addr, shutdown)
+        .await
+    {
+        error!("error serving the gRPC API on {}, {}", addr, e);
+    }
+}
+
+struct Node {
+    requests: mpsc::UnboundedSender<ApiRequest>,
+}
+
+type PeerCarsStream = Pin<Box<dyn Stream<Item = Result<proto::PeerCars, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl CarinfoService for Node {
+    async fn list_local(
+        &self,
+        _: Request<proto::ListLocalRequest>,
+    ) -> Result<Response<proto::ListLocalResponse>, Status> {
+        let carinfos = read_local_carinfos().await.map_err(internal)?;
+        Ok(Response::new(proto::ListLocalResponse {
+            cars: carinfos.iter().map(car).collect(),
+        }))
+    }
+
+    type ListRemoteStream = PeerCarsStream;
+
+    /// Asks the peer given, or every connected one, and sends each answer as it arrives.
+    async fn list_remote(
+        &self,
+        request: Request<proto::ListRemoteRequest>,
+    ) -> Result<Response<PeerCarsStream>, Status> {
+        let peers: Vec<PeerId> = match request.into_inner().peer {
+            Some(peer) => vec![peer.parse().map_err(|e| {
+                Status::invalid_argument(format!("invalid peer id {}, {}", peer, e))
+            })?],
+            None => self
+                .peers()
+                .await?
+                .into_iter()
+                .filter(|p| p.connected)
+                .filter_map(|p| p.peer.parse().ok())
+                .collect(),
+        };
+        let answers: FuturesUnordered<_> = peers
+            .into_iter()
+            .map(|peer| {
+                let requests = self.requests.clone();
+                async move { Ok(peer_cars(&requests, peer).await) }
+            })
+            .collect();
+        Ok(Response::new(Box::pin(answers)))
+    }
+
+    async fn create_car(
+        &self,
+        request: Request<proto::CreateCarRequest>,
+    ) -> Result<Response<proto::Car>, Status> {
+        let request = request.into_inner();
+        let mut details = Details::default();
+        for (name, value) in &request.details {
+            details.set(name, value).map_err(Status::invalid_argument)?;
+        }
+        let new = NewCarinfo {
+            make: request.make,
+            model: request.model,
+            horsepower: request.horsepower,
+            vin: request.vin,
+            details,
+            force: request.force,
+        };
+        match api::create(new).await {
+            Ok(carinfo) => Ok(Response::new(car(&carinfo))),
+            Err(CreateError::Invalid(e)) => Err(Status::invalid_argument(e)),
+            Err(CreateError::Conflict(e)) => Err(Status::already_exists(e)),
+            Err(CreateError::Duplicate(id)) => Err(Status::already_exists(format!(
+                "carinfo {} is the same car, set force to create it anyway",
+                id
+            ))),
+            Err(CreateError::Failed(e)) => Err(Status::internal(e)),
+        }
+    }
+
+    async fn publish_car(
+        &self,
+        request: Request<proto::PublishCarRequest>,
+    ) -> Result<Response<proto::PublishCarResponse>, Status> {
+        let id = request.into_inner().id;
+        let id = usize::try_from(id)
+            .map_err(|_| Status::invalid_argument(format!("invalid id {}", id)))?;
+        if read_local_carinfo(id).await.map_err(internal)?.is_none() {
+            return Err(Status::not_found(format!("no carinfo with id {}", id)));
+        }
+        publish_carinfo(id).await.map_err(internal)?;
+        Ok(Response::new(proto::PublishCarResponse {}))
+    }
+
+    async fn list_peers(
+        &self,
+        _: Request<proto::ListPeersRequest>,
+    ) -> Result<Response<proto::ListPeersResponse>, Status> {
+        let peers = self
+            .peers()
+            .await?
+            .into_iter()
+            .map(|p| proto::Peer {
+                peer_id: p.peer,
+                connected: p.connected,
+            })
+            .collect();
+        Ok(Response::new(proto::ListPeersResponse { peers }))
+    }
+}
+
+impl Node {
+    async fn peers(&self) -> Result<Vec<api::PeerEntry>, Status> {
+        let (reply, peers) = oneshot::channel();
+        self.requests
+            .send(ApiRequest::Peers(reply))
+            .map_err(|_| stopped())?;
+        peers.await.map_err(|_| stopped())
+    }
+}
+
+/// The answer of `peer`, or why there is none.
+async fn peer_cars(requests: &mpsc::UnboundedSender<ApiRequest>, peer: PeerId) -> proto::PeerCars {
+    let (reply, carinfos) = oneshot::channel();
+    let answer = match requests.send(ApiRequest::PeerCarinfos(peer, reply)) {
+        Ok(()) => carinfos.await.ok(),
+        Err(_) => None,
+    };
+    let (cars, error) = match answer {
+        Some(Ok(carinfos)) => (carinfos.iter().map(car).collect(), String::new()),
+        Some(Err(ListError::Outbound(OutboundFailure::Timeout))) => {
+            (Vec::new(), format!("{} didn't answer in time", peer))
+        }
+        Some(Err(ListError::Outbound(e))) => (Vec::new(), format!("can't reach {}, {}", peer, e)),
+        Some(Err(ListError::Unverified(e))) => {
+            (Vec::new(), format!("invalid answer from {}, {}", peer, e))
+        }
+        None => (Vec::new(), "the node is shutting down".to_owned()),
+    };
+    proto::PeerCars {
+        peer: peer.to_string(),
+        cars,
+        error,
+    }
+}
+
+fn car(carinfo: &Carinfo) -> proto::Car {
+    proto::Car {
+        id: carinfo.id as u64,
+        origin: carinfo.origin.clone().unwrap_or_default(),
+        make: carinfo.make.clone(),
+        model: carinfo.model.clone(),
+        horsepower: carinfo.horsepower,
+        vin: carinfo.vin.clone(),
+        year: carinfo.year.map(u32::from),
+        mileage_km: carinfo.mileage_km,
+        price: carinfo.price.as_ref().map(|price| proto::Price {
+            amount: price.amount,
+            currency: price.currency.clone(),
+        }),
+        fuel: carinfo.fuel.map(|fuel| fuel.to_string()),
+        transmission: carinfo.transmission.map(|t| t.to_string()),
+        public: carinfo.public,
+        location: carinfo.location.clone(),
+        channels: carinfo.channels.iter().cloned().collect(),
+        tags: carinfo.tags.clone(),
+        created_at: carinfo.created_at.map(|at| at.to_rfc3339()),
+        updated_at: carinfo.updated_at.map(|at| at.to_rfc3339()),
+    }
+}
+
+fn internal(e: Box<dyn std::error::Error + Send + Sync>) -> Status {
+    Status::internal(e.to_string())
+}
+
+fn stopped() -> Status {
+    Status::unavailable("the node is shutting down")
+}
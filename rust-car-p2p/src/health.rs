@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::time::{Duration, Instant};
+
+/// Node state shared with the health and metrics endpoints, updated by the main loop.
+pub struct Health {
+    started: Instant,
+    pub listening: AtomicBool,
+    pub connected_peers: AtomicUsize,
+    pub discovered_peers: AtomicUsize,
+    pub requests_served: AtomicUsize,
+    pub responses_received: AtomicUsize,
+}
+
+impl Health {
+    pub fn new() -> Health {
+        Health {
+            started: Instant::now(),
+            listening: AtomicBool::new(false),
+            connected_peers: AtomicUsize::new(0),
+            discovered_peers: AtomicUsize::new(0),
+            requests_served: AtomicUsize::new(0),
+            responses_received: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health::new()
+    }
+}
@@ -0,0 +1,155 @@
+use log::{error, info};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde_json::json;
+use std::collections::HashSet;
+use std::env;
+use std::time::Duration;
+
+use crate::{read_local_carinfos, Carinfo, Result, PEER_ID};
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// Publishes the local catalog to an MQTT broker using Home Assistant's discovery format,
+/// so each car shows up as a device with a horsepower sensor and a "public" binary sensor.
+///
+/// Enabled by setting `CARINFO_MQTT=host[:port]` (optionally with `CARINFO_MQTT_USER` and
+/// `CARINFO_MQTT_PASSWORD`). Newly published cars are additionally announced on
+/// `carinfo/<peer_id>/events` for use as automation triggers.
+pub struct HomeAssistant {
+    client: AsyncClient,
+    known_public: HashSet<usize>,
+    synced: bool,
+}
+
+impl HomeAssistant {
+    pub fn from_env() -> Option<HomeAssistant> {
+        let broker = env::var("CARINFO_MQTT").ok()?;
+        let broker = broker.trim_start_matches("mqtt://");
+        let (host, port) = match broker.rsplit_once(':') {
+            Some((host, port)) => match port.parse() {
+                Ok(port) => (host, port),
+                Err(e) => {
+                    error!("invalid CARINFO_MQTT port {}, {}", port, e);
+                    return None;
+                }
+            },
+            None => (broker, DEFAULT_MQTT_PORT),
+        };
+
+        let mut options = MqttOptions::new(format!("carinfo-{}", *PEER_ID), host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let (Ok(user), Ok(password)) = (
+            env::var("CARINFO_MQTT_USER"),
+            env::var("CARINFO_MQTT_PASSWORD"),
+        ) {
+            options.set_credentials(user, password);
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, 64);
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = eventloop.poll().await {
+                    error!("mqtt connection error, {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        });
+        info!(
+            "Home Assistant integration enabled, broker {}:{}",
+            host, port
+        );
+
+        Some(HomeAssistant {
+            client,
+            known_public: HashSet::new(),
+            synced: false,
+        })
+    }
+
+    /// Republishes discovery configs and state for every local car.
+    pub async fn sync(&mut self) {
+        if let Err(e) = self.try_sync().await {
+            error!("error syncing catalog to Home Assistant, {}", e);
+        }
+    }
+
+    async fn try_sync(&mut self) -> Result<()> {
+        let carinfos = read_local_carinfos().await?;
+        for carinfo in carinfos.iter() {
+            self.publish_car(carinfo).await?;
+            if carinfo.public && self.known_public.insert(carinfo.id) && self.synced {
+                self.publish_event("published", carinfo).await?;
+            }
+        }
+        self.synced = true;
+        Ok(())
+    }
+
+    async fn publish_car(&self, carinfo: &Carinfo) -> Result<()> {
+        let object_id = format!("carinfo_{}_{}", *PEER_ID, carinfo.id);
+        let state_topic = format!("carinfo/{}/cars/{}", *PEER_ID, carinfo.id);
+        let device = json!({
+            "identifiers": [object_id],
+            "name": format!("{} {}", carinfo.make.trim(), carinfo.model.trim()),
+            "manufacturer": carinfo.make.trim(),
+            "model": carinfo.model.trim(),
+        });
+
+        let horsepower = json!({
+            "name": "Horsepower",
+            "unique_id": format!("{}_horsepower", object_id),
+            "state_topic": state_topic,
+            "value_template": "{{ value_json.horsepower }}",
+            "unit_of_measurement": "hp",
+            "json_attributes_topic": state_topic,
+            "device": device,
+        });
+        let public = json!({
+            "name": "Public",
+            "unique_id": format!("{}_public", object_id),
+            "state_topic": state_topic,
+            "value_template": "{{ 'ON' if value_json.public else 'OFF' }}",
+            "device": device,
+        });
+
+        self.publish(
+            format!(
+                "{}/sensor/{}/horsepower/config",
+                DISCOVERY_PREFIX, object_id
+            ),
+            horsepower.to_string(),
+        )
+        .await?;
+        self.publish(
+            format!(
+                "{}/binary_sensor/{}/public/config",
+                DISCOVERY_PREFIX, object_id
+            ),
+            public.to_string(),
+        )
+        .await?;
+        self.publish(state_topic, serde_json::to_string(carinfo)?)
+            .await
+    }
+
+    async fn publish_event(&self, event: &str, carinfo: &Carinfo) -> Result<()> {
+        let payload = json!({ "event": event, "car": carinfo });
+        self.client
+            .publish(
+                format!("carinfo/{}/events", *PEER_ID),
+                QoS::AtLeastOnce,
+                false,
+                payload.to_string(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn publish(&self, topic: String, payload: String) -> Result<()> {
+        self.client
+            .publish(topic, QoS::AtLeastOnce, true, payload)
+            .await?;
+        Ok(())
+    }
+}
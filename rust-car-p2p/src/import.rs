@@ -0,0 +1,605 @@
+use chrono::Utc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::{
+    crdt::{self, Field},
+    schema,
+    storage::{new_carinfo, storage},
+    vin, write_local_carinfos, Carinfo, Carinfos, Result, PEER_ID,
+};
+
+/// Catalog fields a CSV column can be imported into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Column {
+    Id,
+    Make,
+    Model,
+    Horsepower,
+    Vin,
+    Public,
+}
+
+impl Column {
+    pub const ALL: [Column; 6] = [
+        Column::Id,
+        Column::Make,
+        Column::Model,
+        Column::Horsepower,
+        Column::Vin,
+        Column::Public,
+    ];
+
+    /// Header names recognised without a mapping, compared case-insensitively. The first is
+    /// the one `export csv` writes.
+    fn headers(self) -> &'static [&'static str] {
+        match self {
+            Column::Id => &["id", "stock", "stock number", "stock no"],
+            Column::Make => &["make", "brand", "manufacturer"],
+            Column::Model => &["model"],
+            Column::Horsepower => &["horsepower", "hp", "power"],
+            Column::Vin => &["vin", "chassis number"],
+            Column::Public => &["public", "published"],
+        }
+    }
+
+    pub fn header(self) -> &'static str {
+        self.headers()[0]
+    }
+}
+
+impl std::str::FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Column::ALL
+            .into_iter()
+            .find(|column| column.headers().contains(&s.to_lowercase().as_str()))
+            .ok_or_else(|| {
+                format!(
+                    "unknown field {}, expected id, make, model, hp, vin or public",
+                    s
+                )
+            })
+    }
+}
+
+/// What to do with a row whose id is already used by a record in the catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Duplicates {
+    /// Leave the record alone and report the row.
+    #[default]
+    Skip,
+    /// Overwrite the record's imported fields, keeping the rest.
+    Replace,
+    /// Import the row as a new record under a free id.
+    Renumber,
+}
+
+impl std::str::FromStr for Duplicates {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Duplicates::Skip),
+            "replace" => Ok(Duplicates::Replace),
+            "renumber" => Ok(Duplicates::Renumber),
+            other => Err(format!(
+                "unknown duplicates policy {}, expected skip, replace or renumber",
+                other
+            )),
+        }
+    }
+}
+
+/// How `import csv` reads a file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CsvImport {
+    /// Columns named explicitly, for headers that aren't recognised on their own.
+    pub mapping: Vec<(Column, String)>,
+    pub duplicates: Duplicates,
+    /// Only report what would be imported.
+    pub dry_run: bool,
+}
+
+/// How `import json` reads a file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct JsonImport {
+    /// Give cars whose id is taken by another car a new one, instead of refusing the import.
+    pub merge: bool,
+    pub dry_run: bool,
+}
+
+/// What an import did, or would do on a dry run.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub added: usize,
+    pub updated: usize,
+    pub renumbered: usize,
+    /// Line numbers of the rows left out (positions in the file for `import json`), with the
+    /// reason.
+    pub skipped: Vec<(usize, String)>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added ({} under a new id), {} updated, {} skipped",
+            self.added,
+            self.renumbered,
+            self.updated,
+            self.skipped.len()
+        )
+    }
+}
+
+/// Imports the rows of the CSV file at `path` into the local catalog in one write. The first
+/// line names the columns; make, model and horsepower are required, id and public optional.
+/// Rows without an id get the next free one.
+pub async fn csv(path: &str, options: &CsvImport) -> Result<ImportReport> {
+    let content = tokio::fs::read(path).await?;
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_slice());
+    let columns = map_columns(reader.headers()?, &options.mapping)?;
+
+    let mut catalog: BTreeMap<usize, Carinfo> = storage()
+        .all()
+        .await?
+        .into_iter()
+        .map(|r| (r.id, r))
+        .collect();
+    let mut report = ImportReport::default();
+    let mut rows = Vec::new();
+    let mut in_file = HashSet::new();
+    let mut vins = HashSet::new();
+    for (i, row) in reader.records().enumerate() {
+        // the header is line 1
+        let line = i + 2;
+        match row
+            .map_err(|e| e.to_string())
+            .and_then(|r| parse_row(&r, &columns))
+        {
+            Ok(row) if row.id.is_some_and(|id| !in_file.insert(id)) => report.skipped.push((
+                line,
+                format!(
+                    "id {} appears earlier in the file",
+                    row.id.expect("checked")
+                ),
+            )),
+            Ok(row)
+                if row
+                    .vin
+                    .as_ref()
+                    .is_some_and(|vin| !vins.insert(vin.clone())) =>
+            {
+                report.skipped.push((
+                    line,
+                    format!(
+                        "VIN {} appears earlier in the file",
+                        row.vin.expect("checked")
+                    ),
+                ))
+            }
+            Ok(row) => rows.push((line, row)),
+            Err(e) => report.skipped.push((line, e)),
+        }
+    }
+
+    // new ids stay clear of the ones rows further down ask for
+    let mut next_id = storage().next_id().await?;
+    for (line, row) in rows {
+        let existing = row.id.filter(|id| catalog.contains_key(id));
+        if let Some(vin) = &row.vin {
+            let replaced = existing.filter(|_| options.duplicates == Duplicates::Replace);
+            if let Some(other) = vin_owner(&catalog, vin).filter(|other| Some(*other) != replaced) {
+                report.skipped.push((
+                    line,
+                    format!("VIN {} is already used by car {}", vin, other),
+                ));
+                continue;
+            }
+        }
+        match (existing, options.duplicates) {
+            (Some(id), Duplicates::Skip) => report
+                .skipped
+                .push((line, format!("id {} is already in the catalog", id))),
+            (Some(id), Duplicates::Replace) => {
+                row.apply(catalog.get_mut(&id).expect("checked"));
+                report.updated += 1;
+            }
+            (existing, _) => {
+                let id = match row.id {
+                    Some(id) if existing.is_none() => id,
+                    _ => {
+                        while catalog.contains_key(&next_id) || in_file.contains(&next_id) {
+                            next_id += 1;
+                        }
+                        report.renumbered += usize::from(existing.is_some());
+                        next_id
+                    }
+                };
+                let mut carinfo = new_carinfo(id, &row.make, &row.model, row.horsepower);
+                carinfo.vin = row.vin;
+                carinfo.public = row.public.unwrap_or(false);
+                catalog.insert(id, carinfo);
+                report.added += 1;
+            }
+        }
+    }
+    report.skipped.sort();
+    if !options.dry_run && report.added + report.updated > 0 {
+        write_local_carinfos(&catalog.into_values().collect()).await?;
+    }
+    Ok(report)
+}
+
+/// Imports the cars of a file written by `export json` on another node, in one write. A car
+/// with the same content as one in the catalog is skipped, a car with the id and creation time
+/// of one in the catalog is another copy of it and merged field by field, see
+/// [`crdt::merge`]. Other cars keep their id if it is free; taken ids are reassigned with
+/// `merge` and make the import fail without.
+pub async fn json(path: &str, options: &JsonImport) -> Result<ImportReport> {
+    let content = tokio::fs::read(path).await?;
+    let incoming = schema::decode(&content)
+        .map_err(|e| format!("{} is not a catalog written by export json, {}", path, e))?;
+    let mut catalog: BTreeMap<usize, Carinfo> = storage()
+        .all()
+        .await?
+        .into_iter()
+        .map(|r| (r.id, r))
+        .collect();
+    let next_id = storage().next_id().await?;
+    let report = merge_into(
+        &mut catalog,
+        incoming,
+        options.merge,
+        next_id,
+        &PEER_ID.to_string(),
+    )?;
+    if !options.dry_run && report.added + report.updated > 0 {
+        write_local_carinfos(&catalog.into_values().collect()).await?;
+    }
+    Ok(report)
+}
+
+/// What [`json`] does with the cars of the file: `incoming` goes into `catalog` as cars of
+/// `origin`, taken ids reassigned from `next_id` on if `merge` is set.
+fn merge_into(
+    catalog: &mut BTreeMap<usize, Carinfo>,
+    incoming: Carinfos,
+    merge: bool,
+    mut next_id: usize,
+    origin: &str,
+) -> Result<ImportReport> {
+    let taken: Vec<usize> = incoming
+        .iter()
+        .filter(|r| {
+            catalog
+                .get(&r.id)
+                .is_some_and(|known| !same_car(known, r) && !same_content(known, r))
+        })
+        .map(|r| r.id)
+        .collect();
+    if !merge && !taken.is_empty() {
+        let taken: Vec<String> = taken.iter().map(usize::to_string).collect();
+        return Err(format!(
+            "car ids {} are used by other cars in the catalog, add --merge to give them new ids",
+            taken.join(", ")
+        )
+        .into());
+    }
+
+    let mut report = ImportReport::default();
+    let wanted: HashSet<usize> = incoming.iter().map(|r| r.id).collect();
+    for (i, mut carinfo) in incoming.into_iter().enumerate() {
+        let position = i + 1;
+        carinfo.origin = Some(origin.to_owned());
+        // signed again as ours when sent
+        carinfo.origin_signature = None;
+        if let Some(known) = catalog.values().find(|known| same_content(known, &carinfo)) {
+            report
+                .skipped
+                .push((position, format!("same as car {}", known.id)));
+            continue;
+        }
+        let copy_of = catalog
+            .get(&carinfo.id)
+            .filter(|known| same_car(known, &carinfo))
+            .map(|known| known.id);
+        if let Some(vin) = &carinfo.vin {
+            if let Some(other) = vin_owner(catalog, vin).filter(|other| Some(*other) != copy_of) {
+                report.skipped.push((
+                    position,
+                    format!("VIN {} is already used by car {}", vin, other),
+                ));
+                continue;
+            }
+        }
+        match catalog.get_mut(&carinfo.id) {
+            Some(known) if same_car(known, &carinfo) => {
+                let before = known.clone();
+                crdt::merge(known, &carinfo);
+                if same_content(&before, known) {
+                    report
+                        .skipped
+                        .push((position, format!("older copy of car {}", known.id)));
+                } else {
+                    report.updated += 1;
+                }
+            }
+            Some(_) => {
+                while catalog.contains_key(&next_id) || wanted.contains(&next_id) {
+                    next_id += 1;
+                }
+                carinfo.id = next_id;
+                catalog.insert(next_id, carinfo);
+                report.added += 1;
+                report.renumbered += 1;
+            }
+            None => {
+                catalog.insert(carinfo.id, carinfo);
+                report.added += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// The record in `catalog` with `vin`.
+fn vin_owner(catalog: &BTreeMap<usize, Carinfo>, vin: &str) -> Option<usize> {
+    catalog
+        .values()
+        .find(|r| {
+            r.vin
+                .as_deref()
+                .is_some_and(|v| v.eq_ignore_ascii_case(vin))
+        })
+        .map(|r| r.id)
+}
+
+/// Whether two records are copies of one car: ours keep their id when exported, and the
+/// creation time never changes.
+fn same_car(known: &Carinfo, other: &Carinfo) -> bool {
+    known.id == other.id && known.created_at.is_some() && known.created_at == other.created_at
+}
+
+/// Whether two records hold the same car in the same state, whatever their ids.
+fn same_content(known: &Carinfo, other: &Carinfo) -> bool {
+    let content = |carinfo: &Carinfo| {
+        let mut carinfo = carinfo.clone();
+        carinfo.id = 0;
+        carinfo.origin = None;
+        carinfo.origin_signature = None;
+        carinfo.content_hash = None;
+        serde_json::to_value(carinfo).ok()
+    };
+    content(known) == content(other)
+}
+
+/// A row of the file, checked.
+struct Row {
+    id: Option<usize>,
+    make: String,
+    model: String,
+    horsepower: u32,
+    vin: Option<String>,
+    public: Option<bool>,
+}
+
+impl Row {
+    fn apply(&self, carinfo: &mut Carinfo) {
+        carinfo.make = self.make.clone();
+        carinfo.model = self.model.clone();
+        carinfo.horsepower = self.horsepower;
+        let mut changed = vec![Field::Make, Field::Model, Field::Horsepower];
+        if self.vin.is_some() {
+            carinfo.vin = self.vin.clone();
+        }
+        if let Some(public) = self.public {
+            carinfo.public = public;
+            changed.push(Field::Public);
+        }
+        carinfo.updated_at = Some(Utc::now());
+        carinfo.stamps.touch(&changed);
+    }
+}
+
+/// Which column each field is read from: explicit mappings first, then recognised headers.
+fn map_columns(
+    headers: &csv::StringRecord,
+    mapping: &[(Column, String)],
+) -> Result<HashMap<Column, usize>> {
+    let mut columns = HashMap::new();
+    for (field, header) in mapping {
+        let index = headers
+            .iter()
+            .position(|h| h.eq_ignore_ascii_case(header))
+            .ok_or_else(|| format!("no column named {}", header))?;
+        columns.insert(*field, index);
+    }
+    for field in Column::ALL {
+        if columns.contains_key(&field) {
+            continue;
+        }
+        if let Some(index) = headers
+            .iter()
+            .position(|h| field.headers().contains(&h.to_lowercase().as_str()))
+        {
+            columns.insert(field, index);
+        }
+    }
+    let missing: Vec<&str> = [Column::Make, Column::Model, Column::Horsepower]
+        .into_iter()
+        .filter(|field| !columns.contains_key(field))
+        .map(Column::header)
+        .collect();
+    if !missing.is_empty() {
+        let found: Vec<&str> = headers.iter().collect();
+        return Err(format!(
+            "no column for {}, map one with <field>=<column>; the columns are {}",
+            missing.join(", "),
+            found.join(", ")
+        )
+        .into());
+    }
+    Ok(columns)
+}
+
+fn parse_row(
+    record: &csv::StringRecord,
+    columns: &HashMap<Column, usize>,
+) -> std::result::Result<Row, String> {
+    let value = |field: Column| {
+        columns
+            .get(&field)
+            .and_then(|index| record.get(*index))
+            .filter(|value| !value.is_empty())
+    };
+    let required = |field: Column| {
+        value(field)
+            .map(str::to_owned)
+            .ok_or_else(|| format!("{} is empty", field.header()))
+    };
+    let horsepower = required(Column::Horsepower)?;
+    Ok(Row {
+        id: value(Column::Id)
+            .map(|id| id.parse().map_err(|e| format!("invalid id {}, {}", id, e)))
+            .transpose()?,
+        make: required(Column::Make)?,
+        model: required(Column::Model)?,
+        horsepower: horsepower
+            .parse::<f64>()
+            .map(|hp| hp.round() as u32)
+            .map_err(|_| format!("invalid horsepower {}", horsepower))?,
+        vin: value(Column::Vin).map(vin::validate).transpose()?,
+        public: value(Column::Public)
+            .map(|public| match public.to_lowercase().as_str() {
+                "true" | "yes" | "y" | "1" | "x" => Ok(true),
+                "false" | "no" | "n" | "0" => Ok(false),
+                other => Err(format!("invalid public {}, expected yes or no", other)),
+            })
+            .transpose()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const CREATED: &str = "2024-03-01T10:00:00Z";
+
+    fn carinfo(id: usize, make: &str, horsepower: u32, extra: Value) -> Carinfo {
+        let mut value = json!({
+            "id": id,
+            "make": make,
+            "model": "Golf",
+            "horsepower": horsepower,
+            "public": true,
+        });
+        if let (Some(value), Value::Object(extra)) = (value.as_object_mut(), extra) {
+            value.extend(extra);
+        }
+        serde_json::from_value(value).expect("valid record")
+    }
+
+    /// A copy of car `id` created at [`CREATED`], its horsepower last changed at `millis`.
+    fn copy(id: usize, horsepower: u32, millis: u64) -> Carinfo {
+        carinfo(
+            id,
+            "VW",
+            horsepower,
+            json!({
+                "created_at": CREATED,
+                "stamps": {"horsepower": {"millis": millis, "counter": 0, "node": "a"}},
+            }),
+        )
+    }
+
+    fn catalog(carinfos: Vec<Carinfo>) -> BTreeMap<usize, Carinfo> {
+        carinfos.into_iter().map(|r| (r.id, r)).collect()
+    }
+
+    #[test]
+    fn adds_new_cars_as_ours() {
+        let mut known = catalog(vec![carinfo(0, "VW", 150, json!({}))]);
+        let report = merge_into(
+            &mut known,
+            vec![carinfo(5, "BMW", 300, json!({}))],
+            false,
+            1,
+            "me",
+        )
+        .unwrap();
+        assert_eq!((report.added, report.renumbered), (1, 0));
+        assert_eq!(known[&5].make, "BMW");
+        assert_eq!(known[&5].origin.as_deref(), Some("me"));
+    }
+
+    #[test]
+    fn skips_cars_the_catalog_has_already() {
+        let mut known = catalog(vec![carinfo(0, "VW", 150, json!({}))]);
+        let report = merge_into(
+            &mut known,
+            vec![carinfo(7, "VW", 150, json!({}))],
+            false,
+            1,
+            "me",
+        )
+        .unwrap();
+        assert_eq!(report.added, 0);
+        assert_eq!(report.skipped, vec![(1, "same as car 0".to_owned())]);
+        assert_eq!(known.len(), 1);
+    }
+
+    #[test]
+    fn merges_copies_of_the_same_car() {
+        let mut known = catalog(vec![copy(2, 150, 1000)]);
+        let report = merge_into(&mut known, vec![copy(2, 200, 2000)], false, 3, "me").unwrap();
+        assert_eq!((report.added, report.updated), (0, 1));
+        assert_eq!(known[&2].horsepower, 200);
+
+        let report = merge_into(&mut known, vec![copy(2, 120, 500)], false, 3, "me").unwrap();
+        assert_eq!(report.updated, 0);
+        assert_eq!(report.skipped, vec![(1, "older copy of car 2".to_owned())]);
+        assert_eq!(known[&2].horsepower, 200);
+    }
+
+    #[test]
+    fn taken_ids_need_merge() {
+        let mut known = catalog(vec![carinfo(0, "VW", 150, json!({}))]);
+        let incoming = || {
+            vec![
+                carinfo(0, "BMW", 300, json!({})),
+                carinfo(1, "Audi", 250, json!({})),
+            ]
+        };
+        assert!(merge_into(&mut known, incoming(), false, 1, "me").is_err());
+        assert_eq!(known.len(), 1);
+
+        let report = merge_into(&mut known, incoming(), true, 1, "me").unwrap();
+        assert_eq!((report.added, report.renumbered), (2, 1));
+        // id 1 is wanted by the second car, so the first one moves past it
+        assert_eq!(known[&1].make, "Audi");
+        assert_eq!(known[&2].make, "BMW");
+    }
+
+    #[test]
+    fn skips_cars_with_a_vin_in_use() {
+        let vin = json!({"vin": "WVWZZZ1JZXW000001"});
+        let mut known = catalog(vec![carinfo(0, "VW", 150, vin.clone())]);
+        let report =
+            merge_into(&mut known, vec![carinfo(1, "BMW", 300, vin)], true, 1, "me").unwrap();
+        assert_eq!(report.added, 0);
+        assert_eq!(
+            report.skipped,
+            vec![(
+                1,
+                "VIN WVWZZZ1JZXW000001 is already used by car 0".to_owned()
+            )]
+        );
+    }
+}
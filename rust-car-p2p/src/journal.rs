@@ -0,0 +1,346 @@
+use log::error;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::{chat, format, storage, Carinfo, Result, PEER_ID};
+
+/// One JSON entry a line. Entries are only ever appended.
+const JOURNAL_FILE_PATH: &str = "./journal.jsonl";
+
+/// The sequence number of the last entry, read from the file on the first change. Held while
+/// appending, so entries are written in order.
+static LAST_SEQ: Lazy<Mutex<Option<u64>>> = Lazy::new(|| Mutex::new(None));
+
+/// How many undone changes `redo` can bring back.
+const REDO_DEPTH: usize = 20;
+
+/// An undone change, with the record as the change left it.
+type Redo = (Entry, Option<Carinfo>);
+
+/// Changes undone this session, latest last. Any other change empties it.
+static REDO: Lazy<std::sync::Mutex<Vec<Redo>>> = Lazy::new(|| std::sync::Mutex::new(Vec::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Created,
+    /// Any change of the record's fields, OBD-II data, photos and the like included.
+    Edited,
+    Published,
+    Unpublished,
+    Granted,
+    Revoked,
+    /// Written by `import` over what the catalog had.
+    Imported,
+    /// Duplicates were merged into the record, see [`crate::merge_duplicates`].
+    Merged,
+    Deleted,
+    /// An earlier change was reverted with `undo`.
+    Undone,
+    /// An undone change was made again with `redo`.
+    Redone,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Change::Created => "created",
+            Change::Edited => "edited",
+            Change::Published => "published",
+            Change::Unpublished => "unpublished",
+            Change::Granted => "granted",
+            Change::Revoked => "revoked",
+            Change::Imported => "imported",
+            Change::Merged => "merged",
+            Change::Deleted => "deleted",
+            Change::Undone => "undone",
+            Change::Redone => "redone",
+        })
+    }
+}
+
+/// A change of one of our records.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Counts from 1 in the order of the changes, so a peer that saw the journal up to one
+    /// entry can later ask for the rest.
+    pub seq: u64,
+    /// Unix timestamp of the change.
+    pub at: u64,
+    /// Peer id of the node that made the change.
+    pub actor: String,
+    pub car_id: usize,
+    pub change: Change,
+    /// Content hash of the record after the change, see [`crate::digest::of`]. None once it
+    /// is deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// The record as it was before the change, which `undo` brings back. None if there was
+    /// no record yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Carinfo>,
+    /// `before` of an unpublished record, encrypted instead when storage keeps those
+    /// encrypted, see [`crate::storage::Storage::encrypt_private`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before_encrypted: Option<String>,
+    /// The entry an undo or redo is about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverts: Option<u64>,
+}
+
+impl Entry {
+    /// e.g. `edited car 3 (2m ago)`.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} car {} ({})",
+            self.change,
+            self.car_id,
+            format::age(self.at)
+        )
+    }
+}
+
+/// Appends a change of car `car_id` to the journal, from `before` to `after`, `None` where
+/// there is no record. Failing to is logged; the change itself is stored anyway.
+pub async fn record(
+    car_id: usize,
+    change: Change,
+    before: Option<Carinfo>,
+    after: Option<&Carinfo>,
+) {
+    REDO.lock().expect("redo lock").clear();
+    append(car_id, change, None, before, after).await;
+}
+
+/// Journals that `entry` was undone, leaving `after`, and keeps what it undid for `redo`.
+pub async fn undone(entry: Entry, before: Option<Carinfo>, after: Option<&Carinfo>) {
+    append(
+        entry.car_id,
+        Change::Undone,
+        Some(entry.seq),
+        before.clone(),
+        after,
+    )
+    .await;
+    push_redo(&mut REDO.lock().expect("redo lock"), (entry, before));
+}
+
+/// Keeps an undone change for `redo`, forgetting the oldest beyond [`REDO_DEPTH`].
+fn push_redo(redo: &mut Vec<Redo>, undone: Redo) {
+    redo.push(undone);
+    if redo.len() > REDO_DEPTH {
+        redo.remove(0);
+    }
+}
+
+/// Journals that `entry` was made again, leaving `after`.
+pub async fn redone(entry: &Entry, before: Option<Carinfo>, after: Option<&Carinfo>) {
+    append(entry.car_id, Change::Redone, Some(entry.seq), before, after).await;
+}
+
+async fn append(
+    car_id: usize,
+    change: Change,
+    reverts: Option<u64>,
+    before: Option<Carinfo>,
+    after: Option<&Carinfo>,
+) {
+    let (before, before_encrypted) = match before {
+        Some(carinfo) if !carinfo.public => match encrypt(&carinfo) {
+            Ok(Some(sealed)) => (None, Some(sealed)),
+            Ok(None) => (Some(carinfo), None),
+            Err(e) => {
+                // undo can't bring it back then, but it isn't left in plaintext either
+                error!("error encrypting carinfo {} for the journal, {}", car_id, e);
+                (None, None)
+            }
+        },
+        before => (before, None),
+    };
+    let entry = Entry {
+        seq: 0,
+        at: chat::now(),
+        actor: PEER_ID.to_string(),
+        car_id,
+        change,
+        hash: after.and_then(|carinfo| carinfo.content_hash.clone()),
+        before,
+        before_encrypted,
+        reverts,
+    };
+    if let Err(e) = write(entry).await {
+        error!("error writing the journal, {}", e);
+    }
+}
+
+fn encrypt(carinfo: &Carinfo) -> Result<Option<String>> {
+    storage().encrypt_private(&serde_json::to_vec(carinfo)?)
+}
+
+/// Fills in `before` of an entry that has it encrypted.
+fn decrypt(entry: &mut Entry) -> Result<()> {
+    if let Some(sealed) = entry.before_encrypted.take() {
+        entry.before = Some(serde_json::from_slice(
+            &storage().decrypt_private(&sealed)?,
+        )?);
+    }
+    Ok(())
+}
+
+async fn write(mut entry: Entry) -> Result<()> {
+    let mut last_seq = LAST_SEQ.lock().await;
+    let seq = match *last_seq {
+        Some(seq) => seq,
+        None => read().await?.last().map_or(0, |entry| entry.seq),
+    } + 1;
+    entry.seq = seq;
+    let mut line = serde_json::to_vec(&entry)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(JOURNAL_FILE_PATH)
+        .await?;
+    file.write_all(&line).await?;
+    file.flush().await?;
+    *last_seq = Some(seq);
+    Ok(())
+}
+
+/// Every entry, oldest first. A line cut short by a crash is skipped.
+pub async fn read() -> Result<Vec<Entry>> {
+    let content = match fs::read_to_string(JOURNAL_FILE_PATH).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// The changes of car `id`, oldest first.
+pub async fn history(id: usize) -> Result<Vec<Entry>> {
+    let mut entries = read().await?;
+    entries.retain(|entry| entry.car_id == id);
+    Ok(entries)
+}
+
+/// The latest change `undo` can revert: not an undo or redo itself, and not undone since.
+pub async fn undoable() -> Result<Option<Entry>> {
+    latest_undoable(read().await?)
+        .map(|mut entry| decrypt(&mut entry).map(|()| entry))
+        .transpose()
+}
+
+/// [`undoable`] among `entries`, oldest first.
+fn latest_undoable(entries: Vec<Entry>) -> Option<Entry> {
+    let mut undone = HashSet::new();
+    for entry in &entries {
+        match (entry.change, entry.reverts) {
+            (Change::Undone, Some(seq)) => undone.insert(seq),
+            (Change::Redone, Some(seq)) => undone.remove(&seq),
+            _ => false,
+        };
+    }
+    entries.into_iter().rev().find(|entry| {
+        !matches!(entry.change, Change::Undone | Change::Redone) && !undone.contains(&entry.seq)
+    })
+}
+
+/// The change undone last this session, and the record it left, for `redo`.
+pub fn take_redo() -> Option<Redo> {
+    REDO.lock().expect("redo lock").pop()
+}
+
+/// The entries after `seq`, what a peer that saw the journal up to `seq` is missing.
+pub async fn since(seq: u64) -> Result<Vec<Entry>> {
+    let mut entries = read().await?;
+    entries.retain(|entry| entry.seq > seq);
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replays `changes`, each with the seq of the entry it reverts for undo and redo.
+    fn journal(changes: &[(Change, Option<u64>)]) -> Vec<Entry> {
+        changes
+            .iter()
+            .enumerate()
+            .map(|(i, &(change, reverts))| Entry {
+                seq: i as u64 + 1,
+                at: 0,
+                actor: "peer".to_owned(),
+                car_id: i,
+                change,
+                hash: None,
+                before: None,
+                before_encrypted: None,
+                reverts,
+            })
+            .collect()
+    }
+
+    fn undoable_seq(changes: &[(Change, Option<u64>)]) -> Option<u64> {
+        latest_undoable(journal(changes)).map(|entry| entry.seq)
+    }
+
+    #[test]
+    fn undo_walks_back_through_changes() {
+        assert_eq!(undoable_seq(&[]), None);
+        let mut changes = vec![(Change::Created, None), (Change::Edited, None)];
+        assert_eq!(undoable_seq(&changes), Some(2));
+        changes.push((Change::Undone, Some(2)));
+        assert_eq!(undoable_seq(&changes), Some(1));
+        changes.push((Change::Undone, Some(1)));
+        assert_eq!(undoable_seq(&changes), None);
+    }
+
+    #[test]
+    fn a_redone_change_can_be_undone_again() {
+        let changes = [
+            (Change::Created, None),
+            (Change::Edited, None),
+            (Change::Undone, Some(2)),
+            (Change::Undone, Some(1)),
+            (Change::Redone, Some(1)),
+        ];
+        assert_eq!(undoable_seq(&changes), Some(1));
+    }
+
+    #[test]
+    fn a_new_change_after_undo_is_undone_first() {
+        let mut changes = vec![
+            (Change::Created, None),
+            (Change::Edited, None),
+            (Change::Undone, Some(2)),
+            (Change::Published, None),
+        ];
+        assert_eq!(undoable_seq(&changes), Some(4));
+        changes.push((Change::Undone, Some(4)));
+        // the change undone before stays undone
+        assert_eq!(undoable_seq(&changes), Some(1));
+    }
+
+    #[test]
+    fn redo_keeps_the_latest_undone_changes() {
+        let mut redo = Vec::new();
+        for entry in journal(&[(Change::Edited, None); REDO_DEPTH + 5]) {
+            push_redo(&mut redo, (entry, None));
+        }
+        assert_eq!(redo.len(), REDO_DEPTH);
+        assert_eq!(redo[0].0.seq, 6);
+        assert_eq!(
+            redo.pop().map(|(entry, _)| entry.seq),
+            Some(REDO_DEPTH as u64 + 5)
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use libp2p::identity::{ed25519, Keypair};
+use log::{error, info};
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use crate::Result;
+
+const DEFAULT_IDENTITY_PATH: &str = "./identity.key";
+
+/// Where the node keypair lives, `./identity.key` unless `CARINFO_IDENTITY` says otherwise.
+pub fn path() -> PathBuf {
+    env::var("CARINFO_IDENTITY")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_IDENTITY_PATH))
+}
+
+/// Loads the stored keypair, generating and storing one on first run. A key file that can't be
+/// read is left alone and a temporary identity is used instead, so it can still be recovered.
+pub fn load_or_generate() -> Keypair {
+    let path = path();
+    match fs::read(&path) {
+        Ok(mut bytes) => match ed25519::Keypair::decode(&mut bytes) {
+            Ok(keypair) => return Keypair::Ed25519(keypair),
+            Err(e) => error!("invalid identity in {}, {}", path.display(), e),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let keypair = ed25519::Keypair::generate();
+            match write_key(&path, &keypair.encode()) {
+                Ok(()) => info!("Generated a new identity in {}", path.display()),
+                Err(e) => error!("error storing identity in {}, {}", path.display(), e),
+            }
+            return Keypair::Ed25519(keypair);
+        }
+        Err(e) => error!("error reading identity from {}, {}", path.display(), e),
+    }
+    error!("using a temporary identity for this session");
+    Keypair::generate_ed25519()
+}
+
+/// `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    name.into()
+}
+
+/// Moves the current key aside so the next start generates a new peer id: to `<path>.old`,
+/// or `<path>.old.1`, `<path>.old.2` and so on if earlier keys are kept there already.
+pub fn rotate() -> Result<()> {
+    let path = path();
+    let old = (0..)
+        .map(|n| match n {
+            0 => with_suffix(&path, ".old"),
+            n => with_suffix(&path, &format!(".old.{}", n)),
+        })
+        .find(|old| !old.exists())
+        .expect("a free name");
+    match fs::rename(&path, old) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Writes `key`, an encoded ed25519 keypair, next to the current key, for [`install`] to put
+/// in place.
+pub fn stage(key: &[u8]) -> Result<()> {
+    let tmp = with_suffix(&path(), ".tmp");
+    match fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    write_key(&tmp, key)?;
+    Ok(())
+}
+
+/// Puts the key [`stage`] wrote in place of the current key, which is moved aside like on
+/// [`rotate`]. Takes effect on the next start.
+pub fn install() -> Result<()> {
+    let path = path();
+    rotate()?;
+    fs::rename(with_suffix(&path, ".tmp"), &path)?;
+    Ok(())
+}
+
+/// Removes a key [`stage`] wrote without putting it in place.
+pub fn discard() {
+    let _ = fs::remove_file(with_suffix(&path(), ".tmp"));
+}
+
+fn write_key(path: &Path, key: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    io::Write::write_all(&mut options.open(path)?, key)
+}
@@ -0,0 +1,275 @@
+//! Records, wire protocol, storage, network behaviour and commands of the carinfo p2p network.
+//! The `rust-car-p2p` binary is a thin terminal front end over [`behaviour`] and [`commands`];
+//! applications can embed a node through [`CarP2pClient`] instead.
+
+pub mod access;
+pub mod aliases;
+pub mod api;
+pub mod atomic;
+pub mod audit;
+pub mod backup;
+pub mod behaviour;
+pub mod cache;
+pub mod can;
+pub mod catchup;
+pub mod channels;
+pub mod chat;
+pub mod client;
+pub mod club;
+pub mod commands;
+pub mod config;
+pub mod control;
+pub mod crdt;
+pub mod dedupe;
+pub mod dht;
+pub mod digest;
+pub mod export;
+pub mod filter;
+pub mod format;
+pub mod geo;
+pub mod grpc;
+pub mod health;
+pub mod homeassistant;
+pub mod import;
+pub mod journal;
+pub mod keystore;
+pub mod listing;
+pub mod lookup;
+pub mod metrics;
+pub mod nat;
+pub mod obd;
+pub mod offers;
+pub mod outbox;
+pub mod output;
+pub mod ownership;
+pub mod pager;
+pub mod parser;
+pub mod peers;
+pub mod photos;
+pub mod price;
+pub mod profile;
+pub mod protocol;
+pub mod queue;
+pub mod ratelimit;
+pub mod replica;
+pub mod round;
+pub mod schema;
+pub mod search;
+pub mod seen;
+pub mod share;
+pub mod storage;
+pub mod tags;
+pub mod telemetry;
+pub mod vault;
+pub mod vehicle;
+pub mod vin;
+pub mod wire;
+
+use can::CanSummary;
+use chrono::{DateTime, Utc};
+use crdt::Stamps;
+use libp2p::{identity, PeerId};
+use lookup::Attributes;
+use obd::ObdStats;
+use once_cell::sync::Lazy;
+use photos::Photo;
+use price::PricePoint;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use vehicle::{Fuel, Price, Transmission};
+
+pub use client::{CarP2pClient, NodeEvent, NodeEvents};
+pub use config::Config;
+pub use protocol::{
+    gossipsub, relay_transport, transport, CarPublished, CarinfoDeleted, ListMode, ListRequest,
+    ListResponse, SearchRequest,
+};
+pub use storage::{
+    attach_can_summary, attach_obd_stats, attach_photo, create_new_carinfo, delete_carinfo,
+    edit_carinfo, find_duplicate, find_local_carinfos, grant_carinfo, init_storage,
+    merge_attributes, merge_duplicates, publish_carinfo, read_carinfos_for, read_local_carinfo,
+    read_local_carinfos, read_public_carinfos, record_prices, revoke_carinfo, set_channels,
+    set_location, storage, tag_carinfo, unpublish_carinfo, untag_carinfo, write_local_carinfos,
+    STORAGE_FILE_PATH,
+};
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+pub type Carinfos = Vec<Carinfo>;
+
+/// The node identity, persisted by [`keystore`] so the peer id survives restarts.
+pub static KEYS: Lazy<identity::Keypair> = Lazy::new(keystore::load_or_generate);
+pub static PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(KEYS.public()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Carinfo {
+    /// Only unique among the records of one peer, see [`CarinfoKey`].
+    pub id: usize,
+    /// Peer id of the node that created the record. Older records don't have it; they were
+    /// created by the node that stores or sends them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// The origin's signature over what the record describes, see [`ownership::sign`]. Records
+    /// from peers that predate signatures have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin_signature: Option<String>,
+    /// Set on records from a peer that name another origin without that origin's signature,
+    /// see [`ownership::verify`]. Their origin is only a claim and isn't used in their key.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unverified: bool,
+    /// Hash of the record's content, see [`digest::of`]. Set whenever the record is stored or
+    /// sent; records from peers that predate hashes have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    pub make: String,
+    pub model: String,
+    #[serde(deserialize_with = "deserialize_horsepower")]
+    pub horsepower: u32,
+    /// Vehicle identification number, checked with [`vin::validate`] and unique among our
+    /// records.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vin: Option<String>,
+    /// Model year.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub year: Option<u16>,
+    /// Odometer reading as given by the owner, unlike the measured one in `obd` or `can`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mileage_km: Option<u32>,
+    /// Asking price.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<Price>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<Fuel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transmission: Option<Transmission>,
+    pub public: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub obd: Option<ObdStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub can: Option<CanSummary>,
+    /// Details added by external lookups, keyed `<provider>.<field>`.
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    pub attributes: Attributes,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub price_history: Vec<PricePoint>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub photos: Vec<Photo>,
+    /// Coarse geohash of where the car is, see [`geo::PRECISION`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Unknown for records created before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the record last changed. Records stored before this field existed only know the
+    /// time of their last `edit`, as a Unix timestamp under `modified`, which is read instead.
+    #[serde(
+        default,
+        alias = "modified",
+        deserialize_with = "deserialize_timestamp",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Peer ids that are sent the record in listings even while it isn't public.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub allowed_peers: BTreeSet<String>,
+    /// Channels the record is announced on instead of the catalog topic, see [`channels`].
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub channels: BTreeSet<String>,
+    /// Free-form labels like `classic`, lower case and in order, see [`tags::normalize`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// When each mergeable field last changed, see [`crdt::merge`].
+    #[serde(default, skip_serializing_if = "Stamps::is_empty")]
+    pub stamps: Stamps,
+}
+
+impl Carinfo {
+    /// The record's key, with `holder`, the peer we have the record from or ourselves, as its
+    /// origin if the record doesn't name one or its origin is unverified.
+    pub fn key(&self, holder: &PeerId) -> CarinfoKey {
+        CarinfoKey {
+            origin: self
+                .origin
+                .as_deref()
+                .filter(|_| !self.unverified)
+                .and_then(|o| o.parse().ok())
+                .unwrap_or(*holder),
+            id: self.id,
+        }
+    }
+}
+
+/// Identifies a record across the network: ids are handed out by each node, so only together
+/// with the peer that created the record do they never collide. Written `<peer id>/<id>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CarinfoKey {
+    pub origin: PeerId,
+    pub id: usize,
+}
+
+impl fmt::Display for CarinfoKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.origin, self.id)
+    }
+}
+
+impl FromStr for CarinfoKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<CarinfoKey, String> {
+        let (origin, id) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected <peer id>/<id>, got {}", s))?;
+        Ok(CarinfoKey {
+            origin: origin
+                .parse()
+                .map_err(|e| format!("invalid peer id: {}, {}", origin, e))?,
+            id: id
+                .parse()
+                .map_err(|e| format!("invalid id: {}, {}", id, e))?,
+        })
+    }
+}
+
+/// Reads RFC 3339 times as well as the Unix timestamps older records have.
+fn deserialize_timestamp<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Unix(i64),
+        Rfc3339(DateTime<Utc>),
+    }
+    Ok(match Option::<Timestamp>::deserialize(deserializer)? {
+        Some(Timestamp::Unix(secs)) => DateTime::from_timestamp(secs, 0),
+        Some(Timestamp::Rfc3339(at)) => Some(at),
+        None => None,
+    })
+}
+
+/// Horsepower used to be free text, and older records and peers still send it as a string.
+/// Text that isn't a number, such as an empty field, reads as 0.
+fn deserialize_horsepower<'de, D>(deserializer: D) -> std::result::Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Horsepower {
+        Number(f64),
+        Text(String),
+    }
+    Ok(match Horsepower::deserialize(deserializer)? {
+        Horsepower::Number(hp) => hp.round() as u32,
+        Horsepower::Text(text) => text
+            .trim()
+            .parse::<f64>()
+            .map(|hp| hp.round() as u32)
+            .unwrap_or(0),
+    })
+}
@@ -0,0 +1,202 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use crate::{
+    format::{self, Column, NUMBER_FORMAT},
+    Carinfo,
+};
+
+/// A field of a record that `ls car` can sort by and show as a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Id,
+    Make,
+    Model,
+    Horsepower,
+    Vin,
+    Year,
+    Mileage,
+    Price,
+    Fuel,
+    Transmission,
+    Public,
+}
+
+const FIELDS: &str = "id, make, model, hp, vin, year, mileage, price, fuel, transmission or public";
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "id" => Field::Id,
+            "make" => Field::Make,
+            "model" => Field::Model,
+            "hp" | "horsepower" => Field::Horsepower,
+            "vin" => Field::Vin,
+            "year" => Field::Year,
+            "mileage" => Field::Mileage,
+            "price" => Field::Price,
+            "fuel" => Field::Fuel,
+            "transmission" => Field::Transmission,
+            "public" => Field::Public,
+            other => return Err(format!("unknown field {}, expected {}", other, FIELDS)),
+        })
+    }
+}
+
+/// What a field is sorted by: numbers as numbers, text ignoring case.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Number(u64),
+    Text(String),
+}
+
+impl Field {
+    /// `None` for a detail the record doesn't have.
+    fn key(self, c: &Carinfo) -> Option<Key> {
+        let text = |s: &str| Some(Key::Text(s.trim().to_lowercase()));
+        match self {
+            Field::Id => Some(Key::Number(c.id as u64)),
+            Field::Make => text(&c.make),
+            Field::Model => text(&c.model),
+            Field::Horsepower => Some(Key::Number(c.horsepower.into())),
+            Field::Vin => c.vin.as_deref().and_then(text),
+            Field::Year => c.year.map(|year| Key::Number(year.into())),
+            Field::Mileage => c.mileage_km.map(|km| Key::Number(km.into())),
+            // amounts in different currencies aren't compared, they end up side by side
+            Field::Price => c
+                .price
+                .as_ref()
+                .map(|price| Key::Text(format!("{} {:020}", price.currency, price.amount))),
+            Field::Fuel => c.fuel.and_then(|fuel| text(&fuel.to_string())),
+            Field::Transmission => c.transmission.and_then(|t| text(&t.to_string())),
+            Field::Public => Some(Key::Number(c.public.into())),
+        }
+    }
+
+    fn cell(self, c: &Carinfo) -> String {
+        match self {
+            Field::Id => c.id.to_string(),
+            Field::Make => c.make.trim().to_owned(),
+            Field::Model => c.model.trim().to_owned(),
+            Field::Horsepower => NUMBER_FORMAT.power(f64::from(c.horsepower)),
+            Field::Vin => c.vin.clone().unwrap_or_default(),
+            Field::Year => c.year.map(|year| year.to_string()).unwrap_or_default(),
+            Field::Mileage => c
+                .mileage_km
+                .map(|km| NUMBER_FORMAT.distance(f64::from(km)))
+                .unwrap_or_default(),
+            Field::Price => c
+                .price
+                .as_ref()
+                .map(|price| NUMBER_FORMAT.price(price.amount as f64, &price.currency))
+                .unwrap_or_default(),
+            Field::Fuel => c.fuel.map(|fuel| fuel.to_string()).unwrap_or_default(),
+            Field::Transmission => c.transmission.map(|t| t.to_string()).unwrap_or_default(),
+            Field::Public => if c.public { "yes" } else { "no" }.to_owned(),
+        }
+    }
+
+    fn column(self) -> Column {
+        Column {
+            name: match self {
+                // shown in kW or hp, whichever the locale uses
+                Field::Horsepower => "power",
+                Field::Id => "id",
+                Field::Make => "make",
+                Field::Model => "model",
+                Field::Vin => "vin",
+                Field::Year => "year",
+                Field::Mileage => "mileage",
+                Field::Price => "price",
+                Field::Fuel => "fuel",
+                Field::Transmission => "transmission",
+                Field::Public => "public",
+            },
+            right: matches!(
+                self,
+                Field::Id | Field::Horsepower | Field::Year | Field::Mileage | Field::Price
+            ),
+        }
+    }
+}
+
+/// How `ls car --sort <field> [--desc] --fields <field>,...` orders and shows local records.
+/// Without options records are listed as stored, one line each.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ListOptions {
+    pub sort: Option<Field>,
+    pub descending: bool,
+    /// Columns of a table to show instead of the usual lines.
+    pub fields: Option<Vec<Field>>,
+}
+
+impl ListOptions {
+    /// Parses the options after `ls car`, e.g. `--sort hp --desc --fields make,model,hp`.
+    pub fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<ListOptions, String> {
+        let mut options = ListOptions::default();
+        while let Some(word) = words.next() {
+            match word {
+                "--sort" => {
+                    let field = words.next().ok_or("--sort needs a field")?;
+                    options.sort = Some(field.parse()?);
+                }
+                "--desc" => options.descending = true,
+                "--asc" => options.descending = false,
+                "--fields" => {
+                    let fields = words.next().ok_or("--fields needs a list of fields")?;
+                    options.fields = Some(
+                        fields
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|field| !field.is_empty())
+                            .map(str::parse)
+                            .collect::<Result<_, _>>()?,
+                    );
+                }
+                other => {
+                    return Err(format!(
+                        "unknown option {}, expected --sort, --desc or --fields",
+                        other
+                    ))
+                }
+            }
+        }
+        if options.fields.as_ref().is_some_and(Vec::is_empty) {
+            return Err("--fields needs at least one field".to_owned());
+        }
+        Ok(options)
+    }
+
+    /// Orders `carinfos` by the sort field, then by id. `--desc` alone lists the newest ids
+    /// first. Records without the field come last either way.
+    pub fn sort(&self, carinfos: &mut [Carinfo]) {
+        let field = match (self.sort, self.descending) {
+            (Some(field), _) => field,
+            (None, true) => Field::Id,
+            (None, false) => return,
+        };
+        carinfos.sort_by(|a, b| {
+            let order = match (field.key(a), field.key(b)) {
+                (Some(a), Some(b)) if self.descending => b.cmp(&a),
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            order.then(a.id.cmp(&b.id))
+        });
+    }
+
+    /// The table asked for with `--fields`, header first.
+    pub fn table(&self, carinfos: &[Carinfo]) -> Option<Vec<String>> {
+        let fields = self.fields.as_ref()?;
+        let columns: Vec<Column> = fields.iter().map(|field| field.column()).collect();
+        let rows: Vec<Vec<String>> = carinfos
+            .iter()
+            .map(|c| fields.iter().map(|field| field.cell(c)).collect())
+            .collect();
+        Some(format::table(&columns, &rows))
+    }
+}
@@ -0,0 +1,134 @@
+use rust_car_p2p::{control::Output, output};
+use std::fmt::Write;
+use std::path::Path;
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Metadata, Subscriber,
+};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    filter::{filter_fn, LevelFilter},
+    fmt::{self, writer::BoxMakeWriter},
+    layer::{Context, Layer},
+    prelude::*,
+    EnvFilter,
+};
+
+use crate::repl::Console;
+
+/// Rotated files kept next to the current one.
+const KEPT_LOG_FILES: usize = 7;
+
+/// Logs everything `RUST_LOG` (default `info`) lets through as JSON lines to `path`, starting
+/// a new file every day, and keeps stdout for people: only our own messages up to info, without
+/// timestamps or targets, plus warnings and errors from libraries. In container mode stdout
+/// gets the same JSON lines as the file instead. With a `console`, the messages for people are
+/// printed above the prompt, in JSON output mode they go to stderr, and with a `tap` they are
+/// copied to control connections. The returned guard flushes the file when dropped.
+pub fn init(
+    path: &Path,
+    container: bool,
+    console: Option<Console>,
+    tap: Option<Output>,
+) -> Result<WorkerGuard, String> {
+    let directory = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("log file {} has no file name", path.display()))?;
+    std::fs::create_dir_all(directory)
+        .map_err(|e| format!("can't create log directory {}, {}", directory.display(), e))?;
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(name.to_string_lossy())
+        .max_log_files(KEPT_LOG_FILES + 1)
+        .build(directory)
+        .map_err(|e| format!("can't open log file {}, {}", path.display(), e))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    let filter = || EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let file = fmt::layer()
+        .json()
+        .with_writer(writer)
+        .with_filter(filter());
+    let stdout = if container {
+        fmt::layer()
+            .json()
+            .with_writer(std::io::stdout)
+            .with_filter(filter())
+            .boxed()
+    } else {
+        let writer = match console {
+            Some(console) => BoxMakeWriter::new(move || console.writer()),
+            None if output::json() => BoxMakeWriter::new(std::io::stderr),
+            None => BoxMakeWriter::new(std::io::stdout),
+        };
+        fmt::layer()
+            .without_time()
+            .with_target(false)
+            .with_level(false)
+            .with_writer(writer)
+            .with_filter(filter_fn(for_people))
+            .with_filter(filter())
+            .boxed()
+    };
+    tracing_subscriber::registry()
+        .with(file)
+        .with(stdout)
+        .with(tap.map(|tap| Tap(tap).with_filter(LevelFilter::INFO)))
+        .try_init()
+        .map_err(|e| format!("can't set up logging, {}", e))?;
+    Ok(guard)
+}
+
+/// Logging of a daemon without a log file: every line `RUST_LOG` (default `info`) lets through
+/// goes to stderr with a timestamp, and our own messages are copied to control connections.
+pub fn init_daemon(tap: Output) -> Result<(), String> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::registry()
+        .with(
+            fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(filter),
+        )
+        .with(Tap(tap).with_filter(LevelFilter::INFO))
+        .try_init()
+        .map_err(|e| format!("can't set up logging, {}", e))
+}
+
+/// Command output and the like: our own messages up to info, and warnings and errors from
+/// libraries.
+fn for_people(meta: &Metadata) -> bool {
+    *meta.level() <= Level::WARN
+        || (*meta.level() <= Level::INFO && meta.target().starts_with("rust_car_p2p"))
+}
+
+/// Copies the messages for people to control connections waiting for a command's output,
+/// whatever `RUST_LOG` lets through to the terminal.
+struct Tap(Output);
+
+impl<S: Subscriber> Layer<S> for Tap {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        if !for_people(event.metadata()) {
+            return;
+        }
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        self.0.send(event.metadata().level().as_str(), message.0);
+    }
+}
+
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        }
+    }
+}
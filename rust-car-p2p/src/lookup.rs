@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use log::{error, info};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::time::{Duration, Instant};
+
+use crate::{Carinfo, Result};
+
+/// Extra key/value details attached to a record by lookups.
+pub type Attributes = BTreeMap<String, String>;
+
+/// How long a lookup result is reused before the provider is asked again.
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Minimum gap between two requests to the same provider.
+const MIN_REQUEST_GAP: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Escaped in values put into a URL: everything but the characters unreserved in RFC 3986.
+const URL_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// An external data source that can enrich a record over HTTP.
+#[async_trait]
+pub trait ExternalLookup: Send + Sync {
+    /// Short name, used to prefix the attributes it returns and in `CARINFO_LOOKUPS`.
+    fn name(&self) -> &str;
+
+    /// Identifies requests that return the same data, for caching.
+    fn cache_key(&self, carinfo: &Carinfo) -> String {
+        format!(
+            "{}|{}",
+            carinfo.make.trim().to_lowercase(),
+            carinfo.model.trim().to_lowercase()
+        )
+    }
+
+    async fn lookup(&self, client: &reqwest::Client, carinfo: &Carinfo) -> Result<Attributes>;
+}
+
+/// NHTSA's vPIC spec database: confirms the make/model combination and returns its ids.
+pub struct NhtsaVpic;
+
+#[async_trait]
+impl ExternalLookup for NhtsaVpic {
+    fn name(&self) -> &str {
+        "nhtsa"
+    }
+
+    async fn lookup(&self, client: &reqwest::Client, carinfo: &Carinfo) -> Result<Attributes> {
+        let url = format!(
+            "https://vpic.nhtsa.dot.gov/api/vehicles/GetModelsForMake/{}?format=json",
+            url_value(&carinfo.make)
+        );
+        let body: Value = client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let model = carinfo.model.trim().to_lowercase();
+        let found = body["Results"].as_array().and_then(|results| {
+            results.iter().find(|r| {
+                r["Model_Name"]
+                    .as_str()
+                    .is_some_and(|name| name.to_lowercase() == model)
+            })
+        });
+
+        let mut attributes = Attributes::new();
+        match found {
+            Some(result) => {
+                attributes.insert("known_model".to_owned(), "true".to_owned());
+                for key in ["Make_ID", "Make_Name", "Model_ID", "Model_Name"] {
+                    if let Some(value) = scalar(&result[key]) {
+                        attributes.insert(key.to_lowercase(), value);
+                    }
+                }
+            }
+            None => {
+                attributes.insert("known_model".to_owned(), "false".to_owned());
+            }
+        }
+        Ok(attributes)
+    }
+}
+
+/// A user-configured JSON endpoint, e.g. a registration/status service. `{make}`, `{model}`
+/// and `{id}` in the URL template are substituted, percent-encoded; the top-level scalar fields of the JSON
+/// object it returns become attributes.
+pub struct HttpJsonLookup {
+    url_template: String,
+}
+
+impl HttpJsonLookup {
+    fn url(&self, carinfo: &Carinfo) -> String {
+        self.url_template
+            .replace("{make}", &url_value(&carinfo.make))
+            .replace("{model}", &url_value(&carinfo.model))
+            .replace("{id}", &url_value(&carinfo.id.to_string()))
+    }
+}
+
+/// `value`, trimmed, made safe to put anywhere in a URL, so a make or model can't add path
+/// segments or query parameters.
+pub(crate) fn url_value(value: &str) -> String {
+    utf8_percent_encode(value.trim(), URL_VALUE).to_string()
+}
+
+#[async_trait]
+impl ExternalLookup for HttpJsonLookup {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn cache_key(&self, carinfo: &Carinfo) -> String {
+        self.url(carinfo)
+    }
+
+    async fn lookup(&self, client: &reqwest::Client, carinfo: &Carinfo) -> Result<Attributes> {
+        let body: Value = client
+            .get(self.url(carinfo))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let object = body
+            .as_object()
+            .ok_or("lookup response is not a JSON object")?;
+        Ok(object
+            .iter()
+            .filter_map(|(k, v)| scalar(v).map(|v| (k.clone(), v)))
+            .collect())
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Runs the enabled lookups with caching and per-provider rate limiting.
+pub struct Enricher {
+    client: reqwest::Client,
+    providers: Vec<Box<dyn ExternalLookup>>,
+    cache: HashMap<(String, String), (Instant, Attributes)>,
+    last_request: HashMap<String, Instant>,
+}
+
+impl Enricher {
+    /// Enables the providers listed in `CARINFO_LOOKUPS` (comma separated: `nhtsa`, `http`).
+    /// The `http` provider takes its URL template from `CARINFO_LOOKUP_URL`.
+    pub fn from_env() -> Enricher {
+        let mut providers: Vec<Box<dyn ExternalLookup>> = Vec::new();
+        for name in env::var("CARINFO_LOOKUPS").unwrap_or_default().split(',') {
+            match name.trim() {
+                "" => {}
+                "nhtsa" => providers.push(Box::new(NhtsaVpic)),
+                "http" => match env::var("CARINFO_LOOKUP_URL") {
+                    Ok(url_template) => providers.push(Box::new(HttpJsonLookup { url_template })),
+                    Err(_) => error!("http lookup enabled but CARINFO_LOOKUP_URL is not set"),
+                },
+                other => error!("unknown lookup provider: {}", other),
+            }
+        }
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(concat!("carinfo-p2p/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("can create http client");
+        Enricher {
+            client,
+            providers,
+            cache: HashMap::new(),
+            last_request: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    /// Queries every provider for `carinfo`, returning attributes prefixed with the provider
+    /// name. Failing providers are logged and skipped.
+    pub async fn enrich(&mut self, carinfo: &Carinfo) -> Attributes {
+        let mut enriched = Attributes::new();
+        for provider in self.providers.iter() {
+            let name = provider.name().to_owned();
+            let key = (name.clone(), provider.cache_key(carinfo));
+            let attributes = match self.cache.get(&key) {
+                Some((at, attributes)) if at.elapsed() < CACHE_TTL => {
+                    info!("Using cached {} lookup", name);
+                    attributes.clone()
+                }
+                _ => {
+                    if let Some(last) = self.last_request.get(&name) {
+                        let wait = MIN_REQUEST_GAP.saturating_sub(last.elapsed());
+                        tokio::time::sleep(wait).await;
+                    }
+                    self.last_request.insert(name.clone(), Instant::now());
+                    match provider.lookup(&self.client, carinfo).await {
+                        Ok(attributes) => {
+                            self.cache.insert(key, (Instant::now(), attributes.clone()));
+                            attributes
+                        }
+                        Err(e) => {
+                            error!("{} lookup failed, {}", name, e);
+                            continue;
+                        }
+                    }
+                }
+            };
+            enriched.extend(
+                attributes
+                    .into_iter()
+                    .map(|(k, v)| (format!("{}.{}", name, k), v)),
+            );
+        }
+        enriched
+    }
+}
@@ -1,335 +1,434 @@
+mod completions;
+mod container;
+mod daemon;
+mod logging;
+mod repl;
+mod tui;
+
 use libp2p::{
-    core::upgrade,
-    floodsub::{Floodsub, FloodsubEvent, Topic},
+    core::ConnectedPoint,
     futures::StreamExt,
-    identity,
-    mdns::{Mdns, MdnsEvent},
-    mplex,
-    noise::{Keypair, NoiseConfig, X25519Spec},
-    swarm::{NetworkBehaviourEventProcess, Swarm, SwarmBuilder},
-    tcp::TokioTcpConfig,
-    NetworkBehaviour, PeerId, Transport,
+    swarm::{Swarm, SwarmBuilder, SwarmEvent},
+    PeerId,
 };
 use log::{error, info};
-use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use tokio::{fs, io::AsyncBufReadExt, sync::mpsc};
-
-const STORAGE_FILE_PATH: &str = "./carinfo.json";
-
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
-type Carinfos = Vec<Carinfo>;
-
-static KEYS: Lazy<identity::Keypair> = Lazy::new(|| identity::Keypair::generate_ed25519());
-static PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(KEYS.public()));
-static TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("carinfos"));
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Carinfo {
-    id: usize,
-    make: String,
-    model: String,
-    horsepower: String,
-    public: bool,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-enum ListMode {
-    ALL,
-    One(String),
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ListRequest {
-    mode: ListMode,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ListResponse {
-    mode: ListMode,
-    data: Carinfos,
-    receiver: String,
-}
+use rust_car_p2p::{
+    api::{self, ApiRequest},
+    backup,
+    behaviour::{self, CarinfoBehaviour, CarinfoEvent},
+    commands::{self, Session},
+    control::{self, ControlCommand},
+    dht, grpc, init_storage, keystore,
+    output::{self, Format},
+    pager, profile, storage,
+    telemetry::TelemetryUpdate,
+    Config, PEER_ID,
+};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
 
 enum EventType {
-    Response(ListResponse),
     Input(String),
+    Connected(PeerId, ConnectedPoint),
+    Disconnected(PeerId),
+    DialFailed(PeerId, String),
+    Telemetry(TelemetryUpdate),
+    Behaviour(CarinfoEvent),
+    Api(ApiRequest),
+    Control(ControlCommand),
 }
 
-#[derive(NetworkBehaviour)]
-struct CarinfoBehaviour {
-    floodsub: Floodsub,
-    mdns: Mdns,
-    #[behaviour(ignore)]
-    response_sender: mpsc::UnboundedSender<ListResponse>,
-}
-
-impl NetworkBehaviourEventProcess<FloodsubEvent> for CarinfoBehaviour {
-    fn inject_event(&mut self, event: FloodsubEvent) {
-        match event {
-            FloodsubEvent::Message(msg) => {
-                if let Ok(resp) = serde_json::from_slice::<ListResponse>(&msg.data) {
-                    if resp.receiver == PEER_ID.to_string() {
-                        info!("Response from {}:", msg.source);
-                        resp.data.iter().for_each(|r| info!("{:?}", r));
-                    }
-                } else if let Ok(req) = serde_json::from_slice::<ListRequest>(&msg.data) {
-                    match req.mode {
-                        ListMode::ALL => {
-                            info!("Received ALL req: {:?} from {:?}", req, msg.source);
-                            respond_with_public_carinfos(
-                                self.response_sender.clone(),
-                                msg.source.to_string(),
-                            );
-                        }
-                        ListMode::One(ref peer_id) => {
-                            if peer_id == &PEER_ID.to_string() {
-                                info!("Received req: {:?} from {:?}", req, msg.source);
-                                respond_with_public_carinfos(
-                                    self.response_sender.clone(),
-                                    msg.source.to_string(),
-                                );
-                            }
-                        }
-                    }
-                }
+#[tokio::main]
+async fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("completions") {
+        if args.get(1).map(String::as_str) == Some("complete") {
+            for candidate in completions::complete(&args[2..]).await {
+                println!("{}", candidate);
+            }
+            return;
+        }
+        match args.get(1).and_then(|shell| completions::script(shell)) {
+            Some(script) => print!("{}", script),
+            None => {
+                eprintln!("usage: completions <{}>", completions::SHELLS.join("|"));
+                std::process::exit(1);
             }
-            _ => (),
         }
+        return;
+    }
+    // before anything reads a state file, so ctl finds the profile's control socket too
+    let profile = match profile::select(&mut args) {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if args.first().map(String::as_str) == Some("ctl") {
+        std::process::exit(daemon::ctl(&args[1..]).await);
     }
-}
 
-fn respond_with_public_carinfos(sender: mpsc::UnboundedSender<ListResponse>, receiver: String) {
-    tokio::spawn(async move {
-        match read_local_carinfos().await {
-            Ok(carinfos) => {
-                let resp = ListResponse {
-                    mode: ListMode::ALL,
-                    receiver,
-                    data: carinfos.into_iter().filter(|r| r.public).collect(),
-                };
-                if let Err(e) = sender.send(resp) {
-                    error!("error sending response via channel, {}", e);
-                }
-            }
-            Err(e) => error!("error fetching local carinfos to answer ALL request, {}", e),
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid configuration, {}", e);
+            std::process::exit(1);
         }
-    });
-}
+    };
+    // RUST_LOG still wins over the config file
+    if let (Err(_), Some(level)) = (std::env::var("RUST_LOG"), &config.log_level) {
+        std::env::set_var("RUST_LOG", level);
+    }
 
-impl NetworkBehaviourEventProcess<MdnsEvent> for CarinfoBehaviour {
-    fn inject_event(&mut self, event: MdnsEvent) {
-        match event {
-            MdnsEvent::Discovered(discovered_list) => {
-                for (peer, _addr) in discovered_list {
-                    self.floodsub.add_node_to_partial_view(peer);
-                }
+    let json = match Format::from_args(&args) {
+        Ok(format) => format == Format::Json,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if json {
+        // results go to stdout whole, logs to stderr
+        output::enable_json();
+        pager::disable();
+    }
+    let container = container::enabled(&args);
+    let daemon = daemon::enabled(&args);
+    let headless = config.headless;
+    let tui_enabled = tui::enabled(&args) && !container && !daemon && !headless && !json;
+    // no prompt without a terminal, and the dashboard reads keys itself
+    let (mut repl, console) = if container || daemon || headless || tui_enabled {
+        (None, None)
+    } else {
+        match repl::Repl::start(json) {
+            Ok((repl, console)) => (Some(repl), console),
+            Err(e) => {
+                eprintln!("can't read commands from the terminal, {}", e);
+                std::process::exit(1);
             }
-            MdnsEvent::Expired(expired_list) => {
-                for (peer, _addr) in expired_list {
-                    if !self.mdns.has_node(&peer) {
-                        self.floodsub.remove_node_from_partial_view(&peer);
-                    }
-                }
+        }
+    };
+    // what commands sent over the control socket print goes back to the sender
+    let control_output = control::Output::default();
+    let mut log_lines = None;
+    let mut log_guard = None;
+    if tui_enabled {
+        // the dashboard's log pane already keeps logs apart from the terminal
+        log_lines = Some(tui::init_logger());
+    } else if let Some(path) = &config.log_file {
+        let tap = daemon.then(|| control_output.clone());
+        match logging::init(path, container, console, tap) {
+            Ok(guard) => log_guard = Some(guard),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
         }
+    } else if daemon {
+        if let Err(e) = logging::init_daemon(control_output.clone()) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    } else if container {
+        container::init_json_logger();
+    } else if let Some(console) = console {
+        repl::init_logger(console);
+    } else {
+        pretty_env_logger::init();
     }
-}
-
-async fn create_new_carinfo(make: &str, model: &str, horsepower: &str) -> Result<()> {
-    let mut local_carinfos = read_local_carinfos().await?;
-    let new_id = match local_carinfos.iter().max_by_key(|r| r.id) {
-        Some(v) => v.id + 1,
-        None => 0,
-    };
-    local_carinfos.push(Carinfo {
-        id: new_id,
-        make: make.to_owned(),
-        model: model.to_owned(),
-        horsepower: horsepower.to_owned(),
-        public: false,
-    });
-    write_local_carinfos(&local_carinfos).await?;
-
-    info!("Created carinfo:");
-    info!("Make: {}", make);
-    info!("Model: {}", model);
-    info!("Horsepower:: {}", horsepower);
-
-    Ok(())
-}
-
-async fn publish_carinfo(id: usize) -> Result<()> {
-    let mut local_carinfos = read_local_carinfos().await?;
-    local_carinfos
-        .iter_mut()
-        .filter(|r| r.id == id)
-        .for_each(|r| r.public = true);
-    write_local_carinfos(&local_carinfos).await?;
-    Ok(())
-}
 
-async fn read_local_carinfos() -> Result<Carinfos> {
-    let content = fs::read(STORAGE_FILE_PATH).await?;
-    let result = serde_json::from_slice(&content)?;
-    Ok(result)
-}
+    if let Some(dir) = &profile {
+        info!("Using profile {}", dir.display());
+    }
 
-async fn write_local_carinfos(carinfos: &Carinfos) -> Result<()> {
-    let json = serde_json::to_string(&carinfos)?;
-    fs::write(STORAGE_FILE_PATH, &json).await?;
-    Ok(())
-}
+    // must happen before the identity is first used
+    if args.iter().any(|a| a == "--new-identity") {
+        match keystore::rotate() {
+            Ok(()) => info!("Rotating identity, the old key is kept with an .old suffix"),
+            Err(e) => {
+                error!("error rotating identity, {}", e);
+                return;
+            }
+        }
+    }
 
-#[tokio::main]
-async fn main() {
-    pretty_env_logger::init();
+    if let Err(e) = init_storage(&config) {
+        error!("error opening storage, {}", e);
+        return;
+    }
+    backup::init(&config);
 
     info!("Peer Id: {}", PEER_ID.clone());
-    let (response_sender, mut response_rcv) = mpsc::unbounded_channel();
-
-    let auth_keys = Keypair::<X25519Spec>::new()
-        .into_authentic(&KEYS)
-        .expect("can create auth keys");
-
-    let transp = TokioTcpConfig::new()
-        .upgrade(upgrade::Version::V1)
-        .authenticate(NoiseConfig::xx(auth_keys).into_authenticated()) // XX Handshake pattern, IX exists as well and IK - only XX currently provides interop with other libp2p impls
-        .multiplex(mplex::MplexConfig::new())
-        .boxed();
-
-    let mut behaviour = CarinfoBehaviour {
-        floodsub: Floodsub::new(PEER_ID.clone()),
-        mdns: Mdns::new(Default::default())
-            .await
-            .expect("can create mdns"),
-        response_sender,
-    };
-
-    behaviour.floodsub.subscribe(TOPIC.clone());
 
+    if let Some(key) = &config.swarm_key {
+        info!(
+            "Private network, swarm key fingerprint {}",
+            key.fingerprint()
+        );
+    }
+    let (transp, relay) = rust_car_p2p::relay_transport(config.swarm_key);
+    let behaviour = CarinfoBehaviour::new(&config, relay).await;
     let mut swarm = SwarmBuilder::new(transp, behaviour, PEER_ID.clone())
         .executor(Box::new(|fut| {
             tokio::spawn(fut);
         }))
         .build();
 
-    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let (telemetry_sender, mut telemetry_rcv) = mpsc::unbounded_channel();
+    let mut session = Session::from_env(telemetry_sender).await;
 
-    Swarm::listen_on(
-        &mut swarm,
-        "/ip4/0.0.0.0/tcp/0"
-            .parse()
-            .expect("can get a local socket"),
-    )
-    .expect("swarm can be started");
+    // the dashboard takes over the terminal, logs go to its log pane from here on
+    let mut dashboard = match log_lines {
+        Some(lines) => match tui::Dashboard::start(lines) {
+            Ok(dashboard) => Some(dashboard),
+            Err(e) => {
+                eprintln!("can't start the dashboard, {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let (ui_sender, mut ui_rcv) = mpsc::unbounded_channel();
+    if dashboard.is_some() {
+        swarm.behaviour_mut().watch(ui_sender);
+    }
+
+    Swarm::listen_on(&mut swarm, config.listen.clone()).expect("swarm can be started");
+    if let Some(addr) = config.ws_listen.clone() {
+        Swarm::listen_on(&mut swarm, addr).expect("swarm can listen for websockets");
+    }
+    behaviour::listen_through_relays(&mut swarm, &config.relays);
+    behaviour::ban_blocked(&mut swarm);
+    behaviour::reconnect_known(&mut swarm);
+
+    let (api_sender, mut api_rcv) = mpsc::unbounded_channel();
+    let (stop_api, api_stopping) = oneshot::channel();
+    let api_server = config.http.map(|addr| {
+        let health = swarm.behaviour().health();
+        tokio::spawn(api::serve(addr, api_sender.clone(), health, async {
+            let _ = api_stopping.await;
+        }))
+    });
+    let (stop_grpc, grpc_stopping) = oneshot::channel();
+    let grpc_server = config.grpc.map(|addr| {
+        tokio::spawn(grpc::serve(addr, api_sender, async {
+            let _ = grpc_stopping.await;
+        }))
+    });
+
+    if headless {
+        // nobody reads pages without a terminal either
+        pager::disable();
+        if config.http.is_none() && config.grpc.is_none() {
+            info!("Running headless without the REST or gRPC API, only peers can reach the node");
+        } else {
+            info!("Running headless, taking commands over the REST and gRPC APIs");
+        }
+    }
+    let (control_sender, mut control_rcv) = mpsc::unbounded_channel();
+    if daemon {
+        // nobody reads pages at a socket
+        pager::disable();
+        #[cfg(unix)]
+        tokio::spawn(control::serve(
+            config.control_socket.clone(),
+            control_sender,
+            control_output,
+        ));
+        #[cfg(not(unix))]
+        {
+            error!("daemon mode needs unix sockets, which this platform doesn't have");
+            return;
+        }
+    }
+
+    let mut dht_refresh = tokio::time::interval(dht::REFRESH_INTERVAL);
+    let mut bootstrap_redial = tokio::time::interval(dht::REDIAL_INTERVAL);
+    // the first backup is one interval after startup
+    let backup_every = config.backup_interval.unwrap_or(dht::REFRESH_INTERVAL);
+    let mut backup_tick =
+        tokio::time::interval_at(tokio::time::Instant::now() + backup_every, backup_every);
+
+    // In container mode there is no terminal: stdin is ignored and health and metrics are
+    // served over HTTP.
+    if container {
+        tokio::spawn(container::serve(swarm.behaviour().health()));
+    }
+    let mut shutdown = Box::pin(shutdown_signal());
 
     loop {
+        behaviour::update_health(&swarm);
+        let round_deadline = swarm.behaviour().round_deadline();
+
         let evt = {
             tokio::select! {
-                line = stdin.next_line() => Some(EventType::Input(line.expect("can get line").expect("can read line from stdin"))),
-                response = response_rcv.recv() => Some(EventType::Response(response.expect("response exists"))),
-                event = swarm.select_next_some() => {
-                    info!("Unhandled Swarm Event: {:?}", event);
+                signal = &mut shutdown => {
+                    info!("Received {}, shutting down", signal);
+                    break;
+                }
+                action = tui::next_action(&mut dashboard) => match action {
+                    tui::Action::Command(line) => Some(EventType::Input(line)),
+                    tui::Action::Quit => break,
+                },
+                Some(event) = ui_rcv.recv() => {
+                    if let Some(dashboard) = dashboard.as_mut() {
+                        dashboard.apply(event);
+                    }
                     None
+                }
+                input = repl::next_input(&mut repl) => match input {
+                    repl::Input::Line(line) => Some(EventType::Input(line)),
+                    repl::Input::Quit => {
+                        info!("Received Ctrl+C, shutting down");
+                        break;
+                    }
+                },
+                _ = dht_refresh.tick() => {
+                    swarm.behaviour_mut().refresh_dht();
+                    // also picks up changes made through the APIs
+                    behaviour::announce_catalog(&mut swarm).await;
+                    None
+                }
+                _ = bootstrap_redial.tick() => {
+                    behaviour::redial_bootstrap(&mut swarm);
+                    None
+                }
+                _ = backup_tick.tick(), if config.backup_interval.is_some() => {
+                    match backup::periodic(config.backup_keep).await {
+                        Ok(path) => info!("Backed up to {}", path.display()),
+                        Err(e) => error!("error backing up, {}", e),
+                    }
+                    None
+                }
+                _ = tokio::time::sleep_until(round_deadline.unwrap_or_else(Instant::now).into()),
+                    if round_deadline.is_some() => {
+                    swarm.behaviour_mut().close_round();
+                    None
+                }
+                Some(request) = api_rcv.recv() => Some(EventType::Api(request)),
+                Some(command) = control_rcv.recv() => Some(EventType::Control(command)),
+                update = telemetry_rcv.recv() => Some(EventType::Telemetry(update.expect("telemetry update exists"))),
+                event = swarm.select_next_some() => match event {
+                    SwarmEvent::Behaviour(event) => Some(EventType::Behaviour(event)),
+                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                        Some(EventType::Connected(peer_id, endpoint))
+                    }
+                    SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                        Some(EventType::Disconnected(peer_id))
+                    }
+                    // refused by the block or allow list, see behaviour::admit
+                    SwarmEvent::BannedPeer { .. } => None,
+                    SwarmEvent::UnreachableAddr { peer_id, error, attempts_remaining: 0, .. } => {
+                        Some(EventType::DialFailed(peer_id, error.to_string()))
+                    }
+                    event => {
+                        info!("Unhandled Swarm Event: {:?}", event);
+                        None
+                    }
                 },
             }
         };
 
         if let Some(event) = evt {
             match event {
-                EventType::Response(resp) => {
-                    let json = serde_json::to_string(&resp).expect("can jsonify response");
-                    swarm
-                        .behaviour_mut()
-                        .floodsub
-                        .publish(TOPIC.clone(), json.as_bytes());
+                EventType::Behaviour(event) => {
+                    behaviour::handle_behaviour_event(event, &mut swarm).await
+                }
+                EventType::Connected(peer, endpoint) => {
+                    if behaviour::admit(&mut swarm, &peer) {
+                        swarm.behaviour_mut().peer_connected(&peer, &endpoint)
+                    }
+                    if let Some(repl) = &repl {
+                        repl.set_peers(swarm.behaviour().peer_book().peers());
+                    }
+                }
+                EventType::Disconnected(peer) => swarm.behaviour_mut().peer_disconnected(&peer),
+                EventType::DialFailed(peer, error) => {
+                    swarm.behaviour_mut().dial_failed(&peer, &error)
+                }
+                EventType::Telemetry(update) => swarm.behaviour_mut().publish_telemetry(&update),
+                EventType::Api(request) => api::handle(request, &mut swarm),
+                EventType::Control(command) => {
+                    commands::execute(&command.line, &mut swarm, &mut session).await;
+                    let _ = command.done.send(());
+                }
+                EventType::Input(line) => {
+                    commands::execute(&line, &mut swarm, &mut session).await;
+                    if let Some(dashboard) = dashboard.as_mut() {
+                        dashboard.refresh_catalog().await;
+                    }
+                    if let Some(repl) = &repl {
+                        repl.set_peers(swarm.behaviour().peer_book().peers());
+                        repl.refresh_ids().await;
+                    }
                 }
-                EventType::Input(line) => match line.as_str() {
-                    "ls p" => handle_list_peers(&mut swarm).await,
-                    cmd if cmd.starts_with("ls car") => handle_list_carinfos(cmd, &mut swarm).await,
-                    cmd if cmd.starts_with("create car") => handle_create_carinfo(cmd).await,
-                    cmd if cmd.starts_with("publish car") => handle_publish_carinfo(cmd).await,
-                    _ => error!("unknown command"),
-                },
             }
         }
     }
-}
-
-async fn handle_list_peers(swarm: &mut Swarm<CarinfoBehaviour>) {
-    info!("Discovered Peers:");
-    let nodes = swarm.behaviour().mdns.discovered_nodes();
-    let mut unique_peers = HashSet::new();
-    for peer in nodes {
-        unique_peers.insert(peer);
-    }
-    unique_peers.iter().for_each(|p| info!("{}", p));
-}
 
-async fn handle_list_carinfos(cmd: &str, swarm: &mut Swarm<CarinfoBehaviour>) {
-    let rest = cmd.strip_prefix("ls car ");
-    match rest {
-        Some("all") => {
-            let req = ListRequest {
-                mode: ListMode::ALL,
-            };
-            let json = serde_json::to_string(&req).expect("can jsonify request");
-            swarm
-                .behaviour_mut()
-                .floodsub
-                .publish(TOPIC.clone(), json.as_bytes());
-        }
-        Some(carinfos_peer_id) => {
-            let req = ListRequest {
-                mode: ListMode::One(carinfos_peer_id.to_owned()),
-            };
-            let json = serde_json::to_string(&req).expect("can jsonify request");
-            swarm
-                .behaviour_mut()
-                .floodsub
-                .publish(TOPIC.clone(), json.as_bytes());
+    drop(dashboard);
+    session.stop();
+    // let API requests in progress finish, then wait for their writes
+    let _ = stop_api.send(());
+    let _ = stop_grpc.send(());
+    for server in api_server.into_iter().chain(grpc_server) {
+        if tokio::time::timeout(API_DRAIN_TIMEOUT, server)
+            .await
+            .is_err()
+        {
+            error!(
+                "api requests still open after {:?}, dropping them",
+                API_DRAIN_TIMEOUT
+            );
         }
-        None => {
-            match read_local_carinfos().await {
-                Ok(v) => {
-                    info!("Local Carinfos ({})", v.len());
-                    v.iter().for_each(|r| info!("{:?}", r));
-                }
-                Err(e) => error!("error fetching local carinfos: {}", e),
-            };
+    }
+    if let Err(e) = storage().flush().await {
+        error!("error flushing storage, {}", e);
+    }
+    if daemon {
+        let _ = std::fs::remove_file(&config.control_socket);
+    }
+    swarm.behaviour_mut().leave();
+    // give the swarm a moment to tell peers we left
+    let _ = tokio::time::timeout(Duration::from_millis(500), async {
+        loop {
+            swarm.select_next_some().await;
         }
-    };
+    })
+    .await;
+    drop(swarm);
+    info!("Stopped");
+    drop(log_guard);
+    drop(repl);
+    // the runtime would wait for the blocking read on stdin before returning
+    std::process::exit(0);
 }
 
-async fn handle_create_carinfo(cmd: &str) {
-    if let Some(rest) = cmd.strip_prefix("create car") {
-        let elements: Vec<&str> = rest.split("|").collect();
-        if elements.len() < 3 {
-            info!("too few arguments - Format: make|model|horsepower");
-        } else {
-            let make = elements.get(0).expect("make is there");
-            let model = elements.get(1).expect("model is there");
-            let horsepower = elements.get(2).expect("horsepower is there");
-            if let Err(e) = create_new_carinfo(make, model, horsepower).await {
-                error!("error creating carinfo: {}", e);
-            };
+/// How long shutdown waits for REST API requests in progress.
+const API_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves with the signal's name once the node is asked to stop, with Ctrl+C (SIGINT) or, on
+/// unix, SIGTERM as sent by `kill` and container orchestrators.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = sigterm.recv() => "SIGTERM",
+            },
+            Err(e) => {
+                error!("error installing SIGTERM handler, {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
         }
     }
-}
-
-async fn handle_publish_carinfo(cmd: &str) {
-    if let Some(rest) = cmd.strip_prefix("publish car") {
-        match rest.trim().parse::<usize>() {
-            Ok(id) => {
-                if let Err(e) = publish_carinfo(id).await {
-                    info!("error publishing carinfo with id {}, {}", id, e)
-                } else {
-                    info!("Published Carinfo with id: {}", id);
-                }
-            }
-            Err(e) => error!("invalid id: {}, {}", rest.trim(), e),
-        };
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
     }
-}
\ No newline at end of file
+}
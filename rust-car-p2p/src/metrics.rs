@@ -0,0 +1,143 @@
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::{health::Health, read_local_carinfos, wire::Message};
+
+/// Upper bounds of the request latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Counters for `/metrics` that are bumped where the event happens, anywhere in the crate.
+/// libp2p 0.39 has no metrics support of its own, the swarm numbers come from [`Health`].
+#[derive(Default)]
+pub struct Metrics {
+    published: Mutex<BTreeMap<&'static str, u64>>,
+    received: Mutex<BTreeMap<&'static str, u64>>,
+    list_latency: Mutex<Histogram>,
+    storage_errors: AtomicU64,
+    requests_limited: AtomicU64,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket of [`LATENCY_BUCKETS`], the last one for slower ones.
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+impl Metrics {
+    /// A message we sent on a topic or in a direct request or response.
+    pub fn published(&self, message: &Message) {
+        *self
+            .published
+            .lock()
+            .expect("metrics lock")
+            .entry(message.kind())
+            .or_default() += 1;
+    }
+
+    /// A message a peer sent us on a topic or in a direct request or response.
+    pub fn received(&self, message: &Message) {
+        *self
+            .received
+            .lock()
+            .expect("metrics lock")
+            .entry(message.kind())
+            .or_default() += 1;
+    }
+
+    /// Time from sending a list request to its response.
+    pub fn list_latency(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let mut histogram = self.list_latency.lock().expect("metrics lock");
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        histogram.counts[bucket] += 1;
+        histogram.sum += seconds;
+    }
+
+    pub fn storage_error(&self) {
+        self.storage_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A list request dropped because its peer made too many.
+    pub fn request_limited(&self) {
+        self.requests_limited.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Prometheus text exposition of the node counters.
+pub async fn render(health: &Health) -> String {
+    let (cars, public) = match read_local_carinfos().await {
+        Ok(v) => (v.len(), v.iter().filter(|r| r.public).count()),
+        Err(_) => (0, 0),
+    };
+    let mut out = String::new();
+    let values = [
+        ("carinfo_uptime_seconds", "gauge", health.uptime().as_secs()),
+        (
+            "carinfo_connected_peers",
+            "gauge",
+            health.connected_peers.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "carinfo_discovered_peers",
+            "gauge",
+            health.discovered_peers.load(Ordering::Relaxed) as u64,
+        ),
+        ("carinfo_catalog_cars", "gauge", cars as u64),
+        ("carinfo_catalog_public_cars", "gauge", public as u64),
+        (
+            "carinfo_requests_served_total",
+            "counter",
+            health.requests_served.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "carinfo_responses_received_total",
+            "counter",
+            health.responses_received.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "carinfo_storage_errors_total",
+            "counter",
+            METRICS.storage_errors.load(Ordering::Relaxed),
+        ),
+        (
+            "carinfo_requests_limited_total",
+            "counter",
+            METRICS.requests_limited.load(Ordering::Relaxed),
+        ),
+    ];
+    for (name, kind, value) in values {
+        let _ = write!(out, "# TYPE {} {}\n{} {}\n", name, kind, name, value);
+    }
+    for (name, counts) in [
+        ("carinfo_messages_published_total", &METRICS.published),
+        ("carinfo_messages_received_total", &METRICS.received),
+    ] {
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (kind, count) in counts.lock().expect("metrics lock").iter() {
+            let _ = writeln!(out, "{}{{type=\"{}\"}} {}", name, kind, count);
+        }
+    }
+    let histogram = METRICS.list_latency.lock().expect("metrics lock");
+    let name = "carinfo_list_request_duration_seconds";
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    let mut cumulative = 0;
+    for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.counts.iter()) {
+        cumulative += count;
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+    }
+    cumulative += histogram.counts[LATENCY_BUCKETS.len()];
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+    let _ = writeln!(out, "{}_sum {}", name, histogram.sum);
+    let _ = writeln!(out, "{}_count {}", name, cumulative);
+    out
+}
@@ -0,0 +1,86 @@
+use libp2p::{core::ConnectedPoint, multiaddr::Protocol, Multiaddr, PeerId};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Instant;
+
+/// What we know about whether peers outside the local network can reach us. libp2p 0.39 has no
+/// AutoNAT, so this is learned from the connections such peers open to us.
+#[derive(Debug, Default)]
+pub struct Reachability {
+    inbound: usize,
+    last: Option<(Multiaddr, Instant)>,
+}
+
+impl Reachability {
+    /// Counts a connection a peer opened to us, if it came from outside the local network.
+    pub fn connected(&mut self, endpoint: &ConnectedPoint) {
+        if let ConnectedPoint::Listener { send_back_addr, .. } = endpoint {
+            if is_public(send_back_addr) {
+                self.inbound += 1;
+                self.last = Some((send_back_addr.clone(), Instant::now()));
+            }
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.last {
+            Some((addr, at)) => format!(
+                "reachable, {} connections from outside the local network, last from {} {}s ago",
+                self.inbound,
+                addr,
+                at.elapsed().as_secs()
+            ),
+            None => "unknown, no peer outside the local network has connected to us yet; \
+                     behind NAT, listen through a relay"
+                .to_owned(),
+        }
+    }
+}
+
+/// Where to listen through `relay`: peers dial us at this address and the relay forwards the
+/// connection.
+pub fn circuit_address(relay: &PeerId, addr: &Multiaddr) -> Multiaddr {
+    addr.clone()
+        .with(Protocol::P2p((*relay).into()))
+        .with(Protocol::P2pCircuit)
+}
+
+/// The relay a listen address goes through, if it is a circuit address.
+pub fn relay_of(addr: &Multiaddr) -> Option<PeerId> {
+    let mut relay = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::P2p(hash) => relay = PeerId::from_multihash(hash).ok(),
+            Protocol::P2pCircuit => return relay,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether `addr` is outside the local network: not loopback, private or link-local, and not
+/// relayed.
+fn is_public(addr: &Multiaddr) -> bool {
+    let mut public = false;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::Ip4(ip) => public = is_public_v4(ip),
+            Protocol::Ip6(ip) => public = is_public_v6(ip),
+            Protocol::P2pCircuit => return false,
+            _ => {}
+        }
+    }
+    public
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified())
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segment = ip.segments()[0];
+    // unique local fc00::/7 and link-local fe80::/10
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || (segment & 0xfe00) == 0xfc00
+        || (segment & 0xffc0) == 0xfe80)
+}
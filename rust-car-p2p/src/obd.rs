@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::Result;
+
+/// Commands sent to a live ELM327 adapter: disable echo and headers, then query odometer
+/// (mode 01 PID A6), fuel level (2F), vehicle speed (0D), coolant temperature (05) and
+/// stored trouble codes (mode 03).
+const ELM327_COMMANDS: &[&str] = &["ATE0", "ATH0", "01A6", "012F", "010D", "0105", "03"];
+const ELM327_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Live stats read from a car's OBD-II port, attached to the matching local record.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ObdStats {
+    pub odometer_km: Option<f64>,
+    pub fuel_level_percent: Option<f64>,
+    pub dtc_codes: Vec<String>,
+    #[serde(default)]
+    pub speed_kmh: Option<f64>,
+    #[serde(default)]
+    pub coolant_temp_c: Option<f64>,
+    /// Unix timestamp of the reading.
+    pub read_at: u64,
+    /// Whether the stats are included when the record is sent to other peers.
+    pub shared: bool,
+}
+
+/// Reads stats from `path`, which is either a live ELM327 device (e.g. `/dev/rfcomm0` or a
+/// USB serial port already configured with `stty`) or a log file of captured responses.
+pub async fn read(path: &str) -> Result<ObdStats> {
+    if path.starts_with("/dev/") {
+        read_device(path).await
+    } else {
+        let content = tokio::fs::read_to_string(path).await?;
+        Ok(parse(&content))
+    }
+}
+
+async fn read_device(path: &str) -> Result<ObdStats> {
+    let mut device = OpenOptions::new().read(true).write(true).open(path).await?;
+    let mut transcript = String::new();
+    for command in ELM327_COMMANDS {
+        device
+            .write_all(format!("{}\r", command).as_bytes())
+            .await?;
+        let response = tokio::time::timeout(ELM327_TIMEOUT, read_until_prompt(&mut device))
+            .await
+            .map_err(|_| format!("no response from {} to {}", path, command))??;
+        transcript.push_str(&response);
+        transcript.push('\n');
+    }
+    Ok(parse(&transcript))
+}
+
+async fn read_until_prompt(device: &mut tokio::fs::File) -> Result<String> {
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if device.read(&mut byte).await? == 0 || byte[0] == b'>' {
+            break;
+        }
+        response.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&response).replace('\r', "\n"))
+}
+
+/// Parses ELM327 response lines, ignoring anything that isn't a known mode 01/03 reply.
+pub fn parse(responses: &str) -> ObdStats {
+    let mut stats = ObdStats {
+        read_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    for line in responses.lines() {
+        let bytes = match hex_bytes(line) {
+            Some(bytes) if !bytes.is_empty() => bytes,
+            _ => continue,
+        };
+        match bytes.as_slice() {
+            [0x41, 0xA6, a, b, c, d, ..] => {
+                let raw = u32::from_be_bytes([*a, *b, *c, *d]);
+                stats.odometer_km = Some(f64::from(raw) / 10.0);
+            }
+            [0x41, 0x2F, a, ..] => {
+                stats.fuel_level_percent = Some(f64::from(*a) * 100.0 / 255.0);
+            }
+            [0x41, 0x0D, a, ..] => {
+                stats.speed_kmh = Some(f64::from(*a));
+            }
+            [0x41, 0x05, a, ..] => {
+                stats.coolant_temp_c = Some(f64::from(*a) - 40.0);
+            }
+            [0x43, rest @ ..] => {
+                // CAN adapters prefix the code list with a count byte, older protocols don't.
+                let codes = if !rest.len().is_multiple_of(2) {
+                    &rest[1..]
+                } else {
+                    rest
+                };
+                stats.dtc_codes.extend(
+                    codes
+                        .chunks_exact(2)
+                        .filter(|c| c != &[0, 0])
+                        .map(|c| dtc_code(c[0], c[1])),
+                );
+            }
+            _ => {}
+        }
+    }
+    stats
+}
+
+fn hex_bytes(line: &str) -> Option<Vec<u8>> {
+    let hex: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn dtc_code(a: u8, b: u8) -> String {
+    let system = ['P', 'C', 'B', 'U'][usize::from(a >> 6)];
+    format!("{}{}{:X}{:02X}", system, (a >> 4) & 0x3, a & 0xF, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_mode_01_replies() {
+        let stats = parse("41 A6 00 01 E2 40\r\n41 2F FF\n41 0D 32\n41 05 5A\nSEARCHING...\n");
+        assert_eq!(stats.odometer_km, Some(12345.6));
+        assert_eq!(stats.fuel_level_percent, Some(100.0));
+        assert_eq!(stats.speed_kmh, Some(50.0));
+        assert_eq!(stats.coolant_temp_c, Some(50.0));
+        assert!(stats.dtc_codes.is_empty());
+        assert!(stats.read_at > 0);
+    }
+
+    #[test]
+    fn reads_trouble_codes_with_and_without_a_count() {
+        assert_eq!(parse("43 01 33 00 00 00 00").dtc_codes, vec!["P0133"]);
+        assert_eq!(parse("43 02 01 33 C1 23").dtc_codes, vec!["P0133", "U0123"]);
+        assert_eq!(parse("43 5A 21 80 04").dtc_codes, vec!["C1A21", "B0004"]);
+    }
+
+    #[test]
+    fn ignores_unknown_and_malformed_lines() {
+        let stats = parse("NO DATA\n41 0C 1A F8\n41 0D\n41 2F F\nATE0\n");
+        assert_eq!(stats.speed_kmh, None);
+        assert_eq!(stats.fuel_level_percent, None);
+        assert_eq!(stats.odometer_km, None);
+    }
+}
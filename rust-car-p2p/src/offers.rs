@@ -0,0 +1,182 @@
+use libp2p::{request_response::RequestId, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::fs;
+
+use crate::{
+    aliases,
+    chat::now,
+    format::{self, NUMBER_FORMAT},
+    vehicle::Price,
+    Result,
+};
+
+const OFFERS_FILE_PATH: &str = "./offers.json";
+
+/// How long an offer stands unless `offer car` says otherwise.
+pub const DEFAULT_VALIDITY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// An offer to buy a car, sent to its owner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Offer {
+    /// Picked by the buyer, so the owner's answer can name the offer.
+    pub id: u64,
+    pub car_id: usize,
+    pub price: Price,
+    /// Unix timestamp after which the offer no longer stands.
+    pub expiry: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OfferRequest {
+    /// From a buyer to the owner of the car.
+    Offer(Offer),
+    /// From the owner back to the buyer.
+    Answer { id: u64, accepted: bool },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OfferResponse {
+    Received,
+    /// Why the request wasn't taken, e.g. an unknown car or an expired offer.
+    Refused(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OfferState {
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+/// An offer we made or received, with the peer on the other side.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OfferEntry {
+    pub peer: String,
+    pub offer: Offer,
+    pub state: OfferState,
+    /// Whether the other side knows about the last change of `state`: for received offers,
+    /// that the buyer got our answer.
+    #[serde(default)]
+    pub told: bool,
+    pub at: u64,
+}
+
+impl OfferEntry {
+    pub fn expired(&self) -> bool {
+        self.state == OfferState::Pending && self.offer.expiry <= now()
+    }
+
+    fn status(&self) -> String {
+        match self.state {
+            OfferState::Pending if self.expired() => "expired".to_owned(),
+            OfferState::Pending => format!("pending, {}", expires_in(self.offer.expiry)),
+            OfferState::Accepted => "accepted".to_owned(),
+            OfferState::Rejected => "rejected".to_owned(),
+        }
+    }
+
+    fn peer(&self) -> String {
+        self.peer
+            .parse()
+            .map_or(self.peer.clone(), |peer| aliases::display(&peer))
+    }
+
+    pub fn price(&self) -> String {
+        NUMBER_FORMAT.price(self.offer.price.amount as f64, &self.offer.price.currency)
+    }
+
+    /// e.g. `alice offers 42.000 EUR for car 3 (2h ago), pending, expires in 6d`.
+    pub fn incoming_summary(&self) -> String {
+        format!(
+            "{} offers {} for car {} ({}), {}",
+            self.peer(),
+            self.price(),
+            self.offer.car_id,
+            format::age(self.at),
+            self.status()
+        )
+    }
+
+    /// e.g. `offered 42.000 EUR for car 3 of alice (2h ago), accepted`.
+    pub fn outgoing_summary(&self) -> String {
+        format!(
+            "offered {} for car {} of {} ({}), {}",
+            self.price(),
+            self.offer.car_id,
+            self.peer(),
+            format::age(self.at),
+            self.status()
+        )
+    }
+}
+
+/// e.g. "expires in 3d".
+fn expires_in(expiry: u64) -> String {
+    match expiry.saturating_sub(now()) {
+        s if s < 60 * 60 => format!("expires in {}m", s.div_ceil(60)),
+        s if s < 24 * 60 * 60 => format!("expires in {}h", s / (60 * 60)),
+        s => format!("expires in {}d", s / (24 * 60 * 60)),
+    }
+}
+
+/// Reads how long an offer stands, `<n>d` or `<n>h`.
+pub fn parse_validity(s: &str) -> std::result::Result<Duration, String> {
+    let (n, unit) = s.split_at(s.len().saturating_sub(1));
+    let n: u64 = n
+        .parse()
+        .map_err(|e| format!("invalid validity {}, {}", s, e))?;
+    let secs = match unit {
+        "d" => n * 24 * 60 * 60,
+        "h" => n * 60 * 60,
+        _ => return Err(format!("invalid validity {}, expected e.g. 7d or 12h", s)),
+    };
+    if secs == 0 {
+        return Err(format!("invalid validity {}, it must be positive", s));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// A request in flight, by what its response concerns.
+#[derive(Debug)]
+pub enum Sending {
+    Offer(Offer),
+    /// The answer to the received offer with this number.
+    Answer(usize),
+}
+
+/// Offers received for our cars and made for peers' cars, persisted locally.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Offers {
+    /// Numbered by position for `accept offer` and `reject offer`.
+    pub incoming: Vec<OfferEntry>,
+    pub outgoing: Vec<OfferEntry>,
+    #[serde(skip)]
+    pub sending: HashMap<RequestId, Sending>,
+}
+
+impl Offers {
+    pub async fn load() -> Result<Offers> {
+        match fs::read(OFFERS_FILE_PATH).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Offers::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        fs::write(OFFERS_FILE_PATH, &json).await?;
+        Ok(())
+    }
+
+    /// The offer we made to `peer` with this id.
+    pub fn outgoing_mut(&mut self, peer: &PeerId, id: u64) -> Option<&mut OfferEntry> {
+        let peer = peer.to_string();
+        self.outgoing
+            .iter_mut()
+            .find(|entry| entry.peer == peer && entry.offer.id == id)
+    }
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+
+use crate::Result;
+
+const OUTBOX_FILE_PATH: &str = "./outbox.json";
+/// Queued announcements older than this are dropped instead of sent.
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// An announcement that could not be delivered because nobody was listening on its topic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedMessage {
+    pub topic: String,
+    /// The encoded [`crate::wire::Message`].
+    pub data: Vec<u8>,
+    /// Unix timestamp of when it was queued.
+    pub queued_at: u64,
+}
+
+/// Announcements waiting for a peer, persisted so they survive restarts.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    messages: Vec<QueuedMessage>,
+}
+
+impl Outbox {
+    pub async fn load() -> Result<Outbox> {
+        match fs::read(OUTBOX_FILE_PATH).await {
+            Ok(content) => Ok(Outbox {
+                messages: serde_json::from_slice(&content)?,
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Outbox::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub async fn push(&mut self, topic: &str, data: Vec<u8>) -> Result<()> {
+        self.messages.push(QueuedMessage {
+            topic: topic.to_owned(),
+            data,
+            queued_at: now(),
+        });
+        self.save().await
+    }
+
+    /// Removes and returns the queued messages for `topic` that are still fresh.
+    pub async fn take(&mut self, topic: &str) -> Result<Vec<QueuedMessage>> {
+        let cutoff = now().saturating_sub(MAX_AGE.as_secs());
+        let (taken, kept) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .partition(|m| m.topic == topic);
+        self.messages = kept;
+        self.save().await?;
+        Ok(taken
+            .into_iter()
+            .filter(|m: &QueuedMessage| m.queued_at >= cutoff)
+            .collect())
+    }
+
+    async fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(&self.messages)?;
+        fs::write(OUTBOX_FILE_PATH, &json).await?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
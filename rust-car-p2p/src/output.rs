@@ -0,0 +1,154 @@
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::Carinfo;
+
+/// How command results are printed, set with `--output <format>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Log lines for people, as always.
+    #[default]
+    Text,
+    /// One JSON object per result on stdout, logs on stderr.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            other => Err(format!(
+                "unknown output format {}, expected text or json",
+                other
+            )),
+        }
+    }
+}
+
+impl Format {
+    /// The format given with `--output`, text without one.
+    pub fn from_args(args: &[String]) -> Result<Format, String> {
+        match args.iter().position(|a| a == "--output") {
+            Some(i) => args
+                .get(i + 1)
+                .ok_or_else(|| "--output needs a value".to_owned())?
+                .parse(),
+            None => Ok(Format::Text),
+        }
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn enable_json() {
+    JSON.store(true, Ordering::Relaxed);
+}
+
+/// Whether results are printed with [`emit`] instead of logged.
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// A command result in JSON output mode, printed as one line tagged with `type`. Cars are
+/// serialized as in the catalog file, times are unix seconds. Fields are only ever added.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Output<'a> {
+    /// `ls p`
+    Peers {
+        peers: Vec<Peer>,
+    },
+    /// `ls car` without a target.
+    LocalCars {
+        cars: &'a [Carinfo],
+    },
+    /// A peer's answer to `ls car`, or its cached listing.
+    RemoteCars {
+        peer: String,
+        cars: &'a [Carinfo],
+        /// When the listing was received, set if it came from the cache.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cached_at: Option<u64>,
+    },
+    /// Records matching a `filter <field><op><value>`, from `peer` or local ones without it.
+    Matches {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        peer: Option<String>,
+        filter: String,
+        cars: &'a [Carinfo],
+    },
+    /// `search <query>`
+    SearchHits {
+        query: String,
+        hits: Vec<Hit<'a>>,
+    },
+    Created {
+        car: &'a Carinfo,
+    },
+    Published {
+        id: usize,
+    },
+    /// The end of an `ls car all`, once every peer answered or its window closed.
+    ListSummary {
+        number: usize,
+        answered: usize,
+        /// Of the answers, how many came from the cache.
+        cached: usize,
+        cars: usize,
+        timed_out: Vec<String>,
+        failed: usize,
+    },
+    /// The peers that announced public cars of the make or model asked for with `find`.
+    Providers {
+        query: String,
+        peers: Vec<String>,
+    },
+    /// A command that couldn't run.
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Peer {
+    pub peer_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// connected, discovered or expired
+    pub state: &'static str,
+    /// Whether it listens on the catalog topic.
+    pub subscribed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<u64>,
+    pub addrs: Vec<String>,
+    /// What the peer told about itself with identify, once connected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub listen_addrs: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hit<'a> {
+    pub score: f32,
+    /// Peer id the car came from, `local` for our own.
+    pub owner: &'a str,
+    pub car: &'a Carinfo,
+}
+
+/// Prints `output` as a JSON line on stdout.
+pub fn emit(output: &Output) {
+    let mut line = serde_json::to_vec(output).expect("can jsonify output");
+    line.push(b'\n');
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(&line).and_then(|_| stdout.flush());
+}
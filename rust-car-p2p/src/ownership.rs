@@ -0,0 +1,116 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use log::error;
+use serde::Serialize;
+
+use crate::{
+    aliases, share,
+    vehicle::{Fuel, Price, Transmission},
+    Carinfo, Carinfos, KEYS, PEER_ID,
+};
+
+/// The fields of a record its origin signs: what the car is and who listed it. Photos,
+/// measurements, location and the like are filled in or left out on the way and aren't covered.
+#[derive(Serialize)]
+struct Signed<'a> {
+    origin: &'a str,
+    id: usize,
+    make: &'a str,
+    model: &'a str,
+    horsepower: u32,
+    vin: &'a Option<String>,
+    year: Option<u16>,
+    mileage_km: Option<u32>,
+    price: &'a Option<Price>,
+    fuel: Option<Fuel>,
+    transmission: Option<Transmission>,
+    created_at: Option<DateTime<Utc>>,
+}
+
+fn signed_bytes(carinfo: &Carinfo, origin: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    ciborium::into_writer(
+        &Signed {
+            origin,
+            id: carinfo.id,
+            make: &carinfo.make,
+            model: &carinfo.model,
+            horsepower: carinfo.horsepower,
+            vin: &carinfo.vin,
+            year: carinfo.year,
+            mileage_km: carinfo.mileage_km,
+            price: &carinfo.price,
+            fuel: carinfo.fuel,
+            transmission: carinfo.transmission,
+            created_at: carinfo.created_at,
+        },
+        &mut data,
+    )
+    .expect("can encode record");
+    data
+}
+
+/// Signs one of our records as its origin before it leaves the node. Records of other peers
+/// keep the signature they came with.
+pub fn sign(carinfo: &mut Carinfo) {
+    let us = PEER_ID.to_string();
+    if carinfo.origin.as_ref().is_some_and(|origin| *origin != us) {
+        return;
+    }
+    let signature = KEYS
+        .sign(&signed_bytes(carinfo, &us))
+        .expect("can sign with the node key");
+    carinfo.origin = Some(us);
+    carinfo.origin_signature = Some(STANDARD.encode(signature));
+}
+
+/// Checks the origin's signature of a record from `holder`. Unsigned records, from peers that
+/// predate signatures, pass, but are marked unverified if they name an origin other than
+/// `holder`; a signature that doesn't match the record or its origin fails.
+pub fn verify(carinfo: &mut Carinfo, holder: &PeerId) -> Result<(), String> {
+    let signature = match &carinfo.origin_signature {
+        Some(signature) => STANDARD
+            .decode(signature)
+            .map_err(|e| format!("invalid signature, {}", e))?,
+        None => {
+            let holder = holder.to_string();
+            carinfo.unverified = carinfo
+                .origin
+                .as_ref()
+                .is_some_and(|origin| *origin != holder);
+            return Ok(());
+        }
+    };
+    carinfo.unverified = false;
+    let origin = carinfo
+        .origin
+        .as_deref()
+        .ok_or("the signed record has no origin")?;
+    let peer: PeerId = origin
+        .parse()
+        .map_err(|e| format!("invalid origin {}, {}", origin, e))?;
+    let key = share::peer_public_key(&peer)
+        .ok_or_else(|| format!("can't check signatures of {}", origin))?;
+    if !key.verify(&signed_bytes(carinfo, origin), &signature) {
+        return Err(format!("it isn't signed by its origin {}", origin));
+    }
+    Ok(())
+}
+
+/// Drops the records from `holder` whose signature doesn't check out, and marks the unsigned
+/// ones it passes on for others.
+pub fn drop_forged(holder: &PeerId, carinfos: &mut Carinfos) {
+    carinfos.retain_mut(|carinfo| match verify(carinfo, holder) {
+        Ok(()) => true,
+        Err(e) => {
+            error!(
+                "dropped carinfo {} from {}, {}",
+                carinfo.id,
+                aliases::display(holder),
+                e
+            );
+            false
+        }
+    });
+}
@@ -0,0 +1,152 @@
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+use terminal_size::{terminal_size, Height};
+
+/// Page height used when the terminal size can't be determined, e.g. when stdout is piped.
+const DEFAULT_PAGE_HEIGHT: usize = 24;
+/// Rows kept free below each page for the pager prompt and the next input line.
+const PROMPT_ROWS: usize = 2;
+
+/// Cleared when nobody types at a terminal, e.g. in daemon mode, where listings are printed
+/// whole.
+static PAGING: AtomicBool = AtomicBool::new(true);
+
+pub fn disable() {
+    PAGING.store(false, Ordering::Relaxed);
+}
+
+/// Internal pager for listings that don't fit on one screen.
+///
+/// While a pager is active, stdin lines are routed to `handle_input` instead of the
+/// command dispatcher: enter shows the next page, `b` the previous one, `/text` searches
+/// forward (case-insensitive), `n` repeats the last search and `q` closes the pager.
+pub struct Pager {
+    lines: Vec<String>,
+    top: usize,
+    height: usize,
+    search: Option<String>,
+}
+
+impl Pager {
+    /// Prints `lines`, returning a pager over them if they don't fit on one screen.
+    pub fn show(lines: Vec<String>) -> Option<Pager> {
+        let height = page_height();
+        if lines.len() <= height {
+            lines.iter().for_each(|l| info!("{}", l));
+            return None;
+        }
+        let pager = Pager {
+            lines,
+            top: 0,
+            height,
+            search: None,
+        };
+        pager.print_page();
+        Some(pager)
+    }
+
+    /// Appends lines to the listing being paged, e.g. from a late peer response.
+    pub fn extend(&mut self, lines: Vec<String>) {
+        let added = lines.len();
+        self.lines.extend(lines);
+        info!(
+            "-- {} more lines received, {} total --",
+            added,
+            self.lines.len()
+        );
+    }
+
+    /// Handles one line of user input. Returns `false` once the pager should be closed.
+    pub fn handle_input(&mut self, input: &str) -> bool {
+        match input.trim() {
+            "q" => return false,
+            "" => {
+                if self.top + self.height >= self.lines.len() {
+                    return false;
+                }
+                self.top += self.height;
+                self.print_page();
+            }
+            "b" => {
+                self.top = self.top.saturating_sub(self.height);
+                self.print_page();
+            }
+            "n" => self.find_next(),
+            cmd if cmd.starts_with('/') => {
+                let pattern = cmd[1..].trim().to_lowercase();
+                if pattern.is_empty() {
+                    info!("usage: /text");
+                } else {
+                    self.search = Some(pattern);
+                    self.find_next();
+                }
+            }
+            _ => info!("pager: [enter] next, [b] back, [/text] search, [n] next match, [q] quit"),
+        }
+        true
+    }
+
+    fn find_next(&mut self) {
+        let pattern = match &self.search {
+            Some(p) => p,
+            None => {
+                info!("no search pattern, use /text first");
+                return;
+            }
+        };
+        let found = self
+            .lines
+            .iter()
+            .enumerate()
+            .skip(self.top + 1)
+            .find(|(_, l)| l.to_lowercase().contains(pattern.as_str()))
+            .map(|(i, _)| i);
+        match found {
+            Some(i) => {
+                self.top = i;
+                self.print_page();
+            }
+            None => info!("pattern not found: {}", pattern),
+        }
+    }
+
+    fn print_page(&self) {
+        let end = (self.top + self.height).min(self.lines.len());
+        self.lines[self.top..end]
+            .iter()
+            .for_each(|l| info!("{}", l));
+        if end < self.lines.len() {
+            info!(
+                "-- lines {}-{} of {} -- [enter] next, [b] back, [/text] search, [n] next match, [q] quit",
+                self.top + 1,
+                end,
+                self.lines.len()
+            );
+        } else {
+            info!(
+                "-- end ({} lines) -- [enter/q] close, [b] back, [/text] search",
+                self.lines.len()
+            );
+        }
+    }
+}
+
+/// Shows `lines` through the active pager if there is one, otherwise starts a new one.
+pub fn page(pager: &mut Option<Pager>, lines: Vec<String>) {
+    if !PAGING.load(Ordering::Relaxed) {
+        lines.iter().for_each(|l| info!("{}", l));
+        return;
+    }
+    match pager {
+        Some(p) => p.extend(lines),
+        None => *pager = Pager::show(lines),
+    }
+}
+
+fn page_height() -> usize {
+    terminal_size()
+        .map(|(_, Height(h))| h as usize)
+        .unwrap_or(DEFAULT_PAGE_HEIGHT)
+        .saturating_sub(PROMPT_ROWS)
+        .max(1)
+}
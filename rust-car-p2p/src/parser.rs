@@ -0,0 +1,1546 @@
+use libp2p::{Multiaddr, PeerId};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::Duration;
+
+use crate::{
+    aliases, channels,
+    commands::Verbosity,
+    dht,
+    filter::{Condition, Filter, Op},
+    geo,
+    import::{CsvImport, JsonImport},
+    listing::ListOptions,
+    offers,
+    replica::Resolution,
+    tags, telemetry,
+    vehicle::{Details, Price},
+    vin, CarinfoKey, PEER_ID,
+};
+
+/// A line of user input with its arguments checked, ready for [`crate::commands::execute`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Usage of every command, or of those starting with the given words.
+    Help(Option<String>),
+    ListPeers,
+    ListRoutedPeers,
+    Dial {
+        peer: PeerId,
+        addr: Multiaddr,
+    },
+    NetStatus,
+    Ping(PeerId),
+    Alias {
+        name: String,
+        peer: PeerId,
+    },
+    ListAliases,
+    Unalias(String),
+    Status,
+    ListCarinfos(ListTarget),
+    /// Local records, sorted and shown as the options say.
+    ListLocal(ListOptions),
+    /// Every peer's cached listing.
+    ListCached,
+    /// The cars received from peers in one table, optionally grouped.
+    ListTable(Option<Grouping>),
+    Queue(QueueCommand),
+    Access(AccessCommand),
+    ListMarket {
+        radius_km: Option<f64>,
+    },
+    Create {
+        make: String,
+        model: String,
+        horsepower: u32,
+        vin: Option<String>,
+        details: Details,
+        /// Create it even if the catalog has the car already.
+        force: bool,
+    },
+    /// `None` fields keep their current value.
+    Edit {
+        id: usize,
+        make: Option<String>,
+        model: Option<String>,
+        horsepower: Option<u32>,
+        vin: Option<String>,
+        details: Details,
+    },
+    /// Lists duplicate records, or merges them.
+    Dedupe {
+        merge: bool,
+    },
+    Publish(usize),
+    Unpublish(usize),
+    Delete(usize),
+    /// `None` clears the location.
+    Geo {
+        id: usize,
+        at: Option<(f64, f64)>,
+    },
+    /// Replaces the channels of a car; none takes it out of every channel.
+    ChannelCar {
+        id: usize,
+        channels: BTreeSet<String>,
+    },
+    Channels(ChannelCommand),
+    Tag {
+        id: usize,
+        tag: String,
+    },
+    Untag {
+        id: usize,
+        tag: String,
+    },
+    Audits(usize),
+    /// The journaled changes of a local car.
+    History(usize),
+    Undo,
+    Redo,
+    Share {
+        id: usize,
+        peer: PeerId,
+    },
+    ListShared,
+    Sync(PeerId),
+    /// Copies synced from one peer, or from all.
+    ListSynced(Option<PeerId>),
+    /// Lists an unpublished car to one peer.
+    Grant {
+        id: usize,
+        peer: PeerId,
+    },
+    Revoke {
+        id: usize,
+        peer: PeerId,
+    },
+    Obd {
+        id: usize,
+        source: String,
+        share: bool,
+    },
+    Can {
+        id: usize,
+        log: String,
+        dbc: String,
+        hp_signal: Option<String>,
+        odometer_signal: Option<String>,
+    },
+    Enrich(usize),
+    Price(usize),
+    ExportParquet(String),
+    ExportCsv(String),
+    ExportJson(String),
+    /// A snapshot into the directory, `./backups` if none.
+    Backup(Option<String>),
+    Restore(String),
+    ImportJson {
+        path: String,
+        options: JsonImport,
+    },
+    ImportCsv {
+        path: String,
+        options: CsvImport,
+    },
+    Telemetry(TelemetryCommand),
+    /// `watch` turns it on, `unwatch` off.
+    Watch(bool),
+    Club(ClubCommand),
+    Photo {
+        id: usize,
+        path: String,
+    },
+    Thumbnails {
+        peer: PeerId,
+        id: usize,
+    },
+    /// Full-size photos of a peer's car; without a peer, of the one listed car with that id.
+    FetchPhoto {
+        peer: Option<PeerId>,
+        id: usize,
+    },
+    Search(String),
+    /// Words to rank the public cars of every peer by.
+    SearchNet(String),
+    /// Peers that announced public cars of a make, or of a model of it.
+    FindProviders {
+        make: String,
+        model: Option<String>,
+    },
+    /// Records matching every condition, locally or from peers.
+    Find {
+        target: ListTarget,
+        filter: Filter,
+    },
+    Chats(Option<usize>),
+    Chat {
+        to: ChatTarget,
+        text: String,
+    },
+    /// Offers to buy a peer's car, standing for `valid_for`.
+    MakeOffer {
+        peer: PeerId,
+        car_id: usize,
+        price: Price,
+        valid_for: Duration,
+    },
+    Offers,
+    /// Accepts or rejects a received offer by its number in `offers`.
+    AnswerOffer {
+        n: usize,
+        accept: bool,
+    },
+    Conflicts,
+    /// Settles a conflict by its number in `conflicts`.
+    ResolveConflict {
+        n: usize,
+        resolution: Resolution,
+    },
+    SetVerbosity(Verbosity),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListTarget {
+    Local,
+    All,
+    Peer(PeerId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryCommand {
+    Start {
+        id: usize,
+        source: String,
+        interval: Duration,
+        region: Option<String>,
+    },
+    Stop,
+    /// Show at most one update per peer in this interval.
+    Watch(Duration),
+    Unwatch,
+}
+
+/// What `ls car table by ...` puts each car under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Peer,
+    Make,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueueCommand {
+    List,
+    /// Drops a queued request by its number.
+    Cancel(usize),
+    CancelAll,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelCommand {
+    List,
+    Join(String),
+    Leave(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessCommand {
+    /// The block and allow lists.
+    Show,
+    Block(PeerId),
+    Unblock(PeerId),
+    Allow(PeerId),
+    Disallow(PeerId),
+    /// Turns allowlist-only mode on or off.
+    AllowlistOnly(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClubCommand {
+    Join(PeerId),
+    Leave,
+    Submit(usize),
+    Withdraw(usize),
+    List,
+    /// Lets a peer into the club we coordinate.
+    Add(PeerId),
+    /// Takes a peer and its cars out of the club we coordinate.
+    Remove(PeerId),
+    Members,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatTarget {
+    /// A thread by its number in `chats`.
+    Thread(usize),
+    /// The owner of a car, opening a new thread.
+    Car { peer: PeerId, car_id: usize },
+}
+
+/// What was wrong with a line, and how the command is used if it was recognized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageError {
+    pub problem: String,
+    pub usage: Option<&'static str>,
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.usage {
+            Some(usage) => write!(f, "{}\nusage: {}", self.problem, usage),
+            None => write!(f, "{}", self.problem),
+        }
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// A command as listed by `help`.
+pub struct Spec {
+    /// The words that select the command.
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub about: &'static str,
+}
+
+pub const COMMANDS: &[Spec] = &[
+    Spec {
+        name: "ls p",
+        usage: "ls p",
+        about: "list peers with their connection state, topic subscription, when they were last seen and their addresses",
+    },
+    Spec {
+        name: "peers",
+        usage: "peers",
+        about: "same as ls p",
+    },
+    Spec {
+        name: "ls p remote",
+        usage: "ls p remote",
+        about: "list peers in the DHT routing table",
+    },
+    Spec {
+        name: "ls car",
+        usage: "ls car [all | <peer id> | cached] | ls car [all] <filter>... | ls car [--sort <field>] [--desc] [--fields <field>,...] | ls car table [by peer | by make] | ls car vin <vin> | ls car channel <name>",
+        about: "list local cars, or ask peers for theirs, answering from recent cached listings; \
+                filters like tag:classic or hp>300 only list matching cars, table shows the cars \
+                received from peers in one table, with a VIN, ask every peer for that car, with \
+                a channel for the cars in it. Local cars can be sorted \
+                by, and shown as a table of, id, make, model, hp, vin, year, mileage, price, \
+                fuel, transmission and public",
+    },
+    Spec {
+        name: "queue",
+        usage: "queue [cancel <n> | cancel all]",
+        about: "show the requests waiting for a peer to connect, or cancel them",
+    },
+    Spec {
+        name: "ls market",
+        usage: "ls market [near <n>km]",
+        about: "list cars received from peers, optionally only those within reach",
+    },
+    Spec {
+        name: "create car",
+        usage: "create car <make> <model> <horsepower> [<vin>] [<detail>=<value>...] [--force] | create car make|model|horsepower[|vin] [<detail>=<value>...] [--force]",
+        about: "create new car data; quote fields with spaces. Details are year, mileage (km or \
+                mi), price (like 89000EUR), fuel and transmission. A car the catalog has \
+                already is refused without --force",
+    },
+    Spec {
+        name: "edit car",
+        usage: "edit car <id> <make> <model> <horsepower> [<vin>] [<detail>=<value>...] | edit car <id> make|model|horsepower[|vin] [<detail>=<value>...]",
+        about: "change a car; empty fields (\"\") and details not given keep their value",
+    },
+    Spec {
+        name: "dedupe",
+        usage: "dedupe [merge]",
+        about: "list local cars that are the same car, or merge each group into its oldest",
+    },
+    Spec {
+        name: "publish car",
+        usage: "publish car <id>",
+        about: "publish the car data",
+    },
+    Spec {
+        name: "unpublish car",
+        usage: "unpublish car <id>",
+        about: "stop sharing a car; peers that received it are told to drop it",
+    },
+    Spec {
+        name: "delete car",
+        usage: "delete car <id>",
+        about: "delete a car; peers that received it are told to drop it",
+    },
+    Spec {
+        name: "geo car",
+        usage: "geo car <id> <lat>,<lon> | geo car <id> clear",
+        about: "tag a car with a coarse location, or remove it",
+    },
+    Spec {
+        name: "channel car",
+        usage: "channel car <id> [<channel>...]",
+        about: "put a car in channels, replacing its others, or take it out of all of them; \
+                published cars are announced on their channels only",
+    },
+    Spec {
+        name: "tag car",
+        usage: "tag car <id> <tag>",
+        about: "label a car with a one-word tag, which peers can filter by too",
+    },
+    Spec {
+        name: "untag car",
+        usage: "untag car <id> <tag>",
+        about: "remove a tag from a car",
+    },
+    Spec {
+        name: "channels",
+        usage: "channels [join <name> | leave <name>]",
+        about: "list the channels we are in, or join or leave one",
+    },
+    Spec {
+        name: "audits car",
+        usage: "audits car <id>",
+        about: "show which peers were sent a car and when",
+    },
+    Spec {
+        name: "history",
+        usage: "history <id>",
+        about: "show when a car was created, changed, published and deleted, and by whom",
+    },
+    Spec {
+        name: "undo",
+        usage: "undo",
+        about: "revert the latest change of the catalog, again for the one before",
+    },
+    Spec {
+        name: "redo",
+        usage: "redo",
+        about: "make the change undone last again",
+    },
+    Spec {
+        name: "share car",
+        usage: "share car <id> with <peer id>",
+        about: "send a car, private fields included, encrypted to one peer",
+    },
+    Spec {
+        name: "shared",
+        usage: "shared",
+        about: "list cars peers shared with you",
+    },
+    Spec {
+        name: "sync",
+        usage: "sync <peer id>",
+        about: "copy a peer's public cars for offline use, replacing the previous copy",
+    },
+    Spec {
+        name: "synced",
+        usage: "synced [<peer id>]",
+        about: "list the copies of peers' cars made with sync",
+    },
+    Spec {
+        name: "grant car",
+        usage: "grant car <id> <peer id>",
+        about: "include a car in one peer's listings even while it is unpublished",
+    },
+    Spec {
+        name: "revoke car",
+        usage: "revoke car <id> <peer id>",
+        about: "undo a grant; the peer is told to drop the car unless it is published",
+    },
+    Spec {
+        name: "obd car",
+        usage: "obd car <id> <device or log file> [share]",
+        about: "attach OBD-II odometer, fuel level and trouble codes to a car",
+    },
+    Spec {
+        name: "can car",
+        usage: "can car <id> <candump log> <dbc file> [hp=<signal>] [odometer=<signal>]",
+        about: "decode a CAN log and attach confirmed horsepower/odometer to a car",
+    },
+    Spec {
+        name: "enrich car",
+        usage: "enrich car <id>",
+        about: "add details from the enabled external lookups to a car",
+    },
+    Spec {
+        name: "price car",
+        usage: "price car <id>",
+        about: "fetch current valuations for a car and show its price trend",
+    },
+    Spec {
+        name: "export parquet",
+        usage: "export parquet <path>",
+        about: "export local cars and listings received this session to a Parquet file",
+    },
+    Spec {
+        name: "export csv",
+        usage: "export csv <path>",
+        about: "export the local catalog to a CSV file",
+    },
+    Spec {
+        name: "import csv",
+        usage: "import csv <path> [dry-run] [duplicates=skip|replace|renumber] [<field>=<column>...]",
+        about: "add the rows of a CSV file to the local catalog; fields are id, make, model, hp \
+                and public, columns with other headers are mapped with <field>=<column>",
+    },
+    Spec {
+        name: "export json",
+        usage: "export json <path>",
+        about: "export the local catalog, every field included, for import json on another node",
+    },
+    Spec {
+        name: "import json",
+        usage: "import json <path> [--merge] [dry-run]",
+        about: "add the cars of an export json file to the local catalog, skipping those already \
+                in it; --merge gives cars with a taken id a new one",
+    },
+    Spec {
+        name: "backup",
+        usage: "backup [<dir>]",
+        about: "write a snapshot of the catalog, the identity and the config file, to ./backups \
+                unless given",
+    },
+    Spec {
+        name: "restore",
+        usage: "restore <path>",
+        about: "check a backup and put its catalog, identity and config file in place; the \
+                identity and config apply on the next start",
+    },
+    Spec {
+        name: "telemetry",
+        usage: "telemetry start <id> <device or log file> [every=<secs>] [region=<name>] | telemetry stop | telemetry watch [<secs>] | telemetry unwatch",
+        about: "stream live data for a published car, or show other peers' telemetry",
+    },
+    Spec {
+        name: "watch",
+        usage: "watch",
+        about: "show cars as peers publish them",
+    },
+    Spec {
+        name: "unwatch",
+        usage: "unwatch",
+        about: "stop showing cars as peers publish them",
+    },
+    Spec {
+        name: "club",
+        usage: "club join <coordinator peer id> | club leave | club submit <id> | club withdraw <id> | club ls | club add <peer id> | club remove <peer id> | club members",
+        about: "take part in a club catalog",
+    },
+    Spec {
+        name: "photo car",
+        usage: "photo car <id> <image file>",
+        about: "attach an image to a car, generating its thumbnail",
+    },
+    Spec {
+        name: "thumbs",
+        usage: "thumbs <peer id> <car id> | thumbs <peer id>/<car id>",
+        about: "fetch the thumbnails of a peer's car",
+    },
+    Spec {
+        name: "fetch photo",
+        usage: "fetch photo [<peer id>] <car id> | fetch photo <peer id>/<car id>",
+        about: "download the full-size photos of a peer's car; an interrupted download continues \
+                where it stopped",
+    },
+    Spec {
+        name: "search",
+        usage: "search <query> | search net <words>",
+        about: "full-text search over local cars and received listings, or ask every peer for \
+                its public cars best matching some words",
+    },
+    Spec {
+        name: "filter",
+        usage: "filter [all|<peer id>] <field><op><value>...",
+        about: "filter on make, model, vin, hp (=, !=, <, <=, >, >=), channel and tag locally, \
+                on all peers or on one",
+    },
+    Spec {
+        name: "find",
+        usage: "find <make> [<model>]",
+        about: "ask the DHT which peers have public cars of a make or model, e.g. find bmw m3, \
+                without asking every peer",
+    },
+    Spec {
+        name: "chats",
+        usage: "chats [<n>]",
+        about: "list chat threads, or show thread n",
+    },
+    Spec {
+        name: "chat",
+        usage: "chat <thread #> <text> | chat <peer id> <car id> <text> | chat <peer id>/<car id> <text>",
+        about: "reply in a chat thread, or write to the owner of a car",
+    },
+    Spec {
+        name: "offer car",
+        usage: "offer car <peer id> <car id> <price> [<n>d|<n>h] | offer car <peer id>/<car id> <price> [<n>d|<n>h]",
+        about: "offer to buy a peer's car, e.g. offer car alice 3 42000EUR 2d; offers stand 7 days \
+                unless given",
+    },
+    Spec {
+        name: "offers",
+        usage: "offers",
+        about: "list the offers received for our cars, numbered, and those we made",
+    },
+    Spec {
+        name: "accept offer",
+        usage: "accept offer <n>",
+        about: "accept received offer n, telling the buyer",
+    },
+    Spec {
+        name: "reject offer",
+        usage: "reject offer <n>",
+        about: "reject received offer n, telling the buyer",
+    },
+    Spec {
+        name: "conflicts",
+        usage: "conflicts",
+        about: "list synced cars whose origin's copy disagrees with ours, numbered",
+    },
+    Spec {
+        name: "resolve conflict",
+        usage: "resolve conflict <n> ours|theirs|newest",
+        about: "settle conflict n, keeping our copy, the origin's or the newest fields of both",
+    },
+    Spec {
+        name: "dial",
+        usage: "dial <multiaddr>/p2p/<peer id>",
+        about: "connect to a peer outside the local network, e.g. dial /ip4/203.0.113.5/tcp/4001/p2p/12D3KooW...",
+    },
+    Spec {
+        name: "ping",
+        usage: "ping <peer id>",
+        about: "show the round trip time to a peer",
+    },
+    Spec {
+        name: "net status",
+        usage: "net status",
+        about: "show whether peers outside the local network can reach us, our listen addresses and relays",
+    },
+    Spec {
+        name: "alias",
+        usage: "alias | alias <name> <peer id>",
+        about: "list peer aliases, or name a peer so commands accept the name for its id",
+    },
+    Spec {
+        name: "unalias",
+        usage: "unalias <name>",
+        about: "forget a peer alias",
+    },
+    Spec {
+        name: "block",
+        usage: "block <peer id>",
+        about: "refuse a peer's connections and ignore its messages",
+    },
+    Spec {
+        name: "unblock",
+        usage: "unblock <peer id>",
+        about: "take a peer off the blocklist",
+    },
+    Spec {
+        name: "allow",
+        usage: "allow <peer id>",
+        about: "put a peer on the allowlist",
+    },
+    Spec {
+        name: "disallow",
+        usage: "disallow <peer id>",
+        about: "take a peer off the allowlist",
+    },
+    Spec {
+        name: "allowlist",
+        usage: "allowlist on|off",
+        about: "only let peers on the allowlist connect, or any peer that isn't blocked",
+    },
+    Spec {
+        name: "access",
+        usage: "access",
+        about: "show the blocked and allowed peers",
+    },
+    Spec {
+        name: "status",
+        usage: "status",
+        about: "show identity, listen addresses, peers, topics and catalog size",
+    },
+    Spec {
+        name: "set verbosity",
+        usage: "set verbosity quiet|normal|verbose",
+        about: "change how much detail commands print",
+    },
+    Spec {
+        name: "help",
+        usage: "help [command]",
+        about: "show this list, or the usage of one command",
+    },
+];
+
+/// A word of input and where it started, so free text can be taken from the line as typed.
+struct Token {
+    text: String,
+    start: usize,
+}
+
+/// Splits on whitespace. Double quotes group words, and `""` is an empty argument.
+fn tokenize(line: &str) -> Result<Vec<Token>, UsageError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut text = String::new();
+        let mut quoted = false;
+        while let Some(&(_, c)) = chars.peek() {
+            if c.is_whitespace() && !quoted {
+                break;
+            }
+            chars.next();
+            if c == '"' {
+                quoted = !quoted;
+            } else {
+                text.push(c);
+            }
+        }
+        if quoted {
+            return Err(UsageError {
+                problem: "unclosed quote".to_owned(),
+                usage: None,
+            });
+        }
+        tokens.push(Token { text, start });
+    }
+    Ok(tokens)
+}
+
+/// The arguments after a command's name, taken in order.
+struct Args<'a> {
+    line: &'a str,
+    tokens: &'a [Token],
+    usage: &'static str,
+}
+
+impl<'a> Args<'a> {
+    fn error(&self, problem: impl Into<String>) -> UsageError {
+        UsageError {
+            problem: problem.into(),
+            usage: Some(self.usage),
+        }
+    }
+
+    fn next(&mut self, what: &str) -> Result<&'a str, UsageError> {
+        match self.tokens.split_first() {
+            Some((token, rest)) => {
+                self.tokens = rest;
+                Ok(&token.text)
+            }
+            None => Err(self.error(format!("missing {}", what))),
+        }
+    }
+
+    fn optional(&mut self) -> Option<&'a str> {
+        self.next("").ok()
+    }
+
+    /// The id of one of our records, also accepted as its full `<peer id>/<id>` key.
+    fn id(&mut self, what: &str) -> Result<usize, UsageError> {
+        let id = self.next(what)?;
+        if id.contains('/') {
+            let key: CarinfoKey = id.parse().map_err(|e| self.error(e))?;
+            if key.origin != *PEER_ID {
+                return Err(self.error(format!("{} is another peer's car", key)));
+            }
+            return Ok(key.id);
+        }
+        id.parse()
+            .map_err(|e| self.error(format!("invalid {}: {}, {}", what, id, e)))
+    }
+
+    /// A peer's record, as `<peer id> <car id>` or `<peer id>/<car id>`, by alias too.
+    fn car(&mut self) -> Result<CarinfoKey, UsageError> {
+        match self.tokens.first() {
+            Some(token) if token.text.contains('/') => {
+                let key = self.next("car")?;
+                let (peer, id) = key.split_once('/').expect("contains /");
+                Ok(CarinfoKey {
+                    origin: self.resolve(peer)?,
+                    id: id
+                        .parse()
+                        .map_err(|e| self.error(format!("invalid car id: {}, {}", id, e)))?,
+                })
+            }
+            _ => Ok(CarinfoKey {
+                origin: self.peer()?,
+                id: self.number("car id")?,
+            }),
+        }
+    }
+
+    fn number(&mut self, what: &str) -> Result<usize, UsageError> {
+        let n = self.next(what)?;
+        n.parse()
+            .map_err(|e| self.error(format!("invalid {}: {}, {}", what, n, e)))
+    }
+
+    fn peer(&mut self) -> Result<PeerId, UsageError> {
+        let peer = self.next("peer id")?;
+        self.resolve(peer)
+    }
+
+    /// A peer id or alias.
+    fn resolve(&self, peer: &str) -> Result<PeerId, UsageError> {
+        aliases::resolve(peer)
+            .ok_or_else(|| self.error(format!("invalid peer id or unknown alias: {}", peer)))
+    }
+
+    /// Everything left on the line, exactly as typed.
+    fn rest(&mut self) -> &'a str {
+        let rest = match self.tokens.first() {
+            Some(token) => self.line[token.start..].trim(),
+            None => "",
+        };
+        self.tokens = &[];
+        rest
+    }
+
+    fn end(&self) -> Result<(), UsageError> {
+        match self.tokens.first() {
+            Some(token) => Err(self.error(format!("unexpected argument {}", token.text))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses one line of input, without a trailing `-v`/`-q`.
+pub fn parse(line: &str) -> Result<Command, UsageError> {
+    let tokens = tokenize(line)?;
+    let words: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+    // the longest command name the line starts with
+    let spec = COMMANDS
+        .iter()
+        .filter(|spec| {
+            let name: Vec<&str> = spec.name.split(' ').collect();
+            words.starts_with(&name)
+        })
+        .max_by_key(|spec| spec.name.len());
+    let spec = match spec {
+        Some(spec) => spec,
+        None => {
+            let closest = closest_command(&words);
+            return Err(UsageError {
+                problem: match (words.first(), closest) {
+                    (Some(word), Some(spec)) => format!(
+                        "unknown command {}, did you mean {}? type help for a list",
+                        word, spec.name
+                    ),
+                    (Some(word), None) => {
+                        format!("unknown command {}, type help for a list", word)
+                    }
+                    (None, _) => "type help for a list of commands".to_owned(),
+                },
+                usage: closest.map(|spec| spec.usage),
+            });
+        }
+    };
+    let mut args = Args {
+        line,
+        tokens: &tokens[spec.name.split(' ').count()..],
+        usage: spec.usage,
+    };
+    let command = match spec.name {
+        "help" => Command::Help(Some(args.rest().to_owned()).filter(|r| !r.is_empty())),
+        "ls p" | "peers" => Command::ListPeers,
+        "ls p remote" => Command::ListRoutedPeers,
+        "status" => Command::Status,
+        "dial" => {
+            let node = args.next("address")?;
+            let (peer, addr) = dht::parse_node(node).ok_or_else(|| {
+                args.error(format!(
+                    "invalid address {}, expected <multiaddr>/p2p/<peer id>",
+                    node
+                ))
+            })?;
+            Command::Dial { peer, addr }
+        }
+        "net status" => Command::NetStatus,
+        "ping" => Command::Ping(args.peer()?),
+        "alias" => match args.optional() {
+            None => Command::ListAliases,
+            Some(name) => Command::Alias {
+                name: name.to_owned(),
+                peer: args.peer()?,
+            },
+        },
+        "unalias" => Command::Unalias(args.next("name")?.to_owned()),
+        "block" => Command::Access(AccessCommand::Block(args.peer()?)),
+        "unblock" => Command::Access(AccessCommand::Unblock(args.peer()?)),
+        "allow" => Command::Access(AccessCommand::Allow(args.peer()?)),
+        "disallow" => Command::Access(AccessCommand::Disallow(args.peer()?)),
+        "allowlist" => Command::Access(AccessCommand::AllowlistOnly(
+            match args.next("on or off")? {
+                "on" => true,
+                "off" => false,
+                other => return Err(args.error(format!("expected on or off, not {}", other))),
+            },
+        )),
+        "access" => Command::Access(AccessCommand::Show),
+        "shared" => Command::ListShared,
+        "sync" => Command::Sync(args.peer()?),
+        "synced" => Command::ListSynced(match args.optional() {
+            None => None,
+            Some(peer) => Some(args.resolve(peer)?),
+        }),
+        "ls car" => match args.optional() {
+            None => Command::ListLocal(ListOptions::default()),
+            Some(option) if option.starts_with("--") => {
+                let words =
+                    std::iter::once(option).chain(args.tokens.iter().map(|t| t.text.as_str()));
+                let options = ListOptions::parse(words).map_err(|e| args.error(e))?;
+                args.rest();
+                Command::ListLocal(options)
+            }
+            Some("all") => match args.tokens.first() {
+                None => Command::ListCarinfos(ListTarget::All),
+                Some(_) => Command::Find {
+                    target: ListTarget::All,
+                    filter: list_filter(&mut args, None)?,
+                },
+            },
+            Some("cached") => Command::ListCached,
+            Some("table") => Command::ListTable(match args.optional() {
+                None => None,
+                Some("by") => match args.next("peer or make")? {
+                    "peer" => Some(Grouping::Peer),
+                    "make" => Some(Grouping::Make),
+                    other => {
+                        return Err(args.error(format!("expected peer or make, not {}", other)))
+                    }
+                },
+                Some(other) => return Err(args.error(format!("unexpected argument {}", other))),
+            }),
+            // a VIN from another market may lack the check digit, so it isn't validated here
+            Some("vin") => Command::Find {
+                target: ListTarget::All,
+                filter: Filter::parse([format!("vin={}", args.next("VIN")?).as_str()])
+                    .map_err(|e| args.error(e))?,
+            },
+            // cars can be in a channel whose topic their owner isn't subscribed to
+            Some("channel") => {
+                let name = channels::normalize(args.next("channel")?).map_err(|e| args.error(e))?;
+                Command::Find {
+                    target: ListTarget::All,
+                    filter: Filter {
+                        conditions: vec![Condition::Channel(Op::Eq, name)],
+                    },
+                }
+            }
+            Some(first) if Condition::is_condition(&filter_word(first)) => Command::Find {
+                target: ListTarget::Local,
+                filter: list_filter(&mut args, Some(first))?,
+            },
+            Some(peer) => Command::ListCarinfos(ListTarget::Peer(args.resolve(peer)?)),
+        },
+        "queue" => Command::Queue(match args.optional() {
+            None => QueueCommand::List,
+            Some("cancel") => match args.next("request number or all")? {
+                "all" => QueueCommand::CancelAll,
+                n => QueueCommand::Cancel(
+                    n.parse()
+                        .map_err(|e| args.error(format!("invalid request number: {}, {}", n, e)))?,
+                ),
+            },
+            Some(other) => return Err(args.error(format!("unknown subcommand {}", other))),
+        }),
+        "ls market" => Command::ListMarket {
+            radius_km: match args.optional() {
+                None => None,
+                Some("near") => {
+                    let radius = args.rest().replace(' ', "");
+                    match radius.trim_end_matches("km").parse::<f64>() {
+                        Ok(km) if km >= 0.0 => Some(km),
+                        _ => return Err(args.error(format!("invalid distance: {}", radius))),
+                    }
+                }
+                Some(other) => return Err(args.error(format!("unexpected argument {}", other))),
+            },
+        },
+        "create car" => {
+            let ([make, model, horsepower, vin], details, force) = car_fields(&mut args)?;
+            match (make, model, horsepower) {
+                (Some(make), Some(model), Some(horsepower)) => Command::Create {
+                    make,
+                    model,
+                    horsepower: parse_horsepower(&args, &horsepower)?,
+                    vin: parse_vin(&args, vin)?,
+                    details,
+                    force,
+                },
+                _ => return Err(args.error("make, model and horsepower can't be empty")),
+            }
+        }
+        "edit car" => {
+            let id = args.id("id")?;
+            let ([make, model, horsepower, vin], details, force) = car_fields(&mut args)?;
+            if force {
+                return Err(args.error("--force only applies to create car"));
+            }
+            Command::Edit {
+                id,
+                make,
+                model,
+                horsepower: match horsepower {
+                    Some(horsepower) => Some(parse_horsepower(&args, &horsepower)?),
+                    None => None,
+                },
+                vin: parse_vin(&args, vin)?,
+                details,
+            }
+        }
+        "dedupe" => Command::Dedupe {
+            merge: match args.optional() {
+                None => false,
+                Some("merge") => true,
+                Some(other) => return Err(args.error(format!("unknown subcommand {}", other))),
+            },
+        },
+        "publish car" => Command::Publish(args.id("id")?),
+        "unpublish car" => Command::Unpublish(args.id("id")?),
+        "delete car" => Command::Delete(args.id("id")?),
+        "audits car" => Command::Audits(args.id("id")?),
+        "history" => Command::History(args.id("id")?),
+        "undo" => Command::Undo,
+        "redo" => Command::Redo,
+        "enrich car" => Command::Enrich(args.id("id")?),
+        "price car" => Command::Price(args.id("id")?),
+        "geo car" => {
+            let id = args.id("id")?;
+            let at = match args.rest() {
+                "" => return Err(args.error("missing location")),
+                "clear" => None,
+                at => Some(
+                    geo::parse_lat_lon(at)
+                        .ok_or_else(|| args.error(format!("invalid location: {}", at)))?,
+                ),
+            };
+            Command::Geo { id, at }
+        }
+        "channel car" => {
+            let id = args.id("id")?;
+            let mut names = BTreeSet::new();
+            while let Some(name) = args.optional() {
+                names.insert(channels::normalize(name).map_err(|e| args.error(e))?);
+            }
+            Command::ChannelCar {
+                id,
+                channels: names,
+            }
+        }
+        "tag car" => Command::Tag {
+            id: args.id("id")?,
+            tag: tags::normalize(args.next("tag")?).map_err(|e| args.error(e))?,
+        },
+        "untag car" => Command::Untag {
+            id: args.id("id")?,
+            tag: tags::normalize(args.next("tag")?).map_err(|e| args.error(e))?,
+        },
+        "channels" => Command::Channels(match args.optional() {
+            None => ChannelCommand::List,
+            Some("join") => ChannelCommand::Join(
+                channels::normalize(args.next("channel")?).map_err(|e| args.error(e))?,
+            ),
+            Some("leave") => ChannelCommand::Leave(
+                channels::normalize(args.next("channel")?).map_err(|e| args.error(e))?,
+            ),
+            Some(other) => return Err(args.error(format!("unknown subcommand {}", other))),
+        }),
+        "share car" => {
+            let id = args.id("id")?;
+            if args.next("with")? != "with" {
+                return Err(args.error("expected with <peer id>"));
+            }
+            Command::Share {
+                id,
+                peer: args.peer()?,
+            }
+        }
+        "grant car" => Command::Grant {
+            id: args.id("id")?,
+            peer: args.peer()?,
+        },
+        "revoke car" => Command::Revoke {
+            id: args.id("id")?,
+            peer: args.peer()?,
+        },
+        "obd car" => Command::Obd {
+            id: args.id("id")?,
+            source: args.next("device or log file")?.to_owned(),
+            share: match args.optional() {
+                None => false,
+                Some("share") => true,
+                Some(other) => return Err(args.error(format!("unexpected argument {}", other))),
+            },
+        },
+        "can car" => {
+            let id = args.id("id")?;
+            let log = args.next("candump log")?.to_owned();
+            let dbc = args.next("dbc file")?.to_owned();
+            let (mut hp_signal, mut odometer_signal) = (None, None);
+            while let Some(option) = args.optional() {
+                match option.split_once('=') {
+                    Some(("hp", signal)) => hp_signal = Some(signal.to_owned()),
+                    Some(("odometer", signal)) => odometer_signal = Some(signal.to_owned()),
+                    _ => return Err(args.error(format!("unknown can option: {}", option))),
+                }
+            }
+            Command::Can {
+                id,
+                log,
+                dbc,
+                hp_signal,
+                odometer_signal,
+            }
+        }
+        "export parquet" => Command::ExportParquet(args.next("path")?.to_owned()),
+        "export csv" => Command::ExportCsv(args.next("path")?.to_owned()),
+        "backup" => Command::Backup(args.optional().map(str::to_owned)),
+        "restore" => Command::Restore(args.next("path")?.to_owned()),
+        "import csv" => {
+            let path = args.next("path")?.to_owned();
+            let mut options = CsvImport::default();
+            while let Some(option) = args.optional() {
+                if option == "dry-run" {
+                    options.dry_run = true;
+                } else if let Some(policy) = option.strip_prefix("duplicates=") {
+                    options.duplicates = policy.parse().map_err(|e: String| args.error(e))?;
+                } else if let Some((field, column)) = option.split_once('=') {
+                    let field = field.parse().map_err(|e: String| args.error(e))?;
+                    options.mapping.push((field, column.to_owned()));
+                } else {
+                    return Err(args.error(format!("unknown import option: {}", option)));
+                }
+            }
+            Command::ImportCsv { path, options }
+        }
+        "export json" => Command::ExportJson(args.next("path")?.to_owned()),
+        "import json" => {
+            let path = args.next("path")?.to_owned();
+            let mut options = JsonImport::default();
+            while let Some(option) = args.optional() {
+                match option {
+                    "--merge" => options.merge = true,
+                    "dry-run" => options.dry_run = true,
+                    other => return Err(args.error(format!("unknown import option: {}", other))),
+                }
+            }
+            Command::ImportJson { path, options }
+        }
+        "watch" => Command::Watch(true),
+        "unwatch" => Command::Watch(false),
+        "telemetry" => Command::Telemetry(match args.next("subcommand")? {
+            "start" => {
+                let id = args.id("id")?;
+                let source = args.next("device or log file")?.to_owned();
+                let (mut interval, mut region) = (telemetry::DEFAULT_INTERVAL, None);
+                while let Some(option) = args.optional() {
+                    if let Some(secs) = option.strip_prefix("every=") {
+                        interval = Duration::from_secs(secs.parse().map_err(|e| {
+                            args.error(format!("invalid interval: {}, {}", secs, e))
+                        })?);
+                    } else if let Some(name) = option.strip_prefix("region=") {
+                        region = Some(name.to_owned());
+                    } else {
+                        return Err(args.error(format!("unknown telemetry option: {}", option)));
+                    }
+                }
+                TelemetryCommand::Start {
+                    id,
+                    source,
+                    interval,
+                    region,
+                }
+            }
+            "stop" => TelemetryCommand::Stop,
+            "watch" => TelemetryCommand::Watch(match args.optional() {
+                Some(secs) => Duration::from_secs(
+                    secs.parse()
+                        .map_err(|e| args.error(format!("invalid interval: {}, {}", secs, e)))?,
+                ),
+                None => telemetry::MIN_INTERVAL,
+            }),
+            "unwatch" => TelemetryCommand::Unwatch,
+            other => return Err(args.error(format!("unknown subcommand {}", other))),
+        }),
+        "club" => Command::Club(match args.next("subcommand")? {
+            "join" => ClubCommand::Join(args.peer()?),
+            "leave" => ClubCommand::Leave,
+            "submit" => ClubCommand::Submit(args.id("id")?),
+            "withdraw" => ClubCommand::Withdraw(args.id("id")?),
+            "ls" => ClubCommand::List,
+            "add" => ClubCommand::Add(args.peer()?),
+            "remove" => ClubCommand::Remove(args.peer()?),
+            "members" => ClubCommand::Members,
+            other => return Err(args.error(format!("unknown subcommand {}", other))),
+        }),
+        "photo car" => Command::Photo {
+            id: args.id("id")?,
+            path: args.next("image file")?.to_owned(),
+        },
+        "thumbs" => {
+            let car = args.car()?;
+            Command::Thumbnails {
+                peer: car.origin,
+                id: car.id,
+            }
+        }
+        "fetch photo" => match args.tokens {
+            [id] if !id.text.contains('/') => Command::FetchPhoto {
+                peer: None,
+                id: args.number("car id")?,
+            },
+            _ => {
+                let car = args.car()?;
+                Command::FetchPhoto {
+                    peer: Some(car.origin),
+                    id: car.id,
+                }
+            }
+        },
+        "search" => match args.tokens.first().map(|t| t.text.as_str()) {
+            Some("net") => {
+                args.next("net")?;
+                match args.rest() {
+                    "" => return Err(args.error("missing words to search for")),
+                    text => Command::SearchNet(text.to_owned()),
+                }
+            }
+            Some(_) => Command::Search(args.rest().to_owned()),
+            None => return Err(args.error("missing query")),
+        },
+        "filter" => {
+            let target = match args.tokens.first().map(|t| t.text.as_str()) {
+                Some("all") => {
+                    args.next("all")?;
+                    ListTarget::All
+                }
+                Some(first) if !Condition::is_condition(first) => ListTarget::Peer(args.peer()?),
+                _ => ListTarget::Local,
+            };
+            let conditions: Vec<&str> = args.tokens.iter().map(|t| t.text.as_str()).collect();
+            if conditions.is_empty() {
+                return Err(args.error("missing conditions"));
+            }
+            let filter = Filter::parse(conditions).map_err(|e| args.error(e))?;
+            args.rest();
+            Command::Find { target, filter }
+        }
+        "find" => {
+            let make = args.next("make")?.to_owned();
+            let model = Some(args.rest().to_owned()).filter(|model| !model.is_empty());
+            Command::FindProviders { make, model }
+        }
+        "chats" => Command::Chats(match args.optional() {
+            Some(n) => Some(
+                n.parse()
+                    .map_err(|e| args.error(format!("invalid thread: {}, {}", n, e)))?,
+            ),
+            None => None,
+        }),
+        "chat" => {
+            let first = args
+                .tokens
+                .first()
+                .map(|t| t.text.as_str())
+                .unwrap_or_default();
+            let to = match first.parse::<usize>() {
+                Ok(thread) => {
+                    args.next("thread #")?;
+                    ChatTarget::Thread(thread)
+                }
+                Err(_) => {
+                    let car = args.car()?;
+                    ChatTarget::Car {
+                        peer: car.origin,
+                        car_id: car.id,
+                    }
+                }
+            };
+            match args.rest() {
+                "" => return Err(args.error("missing text")),
+                text => Command::Chat {
+                    to,
+                    text: text.to_owned(),
+                },
+            }
+        }
+        "offer car" => {
+            let car = args.car()?;
+            let price = args.next("price")?;
+            let price = price.parse().map_err(|e| args.error(e))?;
+            let valid_for = match args.optional() {
+                Some(validity) => offers::parse_validity(validity).map_err(|e| args.error(e))?,
+                None => offers::DEFAULT_VALIDITY,
+            };
+            Command::MakeOffer {
+                peer: car.origin,
+                car_id: car.id,
+                price,
+                valid_for,
+            }
+        }
+        "offers" => Command::Offers,
+        "accept offer" => Command::AnswerOffer {
+            n: args.number("offer number")?,
+            accept: true,
+        },
+        "reject offer" => Command::AnswerOffer {
+            n: args.number("offer number")?,
+            accept: false,
+        },
+        "conflicts" => Command::Conflicts,
+        "resolve conflict" => Command::ResolveConflict {
+            n: args.number("conflict number")?,
+            resolution: args
+                .next("ours, theirs or newest")?
+                .parse()
+                .map_err(|e| args.error(e))?,
+        },
+        "set verbosity" => Command::SetVerbosity(match args.next("level")? {
+            "quiet" => Verbosity::Quiet,
+            "normal" => Verbosity::Normal,
+            "verbose" => Verbosity::Verbose,
+            other => return Err(args.error(format!("unknown verbosity {}", other))),
+        }),
+        _ => unreachable!("every spec is parsed"),
+    };
+    args.end()?;
+    Ok(command)
+}
+
+/// Make, model, horsepower and optionally a VIN, either as arguments or as
+/// `make|model|horsepower|vin`, then any `<detail>=<value>` options and `--force`. Empty fields
+/// are `None`.
+fn car_fields(args: &mut Args) -> Result<([Option<String>; 4], Details, bool), UsageError> {
+    let field = |f: &str| Some(f.trim().to_owned()).filter(|f| !f.is_empty());
+    let mut rest = args.rest();
+    // details come last, so they are taken off the end
+    let mut options = Vec::new();
+    let mut force = false;
+    for token in tokenize(rest)?.iter().rev() {
+        match token.text.split_once('=') {
+            Some((name, value)) if Details::NAMES.contains(&name) => {
+                options.push((name.to_owned(), value.to_owned()));
+            }
+            _ if token.text == "--force" => force = true,
+            _ => break,
+        }
+        rest = rest[..token.start].trim_end();
+    }
+    let mut details = Details::default();
+    for (name, value) in options.iter().rev() {
+        details.set(name, value).map_err(|e| args.error(e))?;
+    }
+    let mut fields: Vec<Option<String>> = if rest.contains('|') && !rest.contains('"') {
+        rest.split('|').map(field).collect()
+    } else {
+        tokenize(rest)?.iter().map(|t| field(&t.text)).collect()
+    };
+    // the VIN is optional, and `edit car <id> year=2019` changes only the details
+    if fields.len() == 3 || (fields.is_empty() && !options.is_empty()) {
+        fields.resize(4, None);
+    }
+    let fields = fields
+        .try_into()
+        .map_err(|_| args.error("expected make, model, horsepower and optionally a VIN"))?;
+    Ok((fields, details, force))
+}
+
+fn parse_horsepower(args: &Args, horsepower: &str) -> Result<u32, UsageError> {
+    horsepower
+        .parse()
+        .map_err(|_| args.error(format!("invalid horsepower: {}", horsepower)))
+}
+
+/// Checks a VIN as typed, so a mistyped one shows the usage like other invalid arguments.
+fn parse_vin(args: &Args, vin: Option<String>) -> Result<Option<String>, UsageError> {
+    vin.map(|vin| vin::validate(&vin).map_err(|e| args.error(e)))
+        .transpose()
+}
+
+/// A filter word of `ls car`, where `tag:classic` is short for `tag=classic`.
+fn filter_word(word: &str) -> String {
+    match word.strip_prefix("tag:") {
+        Some(tag) => format!("tag={}", tag),
+        None => word.to_owned(),
+    }
+}
+
+/// The filter `ls car` was given: `first`, if already taken, and the words left.
+fn list_filter(args: &mut Args, first: Option<&str>) -> Result<Filter, UsageError> {
+    let mut words: Vec<String> = first.map(filter_word).into_iter().collect();
+    while let Some(word) = args.optional() {
+        words.push(filter_word(word));
+    }
+    Filter::parse(words.iter().map(String::as_str)).map_err(|e| args.error(e))
+}
+
+/// Typos of at most this many letters get a suggestion.
+const MAX_TYPO_DISTANCE: usize = 2;
+
+/// The command whose name is closest to how `words` start, if only a typo away.
+fn closest_command(words: &[&str]) -> Option<&'static Spec> {
+    COMMANDS
+        .iter()
+        .filter_map(|spec| {
+            let len = spec.name.split(' ').count();
+            let typed = words.get(..len)?.join(" ");
+            Some((edit_distance(&typed, spec.name), spec))
+        })
+        .filter(|(distance, spec)| *distance <= MAX_TYPO_DISTANCE && *distance < spec.name.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, spec)| spec)
+}
+
+/// Levenshtein distance: letters inserted, removed or replaced to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// `help` output: usage and description of every command, or of those matching `topic`.
+pub fn help(topic: Option<&str>) -> Vec<String> {
+    COMMANDS
+        .iter()
+        .filter(|spec| topic.is_none_or(|t| spec.name.starts_with(t)))
+        .map(|spec| format!("{} - {}", spec.usage, spec.about))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands_with_their_arguments() {
+        assert_eq!(parse("publish car 3"), Ok(Command::Publish(3)));
+        assert_eq!(
+            parse("set verbosity quiet"),
+            Ok(Command::SetVerbosity(Verbosity::Quiet))
+        );
+        assert_eq!(
+            parse("search net turbo diesel"),
+            Ok(Command::SearchNet("turbo diesel".to_owned()))
+        );
+    }
+
+    #[test]
+    fn quoted_fields_and_details_go_into_create() {
+        assert_eq!(
+            parse("create car \"Alfa Romeo\" Giulia 510 year=2017 mileage=45000km"),
+            Ok(Command::Create {
+                make: "Alfa Romeo".to_owned(),
+                model: "Giulia".to_owned(),
+                horsepower: 510,
+                vin: None,
+                details: Details {
+                    year: Some(2017),
+                    mileage_km: Some(45000),
+                    ..Details::default()
+                },
+                force: false,
+            })
+        );
+    }
+
+    #[test]
+    fn create_checks_and_normalises_the_vin() {
+        match parse("create car BMW M5 600 1m8gdm9axkp042788 --force") {
+            Ok(Command::Create { vin, force, .. }) => {
+                assert_eq!(vin.as_deref(), Some("1M8GDM9AXKP042788"));
+                assert!(force);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let error = parse("create car BMW M5 600 1M8GDM9A1KP042788").unwrap_err();
+        assert!(error.problem.contains("check digit"), "{}", error.problem);
+    }
+
+    #[test]
+    fn filter_takes_a_target_and_conditions() {
+        assert_eq!(
+            parse("filter all make=Toyota hp>300"),
+            Ok(Command::Find {
+                target: ListTarget::All,
+                filter: Filter {
+                    conditions: vec![
+                        Condition::Make(Op::Eq, "Toyota".to_owned()),
+                        Condition::Horsepower(Op::Gt, 300),
+                    ],
+                },
+            })
+        );
+        assert!(matches!(
+            parse("filter tag=classic"),
+            Ok(Command::Find {
+                target: ListTarget::Local,
+                ..
+            })
+        ));
+        let peer = PeerId::random();
+        assert_eq!(
+            parse(&format!("filter {} hp>=200", peer)),
+            Ok(Command::Find {
+                target: ListTarget::Peer(peer),
+                filter: Filter {
+                    conditions: vec![Condition::Horsepower(Op::Ge, 200)],
+                },
+            })
+        );
+        assert!(parse("filter all").is_err());
+        let error = parse("filter make>Toyota").unwrap_err();
+        assert!(error.problem.contains("= or !="), "{}", error.problem);
+    }
+
+    #[test]
+    fn search_without_net_is_a_local_text_search() {
+        assert_eq!(
+            parse("search golf gti"),
+            Ok(Command::Search("golf gti".to_owned()))
+        );
+        assert!(parse("search").is_err());
+        assert!(parse("search net").is_err());
+    }
+
+    #[test]
+    fn typos_suggest_the_closest_command() {
+        let error = parse("publsh car 3").unwrap_err();
+        assert_eq!(
+            error.problem,
+            "unknown command publsh, did you mean publish car? type help for a list"
+        );
+        assert_eq!(error.usage, Some("publish car <id>"));
+        assert_eq!(
+            parse("xyzzy").unwrap_err().problem,
+            "unknown command xyzzy, type help for a list"
+        );
+    }
+
+    #[test]
+    fn bad_arguments_come_with_the_usage() {
+        let error = parse("publish car x").unwrap_err();
+        assert_eq!(error.usage, Some("publish car <id>"));
+        assert_eq!(
+            parse("create car \"Alfa").unwrap_err().problem,
+            "unclosed quote"
+        );
+    }
+}
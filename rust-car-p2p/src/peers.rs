@@ -0,0 +1,183 @@
+use libp2p::{core::ConnectedPoint, Multiaddr, PeerId};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+use std::{fs, io};
+
+use crate::{chat, Result};
+
+const PEERS_FILE_PATH: &str = "./peers.json";
+
+/// Peers connected to within this long are dialed again at startup.
+pub const RECONNECT_WITHIN: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// What we learned about a peer, this session or, for peers we had a connection to, earlier.
+#[derive(Debug, Default, Clone)]
+pub struct PeerInfo {
+    /// Addresses the peer was found or dialed at.
+    pub addrs: BTreeSet<Multiaddr>,
+    /// Unix time we last found the peer, connected to it or lost the connection.
+    pub last_seen: u64,
+    /// Round trip time of the last ping, and its unix time.
+    pub rtt: Option<(Duration, u64)>,
+    /// Whether we ever had a connection to the peer, in this session or an earlier one.
+    pub connected_before: bool,
+    /// What the peer told us about itself with identify, once connected.
+    pub identity: Option<Identity>,
+}
+
+impl PeerInfo {
+    /// The addresses the peer was found or dialed at, then those it says it listens on.
+    pub fn reachable_at(&self) -> Vec<Multiaddr> {
+        let mut addrs: Vec<Multiaddr> = self.addrs.iter().cloned().collect();
+        if let Some(identity) = &self.identity {
+            addrs.extend(
+                identity
+                    .listen_addrs
+                    .iter()
+                    .filter(|addr| !self.addrs.contains(addr))
+                    .cloned(),
+            );
+        }
+        addrs
+    }
+}
+
+/// A peer's answer to identify.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    /// e.g. `carinfo-p2p/0.1.0`.
+    pub agent: String,
+    /// e.g. `carinfo/1`, see [`crate::protocol::protocol_version`].
+    pub protocol_version: String,
+    pub listen_addrs: BTreeSet<Multiaddr>,
+}
+
+/// A peer we had a connection to, as kept in `./peers.json`.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredPeer {
+    addrs: Vec<String>,
+    last_seen: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    identity: Option<Identity>,
+}
+
+/// Every peer found or connected to, kept after mDNS expires it or the connection closes, so
+/// `ls p` can still show it. Peers we had a connection to are remembered across restarts.
+#[derive(Debug, Default)]
+pub struct PeerBook {
+    peers: HashMap<PeerId, PeerInfo>,
+}
+
+impl PeerBook {
+    /// The peers remembered from earlier sessions.
+    pub fn load() -> Result<PeerBook> {
+        let stored: BTreeMap<String, StoredPeer> = match fs::read(PEERS_FILE_PATH) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(PeerBook::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let peers = stored
+            .into_iter()
+            .filter_map(|(peer, stored)| {
+                let info = PeerInfo {
+                    addrs: stored.addrs.iter().filter_map(|a| a.parse().ok()).collect(),
+                    last_seen: stored.last_seen,
+                    rtt: None,
+                    connected_before: true,
+                    identity: stored.identity,
+                };
+                Some((peer.parse().ok()?, info))
+            })
+            .collect();
+        Ok(PeerBook { peers })
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.write() {
+            error!("error storing peers, {}", e);
+        }
+    }
+
+    fn write(&self) -> Result<()> {
+        let stored: BTreeMap<String, StoredPeer> = self
+            .peers
+            .iter()
+            .filter(|(_, info)| info.connected_before)
+            .map(|(peer, info)| {
+                let stored = StoredPeer {
+                    addrs: info.addrs.iter().map(Multiaddr::to_string).collect(),
+                    last_seen: info.last_seen,
+                    identity: info.identity.clone(),
+                };
+                (peer.to_string(), stored)
+            })
+            .collect();
+        fs::write(PEERS_FILE_PATH, serde_json::to_string_pretty(&stored)?)?;
+        Ok(())
+    }
+
+    pub fn discovered(&mut self, peer: PeerId, addr: Multiaddr) {
+        let info = self.peers.entry(peer).or_default();
+        info.addrs.insert(addr);
+        info.last_seen = chat::now();
+    }
+
+    /// Remembers the address of a connection we dialed; addresses peers dial us from are
+    /// ephemeral ports, no use for reaching them.
+    pub fn connected(&mut self, peer: PeerId, endpoint: &ConnectedPoint) {
+        let info = self.peers.entry(peer).or_default();
+        if let ConnectedPoint::Dialer { address } = endpoint {
+            info.addrs.insert(address.clone());
+        }
+        info.last_seen = chat::now();
+        info.connected_before = true;
+        self.save();
+    }
+
+    pub fn disconnected(&mut self, peer: PeerId) {
+        let info = self.peers.entry(peer).or_default();
+        info.last_seen = chat::now();
+        if info.connected_before {
+            self.save();
+        }
+    }
+
+    /// Keeps what the peer told about itself, remembered with the peer if we had a connection.
+    pub fn identified(&mut self, peer: PeerId, identity: Identity) {
+        let info = self.peers.entry(peer).or_default();
+        info.identity = Some(identity);
+        if info.connected_before {
+            self.save();
+        }
+    }
+
+    pub fn pinged(&mut self, peer: PeerId, rtt: Duration) {
+        let now = chat::now();
+        let info = self.peers.entry(peer).or_default();
+        info.rtt = Some((rtt, now));
+        info.last_seen = now;
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&PeerInfo> {
+        self.peers.get(peer)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers.keys()
+    }
+
+    /// Peers we had a connection to within `within`, with the addresses to reach them at.
+    /// Peers that only ever dialed us and didn't tell their listen addresses have none and are
+    /// left out.
+    pub fn recent(&self, within: Duration) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let since = chat::now().saturating_sub(within.as_secs());
+        self.peers
+            .iter()
+            .filter(|(_, info)| info.connected_before && info.last_seen >= since)
+            .map(|(peer, info)| (*peer, info.reachable_at()))
+            .filter(|(_, addrs)| !addrs.is_empty())
+            .collect()
+    }
+}
@@ -0,0 +1,297 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::Result;
+
+const PHOTOS_DIR: &str = "./photos";
+const THUMBNAILS_DIR: &str = "./photos/thumbnails";
+/// Longest edge of a thumbnail in pixels.
+const THUMBNAIL_SIZE: u32 = 160;
+/// Bytes of a full-size photo sent per request, well below the message size limit.
+const CHUNK_SIZE: u64 = 256 * 1024;
+/// Largest photo we attach or fetch, whatever size its owner claims.
+const MAX_PHOTO_SIZE: u64 = 32 * 1024 * 1024;
+
+/// An image attached to a record. Only the hashes travel with the record; the files stay with
+/// the owner until someone asks for them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Photo {
+    /// Hex SHA-256 of the original file.
+    pub hash: String,
+    pub file_name: String,
+    pub size: u64,
+    /// Hex SHA-256 of the JPEG thumbnail.
+    pub thumbnail: String,
+}
+
+/// Asks the owner of a record for photo thumbnails, over the photo protocol.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThumbnailRequest {
+    pub hashes: Vec<String>,
+}
+
+/// Base64 encoded thumbnails keyed by their hash, sent back to the requester only.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThumbnailResponse {
+    pub thumbnails: Vec<(String, String)>,
+}
+
+/// A request over the photo protocol, see [`crate::protocol::PhotoBehaviour`].
+#[derive(Debug)]
+pub enum PhotoQuery {
+    Chunk(PhotoRequest),
+    Thumbnails(ThumbnailRequest),
+}
+
+/// The owner's answer to a [`PhotoQuery`] of the same kind.
+#[derive(Debug)]
+pub enum PhotoReply {
+    Chunk(PhotoChunk),
+    Thumbnails(ThumbnailResponse),
+}
+
+/// Asks the owner of a photo for the part of the file starting at `offset`, over the photo
+/// protocol, see [`crate::protocol::PhotoBehaviour`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhotoRequest {
+    pub hash: String,
+    pub offset: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum PhotoChunk {
+    /// Up to [`CHUNK_SIZE`] bytes of the file from `offset`, out of `size`.
+    Data {
+        offset: u64,
+        size: u64,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    /// The owner doesn't have the photo, or doesn't list its car to the requester.
+    Unavailable,
+}
+
+/// A photo being fetched from the owner of its car, by the request for its next chunk.
+#[derive(Debug)]
+pub struct Download {
+    pub car_id: usize,
+    pub photo: Photo,
+}
+
+fn hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// `hash` if it is a hex SHA-256 as [`hash`] writes it. Hashes of peers' photos come from the
+/// network, so they are checked before they become part of a path.
+fn checked(hash: &str) -> Result<&str> {
+    if hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        Ok(hash)
+    } else {
+        Err("not a valid photo hash".into())
+    }
+}
+
+pub fn thumbnail_path(hash: &str) -> Result<PathBuf> {
+    Ok(Path::new(THUMBNAILS_DIR).join(format!("{}.jpg", checked(hash)?)))
+}
+
+/// Where the full-size photo with `hash` is stored, ours or fetched from a peer.
+pub fn photo_path(hash: &str) -> Result<PathBuf> {
+    Ok(Path::new(PHOTOS_DIR).join(checked(hash)?))
+}
+
+/// A photo being fetched, until its last chunk is in.
+fn partial_path(hash: &str) -> Result<PathBuf> {
+    Ok(Path::new(PHOTOS_DIR).join(format!("{}.part", checked(hash)?)))
+}
+
+pub fn is_stored(hash: &str) -> bool {
+    photo_path(hash).is_ok_and(|path| path.exists())
+}
+
+/// Copies an image into the photo store and generates its thumbnail.
+pub async fn import(path: &str) -> Result<Photo> {
+    let data = fs::read(path).await?;
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let photo_hash = hash(&data);
+    let size = data.len() as u64;
+    if size > MAX_PHOTO_SIZE {
+        return Err(format!(
+            "{} has {} bytes, peers fetch photos of up to {}",
+            path, size, MAX_PHOTO_SIZE
+        )
+        .into());
+    }
+
+    let thumbnail = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+        let image = image::load_from_memory(&data)?;
+        let mut out = Cursor::new(Vec::new());
+        image
+            .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+            .into_rgb8()
+            .write_to(&mut out, image::ImageFormat::Jpeg)?;
+        Ok(out.into_inner())
+    })
+    .await??;
+    let thumbnail_hash = hash(&thumbnail);
+
+    fs::create_dir_all(THUMBNAILS_DIR).await?;
+    fs::copy(path, photo_path(&photo_hash)?).await?;
+    fs::write(thumbnail_path(&thumbnail_hash)?, &thumbnail).await?;
+
+    Ok(Photo {
+        hash: photo_hash,
+        file_name,
+        size,
+        thumbnail: thumbnail_hash,
+    })
+}
+
+/// Reads the cached thumbnails among `hashes`, base64 encoded for sending.
+pub async fn read_thumbnails(hashes: &[String]) -> Vec<(String, String)> {
+    let mut thumbnails = Vec::new();
+    for hash in hashes {
+        if let Ok(path) = thumbnail_path(hash) {
+            if let Ok(data) = fs::read(path).await {
+                thumbnails.push((hash.clone(), STANDARD.encode(data)));
+            }
+        }
+    }
+    thumbnails
+}
+
+/// Verifies and caches a received thumbnail, returning where it was stored.
+pub async fn store_thumbnail(expected_hash: &str, encoded: &str) -> Result<PathBuf> {
+    let path = thumbnail_path(expected_hash)?;
+    let data = STANDARD.decode(encoded)?;
+    if hash(&data) != expected_hash {
+        return Err(format!("thumbnail {} does not match its hash", expected_hash).into());
+    }
+    fs::create_dir_all(THUMBNAILS_DIR).await?;
+    fs::write(&path, &data).await?;
+    Ok(path)
+}
+
+/// Reads the chunk of a stored photo starting at `offset`.
+pub async fn read_chunk(hash: &str, offset: u64) -> Result<PhotoChunk> {
+    let mut file = fs::File::open(photo_path(hash)?).await?;
+    let size = file.metadata().await?.len();
+    if offset > size {
+        return Err(format!(
+            "photo {} has only {} bytes, asked from {}",
+            hash, size, offset
+        )
+        .into());
+    }
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut data = Vec::new();
+    file.take(CHUNK_SIZE).read_to_end(&mut data).await?;
+    Ok(PhotoChunk::Data { offset, size, data })
+}
+
+/// Bytes of the photo fetched so far, where the next request continues after an interrupted
+/// download.
+pub async fn fetched_bytes(hash: &str) -> u64 {
+    match partial_path(hash) {
+        Ok(partial) => fs::metadata(partial).await.map_or(0, |meta| meta.len()),
+        Err(_) => 0,
+    }
+}
+
+/// Appends a received chunk to the partial download of a photo. Once the last chunk is in, the
+/// file is checked against its hash and moved into the photo store, and its path returned.
+pub async fn store_chunk(
+    expected_hash: &str,
+    offset: u64,
+    size: u64,
+    data: &[u8],
+) -> Result<Option<PathBuf>> {
+    let partial = partial_path(expected_hash)?;
+    if size > MAX_PHOTO_SIZE {
+        return Err(format!(
+            "photo {} has {} bytes, more than the {} fetched from peers",
+            expected_hash, size, MAX_PHOTO_SIZE
+        )
+        .into());
+    }
+    let fetched = fetched_bytes(expected_hash).await;
+    let end = offset + data.len() as u64;
+    if offset != fetched || end > size || (data.is_empty() && end < size) {
+        return Err(format!(
+            "chunk of photo {} at {} doesn't continue the {} of {} bytes fetched",
+            expected_hash, offset, fetched, size
+        )
+        .into());
+    }
+    fs::create_dir_all(PHOTOS_DIR).await?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&partial)
+        .await?;
+    file.write_all(data).await?;
+    file.flush().await?;
+    if end < size {
+        return Ok(None);
+    }
+    if hash(&fs::read(&partial).await?) != expected_hash {
+        fs::remove_file(&partial).await?;
+        return Err(format!("photo {} does not match its hash", expected_hash).into());
+    }
+    let path = photo_path(expected_hash)?;
+    fs::rename(&partial, &path).await?;
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_hashes_as_written() {
+        let hash = hash(b"photo");
+        assert_eq!(
+            photo_path(&hash).unwrap(),
+            Path::new(PHOTOS_DIR).join(&hash)
+        );
+        assert!(thumbnail_path(&hash).is_ok());
+    }
+
+    #[test]
+    fn rejects_hashes_that_leave_the_photo_store() {
+        let upper = hash(b"photo").to_uppercase();
+        for bad in [
+            "../../carinfo.json",
+            "/etc/passwd",
+            "",
+            upper.as_str(),
+            &"a".repeat(63),
+            &format!("{}/", "a".repeat(63)),
+        ] {
+            assert!(photo_path(bad).is_err(), "{:?}", bad);
+            assert!(partial_path(bad).is_err(), "{:?}", bad);
+            assert!(thumbnail_path(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_chunks_of_oversized_photos() {
+        let hash = hash(b"photo");
+        assert!(store_chunk(&hash, 0, MAX_PHOTO_SIZE + 1, b"")
+            .await
+            .is_err());
+        assert!(store_chunk("../x", 0, 1, b"x").await.is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::format::{self, NUMBER_FORMAT};
+use crate::{lookup, Carinfo, Result};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const SPARKLINE: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One valuation of a car, kept on the record to show price trends.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PricePoint {
+    /// Unix timestamp of the quote.
+    pub at: u64,
+    pub provider: String,
+    pub amount: f64,
+    pub currency: String,
+}
+
+/// A source of market valuations for a car.
+#[async_trait]
+pub trait PriceProvider: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Returns the current valuation as `(amount, currency)`.
+    async fn quote(&self, client: &reqwest::Client, carinfo: &Carinfo) -> Result<(f64, String)>;
+}
+
+/// A user-configured JSON endpoint returning `{"price": <number>, "currency": "<code>"}`.
+/// `{make}`, `{model}` and `{horsepower}` in the URL template are substituted, percent-encoded.
+pub struct HttpPriceProvider {
+    url_template: String,
+}
+
+#[async_trait]
+impl PriceProvider for HttpPriceProvider {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    async fn quote(&self, client: &reqwest::Client, carinfo: &Carinfo) -> Result<(f64, String)> {
+        let url = self
+            .url_template
+            .replace("{make}", &lookup::url_value(&carinfo.make))
+            .replace("{model}", &lookup::url_value(&carinfo.model))
+            .replace("{horsepower}", &carinfo.horsepower.to_string());
+        let body: Value = client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let amount = body["price"]
+            .as_f64()
+            .ok_or("price response has no numeric price field")?;
+        let currency = body["currency"].as_str().unwrap_or("").to_owned();
+        Ok((amount, currency))
+    }
+}
+
+/// The price providers enabled via `CARINFO_PRICE_PROVIDERS` (comma separated; currently
+/// `http`, configured through `CARINFO_PRICE_URL`).
+pub struct PriceProviders {
+    client: reqwest::Client,
+    providers: Vec<Box<dyn PriceProvider>>,
+}
+
+impl PriceProviders {
+    pub fn from_env() -> PriceProviders {
+        let mut providers: Vec<Box<dyn PriceProvider>> = Vec::new();
+        for name in env::var("CARINFO_PRICE_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+        {
+            match name.trim() {
+                "" => {}
+                "http" => match env::var("CARINFO_PRICE_URL") {
+                    Ok(url_template) => {
+                        providers.push(Box::new(HttpPriceProvider { url_template }))
+                    }
+                    Err(_) => {
+                        error!("http price provider enabled but CARINFO_PRICE_URL is not set")
+                    }
+                },
+                other => error!("unknown price provider: {}", other),
+            }
+        }
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(concat!("carinfo-p2p/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("can create http client");
+        PriceProviders { client, providers }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    /// Asks every provider for a quote; failing providers are logged and skipped.
+    pub async fn quote(&self, carinfo: &Carinfo) -> Vec<PricePoint> {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let mut points = Vec::new();
+        for provider in self.providers.iter() {
+            match provider.quote(&self.client, carinfo).await {
+                Ok((amount, currency)) => points.push(PricePoint {
+                    at,
+                    provider: provider.name().to_owned(),
+                    amount,
+                    currency,
+                }),
+                Err(e) => error!("{} price quote failed, {}", provider.name(), e),
+            }
+        }
+        points
+    }
+}
+
+/// Renders a price history as one line per quote with the change to the previous quote from
+/// the same provider, followed by a sparkline of all quotes.
+pub fn trend(history: &[PricePoint]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (i, point) in history.iter().enumerate() {
+        let previous = history[..i]
+            .iter()
+            .rev()
+            .find(|p| p.provider == point.provider && p.currency == point.currency);
+        let change = match previous {
+            Some(p) if p.amount != 0.0 => format!(
+                " ({}{}%)",
+                if point.amount >= p.amount { "+" } else { "" },
+                NUMBER_FORMAT.number((point.amount - p.amount) / p.amount * 100.0, 1)
+            ),
+            _ => String::new(),
+        };
+        lines.push(format!(
+            "{} {}: {}{}",
+            format::age(point.at),
+            point.provider,
+            NUMBER_FORMAT.price(point.amount, &point.currency),
+            change
+        ));
+    }
+
+    let (min, max) = history.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| {
+        (lo.min(p.amount), hi.max(p.amount))
+    });
+    if history.len() > 1 {
+        let spark: String = history
+            .iter()
+            .map(|p| {
+                let level = if max > min {
+                    ((p.amount - min) / (max - min) * (SPARKLINE.len() - 1) as f64).round()
+                } else {
+                    0.0
+                };
+                SPARKLINE[level as usize]
+            })
+            .collect();
+        lines.push(format!("Trend: {}", spark));
+    }
+    lines
+}
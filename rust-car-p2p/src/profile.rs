@@ -0,0 +1,85 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::Result;
+
+/// Each profile's data lives in its own directory under this one.
+const PROFILES_DIR: &str = "./profiles";
+/// Flags and environment variables naming files, which are given relative to the directory
+/// the node was started in rather than to the profile's.
+const PATH_FLAGS: [&str; 5] = [
+    "--config",
+    "--storage",
+    "--log-file",
+    "--swarm-key",
+    "--control-socket",
+];
+const PATH_VARS: [&str; 5] = [
+    "CARINFO_STORAGE_PATH",
+    "CARINFO_LOG_FILE",
+    "CARINFO_SWARM_KEY",
+    "CARINFO_CONTROL_SOCKET",
+    "CARINFO_IDENTITY",
+];
+
+/// Enters the profile `args` or the environment name, see [`enter`], and returns its
+/// directory. Without one the node keeps to the directory it was started in. The paths in
+/// [`PATH_FLAGS`] and [`PATH_VARS`] are made absolute first, so they still name the same files.
+pub fn select(args: &mut [String]) -> Result<Option<PathBuf>> {
+    let name = match from_args(args)? {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    absolutize(args)?;
+    enter(&name).map(Some)
+}
+
+/// Resolves relative paths given in flags or the environment against the current directory.
+fn absolutize(args: &mut [String]) -> Result<()> {
+    let dir = env::current_dir().map_err(|e| format!("can't read current directory, {}", e))?;
+    for i in 1..args.len() {
+        if PATH_FLAGS.contains(&args[i - 1].as_str()) {
+            args[i] = dir.join(&args[i]).to_string_lossy().into_owned();
+        }
+    }
+    for var in PATH_VARS {
+        if let Some(path) = env::var_os(var).filter(|path| !path.is_empty()) {
+            env::set_var(var, dir.join(path));
+        }
+    }
+    Ok(())
+}
+
+/// The profile `--profile <name>` in `args` or `CARINFO_PROFILE` names, if any.
+fn from_args(args: &[String]) -> Result<Option<String>> {
+    match args.iter().position(|a| a == "--profile") {
+        Some(i) => match args.get(i + 1) {
+            Some(name) => Ok(Some(name.clone())),
+            None => Err("--profile needs a value".into()),
+        },
+        None => Ok(env::var("CARINFO_PROFILE").ok().filter(|v| !v.is_empty())),
+    }
+}
+
+/// Makes `./profiles/<name>`, created if needed, the working directory, so the identity, the
+/// catalog, `config.toml`, the peer store and every other state file are the profile's own.
+/// Must happen before any of them is read.
+fn enter(name: &str) -> Result<PathBuf> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "invalid profile {}, use letters, digits, - and _ only",
+            name
+        )
+        .into());
+    }
+    let dir = Path::new(PROFILES_DIR).join(name);
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("can't create profile {}, {}", dir.display(), e))?;
+    env::set_current_dir(&dir)
+        .map_err(|e| format!("can't enter profile {}, {}", dir.display(), e))?;
+    Ok(dir)
+}
@@ -0,0 +1,962 @@
+use async_trait::async_trait;
+use libp2p::{
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, OrTransport},
+        upgrade::{self, read_length_prefixed, write_length_prefixed},
+        ProtocolName,
+    },
+    dns::TokioDnsConfig,
+    futures::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    gossipsub::{Gossipsub, GossipsubConfigBuilder, MessageAuthenticity, ValidationMode},
+    identify::{Identify, IdentifyConfig},
+    identity::PublicKey,
+    mplex,
+    noise::{Keypair, NoiseConfig, X25519Spec},
+    pnet::{PnetConfig, PreSharedKey},
+    relay::{self, Relay, RelayConfig},
+    request_response::{
+        ProtocolSupport, RequestId, RequestResponse, RequestResponseCodec, RequestResponseConfig,
+        RequestResponseEvent,
+    },
+    tcp::TokioTcpConfig,
+    websocket::WsConfig,
+    PeerId, Transport,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::iter;
+use std::time::{Duration, Instant};
+
+use crate::{
+    chat::{ChatMessage, ChatResponse},
+    club::{ClubRequest, ClubResponse},
+    filter::Filter,
+    metrics::METRICS,
+    offers::{OfferRequest, OfferResponse},
+    photos::{PhotoQuery, PhotoReply},
+    wire::{self, Compression, Decoded, Message},
+    Carinfo, Carinfos, KEYS,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ListMode {
+    ALL,
+    One(String),
+}
+
+/// Records per page we ask for, far below the message size limit even with photos attached.
+pub const PAGE_SIZE: usize = 100;
+/// Largest page we send, whatever the requester asked for.
+const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListRequest {
+    pub mode: ListMode,
+    /// Set to only get the public records matching a filter. Peers that don't know about
+    /// searches ignore it and send everything, so responses are filtered again on arrival.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search: Option<SearchRequest>,
+    /// Page to send, counting from 0.
+    #[serde(default)]
+    pub page: usize,
+    /// Records per page. Requesters that don't page leave it out and get everything at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<usize>,
+    /// Random number the response echoes, so an old response can't pass for the answer to
+    /// this request. Older peers leave it out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+}
+
+impl ListRequest {
+    /// Asks for the first page of a listing, see [`ListPages`] for the others.
+    pub fn new(mode: ListMode, search: Option<SearchRequest>) -> ListRequest {
+        ListRequest {
+            mode,
+            search,
+            page: 0,
+            page_size: Some(PAGE_SIZE),
+            nonce: Some(rand::random()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchRequest {
+    pub filter: Filter,
+    /// Words to rank the matching records by, best first, leaving out those without any, see
+    /// [`search::rank`](crate::search::rank). Peers that don't know about it send every
+    /// record the filter matches, so responses are ranked again on arrival.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListResponse {
+    pub mode: ListMode,
+    pub data: Carinfos,
+    pub receiver: String,
+    /// The responding node's coarse geohash, for records without their own location.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Which part of the listing `data` is, out of `total` records. Responders that don't page
+    /// leave them out, which reads as a single page.
+    #[serde(default)]
+    pub page: usize,
+    #[serde(default)]
+    pub page_size: usize,
+    #[serde(default)]
+    pub total: usize,
+    /// The request's nonce, see [`ListPages::answers`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    /// Protobuf encoding of the responder's public key.
+    #[serde(default)]
+    pub public_key: Vec<u8>,
+    /// The responder's signature over every other field, see [`ListResponse::verify`].
+    #[serde(default)]
+    pub signature: Vec<u8>,
+}
+
+/// The fields of a response its signature covers, in their encoded form.
+#[derive(Serialize)]
+struct Signed<'a> {
+    mode: &'a ListMode,
+    data: &'a Carinfos,
+    receiver: &'a str,
+    location: &'a Option<String>,
+    page: usize,
+    page_size: usize,
+    total: usize,
+    // left out when unset, so responses without one are signed as before
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<u64>,
+}
+
+impl ListResponse {
+    /// Answers `request` with its page of `carinfos`, or all of them if it doesn't page.
+    pub fn page(
+        request: &ListRequest,
+        receiver: &PeerId,
+        carinfos: Carinfos,
+        location: Option<String>,
+    ) -> ListResponse {
+        let total = carinfos.len();
+        let page_size = request
+            .page_size
+            .map_or(total, |size| size.clamp(1, MAX_PAGE_SIZE));
+        let data = carinfos
+            .into_iter()
+            .skip(request.page.saturating_mul(page_size))
+            .take(page_size)
+            .collect();
+        let mut response = ListResponse {
+            mode: ListMode::ALL,
+            data,
+            receiver: receiver.to_string(),
+            location,
+            page: request.page,
+            page_size,
+            total,
+            nonce: request.nonce,
+            public_key: KEYS.public().into_protobuf_encoding(),
+            signature: Vec::new(),
+        };
+        response.signature = KEYS
+            .sign(&response.signed_bytes())
+            .expect("can sign with the node key");
+        response
+    }
+
+    /// Fills in what records leave out when they are the responder's: its location and, for
+    /// records from older peers, itself as their origin.
+    pub fn complete(&mut self, responder: &PeerId) {
+        for carinfo in self.data.iter_mut() {
+            if carinfo.location.is_none() {
+                carinfo.location = self.location.clone();
+            }
+            carinfo.origin.get_or_insert_with(|| responder.to_string());
+        }
+    }
+
+    /// Checks that `responder` signed the response and made it for `receiver`, so nobody else
+    /// can forge it or pass on one that was meant for another peer.
+    pub fn verify(&self, responder: &PeerId, receiver: &PeerId) -> Result<(), String> {
+        if self.signature.is_empty() {
+            return Err("the response is not signed".to_owned());
+        }
+        let public_key = PublicKey::from_protobuf_encoding(&self.public_key)
+            .map_err(|e| format!("invalid public key, {}", e))?;
+        let signer = public_key.clone().into_peer_id();
+        if signer != *responder {
+            return Err(format!("the response is signed by {}", signer));
+        }
+        if !public_key.verify(&self.signed_bytes(), &self.signature) {
+            return Err("the signature doesn't match the response".to_owned());
+        }
+        if self.receiver != receiver.to_string() {
+            return Err(format!("the response was made for {}", self.receiver));
+        }
+        Ok(())
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        ciborium::into_writer(
+            &Signed {
+                mode: &self.mode,
+                data: &self.data,
+                receiver: &self.receiver,
+                location: &self.location,
+                page: self.page,
+                page_size: self.page_size,
+                total: self.total,
+                nonce: self.nonce,
+            },
+            &mut data,
+        )
+        .expect("can encode response");
+        data
+    }
+
+    /// The page to ask for next, if this one isn't the last.
+    pub fn next_page(&self) -> Option<usize> {
+        let more = self.page_size > 0 && (self.page + 1) * self.page_size < self.total;
+        // an empty page means the listing shrank since the first one
+        (more && !self.data.is_empty()).then_some(self.page + 1)
+    }
+}
+
+/// Sends list requests and puts paged responses back together, asking for the next page as
+/// each one arrives.
+#[derive(Default)]
+pub struct ListPages {
+    pending: HashMap<RequestId, PartialListing>,
+}
+
+/// A listing still being received.
+struct PartialListing {
+    /// The request for the first page, which callers know the listing by.
+    first: RequestId,
+    request: ListRequest,
+    data: Carinfos,
+    /// When the request for the current page was sent.
+    sent: Instant,
+}
+
+impl ListPages {
+    pub fn send(
+        &mut self,
+        list: &mut ListBehaviour,
+        peer: &PeerId,
+        request: ListRequest,
+    ) -> RequestId {
+        let request_id = list.send_request(peer, request.clone());
+        self.pending.insert(
+            request_id,
+            PartialListing {
+                first: request_id,
+                request,
+                data: Carinfos::new(),
+                sent: Instant::now(),
+            },
+        );
+        request_id
+    }
+
+    /// Checks that `response` echoes the nonce of the request `request_id` it came in for.
+    /// Responses from peers that don't know about nonces have none and pass.
+    pub fn answers(&self, request_id: RequestId, response: &ListResponse) -> Result<(), String> {
+        let asked = self
+            .pending
+            .get(&request_id)
+            .and_then(|partial| partial.request.nonce);
+        match (asked, response.nonce) {
+            (Some(asked), Some(echoed)) if asked != echoed => {
+                Err("the response answers another request".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes in the response to `request_id`. Once the last page is in, returns the id of the
+    /// first request with the whole listing; until then, asks `peer` for the next page.
+    pub fn receive(
+        &mut self,
+        list: &mut ListBehaviour,
+        peer: &PeerId,
+        request_id: RequestId,
+        mut response: ListResponse,
+    ) -> Option<(RequestId, ListResponse)> {
+        let mut partial = match self.pending.remove(&request_id) {
+            Some(partial) => partial,
+            None => return Some((request_id, response)),
+        };
+        METRICS.list_latency(partial.sent.elapsed());
+        // records published between two pages can push others into a page we already have
+        let seen: HashSet<usize> = partial.data.iter().map(|r| r.id).collect();
+        partial
+            .data
+            .extend(response.data.drain(..).filter(|r| !seen.contains(&r.id)));
+        match response.next_page() {
+            Some(page) => {
+                partial.request.page = page;
+                partial.request.nonce = Some(rand::random());
+                let next = list.send_request(peer, partial.request.clone());
+                partial.sent = Instant::now();
+                self.pending.insert(next, partial);
+                None
+            }
+            None => {
+                response.data = partial.data;
+                Some((partial.first, response))
+            }
+        }
+    }
+
+    /// Forgets the listing a failed request belonged to and returns the id it is known by.
+    pub fn fail(&mut self, request_id: RequestId) -> RequestId {
+        self.pending
+            .remove(&request_id)
+            .map_or(request_id, |partial| partial.first)
+    }
+}
+
+/// Tells peers that a published record was deleted or unpublished, so they drop it from their
+/// caches.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CarinfoDeleted {
+    pub deleted: usize,
+}
+
+/// Tells peers that a record was just published, so nodes running `watch` can show it without
+/// listing our catalog.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CarPublished {
+    pub carinfo: Box<Carinfo>,
+}
+
+type TcpAndWs =
+    OrTransport<TokioDnsConfig<TokioTcpConfig>, WsConfig<TokioDnsConfig<TokioTcpConfig>>>;
+
+/// TCP and WebSocket transport authenticated with noise and multiplexed with mplex, used by
+/// embedded nodes.
+pub fn transport(swarm_key: Option<PreSharedKey>) -> Boxed<(PeerId, StreamMuxerBox)> {
+    secure(tcp_and_ws(), swarm_key)
+}
+
+/// [`transport`] that can also dial and listen through circuit relays, with the behaviour that
+/// drives it. Used by the full node.
+pub fn relay_transport(
+    swarm_key: Option<PreSharedKey>,
+) -> (Boxed<(PeerId, StreamMuxerBox)>, Relay) {
+    let (transport, relay) =
+        relay::new_transport_and_behaviour(RelayConfig::default(), tcp_and_ws());
+    (secure(transport, swarm_key), relay)
+}
+
+fn tcp_and_ws() -> TcpAndWs {
+    // DNS resolution in front of TCP, so bootstrap nodes can be given as /dns4/<host>/...
+    let tcp = TokioDnsConfig::system(TokioTcpConfig::new())
+        .expect("can read the system DNS configuration");
+    // /ws addresses for browser peers, on top of the same TCP transport
+    let ws = WsConfig::new(tcp.clone());
+    tcp.or_transport(ws)
+}
+
+/// With a swarm key, every connection starts with the private network handshake and is
+/// encrypted with the key underneath Noise, so nodes without it can't connect at all.
+fn secure<T>(transport: T, swarm_key: Option<PreSharedKey>) -> Boxed<(PeerId, StreamMuxerBox)>
+where
+    T: Transport + Clone + Send + Sync + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Listener: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    T::Dial: Send + 'static,
+{
+    match swarm_key {
+        Some(key) => authenticate(
+            transport.and_then(move |socket, _| PnetConfig::new(key).handshake(socket)),
+        ),
+        None => authenticate(transport),
+    }
+}
+
+fn authenticate<T>(transport: T) -> Boxed<(PeerId, StreamMuxerBox)>
+where
+    T: Transport + Clone + Send + Sync + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Listener: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    T::Dial: Send + 'static,
+{
+    let auth_keys = Keypair::<X25519Spec>::new()
+        .into_authentic(&KEYS)
+        .expect("can create auth keys");
+    transport
+        .upgrade(upgrade::Version::V1)
+        .authenticate(NoiseConfig::xx(auth_keys).into_authenticated()) // XX Handshake pattern, IX exists as well and IK - only XX currently provides interop with other libp2p impls
+        .multiplex(mplex::MplexConfig::new())
+        .boxed()
+}
+
+/// Largest message gossipsub will send or accept, enough for big listings and thumbnail batches.
+const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+/// Gossipsub that signs our messages with the node identity and drops unsigned or forged ones.
+pub fn gossipsub() -> Gossipsub {
+    let config = GossipsubConfigBuilder::default()
+        .validation_mode(ValidationMode::Strict)
+        .max_transmit_size(MAX_MESSAGE_BYTES)
+        .build()
+        .expect("valid gossipsub config");
+    Gossipsub::new(MessageAuthenticity::Signed(KEYS.clone()), config).expect("can create gossipsub")
+}
+
+/// Direct list queries between two peers, so answers only reach the peer that asked.
+pub type ListBehaviour = RequestResponse<ListCodec>;
+pub type ListEvent = RequestResponseEvent<ListRequest, ListResponse>;
+
+#[derive(Debug, Clone)]
+pub enum ListProtocol {
+    /// The whole listing in one response, streamed in batches of records. Preferred.
+    Streamed,
+    /// A page per response, for peers from before streaming.
+    Paged,
+}
+
+impl ProtocolName for ListProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        match self {
+            ListProtocol::Streamed => b"/carinfo/list/3.0.0",
+            ListProtocol::Paged => b"/carinfo/list/2.0.0",
+        }
+    }
+}
+
+/// Most records a streamed listing may announce, so a peer can't make us collect records
+/// without end.
+const MAX_STREAMED_RECORDS: usize = 200_000;
+/// How long a listing may take to arrive. The default 10 seconds are too short for a large
+/// catalog streamed over a slow link.
+const LIST_REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Length-prefixed [`wire`] messages, the same encoding used on the topics. On
+/// [`ListProtocol::Streamed`] the response is a [`Message::ListResponse`] without records,
+/// then [`Message::ListRecords`] of up to [`PAGE_SIZE`] records each until all `total` are
+/// sent. Writes wait for the substream's flow control, so a large catalog goes out as fast as
+/// the requester reads it, and no frame comes near the message size limit. Requests on that
+/// protocol always ask for the whole listing.
+///
+/// Requests advertise the compression we read, and answers are compressed accordingly, see
+/// [`wire::encode_compressed`]. The codec handling an inbound request also writes its answer,
+/// so it keeps what the request asked for until then.
+#[derive(Debug, Clone, Default)]
+pub struct ListCodec {
+    answer_compression: Option<Compression>,
+}
+
+#[async_trait]
+impl RequestResponseCodec for ListCodec {
+    type Protocol = ListProtocol;
+    type Request = ListRequest;
+    type Response = ListResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        protocol: &ListProtocol,
+        io: &mut T,
+    ) -> io::Result<ListRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let decoded = read_message(io).await?;
+        self.answer_compression = decoded.answer_compression;
+        match decoded.message {
+            Message::ListRequest(req) => Ok(for_protocol(protocol, req)),
+            _ => Err(unexpected("list request")),
+        }
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        protocol: &ListProtocol,
+        io: &mut T,
+    ) -> io::Result<ListResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut res = match read_message(io).await?.message {
+            Message::ListResponse(res) => res,
+            _ => return Err(unexpected("list response")),
+        };
+        if let ListProtocol::Streamed = protocol {
+            if res.total > MAX_STREAMED_RECORDS {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("listing of {} records is too large", res.total),
+                ));
+            }
+            // grows as batches arrive rather than on the peer's word alone
+            res.data = Carinfos::with_capacity(res.total.min(MAX_PAGE_SIZE));
+            while res.data.len() < res.total {
+                match read_frame(io).await?.message {
+                    Message::ListRecords(batch)
+                        if !batch.is_empty() && res.data.len() + batch.len() <= res.total =>
+                    {
+                        res.data.extend(batch)
+                    }
+                    _ => return Err(unexpected("batch of the records announced")),
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        protocol: &ListProtocol,
+        io: &mut T,
+        req: ListRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let message = Message::ListRequest(for_protocol(protocol, req));
+        write_message(io, &message, wire::encode_request(&message)).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        protocol: &ListProtocol,
+        io: &mut T,
+        mut res: ListResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let compression = self.answer_compression;
+        if let ListProtocol::Paged = protocol {
+            let message = Message::ListResponse(res);
+            let data = wire::encode_compressed(&message, compression);
+            return write_message(io, &message, data).await;
+        }
+        let records = std::mem::take(&mut res.data);
+        // `total` is signed, so it can't be fixed up here
+        if records.len() != res.total {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a streamed listing must hold every record",
+            ));
+        }
+        let header = Message::ListResponse(res);
+        write_length_prefixed(io, wire::encode(&header)).await?;
+        METRICS.published(&header);
+        for batch in records.chunks(PAGE_SIZE) {
+            let message = Message::ListRecords(batch.to_vec());
+            write_length_prefixed(io, wire::encode_compressed(&message, compression)).await?;
+        }
+        io.close().await
+    }
+}
+
+/// `req` as it is sent on `protocol`: a streamed listing is never paged.
+fn for_protocol(protocol: &ListProtocol, mut req: ListRequest) -> ListRequest {
+    if let ListProtocol::Streamed = protocol {
+        req.page = 0;
+        req.page_size = None;
+    }
+    req
+}
+
+/// Full-size photos fetched from their owner a chunk per request, so a download can stop and
+/// continue where it left off, and thumbnails answered to the requester alone.
+pub type PhotoBehaviour = RequestResponse<PhotoCodec>;
+pub type PhotoEvent = RequestResponseEvent<PhotoQuery, PhotoReply>;
+
+#[derive(Debug, Clone)]
+pub struct PhotoProtocol;
+
+impl ProtocolName for PhotoProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/carinfo/photo/1.0.0"
+    }
+}
+
+/// Length-prefixed [`wire`] messages, like [`ListCodec`] but never compressed: photos are JPEG
+/// or PNG already.
+#[derive(Debug, Clone, Default)]
+pub struct PhotoCodec;
+
+#[async_trait]
+impl RequestResponseCodec for PhotoCodec {
+    type Protocol = PhotoProtocol;
+    type Request = PhotoQuery;
+    type Response = PhotoReply;
+
+    async fn read_request<T>(&mut self, _: &PhotoProtocol, io: &mut T) -> io::Result<PhotoQuery>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read_message(io).await?.message {
+            Message::PhotoRequest(req) => Ok(PhotoQuery::Chunk(req)),
+            Message::ThumbnailRequest(req) => Ok(PhotoQuery::Thumbnails(req)),
+            _ => Err(unexpected("photo or thumbnail request")),
+        }
+    }
+
+    async fn read_response<T>(&mut self, _: &PhotoProtocol, io: &mut T) -> io::Result<PhotoReply>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read_message(io).await?.message {
+            Message::PhotoChunk(chunk) => Ok(PhotoReply::Chunk(chunk)),
+            Message::ThumbnailResponse(resp) => Ok(PhotoReply::Thumbnails(resp)),
+            _ => Err(unexpected("photo chunk or thumbnails")),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &PhotoProtocol,
+        io: &mut T,
+        req: PhotoQuery,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let message = match req {
+            PhotoQuery::Chunk(req) => Message::PhotoRequest(req),
+            PhotoQuery::Thumbnails(req) => Message::ThumbnailRequest(req),
+        };
+        write_message(io, &message, wire::encode(&message)).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &PhotoProtocol,
+        io: &mut T,
+        reply: PhotoReply,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let message = match reply {
+            PhotoReply::Chunk(chunk) => Message::PhotoChunk(chunk),
+            PhotoReply::Thumbnails(resp) => Message::ThumbnailResponse(resp),
+        };
+        write_message(io, &message, wire::encode(&message)).await
+    }
+}
+
+/// Offers to buy a car sent to its owner, and the owner's answers sent back.
+pub type OfferBehaviour = RequestResponse<OfferCodec>;
+pub type OfferEvent = RequestResponseEvent<OfferRequest, OfferResponse>;
+
+#[derive(Debug, Clone)]
+pub struct OfferProtocol;
+
+impl ProtocolName for OfferProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/carinfo/offer/1.0.0"
+    }
+}
+
+/// Length-prefixed [`wire`] messages like [`PhotoCodec`]; offers are too small to compress.
+#[derive(Debug, Clone, Default)]
+pub struct OfferCodec;
+
+#[async_trait]
+impl RequestResponseCodec for OfferCodec {
+    type Protocol = OfferProtocol;
+    type Request = OfferRequest;
+    type Response = OfferResponse;
+
+    async fn read_request<T>(&mut self, _: &OfferProtocol, io: &mut T) -> io::Result<OfferRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read_message(io).await?.message {
+            Message::OfferRequest(req) => Ok(req),
+            _ => Err(unexpected("offer request")),
+        }
+    }
+
+    async fn read_response<T>(&mut self, _: &OfferProtocol, io: &mut T) -> io::Result<OfferResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read_message(io).await?.message {
+            Message::OfferResponse(res) => Ok(res),
+            _ => Err(unexpected("offer response")),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &OfferProtocol,
+        io: &mut T,
+        req: OfferRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let message = Message::OfferRequest(req);
+        write_message(io, &message, wire::encode(&message)).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &OfferProtocol,
+        io: &mut T,
+        res: OfferResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let message = Message::OfferResponse(res);
+        write_message(io, &message, wire::encode(&message)).await
+    }
+}
+
+/// Chat lines sent to the other side of a thread, and its confirmations.
+pub type ChatBehaviour = RequestResponse<ChatCodec>;
+pub type ChatEvent = RequestResponseEvent<ChatMessage, ChatResponse>;
+
+#[derive(Debug, Clone)]
+pub struct ChatProtocol;
+
+impl ProtocolName for ChatProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/carinfo/chat/1.0.0"
+    }
+}
+
+/// Length-prefixed [`wire`] messages like [`OfferCodec`].
+#[derive(Debug, Clone, Default)]
+pub struct ChatCodec;
+
+#[async_trait]
+impl RequestResponseCodec for ChatCodec {
+    type Protocol = ChatProtocol;
+    type Request = ChatMessage;
+    type Response = ChatResponse;
+
+    async fn read_request<T>(&mut self, _: &ChatProtocol, io: &mut T) -> io::Result<ChatMessage>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read_message(io).await?.message {
+            Message::Chat(msg) => Ok(msg),
+            _ => Err(unexpected("chat message")),
+        }
+    }
+
+    async fn read_response<T>(&mut self, _: &ChatProtocol, io: &mut T) -> io::Result<ChatResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read_message(io).await?.message {
+            Message::ChatResponse(res) => Ok(res),
+            _ => Err(unexpected("chat response")),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &ChatProtocol,
+        io: &mut T,
+        msg: ChatMessage,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let message = Message::Chat(msg);
+        write_message(io, &message, wire::encode(&message)).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &ChatProtocol,
+        io: &mut T,
+        res: ChatResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let message = Message::ChatResponse(res);
+        write_message(io, &message, wire::encode(&message)).await
+    }
+}
+
+/// Submissions of club members to their coordinator, and the coordinator's catalog sent to
+/// its members.
+pub type ClubBehaviour = RequestResponse<ClubCodec>;
+pub type ClubEvent = RequestResponseEvent<ClubRequest, ClubResponse>;
+
+#[derive(Debug, Clone)]
+pub struct ClubProtocol;
+
+impl ProtocolName for ClubProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/carinfo/club/1.0.0"
+    }
+}
+
+/// Length-prefixed [`wire`] messages like [`OfferCodec`], compressed like listings since a
+/// catalog can be large.
+#[derive(Debug, Clone, Default)]
+pub struct ClubCodec;
+
+#[async_trait]
+impl RequestResponseCodec for ClubCodec {
+    type Protocol = ClubProtocol;
+    type Request = ClubRequest;
+    type Response = ClubResponse;
+
+    async fn read_request<T>(&mut self, _: &ClubProtocol, io: &mut T) -> io::Result<ClubRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read_message(io).await?.message {
+            Message::ClubRequest(req) => Ok(req),
+            _ => Err(unexpected("club request")),
+        }
+    }
+
+    async fn read_response<T>(&mut self, _: &ClubProtocol, io: &mut T) -> io::Result<ClubResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read_message(io).await?.message {
+            Message::ClubResponse(res) => Ok(res),
+            _ => Err(unexpected("club response")),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &ClubProtocol,
+        io: &mut T,
+        req: ClubRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let message = Message::ClubRequest(req);
+        let data = wire::encode_compressed(&message, Some(Compression::Gzip));
+        write_message(io, &message, data).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &ClubProtocol,
+        io: &mut T,
+        res: ClubResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let message = Message::ClubResponse(res);
+        let data = wire::encode_compressed(&message, Some(Compression::Gzip));
+        write_message(io, &message, data).await
+    }
+}
+
+async fn read_message<T>(io: &mut T) -> io::Result<Decoded>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let decoded = read_frame(io).await?;
+    METRICS.received(&decoded.message);
+    Ok(decoded)
+}
+
+/// One frame, not counted in the metrics: the records of a streamed listing count as part of
+/// its response.
+async fn read_frame<T>(io: &mut T) -> io::Result<Decoded>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let data = read_length_prefixed(io, MAX_MESSAGE_BYTES).await?;
+    wire::decode_envelope(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes `data`, the encoding of `msg`, as the last frame.
+async fn write_message<T>(io: &mut T, msg: &Message, data: Vec<u8>) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    write_length_prefixed(io, data).await?;
+    METRICS.published(msg);
+    io.close().await
+}
+
+fn unexpected(expected: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("expected a {}", expected),
+    )
+}
+
+pub fn list_behaviour() -> ListBehaviour {
+    let mut config = RequestResponseConfig::default();
+    config.set_request_timeout(LIST_REQUEST_TIMEOUT);
+    RequestResponse::new(
+        ListCodec::default(),
+        [
+            (ListProtocol::Streamed, ProtocolSupport::Full),
+            (ListProtocol::Paged, ProtocolSupport::Full),
+        ],
+        config,
+    )
+}
+
+pub fn photo_behaviour() -> PhotoBehaviour {
+    RequestResponse::new(
+        PhotoCodec,
+        iter::once((PhotoProtocol, ProtocolSupport::Full)),
+        RequestResponseConfig::default(),
+    )
+}
+
+pub fn offer_behaviour() -> OfferBehaviour {
+    RequestResponse::new(
+        OfferCodec,
+        iter::once((OfferProtocol, ProtocolSupport::Full)),
+        RequestResponseConfig::default(),
+    )
+}
+
+pub fn chat_behaviour() -> ChatBehaviour {
+    RequestResponse::new(
+        ChatCodec,
+        iter::once((ChatProtocol, ProtocolSupport::Full)),
+        RequestResponseConfig::default(),
+    )
+}
+
+pub fn club_behaviour() -> ClubBehaviour {
+    RequestResponse::new(
+        ClubCodec,
+        iter::once((ClubProtocol, ProtocolSupport::Full)),
+        RequestResponseConfig::default(),
+    )
+}
+
+/// What we tell peers we speak, `carinfo/<wire protocol version>`.
+pub fn protocol_version() -> String {
+    format!("carinfo/{}", wire::PROTOCOL_VERSION)
+}
+
+/// Tells connected peers our agent, protocol version and listen addresses, and asks for theirs.
+pub fn identify_behaviour() -> Identify {
+    Identify::new(
+        IdentifyConfig::new(protocol_version(), KEYS.public())
+            .with_agent_version(format!("carinfo-p2p/{}", env!("CARGO_PKG_VERSION"))),
+    )
+}
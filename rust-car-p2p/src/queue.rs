@@ -0,0 +1,94 @@
+use libp2p::PeerId;
+use std::fmt;
+
+use crate::{chat, filter::Filter};
+
+/// What the answer to a list request is for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Purpose {
+    /// `ls car <peer id>`, shown as a listing.
+    List,
+    /// `filter <peer id> <conditions>`, shown as search results.
+    Search(Filter),
+    /// `search net <text>`, shown as ranked search results.
+    TextSearch(String),
+    /// `sync <peer id>`, stored as our copy of the catalog.
+    Sync,
+}
+
+impl fmt::Display for Purpose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Purpose::List => write!(f, "ls car"),
+            Purpose::Search(filter) => write!(f, "filter {}", filter),
+            Purpose::TextSearch(text) => write!(f, "search net {}", text),
+            Purpose::Sync => write!(f, "sync"),
+        }
+    }
+}
+
+/// A request for a peer that wasn't connected when it was made.
+#[derive(Debug, Clone)]
+pub struct QueuedRequest {
+    /// Shown by `queue` and used to cancel it.
+    pub number: usize,
+    pub peer: PeerId,
+    pub purpose: Purpose,
+    /// Unix timestamp of when it was queued.
+    pub queued_at: u64,
+}
+
+/// Requests waiting for their peer to connect, sent as soon as it does. They only live as long
+/// as the node runs.
+#[derive(Debug, Default)]
+pub struct RequestQueue {
+    requests: Vec<QueuedRequest>,
+    last_number: usize,
+}
+
+impl RequestQueue {
+    /// Queues a request, returning its number. The same request for the same peer is only
+    /// queued once.
+    pub fn push(&mut self, peer: PeerId, purpose: Purpose) -> usize {
+        if let Some(queued) = self
+            .requests
+            .iter()
+            .find(|r| r.peer == peer && r.purpose == purpose)
+        {
+            return queued.number;
+        }
+        self.last_number += 1;
+        self.requests.push(QueuedRequest {
+            number: self.last_number,
+            peer,
+            purpose,
+            queued_at: chat::now(),
+        });
+        self.last_number
+    }
+
+    /// Removes and returns the requests waiting for `peer`, oldest first.
+    pub fn take(&mut self, peer: &PeerId) -> Vec<QueuedRequest> {
+        let (taken, waiting) = std::mem::take(&mut self.requests)
+            .into_iter()
+            .partition(|r| r.peer == *peer);
+        self.requests = waiting;
+        taken
+    }
+
+    pub fn cancel(&mut self, number: usize) -> Option<QueuedRequest> {
+        let index = self.requests.iter().position(|r| r.number == number)?;
+        Some(self.requests.remove(index))
+    }
+
+    /// Cancels every request, returning how many there were.
+    pub fn clear(&mut self) -> usize {
+        let count = self.requests.len();
+        self.requests.clear();
+        count
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &QueuedRequest> {
+        self.requests.iter()
+    }
+}
@@ -0,0 +1,98 @@
+use libp2p::PeerId;
+use log::info;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::{aliases, metrics::METRICS};
+
+/// List requests a peer may make per minute by default, with bursts of up to
+/// [`DEFAULT_BURST`].
+pub const DEFAULT_PER_MINUTE: u32 = 30;
+pub const DEFAULT_BURST: u32 = 10;
+
+/// A token bucket per peer for the list requests we answer, since each one reads and encodes
+/// the catalog. A peer can make `burst` requests at once, then `per_minute` a minute.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    burst: u32,
+    buckets: HashMap<PeerId, Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    /// Requests dropped since the peer was last let through.
+    dropped: u64,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(DEFAULT_PER_MINUTE, DEFAULT_BURST)
+    }
+}
+
+impl RateLimiter {
+    /// A `per_minute` of 0 lets every request through.
+    pub fn new(per_minute: u32, burst: u32) -> RateLimiter {
+        RateLimiter {
+            per_minute,
+            burst: burst.max(1),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes a token for a request from `peer`, or returns false if it has none left and the
+    /// request should be dropped. The first drop after a request was let through is logged,
+    /// and how many followed once the peer is let through again.
+    pub fn allow(&mut self, peer: &PeerId) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let burst = f64::from(self.burst);
+        let bucket = self.buckets.entry(*peer).or_insert(Bucket {
+            tokens: burst,
+            refilled: now,
+            dropped: 0,
+        });
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * f64::from(self.per_minute) / 60.0).min(burst);
+        bucket.refilled = now;
+        if bucket.tokens < 1.0 {
+            if bucket.dropped == 0 {
+                info!(
+                    "{} makes more than {} list requests a minute, dropping them",
+                    aliases::display(peer),
+                    self.per_minute
+                );
+            }
+            bucket.dropped += 1;
+            METRICS.request_limited();
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        if bucket.dropped > 0 {
+            info!(
+                "Dropped {} list requests from {}",
+                bucket.dropped,
+                aliases::display(peer)
+            );
+            bucket.dropped = 0;
+        }
+        true
+    }
+
+    /// Forgets the buckets that have filled up again, which a new bucket would be like
+    /// anyway, so peers that come and go don't pile up.
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        let per_second = f64::from(self.per_minute) / 60.0;
+        let burst = f64::from(self.burst);
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens + elapsed * per_second < burst
+        });
+    }
+}
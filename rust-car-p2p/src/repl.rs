@@ -0,0 +1,433 @@
+use libp2p::PeerId;
+use log::{error, Log, Metadata, Record};
+use rust_car_p2p::{aliases, parser::COMMANDS, read_local_carinfos};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::FileHistory,
+    validate::Validator,
+    Behavior, CompletionType, Config, Context, Editor, ExternalPrinter, Helper,
+};
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const HISTORY_FILE_PATH: &str = "./history.txt";
+/// Commands kept in the history file.
+const HISTORY_SIZE: usize = 1000;
+const PROMPT: &str = "> ";
+/// How often the car ids offered for completion are re-read, on top of after every command.
+const IDS_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What the user did at the prompt.
+pub enum Input {
+    Line(String),
+    /// Ctrl+C or Ctrl+D, which stop the node as Ctrl+C always did.
+    Quit,
+}
+
+/// Prints log lines and other output above the prompt, redrawing the line being typed below
+/// them instead of writing over it.
+#[derive(Clone)]
+pub struct Console(Arc<Mutex<Box<dyn ExternalPrinter + Send>>>);
+
+impl Console {
+    pub fn print(&self, mut text: String) {
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        if let Ok(mut printer) = self.0.lock() {
+            let _ = printer.print(text);
+        }
+    }
+
+    /// A writer that prints what was written to it once flushed or dropped.
+    pub fn writer(&self) -> ConsoleWriter {
+        ConsoleWriter {
+            console: self.clone(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+pub struct ConsoleWriter {
+    console: Console,
+    buf: Vec<u8>,
+}
+
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            let text = String::from_utf8_lossy(&self.buf).into_owned();
+            self.buf.clear();
+            self.console.print(text);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ConsoleWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Writes log records above the prompt, formatted like `pretty_env_logger` without colors.
+struct ConsoleLogger {
+    filter: env_logger::filter::Filter,
+    console: Console,
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filter.matches(record) {
+            self.console.print(format!(
+                " {:<5} {} > {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sends log output to `console`, honouring `RUST_LOG` (default `info`).
+pub fn init_logger(console: Console) {
+    let filter = env_logger::filter::Builder::new()
+        .parse(&std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_owned()))
+        .build();
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(ConsoleLogger { filter, console }))
+        .expect("no logger installed yet");
+}
+
+/// What arguments complete to besides the words of the commands, kept up to date by the main
+/// loop since the completer runs on the prompt's thread.
+#[derive(Debug, Default)]
+struct Words {
+    peers: Vec<String>,
+    aliases: Vec<String>,
+    ids: Vec<String>,
+}
+
+/// The line editor: history in `./history.txt`, and tab completion of commands, peer ids,
+/// aliases and car ids.
+pub struct Repl {
+    inputs: mpsc::UnboundedReceiver<Input>,
+    words: Arc<RwLock<Words>>,
+    ids_refresh: tokio::time::Interval,
+    #[cfg(unix)]
+    terminal: Option<libc::termios>,
+}
+
+impl Repl {
+    /// Starts reading commands on a thread of its own. The console is `None` if stdin or
+    /// stdout isn't a terminal, output then goes to stdout and stderr as before. With `json`
+    /// output stdout is kept for results: the prompt is drawn on the terminal directly and
+    /// there is no console.
+    pub fn start(json: bool) -> rustyline::Result<(Repl, Option<Console>)> {
+        // reading the terminal directly only makes sense when commands are typed there
+        let behavior = if json && io::stdin().is_terminal() {
+            Behavior::PreferTerm
+        } else {
+            Behavior::Stdio
+        };
+        let config = Config::builder()
+            .max_history_size(HISTORY_SIZE)?
+            .history_ignore_dups(true)?
+            .history_ignore_space(true)
+            .completion_type(CompletionType::List)
+            .behavior(behavior)
+            .build();
+        let words = Arc::new(RwLock::new(Words::default()));
+        let mut editor: Editor<Completion, FileHistory> = Editor::with_config(config)?;
+        editor.set_helper(Some(Completion {
+            words: words.clone(),
+        }));
+        match editor.load_history(HISTORY_FILE_PATH) {
+            Err(ReadlineError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+        let console = editor
+            .create_external_printer()
+            .ok()
+            .filter(|_| !json)
+            .map(|printer| Console(Arc::new(Mutex::new(Box::new(printer)))));
+        // before the prompt thread switches the terminal to raw mode
+        #[cfg(unix)]
+        let terminal = terminal_settings();
+        let repl = Repl {
+            inputs: read_lines(editor),
+            words,
+            ids_refresh: tokio::time::interval(IDS_REFRESH_INTERVAL),
+            #[cfg(unix)]
+            terminal,
+        };
+        Ok((repl, console))
+    }
+
+    /// Waits for the next line, refreshing the car ids offered for completion meanwhile.
+    async fn next_input(&mut self) -> Input {
+        loop {
+            tokio::select! {
+                input = self.inputs.recv() => match input {
+                    Some(input) => return input,
+                    // stdin was closed, the node keeps running without it
+                    None => std::future::pending().await,
+                },
+                _ = self.ids_refresh.tick() => self.refresh_ids().await,
+            }
+        }
+    }
+
+    pub async fn refresh_ids(&self) {
+        match read_local_carinfos().await {
+            Ok(carinfos) => {
+                let ids = carinfos.iter().map(|r| r.id.to_string()).collect();
+                self.words.write().expect("words lock").ids = ids;
+            }
+            Err(e) => error!("error reading local carinfos for completion, {}", e),
+        }
+    }
+
+    pub fn set_peers<'a>(&self, peers: impl Iterator<Item = &'a PeerId>) {
+        let mut words = self.words.write().expect("words lock");
+        words.peers = peers.map(PeerId::to_string).collect();
+        words.aliases = aliases::all().into_iter().map(|(name, _)| name).collect();
+    }
+}
+
+impl Drop for Repl {
+    /// Puts the terminal back as it was, since the prompt thread may be waiting for input in
+    /// raw mode when the node stops.
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(settings) = &self.terminal {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, settings) };
+        }
+    }
+}
+
+#[cfg(unix)]
+fn terminal_settings() -> Option<libc::termios> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+    let mut settings = std::mem::MaybeUninit::uninit();
+    match unsafe { libc::tcgetattr(libc::STDIN_FILENO, settings.as_mut_ptr()) } {
+        0 => Some(unsafe { settings.assume_init() }),
+        _ => None,
+    }
+}
+
+/// Reads lines with `editor` until stdin is closed, appending each one to the history file.
+fn read_lines(mut editor: Editor<Completion, FileHistory>) -> mpsc::UnboundedReceiver<Input> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let terminal = io::stdin().is_terminal();
+    std::thread::spawn(move || loop {
+        let input = match editor.readline(PROMPT) {
+            Ok(line) => {
+                if !line.trim().is_empty() && editor.add_history_entry(line.as_str()).is_ok() {
+                    if let Err(e) = editor.append_history(HISTORY_FILE_PATH) {
+                        error!("error saving command history, {}", e);
+                    }
+                }
+                Input::Line(line)
+            }
+            Err(ReadlineError::Interrupted) => Input::Quit,
+            Err(ReadlineError::Eof) if terminal => Input::Quit,
+            Err(ReadlineError::Eof) => return,
+            Err(e) => {
+                error!("error reading input, {}", e);
+                return;
+            }
+        };
+        if sender.send(input).is_err() {
+            return;
+        }
+    });
+    receiver
+}
+
+/// Waits for the next input at `repl`'s prompt, or forever if there is no prompt.
+pub async fn next_input(repl: &mut Option<Repl>) -> Input {
+    match repl {
+        Some(repl) => repl.next_input().await,
+        None => std::future::pending().await,
+    }
+}
+
+struct Completion {
+    words: Arc<RwLock<Words>>,
+}
+
+impl Completer for Completion {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let words = self.words.read().expect("words lock");
+        let (start, candidates) = complete(&line[..pos], &words);
+        let pairs = candidates
+            .into_iter()
+            .map(|word| Pair {
+                replacement: format!("{} ", word),
+                display: word,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/// What an argument of a command's usage completes to.
+enum Arg {
+    Word(&'static str),
+    Peer,
+    Id,
+    Command,
+    /// Anything, e.g. a make or a file.
+    Other,
+}
+
+/// Where the word being typed at the end of `line` starts, and what it may complete to: the
+/// next word of a command's name, or what the usage in `help` has at that argument.
+fn complete(line: &str, words: &Words) -> (usize, Vec<String>) {
+    let start = line.len()
+        - line
+            .chars()
+            .rev()
+            .take_while(|c| !c.is_whitespace())
+            .map(char::len_utf8)
+            .sum::<usize>();
+    let typed: Vec<&str> = line[..start].split_whitespace().collect();
+    let prefix = &line[start..];
+    let mut candidates = Vec::new();
+    for spec in COMMANDS {
+        let name: Vec<&str> = spec.name.split(' ').collect();
+        let matched = typed.len().min(name.len());
+        if typed[..matched] != name[..matched] {
+            continue;
+        }
+        if typed.len() < name.len() {
+            candidates.push(name[typed.len()].to_owned());
+            continue;
+        }
+        for arg in args(spec.usage, &name, &typed[name.len()..]) {
+            match arg {
+                Arg::Word(word) => candidates.push(word.to_owned()),
+                Arg::Peer => {
+                    candidates.extend(words.aliases.iter().cloned());
+                    candidates.extend(words.peers.iter().cloned());
+                }
+                Arg::Id => candidates.extend(words.ids.iter().cloned()),
+                Arg::Command => candidates.extend(
+                    COMMANDS
+                        .iter()
+                        .filter_map(|spec| spec.name.split(' ').next())
+                        .map(str::to_owned),
+                ),
+                Arg::Other => {}
+            }
+        }
+    }
+    candidates.retain(|c| c.starts_with(prefix));
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+/// The arguments `usage` allows after the command's `name` and the arguments `typed` so far,
+/// in any of its forms whose words before agree with them.
+fn args(usage: &'static str, name: &[&str], typed: &[&str]) -> Vec<Arg> {
+    let mut args = Vec::new();
+    for form in split_top_level(usage, " | ") {
+        let form = split_top_level(form, " ");
+        if form.len() <= name.len() + typed.len() || form[..name.len()] != *name {
+            continue;
+        }
+        let form = &form[name.len()..];
+        let agrees = typed.iter().zip(form).all(|(typed, word)| {
+            options(word)
+                .iter()
+                .any(|arg| !matches!(arg, Arg::Word(word) if word != typed))
+        });
+        if agrees {
+            args.extend(options(form[typed.len()]));
+        }
+    }
+    args
+}
+
+/// What one word of a usage stands for, e.g. `[all | <peer id> | cached]` or `on|off`.
+fn options(word: &'static str) -> Vec<Arg> {
+    let word = word.trim_start_matches('[').trim_end_matches(']');
+    if word.contains(['[', ']']) {
+        // a compound like make|model|horsepower[|vin], nothing to offer
+        return vec![Arg::Other];
+    }
+    split_top_level(word, "|")
+        .into_iter()
+        .map(|option| {
+            match split_top_level(option, " ")
+                .into_iter()
+                .next()
+                .unwrap_or("")
+            {
+                "<peer id>" | "<coordinator peer id>" => Arg::Peer,
+                "<id>" | "<car id>" => Arg::Id,
+                "command" => Arg::Command,
+                option if option.contains('<') => Arg::Other,
+                option => Arg::Word(option),
+            }
+        })
+        .collect()
+}
+
+/// Splits `s` on `separator` outside of `[...]` and `<...>`, trimming the parts.
+fn split_top_level<'a>(s: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' | '<' => depth += 1,
+            ']' | '>' => depth = depth.saturating_sub(1),
+            _ if depth == 0 && i >= start && s[i..].starts_with(separator) => {
+                parts.push(s[start..i].trim());
+                start = i + separator.len();
+            }
+            _ => {}
+        }
+    }
+    parts.push(s[start..].trim());
+    parts.retain(|p| !p.is_empty());
+    parts
+}
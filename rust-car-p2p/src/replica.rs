@@ -0,0 +1,327 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use tokio::fs;
+
+use crate::{aliases, chat, crdt, digest, format, Carinfo, Carinfos, Result};
+
+const REPLICA_FILE_PATH: &str = "./remote.json";
+const CONFLICTS_FILE_PATH: &str = "./conflicts.json";
+
+/// Fields that differ between any two copies of a changed record, left out of
+/// [`Conflict::fields`].
+const BOOKKEEPING: [&str; 4] = ["content_hash", "origin_signature", "stamps", "updated_at"];
+
+/// What `sync` does when the origin's copy of a record disagrees with the merge of ours and
+/// theirs, see [`Replica::sync`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Keeps the merge: every field its latest change, wherever it came from.
+    #[default]
+    NewestWins,
+    /// Takes the origin's copy as it is.
+    OriginWins,
+    /// Keeps our copy and queues the conflict for `resolve conflict`.
+    Manual,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "newest-wins" => Ok(ConflictPolicy::NewestWins),
+            "origin-wins" => Ok(ConflictPolicy::OriginWins),
+            "manual" => Ok(ConflictPolicy::Manual),
+            other => Err(format!(
+                "unknown conflict policy {}, expected newest-wins, origin-wins or manual",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConflictPolicy::NewestWins => "newest-wins",
+            ConflictPolicy::OriginWins => "origin-wins",
+            ConflictPolicy::Manual => "manual",
+        })
+    }
+}
+
+/// Which copy `resolve conflict` keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    /// The merge of both, as with [`ConflictPolicy::NewestWins`].
+    Newest,
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ours" => Ok(Resolution::Ours),
+            "theirs" => Ok(Resolution::Theirs),
+            "newest" => Ok(Resolution::Newest),
+            other => Err(format!(
+                "unknown resolution {}, expected ours, theirs or newest",
+                other
+            )),
+        }
+    }
+}
+
+/// A synced record whose origin's copy disagrees with ours, queued under
+/// [`ConflictPolicy::Manual`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conflict {
+    /// Peer id of the catalog the record is from.
+    pub origin: String,
+    /// Our copy, which stays in the replica until the conflict is resolved.
+    pub ours: Carinfo,
+    /// The copy the origin listed.
+    pub theirs: Carinfo,
+    /// When the conflict was last seen.
+    pub at: u64,
+}
+
+impl Conflict {
+    /// The fields the copies disagree on, e.g. `horsepower` or `price`.
+    pub fn fields(&self) -> Vec<String> {
+        let object = |carinfo: &Carinfo| match serde_json::to_value(carinfo) {
+            Ok(serde_json::Value::Object(object)) => object,
+            _ => serde_json::Map::new(),
+        };
+        let (ours, theirs) = (object(&self.ours), object(&self.theirs));
+        ours.keys()
+            .chain(theirs.keys())
+            .filter(|key| !BOOKKEEPING.contains(&key.as_str()))
+            .filter(|key| ours.get(*key) != theirs.get(*key))
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// e.g. `car 3 of alice (2h ago) differs in horsepower, price`.
+    pub fn summary(&self) -> String {
+        let origin = self
+            .origin
+            .parse()
+            .map_or(self.origin.clone(), |peer| aliases::display(&peer));
+        format!(
+            "car {} of {} ({}) differs in {}",
+            self.ours.id,
+            origin,
+            format::age(self.at),
+            self.fields().join(", ")
+        )
+    }
+}
+
+/// A record copied from a peer's catalog by `sync`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteCarinfo {
+    /// Peer id of the catalog the record is from.
+    pub origin: String,
+    /// When the record was last seen in that catalog.
+    pub synced_at: u64,
+    pub carinfo: Carinfo,
+    /// Hash of the origin's copy `resolve conflict ours` kept this copy over, so syncing that
+    /// copy again doesn't raise the conflict again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kept_over: Option<String>,
+}
+
+/// What a sync changed in the copy of one peer's catalog.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SyncSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    /// Records whose origin's copy disagreed with ours, see [`ConflictPolicy`].
+    pub conflicts: usize,
+}
+
+/// Offline copies of peers' public catalogs, kept apart from our own records and persisted
+/// locally, with the conflicts waiting to be resolved.
+#[derive(Debug, Default)]
+pub struct Replica {
+    pub carinfos: Vec<RemoteCarinfo>,
+    /// Numbered by position for `resolve conflict`.
+    pub conflicts: Vec<Conflict>,
+    pub policy: ConflictPolicy,
+}
+
+impl Replica {
+    pub async fn load(policy: ConflictPolicy) -> Result<Replica> {
+        Ok(Replica {
+            carinfos: read(REPLICA_FILE_PATH).await?,
+            conflicts: read(CONFLICTS_FILE_PATH).await?,
+            policy,
+        })
+    }
+
+    /// The copied records of `origin`, or of every peer.
+    pub fn of(&self, origin: Option<&PeerId>) -> impl Iterator<Item = &RemoteCarinfo> {
+        let origin = origin.map(PeerId::to_string);
+        self.carinfos
+            .iter()
+            .filter(move |r| origin.as_ref().is_none_or(|o| *o == r.origin))
+    }
+
+    /// Replaces the copy of `origin`'s catalog with `carinfos`, its full current listing.
+    /// Records we already had are merged with the new copy field by field, so a copy that
+    /// reached us late doesn't undo newer changes, unless their hashes show nothing changed.
+    /// Records it no longer lists are removed.
+    ///
+    /// A merge that differs from the origin's copy is a conflict: ours holds a change the
+    /// origin doesn't, e.g. after the origin restored an older catalog. The policy decides
+    /// which copy is kept.
+    pub async fn sync(&mut self, origin: &PeerId, carinfos: Carinfos) -> Result<SyncSummary> {
+        let origin = origin.to_string();
+        let now = chat::now();
+        let mut summary = SyncSummary::default();
+        let (mut previous, others): (Vec<_>, Vec<_>) = std::mem::take(&mut self.carinfos)
+            .into_iter()
+            .partition(|r| r.origin == origin);
+        self.carinfos = others;
+        for mut carinfo in carinfos {
+            // the hash covers what the listing filled in on the way, like the location
+            digest::seal(&mut carinfo);
+            match previous.iter().position(|r| r.carinfo.id == carinfo.id) {
+                Some(index) => {
+                    let old = previous.swap_remove(index);
+                    if digest::same(&old.carinfo, &carinfo)
+                        || old.kept_over.is_some() && old.kept_over == carinfo.content_hash
+                    {
+                        self.carinfos.push(RemoteCarinfo {
+                            synced_at: now,
+                            ..old
+                        });
+                        continue;
+                    }
+                    let old = old.carinfo;
+                    let mut merged = old.clone();
+                    crdt::merge(&mut merged, &carinfo);
+                    digest::seal(&mut merged);
+                    if !digest::same(&merged, &carinfo) {
+                        summary.conflicts += 1;
+                        match self.policy {
+                            ConflictPolicy::NewestWins => {}
+                            ConflictPolicy::OriginWins => merged = carinfo.clone(),
+                            ConflictPolicy::Manual => {
+                                self.queue(Conflict {
+                                    origin: origin.clone(),
+                                    ours: old.clone(),
+                                    theirs: carinfo,
+                                    at: now,
+                                });
+                                merged = old.clone();
+                            }
+                        }
+                    }
+                    if !digest::same(&old, &merged) {
+                        summary.updated += 1;
+                    }
+                    carinfo = merged;
+                }
+                None => summary.added += 1,
+            }
+            self.carinfos.push(RemoteCarinfo {
+                origin: origin.clone(),
+                synced_at: now,
+                carinfo,
+                kept_over: None,
+            });
+        }
+        summary.removed = previous.len();
+        let carinfos = &self.carinfos;
+        self.conflicts.retain(|conflict| {
+            carinfos
+                .iter()
+                .any(|r| r.origin == conflict.origin && r.carinfo.id == conflict.ours.id)
+        });
+        self.save().await?;
+        Ok(summary)
+    }
+
+    /// Queues a conflict, replacing an older one about the same record.
+    fn queue(&mut self, conflict: Conflict) {
+        self.conflicts
+            .retain(|c| c.origin != conflict.origin || c.ours.id != conflict.ours.id);
+        self.conflicts.push(conflict);
+    }
+
+    /// Settles conflict `n`, keeping the copy `resolution` picks. Returns the conflict, or
+    /// `None` if there's no conflict `n`.
+    pub async fn resolve(&mut self, n: usize, resolution: Resolution) -> Result<Option<Conflict>> {
+        if n >= self.conflicts.len() {
+            return Ok(None);
+        }
+        let conflict = self.conflicts.remove(n);
+        let kept = match resolution {
+            Resolution::Ours => conflict.ours.clone(),
+            Resolution::Theirs => conflict.theirs.clone(),
+            Resolution::Newest => {
+                let mut merged = conflict.ours.clone();
+                crdt::merge(&mut merged, &conflict.theirs);
+                digest::seal(&mut merged);
+                merged
+            }
+        };
+        let kept_over = match resolution {
+            Resolution::Ours => conflict.theirs.content_hash.clone(),
+            _ => None,
+        };
+        if let Some(r) = self
+            .carinfos
+            .iter_mut()
+            .find(|r| r.origin == conflict.origin && r.carinfo.id == conflict.ours.id)
+        {
+            r.carinfo = kept;
+            r.kept_over = kept_over;
+        }
+        self.save().await?;
+        Ok(Some(conflict))
+    }
+
+    /// Drops a record its origin withdrew. Returns false if we had no copy of it.
+    pub async fn remove(&mut self, origin: &PeerId, id: usize) -> Result<bool> {
+        let origin = origin.to_string();
+        let before = self.carinfos.len();
+        self.carinfos
+            .retain(|r| r.origin != origin || r.carinfo.id != id);
+        if self.carinfos.len() == before {
+            return Ok(false);
+        }
+        self.conflicts
+            .retain(|c| c.origin != origin || c.ours.id != id);
+        self.save().await?;
+        Ok(true)
+    }
+
+    async fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(&self.carinfos)?;
+        fs::write(REPLICA_FILE_PATH, &json).await?;
+        let json = serde_json::to_string(&self.conflicts)?;
+        fs::write(CONFLICTS_FILE_PATH, &json).await?;
+        Ok(())
+    }
+}
+
+/// The list stored at `path`, empty if there's no file yet.
+async fn read<T: serde::de::DeserializeOwned>(path: &str) -> Result<Vec<T>> {
+    match fs::read(path).await {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,153 @@
+use libp2p::{request_response::RequestId, PeerId};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long `ls car all` waits for answers by default, as long as a request takes to time out.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+
+/// `ls car all` requests, numbered so their summaries can be told apart. Only the latest one is
+/// open; starting another closes it.
+#[derive(Debug)]
+pub struct ListRounds {
+    /// How long a round collects answers.
+    pub window: Duration,
+    last_number: usize,
+    open: Option<ListRound>,
+}
+
+/// One `ls car all`, from sending its requests until every peer answered or its window closed.
+#[derive(Debug)]
+pub struct ListRound {
+    pub number: usize,
+    /// Peers still to answer, by the request they were sent.
+    pending: HashMap<RequestId, PeerId>,
+    pub answered: usize,
+    /// Of the answers, how many came from the cache.
+    pub cached: usize,
+    pub cars: usize,
+    /// Peers that didn't answer within the window or the request timeout.
+    pub timed_out: Vec<PeerId>,
+    pub failed: usize,
+    deadline: Instant,
+}
+
+impl Default for ListRounds {
+    fn default() -> Self {
+        ListRounds::new(DEFAULT_WINDOW)
+    }
+}
+
+impl ListRounds {
+    pub fn new(window: Duration) -> ListRounds {
+        ListRounds {
+            window,
+            last_number: 0,
+            open: None,
+        }
+    }
+
+    /// Opens a new round, returning the one it closes, if any, with its peers timed out.
+    pub fn start(&mut self) -> Option<ListRound> {
+        let closed = self.close();
+        self.last_number += 1;
+        self.open = Some(ListRound {
+            number: self.last_number,
+            pending: HashMap::new(),
+            answered: 0,
+            cached: 0,
+            cars: 0,
+            timed_out: Vec::new(),
+            failed: 0,
+            deadline: Instant::now() + self.window,
+        });
+        closed
+    }
+
+    pub fn open(&mut self) -> Option<&mut ListRound> {
+        self.open.as_mut()
+    }
+
+    /// When the open round stops waiting.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.open.as_ref().map(|round| round.deadline)
+    }
+
+    /// Closes the open round, counting the peers that didn't answer as timed out.
+    pub fn close(&mut self) -> Option<ListRound> {
+        let mut round = self.open.take()?;
+        round
+            .timed_out
+            .extend(round.pending.drain().map(|(_, peer)| peer));
+        Some(round)
+    }
+
+    /// Counts the answer to `request_id`, `None` if the request failed, returning the round
+    /// once every peer answered. Requests of other rounds are ignored.
+    pub fn answered(
+        &mut self,
+        request_id: RequestId,
+        answer: Option<usize>,
+        timed_out: bool,
+    ) -> Option<ListRound> {
+        let round = self.open.as_mut()?;
+        let peer = round.pending.remove(&request_id)?;
+        match answer {
+            Some(cars) => {
+                round.answered += 1;
+                round.cars += cars;
+            }
+            None if timed_out => round.timed_out.push(peer),
+            None => round.failed += 1,
+        }
+        self.finished()
+    }
+
+    /// Closes the open round if no answer is missing.
+    pub fn finished(&mut self) -> Option<ListRound> {
+        match &self.open {
+            Some(round) if round.pending.is_empty() => self.open.take(),
+            _ => None,
+        }
+    }
+}
+
+impl ListRound {
+    pub fn expect(&mut self, request_id: RequestId, peer: PeerId) {
+        self.pending.insert(request_id, peer);
+    }
+
+    /// Counts a listing answered from the cache.
+    pub fn from_cache(&mut self, cars: usize) {
+        self.answered += 1;
+        self.cached += 1;
+        self.cars += cars;
+    }
+
+    pub fn waiting_for(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// e.g. `4 peers answered (1 from the cache), 132 cars, 1 peer timed out`.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} answered", peers(self.answered));
+        if self.cached > 0 {
+            summary += &format!(" ({} from the cache)", self.cached);
+        }
+        summary += &format!(
+            ", {} car{}",
+            self.cars,
+            if self.cars == 1 { "" } else { "s" }
+        );
+        if !self.timed_out.is_empty() {
+            summary += &format!(", {} timed out", peers(self.timed_out.len()));
+        }
+        if self.failed > 0 {
+            summary += &format!(", {} failed", peers(self.failed));
+        }
+        summary
+    }
+}
+
+fn peers(count: usize) -> String {
+    format!("{} peer{}", count, if count == 1 { "" } else { "s" })
+}
@@ -0,0 +1,244 @@
+use chrono::DateTime;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::{atomic, Carinfo, Carinfos, Result};
+
+/// Version of the catalog file this build writes. Raise it with every entry added to
+/// [`MIGRATIONS`].
+pub const CURRENT_VERSION: u32 = 2;
+
+/// A step from the version before `to`, applied to every record as raw JSON, since records of
+/// an older version may not deserialize anymore.
+struct Migration {
+    to: u32,
+    what: &'static str,
+    migrate: fn(&mut Map<String, Value>),
+}
+
+/// Every schema change, oldest first. Files before version 1 are a bare array of records.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to: 1,
+        what: "wrap the records in a versioned document",
+        migrate: unchanged,
+    },
+    Migration {
+        to: 2,
+        what: "store `modified` as `updated_at` and horsepower as a number",
+        migrate: timestamps_and_horsepower,
+    },
+];
+
+fn unchanged(_: &mut Map<String, Value>) {}
+
+fn timestamps_and_horsepower(record: &mut Map<String, Value>) {
+    if let Some(modified) = record.remove("modified") {
+        let updated_at = modified
+            .as_i64()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0));
+        if let (false, Some(at)) = (record.contains_key("updated_at"), updated_at) {
+            record.insert("updated_at".to_owned(), Value::String(at.to_rfc3339()));
+        }
+    }
+    if let Some(Value::String(text)) = record.get("horsepower") {
+        let hp = text.trim().parse::<f64>().map_or(0, |hp| hp.round() as u32);
+        record.insert("horsepower".to_owned(), hp.into());
+    }
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    schema_version: u32,
+    carinfos: &'a [Carinfo],
+}
+
+/// A catalog file of any version, with its records left as JSON until they are migrated.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum StoredCatalog {
+    Versioned {
+        schema_version: u32,
+        carinfos: Vec<Value>,
+    },
+    Unversioned(Vec<Value>),
+}
+
+impl StoredCatalog {
+    pub fn version(&self) -> u32 {
+        match self {
+            StoredCatalog::Versioned { schema_version, .. } => *schema_version,
+            StoredCatalog::Unversioned(_) => 0,
+        }
+    }
+
+    /// Applies the migrations after the file's version and reads the records. Fails for files
+    /// of a newer version, whose records this build may misread.
+    pub fn migrate(self) -> Result<Carinfos> {
+        let version = self.version();
+        if version > CURRENT_VERSION {
+            return Err(format!(
+                "the catalog is schema version {}, written by a newer version of the node; this \
+                 one reads up to version {}",
+                version, CURRENT_VERSION
+            )
+            .into());
+        }
+        let mut records = match self {
+            StoredCatalog::Versioned { carinfos, .. } => carinfos,
+            StoredCatalog::Unversioned(carinfos) => carinfos,
+        };
+        for migration in MIGRATIONS.iter().filter(|m| m.to > version) {
+            for record in records.iter_mut() {
+                if let Value::Object(fields) = record {
+                    (migration.migrate)(fields);
+                }
+            }
+        }
+        Ok(serde_json::from_value(Value::Array(records))?)
+    }
+}
+
+/// The catalog file for `carinfos`, at the current version.
+pub fn encode(carinfos: &[Carinfo]) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&Document {
+        schema_version: CURRENT_VERSION,
+        carinfos,
+    })?)
+}
+
+/// [`encode`] for files meant to be read by people too.
+pub fn encode_pretty(carinfos: &[Carinfo]) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec_pretty(&Document {
+        schema_version: CURRENT_VERSION,
+        carinfos,
+    })?)
+}
+
+/// Reads a catalog file of this or any older version.
+pub fn decode(content: &[u8]) -> Result<Carinfos> {
+    serde_json::from_slice::<StoredCatalog>(content)?.migrate()
+}
+
+/// `<path>.v<version>`, where the file is kept before it is migrated.
+pub fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".v{}", version));
+    name.into()
+}
+
+/// Checked once at startup: upgrades the catalog file at `path` to the current version, after
+/// copying it to [`backup_path`]. Does nothing for a missing file or one that is current.
+pub fn migrate_file(path: &Path) -> Result<()> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let stored: StoredCatalog = serde_json::from_slice(&content)?;
+    let version = stored.version();
+    if version == CURRENT_VERSION {
+        return Ok(());
+    }
+    let carinfos = stored
+        .migrate()
+        .map_err(|e| format!("can't migrate {}, {}", path.display(), e))?;
+    let backup = backup_path(path, version);
+    std::fs::copy(path, &backup)?;
+    for migration in MIGRATIONS.iter().filter(|m| m.to > version) {
+        info!(
+            "Migrating {} to version {}: {}",
+            path.display(),
+            migration.to,
+            migration.what
+        );
+    }
+    atomic::write_blocking(path, &encode(&carinfos)?)?;
+    info!(
+        "Migrated {} cars in {} from schema version {} to {}, the old file is kept as {}",
+        carinfos.len(),
+        path.display(),
+        version,
+        CURRENT_VERSION,
+        backup.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_unversioned_catalogs() {
+        let carinfos = decode(
+            br#"[{"id":1,"make":"VW","model":"Golf","horsepower":" 150.4 ","public":true,
+                  "modified":1600000000}]"#,
+        )
+        .unwrap();
+        assert_eq!(carinfos.len(), 1);
+        assert_eq!(carinfos[0].horsepower, 150);
+        assert_eq!(
+            carinfos[0].updated_at.map(|at| at.to_rfc3339()).as_deref(),
+            Some("2020-09-13T12:26:40+00:00")
+        );
+    }
+
+    #[test]
+    fn keeps_updated_at_over_modified() {
+        let carinfos = decode(
+            br#"{"schema_version":1,"carinfos":[{"id":1,"make":"VW","model":"Golf",
+                 "horsepower":"","public":false,"modified":1600000000,
+                 "updated_at":"2021-01-01T00:00:00Z"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(carinfos[0].horsepower, 0);
+        assert_eq!(
+            carinfos[0].updated_at.map(|at| at.to_rfc3339()).as_deref(),
+            Some("2021-01-01T00:00:00+00:00")
+        );
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let carinfos = decode(
+            br#"[{"id":7,"make":"Alfa Romeo","model":"Giulia","horsepower":510,"public":true}]"#,
+        )
+        .unwrap();
+        let encoded = encode(&carinfos).unwrap();
+        let document: Value = serde_json::from_slice(&encoded).unwrap();
+        assert_eq!(document["schema_version"], CURRENT_VERSION);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(
+            (
+                decoded[0].id,
+                decoded[0].make.as_str(),
+                decoded[0].horsepower
+            ),
+            (7, "Alfa Romeo", 510)
+        );
+        assert_eq!(decode(&encode_pretty(&carinfos).unwrap()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let newer = format!(
+            r#"{{"schema_version":{},"carinfos":[]}}"#,
+            CURRENT_VERSION + 1
+        );
+        let error = decode(newer.as_bytes()).unwrap_err().to_string();
+        assert!(error.contains("newer version of the node"), "{}", error);
+    }
+
+    #[test]
+    fn backups_are_named_after_the_version() {
+        assert_eq!(
+            backup_path(Path::new("data/carinfo.json"), 0),
+            PathBuf::from("data/carinfo.json.v0")
+        );
+    }
+}
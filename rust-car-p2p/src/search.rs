@@ -0,0 +1,158 @@
+use tantivy::{
+    collector::TopDocs,
+    directory::MmapDirectory,
+    doc,
+    query::QueryParser,
+    schema::{Field, Schema, Value, STORED, STRING, TEXT},
+    Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term,
+};
+
+use crate::{Carinfo, Carinfos, Result};
+
+const SEARCH_INDEX_PATH: &str = "./search_index";
+/// Indexing memory budget; tantivy needs at least 15MB per writer thread.
+const WRITER_HEAP_BYTES: usize = 20_000_000;
+/// Words of a peer's query looked at; the rest are ignored, so a query can't make us do much.
+const MAX_QUERY_WORDS: usize = 16;
+
+/// Owner recorded for our own records in the index.
+pub const LOCAL_OWNER: &str = "local";
+
+/// A record matching a search, best match first.
+#[derive(Debug)]
+pub struct Hit {
+    pub score: f32,
+    pub owner: String,
+    pub carinfo: Carinfo,
+}
+
+/// Full-text index over local and received records, persisted so remote listings stay
+/// searchable across restarts.
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: IndexWriter,
+    owner: Field,
+    make: Field,
+    model: Field,
+    details: Field,
+    record: Field,
+}
+
+impl SearchIndex {
+    pub fn open() -> Result<SearchIndex> {
+        std::fs::create_dir_all(SEARCH_INDEX_PATH)?;
+        SearchIndex::create(|schema| {
+            Ok(Index::open_or_create(
+                MmapDirectory::open(SEARCH_INDEX_PATH)?,
+                schema,
+            )?)
+        })
+    }
+
+    /// An index kept in memory only, gone when dropped.
+    fn in_ram() -> Result<SearchIndex> {
+        SearchIndex::create(|schema| Ok(Index::create_in_ram(schema)))
+    }
+
+    fn create(index: impl FnOnce(Schema) -> Result<Index>) -> Result<SearchIndex> {
+        let mut builder = Schema::builder();
+        let owner = builder.add_text_field("owner", STRING | STORED);
+        let make = builder.add_text_field("make", TEXT);
+        let model = builder.add_text_field("model", TEXT);
+        // everything else worth finding a car by: tags, looked up attributes, photo names
+        let details = builder.add_text_field("details", TEXT);
+        let record = builder.add_text_field("record", STORED);
+
+        let index = index(builder.build())?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let writer = index.writer_with_num_threads(1, WRITER_HEAP_BYTES)?;
+        Ok(SearchIndex {
+            index,
+            reader,
+            writer,
+            owner,
+            make,
+            model,
+            details,
+            record,
+        })
+    }
+
+    /// Replaces everything indexed for `owner` with `carinfos`.
+    pub fn index_carinfos(&mut self, owner: &str, carinfos: &[Carinfo]) -> Result<()> {
+        self.writer
+            .delete_term(Term::from_field_text(self.owner, owner));
+        for carinfo in carinfos {
+            let details = carinfo
+                .tags
+                .iter()
+                .chain(carinfo.attributes.values())
+                .map(String::as_str)
+                .chain(carinfo.photos.iter().map(|p| p.file_name.as_str()))
+                .collect::<Vec<_>>()
+                .join(" ");
+            self.writer.add_document(doc!(
+                self.owner => owner,
+                self.make => carinfo.make.as_str(),
+                self.model => carinfo.model.as_str(),
+                self.details => details,
+                self.record => serde_json::to_string(carinfo)?,
+            ))?;
+        }
+        self.writer.commit()?;
+        self.reader.reload()?;
+        Ok(())
+    }
+
+    /// Runs a query in tantivy syntax (`"quoted phrases"`, `make:bmw`, `AND`/`OR`) over make,
+    /// model and details.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Hit>> {
+        let parser = QueryParser::for_index(&self.index, vec![self.make, self.model, self.details]);
+        let query = parser.parse_query(query)?;
+        let searcher = self.reader.searcher();
+        let mut hits = Vec::new();
+        for (score, address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+            let doc: TantivyDocument = searcher.doc(address)?;
+            let owner = doc.get_first(self.owner).and_then(|v| v.as_str());
+            let record = doc.get_first(self.record).and_then(|v| v.as_str());
+            if let (Some(owner), Some(record)) = (owner, record) {
+                hits.push(Hit {
+                    score,
+                    owner: owner.to_owned(),
+                    carinfo: serde_json::from_str(record)?,
+                });
+            }
+        }
+        Ok(hits)
+    }
+}
+
+/// The lower-cased words of `text`, split at anything but letters and digits.
+pub fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The records matching any of the words of `text`, best first, with their scores, ranked like
+/// [`SearchIndex::search`] in an index of their own. For peers' text searches, so only plain
+/// words count, not tantivy's query syntax.
+pub fn rank(text: &str, carinfos: Carinfos) -> Result<Vec<(f32, Carinfo)>> {
+    let mut query = words(text);
+    query.truncate(MAX_QUERY_WORDS);
+    if query.is_empty() || carinfos.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut index = SearchIndex::in_ram()?;
+    index.index_carinfos(LOCAL_OWNER, &carinfos)?;
+    Ok(index
+        .search(&query.join(" "), carinfos.len())?
+        .into_iter()
+        .map(|hit| (hit.score, hit.carinfo))
+        .collect())
+}
@@ -0,0 +1,51 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How long a message is remembered. Gossipsub forgets the ids it deduplicates after a minute,
+/// so copies arriving later are caught here.
+pub const SEEN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Ids of the messages handled recently, so a message delivered twice, or replayed, is only
+/// handled once. Ids are hashed, and forgotten after the ttl.
+#[derive(Debug)]
+pub struct SeenCache {
+    ttl: Duration,
+    ids: HashSet<[u8; 32]>,
+    /// The ids in the order they were seen, to forget them.
+    order: VecDeque<([u8; 32], Instant)>,
+}
+
+impl Default for SeenCache {
+    fn default() -> Self {
+        SeenCache::new(SEEN_TTL)
+    }
+}
+
+impl SeenCache {
+    pub fn new(ttl: Duration) -> SeenCache {
+        SeenCache {
+            ttl,
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Remembers `id`, returning false if it was seen within the ttl.
+    pub fn first_time(&mut self, id: &[u8]) -> bool {
+        let now = Instant::now();
+        while let Some((old, at)) = self.order.front() {
+            if now.duration_since(*at) < self.ttl {
+                break;
+            }
+            self.ids.remove(old);
+            self.order.pop_front();
+        }
+        let hash: [u8; 32] = Sha256::digest(id).into();
+        if !self.ids.insert(hash) {
+            return false;
+        }
+        self.order.push_back((hash, now));
+        true
+    }
+}
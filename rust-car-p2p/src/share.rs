@@ -0,0 +1,262 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Nonce,
+};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use libp2p::{
+    gossipsub::IdentTopic,
+    identity::{self, ed25519},
+    PeerId,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use tokio::fs;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{chat, Carinfo, Result, KEYS};
+
+/// One-to-one record shares. Everyone on the topic sees the envelopes, only the recipient can
+/// open them.
+pub static SHARE_TOPIC: Lazy<IdentTopic> = Lazy::new(|| IdentTopic::new("carinfoshare"));
+
+const SHARED_FILE_PATH: &str = "./shared.json";
+/// Multihash code of the identity hash, which peer ids of ed25519 keys are built with.
+const IDENTITY_MULTIHASH: u8 = 0x00;
+
+/// What gets encrypted: the full record, private fields included, with its photo thumbnails.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SharedCarinfo {
+    pub carinfo: Carinfo,
+    /// Base64 encoded thumbnails keyed by their hash.
+    pub thumbnails: Vec<(String, String)>,
+}
+
+/// A [`SharedCarinfo`] encrypted to `to` with an ephemeral X25519 key and signed by the sender's
+/// identity, so it can't be read by others nor forged under someone else's peer id.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SealedShare {
+    pub to: String,
+    ephemeral: String,
+    nonce: String,
+    ciphertext: String,
+    signature: String,
+}
+
+/// A share we received and opened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedShare {
+    pub from: String,
+    pub at: u64,
+    pub carinfo: Carinfo,
+}
+
+/// Recovers the ed25519 key a peer id was derived from; other key types are hashed into the id
+/// and can't be recovered.
+pub(crate) fn peer_public_key(peer: &PeerId) -> Option<ed25519::PublicKey> {
+    let bytes = peer.to_bytes();
+    if bytes.first() != Some(&IDENTITY_MULTIHASH) {
+        return None;
+    }
+    match identity::PublicKey::from_protobuf_encoding(bytes.get(2..)?).ok()? {
+        identity::PublicKey::Ed25519(key) => Some(key),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// The X25519 form of an ed25519 public key.
+fn agreement_key(key: &ed25519::PublicKey) -> Option<PublicKey> {
+    let point = CompressedEdwardsY(key.encode()).decompress()?;
+    Some(PublicKey::from(point.to_montgomery().to_bytes()))
+}
+
+/// The X25519 secret matching [`agreement_key`] of the identity `keys`.
+fn agreement_secret(keys: &identity::Keypair) -> Option<StaticSecret> {
+    match keys {
+        identity::Keypair::Ed25519(keypair) => {
+            let hash = Sha512::digest(keypair.secret().as_ref());
+            let mut scalar = [0u8; 32];
+            scalar.copy_from_slice(&hash[..32]);
+            Some(StaticSecret::from(scalar))
+        }
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+fn cipher(shared_secret: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> ChaCha20Poly1305 {
+    let mut hasher = Sha256::new();
+    hasher.update(shared_secret);
+    hasher.update(ephemeral.as_bytes());
+    hasher.update(recipient.as_bytes());
+    ChaCha20Poly1305::new(&hasher.finalize())
+}
+
+fn signed_bytes(to: &str, ephemeral: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    [to.as_bytes(), ephemeral, nonce, ciphertext].concat()
+}
+
+/// Encrypts `share` so only `to` can read it.
+pub fn seal(to: &PeerId, share: &SharedCarinfo) -> Result<SealedShare> {
+    seal_as(&KEYS, to, share)
+}
+
+/// [`seal`], signed by the identity `keys`.
+fn seal_as(keys: &identity::Keypair, to: &PeerId, share: &SharedCarinfo) -> Result<SealedShare> {
+    let recipient = peer_public_key(to)
+        .and_then(|k| agreement_key(&k))
+        .ok_or_else(|| format!("can't derive an encryption key from peer id {}", to))?;
+    let ephemeral_secret = StaticSecret::from(rand::random::<[u8; 32]>());
+    let ephemeral = PublicKey::from(&ephemeral_secret);
+    let nonce = rand::random::<[u8; 12]>();
+    let plaintext = serde_json::to_vec(share)?;
+    let ciphertext = cipher(
+        ephemeral_secret.diffie_hellman(&recipient).as_bytes(),
+        &ephemeral,
+        &recipient,
+    )
+    .encrypt(&Nonce::from(nonce), plaintext.as_slice())
+    .map_err(|_| "encrypting share failed")?;
+
+    let to = to.to_string();
+    let signature = keys.sign(&signed_bytes(
+        &to,
+        ephemeral.as_bytes(),
+        &nonce,
+        &ciphertext,
+    ))?;
+    Ok(SealedShare {
+        to,
+        ephemeral: STANDARD.encode(ephemeral.as_bytes()),
+        nonce: STANDARD.encode(nonce),
+        ciphertext: STANDARD.encode(ciphertext),
+        signature: STANDARD.encode(signature),
+    })
+}
+
+/// Checks that `from` sealed the share and decrypts it with our identity.
+pub fn open(from: &PeerId, sealed: &SealedShare) -> Result<SharedCarinfo> {
+    open_as(&KEYS, from, sealed)
+}
+
+/// [`open`] with the identity `keys`.
+fn open_as(keys: &identity::Keypair, from: &PeerId, sealed: &SealedShare) -> Result<SharedCarinfo> {
+    let ephemeral: [u8; 32] = STANDARD
+        .decode(&sealed.ephemeral)?
+        .try_into()
+        .map_err(|_| "invalid ephemeral key")?;
+    let nonce: [u8; 12] = STANDARD
+        .decode(&sealed.nonce)?
+        .try_into()
+        .map_err(|_| "invalid nonce")?;
+    let ciphertext = STANDARD.decode(&sealed.ciphertext)?;
+    let signature = STANDARD.decode(&sealed.signature)?;
+
+    let sender = peer_public_key(from).ok_or("sender has no ed25519 peer id")?;
+    if !sender.verify(
+        &signed_bytes(&sealed.to, &ephemeral, &nonce, &ciphertext),
+        &signature,
+    ) {
+        return Err(format!("share is not signed by {}", from).into());
+    }
+
+    let secret = agreement_secret(keys).ok_or("our identity can't decrypt shares")?;
+    let ephemeral = PublicKey::from(ephemeral);
+    let plaintext = cipher(
+        secret.diffie_hellman(&ephemeral).as_bytes(),
+        &ephemeral,
+        &PublicKey::from(&secret),
+    )
+    .decrypt(&Nonce::from(nonce), ciphertext.as_slice())
+    .map_err(|_| "decrypting share failed")?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+pub async fn read_received() -> Result<Vec<ReceivedShare>> {
+    match fs::read(SHARED_FILE_PATH).await {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Keeps a received record, replacing an earlier share of the same car by the same peer.
+pub async fn store_received(from: &PeerId, carinfo: Carinfo) -> Result<()> {
+    let from = from.to_string();
+    let mut received = read_received().await?;
+    received.retain(|r| !(r.from == from && r.carinfo.id == carinfo.id));
+    received.push(ReceivedShare {
+        from,
+        at: chat::now(),
+        carinfo,
+    });
+    let json = serde_json::to_string(&received)?;
+    fs::write(SHARED_FILE_PATH, &json).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn share() -> SharedCarinfo {
+        SharedCarinfo {
+            carinfo: serde_json::from_value(json!({
+                "id": 4,
+                "make": "Hidden",
+                "model": "Golf",
+                "horsepower": 150,
+                "public": false,
+            }))
+            .expect("valid record"),
+            thumbnails: vec![("ab".to_owned(), "AAAA".to_owned())],
+        }
+    }
+
+    fn identity() -> (identity::Keypair, PeerId) {
+        let keys = identity::Keypair::generate_ed25519();
+        let peer = PeerId::from(keys.public());
+        (keys, peer)
+    }
+
+    #[test]
+    fn only_the_recipient_opens_a_share() {
+        let (sender, sender_id) = identity();
+        let (recipient, recipient_id) = identity();
+        let (other, _) = identity();
+        let sealed = seal_as(&sender, &recipient_id, &share()).unwrap();
+        assert_eq!(sealed.to, recipient_id.to_string());
+        assert!(!sealed.ciphertext.contains("Hidden"));
+
+        let opened = open_as(&recipient, &sender_id, &sealed).unwrap();
+        assert_eq!(opened.carinfo.make, "Hidden");
+        assert_eq!(opened.thumbnails, share().thumbnails);
+        assert!(open_as(&other, &sender_id, &sealed).is_err());
+    }
+
+    #[test]
+    fn refuses_forged_or_altered_shares() {
+        let (sender, sender_id) = identity();
+        let (recipient, recipient_id) = identity();
+        let (_, impostor_id) = identity();
+        let sealed = seal_as(&sender, &recipient_id, &share()).unwrap();
+        assert!(open_as(&recipient, &impostor_id, &sealed).is_err());
+
+        let mut altered = sealed;
+        let mut ciphertext = STANDARD.decode(&altered.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        altered.ciphertext = STANDARD.encode(ciphertext);
+        assert!(open_as(&recipient, &sender_id, &altered).is_err());
+    }
+
+    #[test]
+    fn recovers_ed25519_keys_from_peer_ids() {
+        let (keys, peer) = identity();
+        let key = peer_public_key(&peer).unwrap();
+        assert_eq!(identity::PublicKey::Ed25519(key), keys.public());
+        assert!(peer_public_key(&PeerId::random()).is_none());
+    }
+}
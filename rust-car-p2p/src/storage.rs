@@ -0,0 +1,1148 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use libp2p::PeerId;
+use log::info;
+use once_cell::sync::OnceCell;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::fs;
+
+use crate::{
+    atomic,
+    can::CanSummary,
+    crdt::{Field, Stamps},
+    dedupe::{self, DuplicateKey},
+    digest, geo,
+    journal::{self, Change, Entry},
+    lookup::Attributes,
+    metrics::METRICS,
+    obd::ObdStats,
+    ownership,
+    photos::Photo,
+    price::PricePoint,
+    schema::{self, StoredCatalog},
+    tags,
+    vault::Vault,
+    vehicle::Details,
+    vin, Carinfo, Carinfos, Config, Result, PEER_ID,
+};
+
+pub const STORAGE_FILE_PATH: &str = "./carinfo.json";
+const SQLITE_FILE_PATH: &str = "./carinfo.db";
+const VAULT_FILE_PATH: &str = "./carinfo.private";
+
+static STORAGE: OnceCell<Box<dyn Storage>> = OnceCell::new();
+static DUPLICATE_KEY: OnceCell<DuplicateKey> = OnceCell::new();
+
+/// Where local records are kept. Every record operation in the crate goes through this, so the
+/// backend can be swapped without touching callers.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Where the records are, for display.
+    fn describe(&self) -> String;
+    /// All records, ordered by id.
+    async fn all(&self) -> Result<Carinfos>;
+    async fn get(&self, id: usize) -> Result<Option<Carinfo>>;
+    /// Records matching `make` and `model` case-insensitively; `None` matches anything.
+    async fn find(&self, make: Option<&str>, model: Option<&str>) -> Result<Carinfos>;
+    /// Inserts the record, or replaces the one with the same id.
+    async fn put(&self, carinfo: &Carinfo) -> Result<()>;
+    /// Removes the record and returns it, if there was one.
+    async fn remove(&self, id: usize) -> Result<Option<Carinfo>>;
+    /// Replaces every record with `carinfos`.
+    async fn replace_all(&self, carinfos: &Carinfos) -> Result<()>;
+    /// An id not used by any record yet.
+    async fn next_id(&self) -> Result<usize>;
+    /// Waits for writes in progress to reach the disk, so the process can exit without
+    /// leaving a file half written.
+    async fn flush(&self) -> Result<()>;
+    /// Replaces copies of earlier versions the backend keeps, such as the backup of
+    /// [`JsonStorage`], with the current records, so records moved elsewhere don't linger.
+    async fn refresh_backup(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Whether unpublished records are kept encrypted, so they shouldn't be copied anywhere
+    /// in plaintext either.
+    fn encrypts_private(&self) -> bool {
+        false
+    }
+    /// Encrypts `data` with the key of the unpublished records, for copies of them kept in
+    /// other files. None if they aren't kept encrypted.
+    fn encrypt_private(&self, _data: &[u8]) -> Result<Option<String>> {
+        Ok(None)
+    }
+    /// Decrypts what [`Storage::encrypt_private`] returned.
+    fn decrypt_private(&self, _sealed: &str) -> Result<Vec<u8>> {
+        Err("encrypted data needs CARINFO_PASSPHRASE".into())
+    }
+}
+
+/// Opens the backend chosen with `CARINFO_STORAGE` at `path`: `json` (the default) keeps
+/// everything in one file, `./carinfo.json` unless given, `sqlite` uses `./carinfo.db`. With
+/// `CARINFO_PASSPHRASE` set, unpublished records go to an encrypted file next to it instead.
+/// Errors of the backend are counted for `/metrics`.
+pub fn open(path: Option<PathBuf>) -> Result<Box<dyn Storage>> {
+    let storage: Box<dyn Storage> = match env::var("CARINFO_STORAGE").as_deref() {
+        Ok("sqlite") => Box::new(SqliteStorage::open(
+            path.clone().unwrap_or_else(|| SQLITE_FILE_PATH.into()),
+        )?),
+        Ok("json") | Ok("") | Err(_) => Box::new(JsonStorage::open(
+            path.clone().unwrap_or_else(|| STORAGE_FILE_PATH.into()),
+        )?),
+        Ok(other) => {
+            return Err(
+                format!("unknown CARINFO_STORAGE {}, expected json or sqlite", other).into(),
+            )
+        }
+    };
+    let storage: Box<dyn Storage> = match env::var("CARINFO_PASSPHRASE") {
+        Ok(passphrase) if !passphrase.is_empty() => {
+            let vault_path = path
+                .map(|p| p.with_extension("private"))
+                .unwrap_or_else(|| VAULT_FILE_PATH.into());
+            Box::new(EncryptedStorage {
+                public: storage,
+                vault: Vault::open(vault_path, &passphrase)?,
+                write_lock: tokio::sync::Mutex::new(()),
+                migrated: tokio::sync::OnceCell::new(),
+            })
+        }
+        _ => storage,
+    };
+    Ok(Box::new(Metered(storage)))
+}
+
+/// Counts the errors of the backend it wraps in [`METRICS`].
+struct Metered(Box<dyn Storage>);
+
+fn counted<T>(result: Result<T>) -> Result<T> {
+    if result.is_err() {
+        METRICS.storage_error();
+    }
+    result
+}
+
+#[async_trait]
+impl Storage for Metered {
+    fn describe(&self) -> String {
+        self.0.describe()
+    }
+
+    async fn all(&self) -> Result<Carinfos> {
+        counted(self.0.all().await)
+    }
+
+    async fn get(&self, id: usize) -> Result<Option<Carinfo>> {
+        counted(self.0.get(id).await)
+    }
+
+    async fn find(&self, make: Option<&str>, model: Option<&str>) -> Result<Carinfos> {
+        counted(self.0.find(make, model).await)
+    }
+
+    async fn put(&self, carinfo: &Carinfo) -> Result<()> {
+        counted(self.0.put(carinfo).await)
+    }
+
+    async fn remove(&self, id: usize) -> Result<Option<Carinfo>> {
+        counted(self.0.remove(id).await)
+    }
+
+    async fn replace_all(&self, carinfos: &Carinfos) -> Result<()> {
+        counted(self.0.replace_all(carinfos).await)
+    }
+
+    async fn next_id(&self) -> Result<usize> {
+        counted(self.0.next_id().await)
+    }
+
+    async fn flush(&self) -> Result<()> {
+        counted(self.0.flush().await)
+    }
+
+    async fn refresh_backup(&self) -> Result<()> {
+        counted(self.0.refresh_backup().await)
+    }
+
+    fn encrypts_private(&self) -> bool {
+        self.0.encrypts_private()
+    }
+
+    fn encrypt_private(&self, data: &[u8]) -> Result<Option<String>> {
+        self.0.encrypt_private(data)
+    }
+
+    fn decrypt_private(&self, sealed: &str) -> Result<Vec<u8>> {
+        self.0.decrypt_private(sealed)
+    }
+}
+
+/// The whole catalog as one JSON array, rewritten on every change. Fine for small catalogs.
+/// Writes replace the file atomically and keep the previous version, see [`atomic::write`].
+pub struct JsonStorage {
+    path: PathBuf,
+    /// Serializes read-modify-write cycles within this process.
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl JsonStorage {
+    pub fn new(path: PathBuf) -> JsonStorage {
+        JsonStorage {
+            path,
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Like [`JsonStorage::new`], restoring the file from its backup first if it is damaged
+    /// and then migrating it to the current schema version, see [`schema::migrate_file`].
+    pub fn open(path: PathBuf) -> Result<JsonStorage> {
+        atomic::recover::<StoredCatalog>(&path)?;
+        schema::migrate_file(&path)?;
+        Ok(JsonStorage::new(path))
+    }
+
+    async fn write(&self, carinfos: &Carinfos) -> Result<()> {
+        atomic::write(&self.path, &schema::encode(carinfos)?).await
+    }
+}
+
+#[async_trait]
+impl Storage for JsonStorage {
+    fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    async fn all(&self) -> Result<Carinfos> {
+        let content = fs::read(&self.path).await?;
+        schema::decode(&content)
+    }
+
+    async fn get(&self, id: usize) -> Result<Option<Carinfo>> {
+        Ok(self.all().await?.into_iter().find(|r| r.id == id))
+    }
+
+    async fn find(&self, make: Option<&str>, model: Option<&str>) -> Result<Carinfos> {
+        let matches = |field: &str, wanted: Option<&str>| {
+            wanted.is_none_or(|w| field.eq_ignore_ascii_case(w))
+        };
+        Ok(self
+            .all()
+            .await?
+            .into_iter()
+            .filter(|r| matches(&r.make, make) && matches(&r.model, model))
+            .collect())
+    }
+
+    async fn put(&self, carinfo: &Carinfo) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        let mut carinfos = self.all().await?;
+        match carinfos.iter_mut().find(|r| r.id == carinfo.id) {
+            Some(existing) => *existing = carinfo.clone(),
+            None => carinfos.push(carinfo.clone()),
+        }
+        self.write(&carinfos).await
+    }
+
+    async fn remove(&self, id: usize) -> Result<Option<Carinfo>> {
+        let _guard = self.write_lock.lock().await;
+        let mut carinfos = self.all().await?;
+        let removed = match carinfos.iter().position(|r| r.id == id) {
+            Some(index) => carinfos.remove(index),
+            None => return Ok(None),
+        };
+        self.write(&carinfos).await?;
+        Ok(Some(removed))
+    }
+
+    async fn replace_all(&self, carinfos: &Carinfos) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        self.write(carinfos).await
+    }
+
+    async fn next_id(&self) -> Result<usize> {
+        Ok(self
+            .all()
+            .await?
+            .iter()
+            .map(|r| r.id + 1)
+            .max()
+            .unwrap_or(0))
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        Ok(())
+    }
+
+    async fn refresh_backup(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        atomic::refresh_backup(&self.path).await
+    }
+}
+
+/// One row per record, with make and model indexed for lookups. The full record is stored as
+/// JSON next to them, so new fields don't need a schema change.
+pub struct SqliteStorage {
+    path: PathBuf,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Opens or creates the database. A new, empty database imports `./carinfo.json` if it
+    /// exists, so switching backends keeps the catalog.
+    pub fn open(path: PathBuf) -> Result<SqliteStorage> {
+        let mut conn = Connection::open(&path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS carinfos (
+                id INTEGER PRIMARY KEY,
+                make TEXT NOT NULL,
+                model TEXT NOT NULL,
+                record TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS carinfos_make ON carinfos (make COLLATE NOCASE);
+            CREATE INDEX IF NOT EXISTS carinfos_model ON carinfos (model COLLATE NOCASE);",
+        )?;
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM carinfos", [], |row| row.get(0))?;
+        if count == 0 {
+            if let Ok(content) = std::fs::read(STORAGE_FILE_PATH) {
+                let carinfos = schema::decode(&content)?;
+                insert_all(&mut conn, &carinfos)?;
+                info!(
+                    "Imported {} cars from {} into {}",
+                    carinfos.len(),
+                    STORAGE_FILE_PATH,
+                    path.display()
+                );
+            }
+        }
+        Ok(SqliteStorage {
+            path,
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` on the connection without blocking the runtime.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|_| "sqlite connection poisoned")?;
+            f(&mut conn)
+        })
+        .await?
+    }
+}
+
+fn insert(conn: &Connection, carinfo: &Carinfo) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO carinfos (id, make, model, record) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            carinfo.id as i64,
+            carinfo.make,
+            carinfo.model,
+            serde_json::to_string(carinfo)?
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_all(conn: &mut Connection, carinfos: &Carinfos) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM carinfos", [])?;
+    for carinfo in carinfos {
+        insert(&tx, carinfo)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn decode_all(records: Vec<String>) -> Result<Carinfos> {
+    records
+        .iter()
+        .map(|r| serde_json::from_str(r).map_err(Into::into))
+        .collect()
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    fn describe(&self) -> String {
+        format!("sqlite {}", self.path.display())
+    }
+
+    async fn all(&self) -> Result<Carinfos> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT record FROM carinfos ORDER BY id")?;
+            let records = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            decode_all(records)
+        })
+        .await
+    }
+
+    async fn get(&self, id: usize) -> Result<Option<Carinfo>> {
+        self.with_conn(move |conn| {
+            let record: Option<String> = conn
+                .query_row(
+                    "SELECT record FROM carinfos WHERE id = ?1",
+                    [id as i64],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match record {
+                Some(record) => Ok(Some(serde_json::from_str(&record)?)),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn find(&self, make: Option<&str>, model: Option<&str>) -> Result<Carinfos> {
+        let (make, model) = (make.map(str::to_owned), model.map(str::to_owned));
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT record FROM carinfos
+                WHERE (?1 IS NULL OR make = ?1 COLLATE NOCASE)
+                AND (?2 IS NULL OR model = ?2 COLLATE NOCASE)
+                ORDER BY id",
+            )?;
+            let records = stmt
+                .query_map(params![make, model], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            decode_all(records)
+        })
+        .await
+    }
+
+    async fn put(&self, carinfo: &Carinfo) -> Result<()> {
+        let carinfo = carinfo.clone();
+        self.with_conn(move |conn| insert(conn, &carinfo)).await
+    }
+
+    async fn remove(&self, id: usize) -> Result<Option<Carinfo>> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let record: Option<String> = tx
+                .query_row(
+                    "SELECT record FROM carinfos WHERE id = ?1",
+                    [id as i64],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            tx.execute("DELETE FROM carinfos WHERE id = ?1", [id as i64])?;
+            tx.commit()?;
+            match record {
+                Some(record) => Ok(Some(serde_json::from_str(&record)?)),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn replace_all(&self, carinfos: &Carinfos) -> Result<()> {
+        let carinfos = carinfos.clone();
+        self.with_conn(move |conn| insert_all(conn, &carinfos))
+            .await
+    }
+
+    async fn next_id(&self) -> Result<usize> {
+        self.with_conn(|conn| {
+            let next: i64 =
+                conn.query_row("SELECT COALESCE(MAX(id) + 1, 0) FROM carinfos", [], |row| {
+                    row.get(0)
+                })?;
+            Ok(next as usize)
+        })
+        .await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        // every statement commits on its own, holding the connection is enough
+        self.with_conn(|_| Ok(())).await
+    }
+}
+
+/// Keeps published records in another backend and unpublished ones in a [`Vault`], so records
+/// that never leave the node are not readable on disk without the passphrase.
+pub struct EncryptedStorage {
+    public: Box<dyn Storage>,
+    vault: Vault,
+    /// Serializes read-modify-write cycles on the vault within this process.
+    write_lock: tokio::sync::Mutex<()>,
+    /// Set once unpublished records left in the backend from before were moved to the vault.
+    migrated: tokio::sync::OnceCell<()>,
+}
+
+impl EncryptedStorage {
+    /// Moves unpublished records the backend still holds into the vault, once per process.
+    async fn migrate(&self) -> Result<()> {
+        self.migrated
+            .get_or_try_init(|| async {
+                let _guard = self.write_lock.lock().await;
+                let plaintext: Carinfos = self
+                    .public
+                    .all()
+                    .await?
+                    .into_iter()
+                    .filter(|r| !r.public)
+                    .collect();
+                if plaintext.is_empty() {
+                    return Ok(());
+                }
+                let mut private = self.vault.load().await?;
+                private.retain(|r| !plaintext.iter().any(|p| p.id == r.id));
+                private.extend(plaintext.iter().cloned());
+                self.vault.save(&private).await?;
+                for carinfo in plaintext.iter() {
+                    self.public.remove(carinfo.id).await?;
+                }
+                // the backend's backup still holds the records removed last
+                self.public.refresh_backup().await?;
+                info!(
+                    "Encrypted {} unpublished cars into {}",
+                    plaintext.len(),
+                    self.vault.path().display()
+                );
+                Result::<()>::Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Updates the vault with `update` and stores the result.
+    async fn update_vault<T>(&self, update: impl FnOnce(&mut Carinfos) -> T) -> Result<T> {
+        let _guard = self.write_lock.lock().await;
+        let mut private = self.vault.load().await?;
+        let result = update(&mut private);
+        private.sort_by_key(|r| r.id);
+        self.vault.save(&private).await?;
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl Storage for EncryptedStorage {
+    fn describe(&self) -> String {
+        format!(
+            "{}, unpublished cars encrypted in {}",
+            self.public.describe(),
+            self.vault.path().display()
+        )
+    }
+
+    async fn all(&self) -> Result<Carinfos> {
+        self.migrate().await?;
+        let mut carinfos = self.public.all().await?;
+        carinfos.extend(self.vault.load().await?);
+        carinfos.sort_by_key(|r| r.id);
+        Ok(carinfos)
+    }
+
+    async fn get(&self, id: usize) -> Result<Option<Carinfo>> {
+        self.migrate().await?;
+        match self.public.get(id).await? {
+            Some(carinfo) => Ok(Some(carinfo)),
+            None => Ok(self.vault.load().await?.into_iter().find(|r| r.id == id)),
+        }
+    }
+
+    async fn find(&self, make: Option<&str>, model: Option<&str>) -> Result<Carinfos> {
+        let matches = |field: &str, wanted: Option<&str>| {
+            wanted.is_none_or(|w| field.eq_ignore_ascii_case(w))
+        };
+        let mut carinfos = self.public.find(make, model).await?;
+        carinfos.extend(
+            self.vault
+                .load()
+                .await?
+                .into_iter()
+                .filter(|r| matches(&r.make, make) && matches(&r.model, model)),
+        );
+        carinfos.sort_by_key(|r| r.id);
+        Ok(carinfos)
+    }
+
+    async fn put(&self, carinfo: &Carinfo) -> Result<()> {
+        self.migrate().await?;
+        // publishing moves a record out of the vault, unpublishing moves it back in
+        let id = carinfo.id;
+        if carinfo.public {
+            self.public.put(carinfo).await?;
+            self.update_vault(|private| private.retain(|r| r.id != id))
+                .await
+        } else {
+            self.update_vault(|private| {
+                private.retain(|r| r.id != id);
+                private.push(carinfo.clone());
+            })
+            .await?;
+            self.public.remove(id).await?;
+            Ok(())
+        }
+    }
+
+    async fn remove(&self, id: usize) -> Result<Option<Carinfo>> {
+        self.migrate().await?;
+        let removed = self
+            .update_vault(|private| {
+                let index = private.iter().position(|r| r.id == id)?;
+                Some(private.remove(index))
+            })
+            .await?;
+        match self.public.remove(id).await? {
+            Some(carinfo) => Ok(Some(carinfo)),
+            None => Ok(removed),
+        }
+    }
+
+    async fn replace_all(&self, carinfos: &Carinfos) -> Result<()> {
+        let (public, private): (Carinfos, Carinfos) =
+            carinfos.iter().cloned().partition(|r| r.public);
+        self.public.replace_all(&public).await?;
+        self.update_vault(|stored| *stored = private).await
+    }
+
+    async fn next_id(&self) -> Result<usize> {
+        let public = self.public.next_id().await?;
+        let private = self
+            .vault
+            .load()
+            .await?
+            .iter()
+            .map(|r| r.id + 1)
+            .max()
+            .unwrap_or(0);
+        Ok(public.max(private))
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        self.public.flush().await
+    }
+
+    async fn refresh_backup(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        self.public.refresh_backup().await
+    }
+
+    fn encrypts_private(&self) -> bool {
+        true
+    }
+
+    fn encrypt_private(&self, data: &[u8]) -> Result<Option<String>> {
+        self.vault.encrypt(data).map(Some)
+    }
+
+    fn decrypt_private(&self, sealed: &str) -> Result<Vec<u8>> {
+        self.vault.decrypt_data(sealed)
+    }
+}
+
+/// Opens local storage where `config` says. Call it before any record is read or written; once
+/// storage is open it can't be moved.
+pub fn init_storage(config: &Config) -> Result<()> {
+    let _ = DUPLICATE_KEY.set(config.duplicate_key);
+    let opened = open(config.storage_path.clone())?;
+    STORAGE
+        .set(opened)
+        .map_err(|_| "storage is already open".into())
+}
+
+/// Local records, in the default location unless [`init_storage`] said otherwise.
+pub fn storage() -> &'static dyn Storage {
+    STORAGE
+        .get_or_init(|| open(None).expect("can open storage"))
+        .as_ref()
+}
+
+/// Stores a new, unpublished record and returns its id.
+pub async fn create_new_carinfo(
+    make: &str,
+    model: &str,
+    horsepower: u32,
+    vin: Option<&str>,
+    details: &Details,
+) -> Result<usize> {
+    let vin = match vin {
+        Some(vin) => Some(check_vin(vin, None).await?),
+        None => None,
+    };
+    let new_id = storage().next_id().await?;
+    let mut carinfo = new_carinfo(new_id, make, model, horsepower);
+    carinfo.vin = vin;
+    details.apply(&mut carinfo);
+    digest::seal(&mut carinfo);
+    storage().put(&carinfo).await?;
+    journal::record(new_id, Change::Created, None, Some(&carinfo)).await;
+    Ok(new_id)
+}
+
+/// What makes two records the same car, from the config given to [`init_storage`].
+pub fn duplicate_key() -> DuplicateKey {
+    DUPLICATE_KEY.get().copied().unwrap_or_default()
+}
+
+/// A record that would be a duplicate of a new one with these fields, see [`duplicate_key`].
+pub async fn find_duplicate(
+    make: &str,
+    model: &str,
+    horsepower: u32,
+    vin: Option<&str>,
+) -> Result<Option<Carinfo>> {
+    let mut candidate = new_carinfo(0, make, model, horsepower);
+    candidate.vin = vin.map(str::to_owned);
+    Ok(storage()
+        .all()
+        .await?
+        .into_iter()
+        .find(|r| duplicate_key().same_car(&candidate, r)))
+}
+
+/// Replaces a group of duplicates with their merge, see [`dedupe::merge`], and returns it.
+pub async fn merge_duplicates(group: &[Carinfo]) -> Result<Carinfo> {
+    let mut merged = dedupe::merge(group);
+    digest::seal(&mut merged);
+    storage().put(&merged).await?;
+    let before = group.iter().find(|r| r.id == merged.id).cloned();
+    journal::record(merged.id, Change::Merged, before, Some(&merged)).await;
+    for duplicate in group.iter().filter(|r| r.id != merged.id) {
+        storage().remove(duplicate.id).await?;
+        journal::record(duplicate.id, Change::Deleted, Some(duplicate.clone()), None).await;
+    }
+    Ok(merged)
+}
+
+/// The normalised VIN, if it is valid and no record other than `id` has it.
+pub async fn check_vin(vin: &str, id: Option<usize>) -> Result<String> {
+    let vin = vin::validate(vin)?;
+    if let Some(other) = find_vin(&vin).await?.filter(|other| Some(*other) != id) {
+        return Err(format!("VIN {} is already used by carinfo {}", vin, other).into());
+    }
+    Ok(vin)
+}
+
+/// The id of our record with `vin`, compared case-insensitively.
+pub async fn find_vin(vin: &str) -> Result<Option<usize>> {
+    Ok(storage()
+        .all()
+        .await?
+        .into_iter()
+        .find(|r| {
+            r.vin
+                .as_deref()
+                .is_some_and(|v| v.eq_ignore_ascii_case(vin))
+        })
+        .map(|r| r.id))
+}
+
+/// An unpublished record of ours, created now.
+pub fn new_carinfo(id: usize, make: &str, model: &str, horsepower: u32) -> Carinfo {
+    let now = Utc::now();
+    let mut stamps = Stamps::default();
+    stamps.touch(&Field::ALL);
+    Carinfo {
+        id,
+        origin: Some(PEER_ID.to_string()),
+        origin_signature: None,
+        unverified: false,
+        content_hash: None,
+        make: make.to_owned(),
+        model: model.to_owned(),
+        horsepower,
+        vin: None,
+        year: None,
+        mileage_km: None,
+        price: None,
+        fuel: None,
+        transmission: None,
+        public: false,
+        obd: None,
+        can: None,
+        attributes: Attributes::new(),
+        price_history: Vec::new(),
+        photos: Vec::new(),
+        location: None,
+        created_at: Some(now),
+        updated_at: Some(now),
+        allowed_peers: BTreeSet::new(),
+        channels: BTreeSet::new(),
+        tags: Vec::new(),
+        stamps,
+    }
+}
+
+/// Applies `update` to record `id` and stores the result with a new `updated_at`, journaled as
+/// `change`.
+async fn update_carinfo<T>(
+    id: usize,
+    change: Change,
+    update: impl FnOnce(&mut Carinfo) -> T,
+) -> Result<T> {
+    let mut carinfo = read_local_carinfo(id)
+        .await?
+        .ok_or_else(|| format!("no carinfo with id {}", id))?;
+    let before = carinfo.clone();
+    carinfo.updated_at = Some(Utc::now());
+    let result = update(&mut carinfo);
+    digest::seal(&mut carinfo);
+    storage().put(&carinfo).await?;
+    journal::record(id, change, Some(before), Some(&carinfo)).await;
+    Ok(result)
+}
+
+pub async fn publish_carinfo(id: usize) -> Result<()> {
+    update_carinfo(id, Change::Published, |carinfo| {
+        carinfo.public = true;
+        carinfo.stamps.touch(&[Field::Public]);
+    })
+    .await
+}
+
+/// Replaces the given fields of a record, keeping the others, and returns the updated record.
+pub async fn edit_carinfo(
+    id: usize,
+    make: Option<&str>,
+    model: Option<&str>,
+    horsepower: Option<u32>,
+    vin: Option<&str>,
+    details: &Details,
+) -> Result<Carinfo> {
+    let vin = match vin {
+        Some(vin) => Some(check_vin(vin, Some(id)).await?),
+        None => None,
+    };
+    update_carinfo(id, Change::Edited, |carinfo| {
+        let mut changed = Vec::new();
+        if let Some(make) = make {
+            carinfo.make = make.to_owned();
+            changed.push(Field::Make);
+        }
+        if let Some(model) = model {
+            carinfo.model = model.to_owned();
+            changed.push(Field::Model);
+        }
+        if let Some(horsepower) = horsepower {
+            carinfo.horsepower = horsepower;
+            changed.push(Field::Horsepower);
+        }
+        if vin.is_some() {
+            carinfo.vin = vin;
+        }
+        details.apply(carinfo);
+        carinfo.stamps.touch(&changed);
+        carinfo.clone()
+    })
+    .await
+}
+
+/// What `undo` or `redo` did to a record, `None` where there is no record.
+#[derive(Debug)]
+pub struct Reverted {
+    pub entry: Entry,
+    pub was: Option<Carinfo>,
+    pub now: Option<Carinfo>,
+}
+
+/// Reverts the latest journaled change that isn't undone yet, see [`journal::undoable`].
+/// Returns `None` if there's nothing to undo.
+pub async fn undo_change() -> Result<Option<Reverted>> {
+    let entry = match journal::undoable().await? {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    if entry.before.is_none() && !matches!(entry.change, Change::Created | Change::Imported) {
+        return Err(format!(
+            "change {} was journaled without the car it changed",
+            entry.seq
+        )
+        .into());
+    }
+    let was = storage().get(entry.car_id).await?;
+    let now = restore(entry.car_id, entry.before.clone()).await?;
+    journal::undone(entry.clone(), was.clone(), now.as_ref()).await;
+    Ok(Some(Reverted { entry, was, now }))
+}
+
+/// Makes the change undone last this session again. Returns `None` if there's nothing to redo.
+pub async fn redo_change() -> Result<Option<Reverted>> {
+    let (entry, state) = match journal::take_redo() {
+        Some(redo) => redo,
+        None => return Ok(None),
+    };
+    let was = storage().get(entry.car_id).await?;
+    let now = restore(entry.car_id, state).await?;
+    journal::redone(&entry, was.clone(), now.as_ref()).await;
+    Ok(Some(Reverted { entry, was, now }))
+}
+
+/// Stores `state` as record `id`, or removes the record for `None`. The record counts as
+/// changed now, so its fields win over older copies peers merge it with.
+async fn restore(id: usize, state: Option<Carinfo>) -> Result<Option<Carinfo>> {
+    match state {
+        Some(mut carinfo) => {
+            carinfo.updated_at = Some(Utc::now());
+            carinfo.stamps.touch(&Field::ALL);
+            digest::seal(&mut carinfo);
+            storage().put(&carinfo).await?;
+            Ok(Some(carinfo))
+        }
+        None => {
+            storage().remove(id).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Removes a record from local storage and returns it.
+pub async fn delete_carinfo(id: usize) -> Result<Carinfo> {
+    let carinfo = storage()
+        .remove(id)
+        .await?
+        .ok_or_else(|| format!("no carinfo with id {}", id))?;
+    journal::record(id, Change::Deleted, Some(carinfo.clone()), None).await;
+    Ok(carinfo)
+}
+
+/// Makes a record private again; it is left out of list responses from now on.
+pub async fn unpublish_carinfo(id: usize) -> Result<()> {
+    update_carinfo(id, Change::Unpublished, |carinfo| {
+        carinfo.public = false;
+        carinfo.stamps.touch(&[Field::Public]);
+    })
+    .await
+}
+
+/// Lets `peer` list the record even while it isn't public. Returns false if it already could.
+pub async fn grant_carinfo(id: usize, peer: &PeerId) -> Result<bool> {
+    update_carinfo(id, Change::Granted, |carinfo| {
+        carinfo.allowed_peers.insert(peer.to_string())
+    })
+    .await
+}
+
+/// Undoes [`grant_carinfo`]. Returns false if `peer` had no grant.
+pub async fn revoke_carinfo(id: usize, peer: &PeerId) -> Result<bool> {
+    update_carinfo(id, Change::Revoked, |carinfo| {
+        carinfo.allowed_peers.remove(&peer.to_string())
+    })
+    .await
+}
+
+pub async fn attach_obd_stats(id: usize, stats: ObdStats) -> Result<()> {
+    update_carinfo(id, Change::Edited, |carinfo| carinfo.obd = Some(stats)).await
+}
+
+pub async fn attach_can_summary(id: usize, summary: CanSummary) -> Result<()> {
+    update_carinfo(id, Change::Edited, |carinfo| carinfo.can = Some(summary)).await
+}
+
+pub async fn merge_attributes(id: usize, attributes: Attributes) -> Result<()> {
+    update_carinfo(id, Change::Edited, |carinfo| {
+        carinfo.attributes.extend(attributes)
+    })
+    .await
+}
+
+pub async fn attach_photo(id: usize, photo: Photo) -> Result<()> {
+    update_carinfo(id, Change::Edited, |carinfo| carinfo.photos.push(photo)).await
+}
+
+/// Sets or clears where a car is, given as a geohash that is cut to [`geo::PRECISION`].
+pub async fn set_location(id: usize, location: Option<String>) -> Result<()> {
+    update_carinfo(id, Change::Edited, |carinfo| {
+        carinfo.location = location.map(|l| l.chars().take(geo::PRECISION).collect());
+        carinfo.stamps.touch(&[Field::Location]);
+    })
+    .await
+}
+
+/// Adds a tag, normalized with [`tags::normalize`], to a record. Returns false if the record
+/// already had it.
+pub async fn tag_carinfo(id: usize, tag: &str) -> Result<bool> {
+    let tag = tags::normalize(tag)?;
+    update_carinfo(id, Change::Edited, |carinfo| {
+        match carinfo.tags.binary_search(&tag) {
+            Ok(_) => return false,
+            Err(at) => carinfo.tags.insert(at, tag),
+        }
+        carinfo.stamps.touch(&[Field::Tags]);
+        true
+    })
+    .await
+}
+
+/// Undoes [`tag_carinfo`]. Returns false if the record didn't have the tag.
+pub async fn untag_carinfo(id: usize, tag: &str) -> Result<bool> {
+    let tag = tags::normalize(tag)?;
+    update_carinfo(id, Change::Edited, |carinfo| {
+        match carinfo.tags.binary_search(&tag) {
+            Ok(at) => carinfo.tags.remove(at),
+            Err(_) => return false,
+        };
+        carinfo.stamps.touch(&[Field::Tags]);
+        true
+    })
+    .await
+}
+
+/// Replaces the channels of a record, returning the updated record.
+pub async fn set_channels(id: usize, channels: BTreeSet<String>) -> Result<Carinfo> {
+    update_carinfo(id, Change::Edited, |carinfo| {
+        carinfo.channels = channels;
+        carinfo.clone()
+    })
+    .await
+}
+
+pub async fn record_prices(id: usize, points: Vec<PricePoint>) -> Result<Vec<PricePoint>> {
+    update_carinfo(id, Change::Edited, |carinfo| {
+        carinfo.price_history.extend(points);
+        carinfo.price_history.clone()
+    })
+    .await
+}
+
+/// The records other peers may see: published ones, with OBD-II data only if it is shared.
+pub async fn read_public_carinfos() -> Result<Carinfos> {
+    Ok(read_local_carinfos()
+        .await?
+        .into_iter()
+        .filter(|r| r.public)
+        .map(outgoing)
+        .collect())
+}
+
+/// The records `peer` gets in listings: public ones and those granted to it.
+pub async fn read_carinfos_for(peer: &PeerId) -> Result<Carinfos> {
+    let peer = peer.to_string();
+    Ok(read_local_carinfos()
+        .await?
+        .into_iter()
+        .filter(|r| r.public || r.allowed_peers.contains(&peer))
+        .map(outgoing)
+        .collect())
+}
+
+/// A record as sent to peers, without the fields only we should see and signed as ours.
+pub fn outgoing(mut carinfo: Carinfo) -> Carinfo {
+    if !carinfo.obd.as_ref().is_some_and(|o| o.shared) {
+        carinfo.obd = None;
+    }
+    carinfo.allowed_peers.clear();
+    ownership::sign(&mut carinfo);
+    digest::seal(&mut carinfo);
+    carinfo
+}
+
+pub async fn read_local_carinfos() -> Result<Carinfos> {
+    Ok(storage()
+        .all()
+        .await?
+        .into_iter()
+        .map(with_origin)
+        .collect())
+}
+
+pub async fn read_local_carinfo(id: usize) -> Result<Option<Carinfo>> {
+    Ok(storage().get(id).await?.map(with_origin))
+}
+
+/// Local records of the given make and/or model, matched case-insensitively.
+pub async fn find_local_carinfos(make: Option<&str>, model: Option<&str>) -> Result<Carinfos> {
+    Ok(storage()
+        .find(make, model)
+        .await?
+        .into_iter()
+        .map(with_origin)
+        .collect())
+}
+
+/// Local records stored before they named their origin were created here. The origin is
+/// filled in on reading and stored the next time the record changes.
+fn with_origin(mut carinfo: Carinfo) -> Carinfo {
+    carinfo.origin.get_or_insert_with(|| PEER_ID.to_string());
+    carinfo
+}
+
+/// Replaces the whole catalog, journaling the records that were added, changed or left out.
+pub async fn write_local_carinfos(carinfos: &Carinfos) -> Result<()> {
+    let mut carinfos = carinfos.clone();
+    carinfos.iter_mut().for_each(digest::seal);
+    let mut before: HashMap<usize, Carinfo> = storage()
+        .all()
+        .await?
+        .into_iter()
+        .map(|r| (r.id, r))
+        .collect();
+    storage().replace_all(&carinfos).await?;
+    for carinfo in &carinfos {
+        let old = before.remove(&carinfo.id);
+        if old.as_ref().map(digest::of) != carinfo.content_hash {
+            journal::record(carinfo.id, Change::Imported, old, Some(carinfo)).await;
+        }
+    }
+    for (id, old) in before {
+        journal::record(id, Change::Deleted, Some(old), None).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn carinfo(id: usize, make: &str, public: bool) -> Carinfo {
+        serde_json::from_value(json!({
+            "id": id,
+            "make": make,
+            "model": "Golf",
+            "horsepower": 150,
+            "public": public,
+        }))
+        .expect("valid record")
+    }
+
+    #[tokio::test]
+    async fn migration_leaves_no_private_records_in_the_backup() {
+        let dir = std::env::temp_dir().join(format!("carinfo-migrate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("carinfo.json");
+        let json = JsonStorage::new(path.clone());
+        json.replace_all(&vec![carinfo(0, "Public", true)])
+            .await
+            .unwrap();
+        json.replace_all(&vec![
+            carinfo(0, "Public", true),
+            carinfo(1, "Hidden", false),
+        ])
+        .await
+        .unwrap();
+        json.put(&carinfo(2, "Hidden", false)).await.unwrap();
+
+        let storage = EncryptedStorage {
+            public: Box::new(json),
+            vault: Vault::open(dir.join("carinfo.private"), "passphrase").unwrap(),
+            write_lock: tokio::sync::Mutex::new(()),
+            migrated: tokio::sync::OnceCell::new(),
+        };
+        assert_eq!(storage.all().await.unwrap().len(), 3);
+
+        for file in ["carinfo.json", "carinfo.json.bak", "carinfo.private"] {
+            let content = std::fs::read_to_string(dir.join(file)).unwrap();
+            assert!(
+                !content.contains("Hidden"),
+                "{} holds a private record",
+                file
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
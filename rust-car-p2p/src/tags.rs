@@ -0,0 +1,18 @@
+/// Longest tag, in characters.
+const MAX_LEN: usize = 32;
+
+/// Lower-cases a tag, which is one word of up to 32 characters so it can be typed in commands
+/// and filters.
+pub fn normalize(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.chars().any(|c| c.is_whitespace() || c == ',') {
+        return Err(format!("invalid tag {}, expected one word", tag));
+    }
+    if tag.chars().count() > MAX_LEN {
+        return Err(format!(
+            "invalid tag {}, at most {} characters",
+            tag, MAX_LEN
+        ));
+    }
+    Ok(tag)
+}
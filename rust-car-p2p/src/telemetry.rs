@@ -0,0 +1,129 @@
+use libp2p::{gossipsub::IdentTopic, PeerId};
+use log::error;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{format::NUMBER_FORMAT, obd, Carinfo};
+
+/// Opt-in channel for live car data, separate from the catalog topic so only interested
+/// nodes subscribe to it.
+pub static TELEMETRY_TOPIC: Lazy<IdentTopic> = Lazy::new(|| IdentTopic::new("cartelemetry"));
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+/// Lower bound on the send interval so a node can't flood the channel.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Speeds are only shared as buckets of this width, capped at `TOP_SPEED_BUCKET`.
+const SPEED_BUCKET_KMH: u32 = 30;
+const TOP_SPEED_BUCKET: u32 = 5;
+
+/// A coarse, privacy-preserving snapshot of one car's live state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelemetryUpdate {
+    pub car_id: usize,
+    pub car: String,
+    /// Free-form region chosen by the sender (e.g. "Munich"), never a precise position.
+    pub region: Option<String>,
+    pub speed_bucket: Option<u32>,
+    pub engine_temp_c: Option<f64>,
+    pub sent_at: u64,
+}
+
+/// Starts streaming telemetry for `carinfo`, reading OBD-II data from `source` every `interval`.
+pub fn start(
+    carinfo: &Carinfo,
+    source: String,
+    region: Option<String>,
+    interval: Duration,
+    sender: mpsc::UnboundedSender<TelemetryUpdate>,
+) -> JoinHandle<()> {
+    let car_id = carinfo.id;
+    let car = format!("{} {}", carinfo.make.trim(), carinfo.model.trim());
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval.max(MIN_INTERVAL));
+        loop {
+            ticker.tick().await;
+            let stats = match obd::read(&source).await {
+                Ok(stats) => stats,
+                Err(e) => {
+                    error!("error reading telemetry from {}, {}", source, e);
+                    continue;
+                }
+            };
+            let update = TelemetryUpdate {
+                car_id,
+                car: car.clone(),
+                region: region.clone(),
+                speed_bucket: stats
+                    .speed_kmh
+                    .map(|kmh| (kmh as u32 / SPEED_BUCKET_KMH).min(TOP_SPEED_BUCKET)),
+                engine_temp_c: stats.coolant_temp_c,
+                sent_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default(),
+            };
+            if sender.send(update).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Subscriber-side display state: shows at most one update per peer every `min_gap`.
+pub struct TelemetryViewer {
+    min_gap: Duration,
+    last_shown: HashMap<PeerId, Instant>,
+}
+
+impl TelemetryViewer {
+    pub fn new(min_gap: Duration) -> Self {
+        TelemetryViewer {
+            min_gap,
+            last_shown: HashMap::new(),
+        }
+    }
+
+    pub fn should_show(&mut self, peer: PeerId) -> bool {
+        let now = Instant::now();
+        match self.last_shown.get(&peer) {
+            Some(at) if now.duration_since(*at) < self.min_gap => false,
+            _ => {
+                self.last_shown.insert(peer, now);
+                true
+            }
+        }
+    }
+}
+
+pub fn describe(update: &TelemetryUpdate) -> String {
+    let mut line = format!("#{} {}", update.car_id, update.car);
+    if let Some(region) = &update.region {
+        line.push_str(&format!(", near {}", region));
+    }
+    if let Some(bucket) = update.speed_bucket {
+        let low = f64::from(bucket * SPEED_BUCKET_KMH);
+        if bucket >= TOP_SPEED_BUCKET {
+            line.push_str(&format!(", speed {}+", NUMBER_FORMAT.speed(low)));
+        } else if bucket == 0 {
+            line.push_str(&format!(
+                ", speed under {}",
+                NUMBER_FORMAT.speed(f64::from(SPEED_BUCKET_KMH))
+            ));
+        } else {
+            let high = low + f64::from(SPEED_BUCKET_KMH);
+            line.push_str(&format!(
+                ", speed {} to {}",
+                NUMBER_FORMAT.speed(low),
+                NUMBER_FORMAT.speed(high)
+            ));
+        }
+    }
+    if let Some(temp) = update.engine_temp_c {
+        line.push_str(&format!(", engine {}", NUMBER_FORMAT.temperature(temp)));
+    }
+    line
+}
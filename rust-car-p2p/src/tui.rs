@@ -0,0 +1,261 @@
+use crossterm::{
+    event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use libp2p::{futures::StreamExt, PeerId};
+use log::{Log, Metadata, Record};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    widgets::{Block, Borders, List, ListItem, Paragraph},
+    Terminal,
+};
+use rust_car_p2p::{behaviour::UiEvent, format, read_local_carinfos, Carinfos};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Stdout};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Log lines kept for the log pane.
+const LOG_LINES: usize = 500;
+/// Listings kept for the responses pane, newest first.
+const LISTINGS: usize = 20;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+/// How often the catalog pane re-reads storage, on top of after every command.
+const CATALOG_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+type LogLines = Arc<Mutex<VecDeque<String>>>;
+
+/// Whether `--tui` was passed.
+pub fn enabled(args: &[String]) -> bool {
+    args.iter().any(|a| a == "--tui")
+}
+
+/// Collects log records for the log pane instead of writing them over the dashboard.
+struct PaneLogger {
+    filter: env_logger::filter::Filter,
+    lines: LogLines,
+}
+
+impl Log for PaneLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        if let Ok(mut lines) = self.lines.lock() {
+            if lines.len() == LOG_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(format!("{:<5} {}", record.level(), record.args()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sends log output to the dashboard's log pane, honouring `RUST_LOG` (default `info`).
+pub fn init_logger() -> LogLines {
+    let filter = env_logger::filter::Builder::new()
+        .parse(&std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_owned()))
+        .build();
+    let lines = LogLines::default();
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(PaneLogger {
+        filter,
+        lines: lines.clone(),
+    }))
+    .expect("no logger installed yet");
+    lines
+}
+
+/// What the user did on the dashboard.
+pub enum Action {
+    Command(String),
+    Quit,
+}
+
+/// Live panes for discovered peers, the local catalog and incoming listings, with the log and a
+/// command line below them. Commands typed there run exactly as on the plain terminal.
+pub struct Dashboard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    events: EventStream,
+    redraw: tokio::time::Interval,
+    catalog_refresh: tokio::time::Interval,
+    input: String,
+    /// Discovered peers, with the size of their last listing once they sent one.
+    peers: BTreeMap<PeerId, Option<usize>>,
+    catalog: Carinfos,
+    listings: VecDeque<(PeerId, Carinfos)>,
+    log: LogLines,
+}
+
+impl Dashboard {
+    /// Switches the terminal to the dashboard until the returned value is dropped.
+    pub fn start(log: LogLines) -> io::Result<Dashboard> {
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        let dashboard = Dashboard {
+            terminal: Terminal::new(CrosstermBackend::new(stdout))?,
+            events: EventStream::new(),
+            redraw: tokio::time::interval(REDRAW_INTERVAL),
+            catalog_refresh: tokio::time::interval(CATALOG_REFRESH_INTERVAL),
+            input: String::new(),
+            peers: BTreeMap::new(),
+            catalog: Carinfos::new(),
+            listings: VecDeque::new(),
+            log,
+        };
+        Ok(dashboard)
+    }
+
+    /// Waits for the user to enter a command or quit, redrawing in the meantime.
+    pub async fn next_action(&mut self) -> Action {
+        loop {
+            tokio::select! {
+                event = self.events.next() => {
+                    let key = match event {
+                        Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => key,
+                        Some(Ok(_)) => continue,
+                        Some(Err(_)) | None => return Action::Quit,
+                    };
+                    match key.code {
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            return Action::Quit
+                        }
+                        KeyCode::Esc => return Action::Quit,
+                        KeyCode::Char(c) => self.input.push(c),
+                        KeyCode::Backspace => {
+                            self.input.pop();
+                        }
+                        KeyCode::Enter if !self.input.trim().is_empty() => {
+                            return Action::Command(std::mem::take(&mut self.input))
+                        }
+                        _ => {}
+                    }
+                    self.draw();
+                }
+                _ = self.redraw.tick() => self.draw(),
+                _ = self.catalog_refresh.tick() => self.refresh_catalog().await,
+            }
+        }
+    }
+
+    pub fn apply(&mut self, event: UiEvent) {
+        match event {
+            UiEvent::PeerDiscovered(peer) => {
+                self.peers.entry(peer).or_insert(None);
+            }
+            UiEvent::PeerExpired(peer) => {
+                self.peers.remove(&peer);
+            }
+            UiEvent::Listing { peer, carinfos } => {
+                self.peers.insert(peer, Some(carinfos.len()));
+                self.listings.retain(|(p, _)| *p != peer);
+                self.listings.push_front((peer, carinfos));
+                self.listings.truncate(LISTINGS);
+            }
+            UiEvent::Served { .. } => return,
+        }
+        self.draw();
+    }
+
+    pub async fn refresh_catalog(&mut self) {
+        // a failed read keeps showing the last catalog, the error is in the log pane
+        if let Ok(catalog) = read_local_carinfos().await {
+            self.catalog = catalog;
+        }
+    }
+
+    pub fn draw(&mut self) {
+        let peers: Vec<ListItem> = self
+            .peers
+            .iter()
+            .map(|(peer, cars)| match cars {
+                Some(n) => ListItem::new(format!("{} ({} cars)", peer, n)),
+                None => ListItem::new(peer.to_string()),
+            })
+            .collect();
+        let catalog: Vec<ListItem> = self
+            .catalog
+            .iter()
+            .map(|r| ListItem::new(format::carinfo(r)))
+            .collect();
+        let listings: Vec<ListItem> = self
+            .listings
+            .iter()
+            .flat_map(|(peer, carinfos)| {
+                std::iter::once(ListItem::new(format!("{}:", peer))).chain(
+                    carinfos
+                        .iter()
+                        .map(|r| ListItem::new(format!("  {}", format::carinfo(r)))),
+                )
+            })
+            .collect();
+        let log: Vec<String> = self
+            .log
+            .lock()
+            .map(|lines| lines.iter().cloned().collect())
+            .unwrap_or_default();
+        let input = format!("> {}", self.input);
+
+        // a failed draw is retried on the next tick
+        let _ = self.terminal.draw(|frame| {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Percentage(55),
+                    Constraint::Min(5),
+                    Constraint::Length(3),
+                ])
+                .split(frame.size());
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Percentage(30),
+                    Constraint::Percentage(35),
+                    Constraint::Percentage(35),
+                ])
+                .split(rows[0]);
+            frame.render_widget(List::new(peers).block(pane("Peers")), panes[0]);
+            frame.render_widget(List::new(catalog).block(pane("My cars")), panes[1]);
+            frame.render_widget(List::new(listings).block(pane("Listings")), panes[2]);
+            // newest lines at the bottom, as on a terminal
+            let visible = rows[1].height.saturating_sub(2) as usize;
+            let log: Vec<ListItem> = log[log.len().saturating_sub(visible)..]
+                .iter()
+                .map(|l| ListItem::new(l.as_str()))
+                .collect();
+            frame.render_widget(List::new(log).block(pane("Log")), rows[1]);
+            frame.render_widget(
+                Paragraph::new(input.as_str()).block(pane("Command (Esc quits)")),
+                rows[2],
+            );
+        });
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+/// Waits for the next action on `dashboard`, or forever if there is none.
+pub async fn next_action(dashboard: &mut Option<Dashboard>) -> Action {
+    match dashboard {
+        Some(dashboard) => dashboard.next_action().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn pane(title: &str) -> Block<'_> {
+    Block::default().title(title).borders(Borders::ALL)
+}
@@ -0,0 +1,211 @@
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::{atomic, Carinfos, Result};
+
+/// The encrypted file, with what is needed to derive the key again.
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Records kept in one file encrypted with ChaCha20-Poly1305, under a key derived from a
+/// passphrase with Argon2. The whole file is replaced with a new nonce on every save, keeping
+/// the previous version as a backup.
+pub struct Vault {
+    path: PathBuf,
+    salt: [u8; 16],
+    cipher: ChaCha20Poly1305,
+    /// For data encrypted under another salt, like a backup from another node.
+    passphrase: String,
+}
+
+fn derive(passphrase: &str, salt: &[u8; 16]) -> Result<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("deriving the storage key failed, {}", e))?;
+    Ok(ChaCha20Poly1305::new(&Key::from(key)))
+}
+
+impl Vault {
+    /// Opens the vault at `path`, or prepares a new one if there is no file yet. Fails if the
+    /// passphrase doesn't decrypt an existing file.
+    pub fn open(path: PathBuf, passphrase: &str) -> Result<Vault> {
+        atomic::recover::<VaultFile>(&path)?;
+        let existing = match std::fs::read(&path) {
+            Ok(content) => Some(serde_json::from_slice::<VaultFile>(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let salt = match &existing {
+            Some(file) => salt_of(file)?,
+            None => rand::random::<[u8; 16]>(),
+        };
+        let vault = Vault {
+            path,
+            salt,
+            cipher: derive(passphrase, &salt)?,
+            passphrase: passphrase.to_owned(),
+        };
+        if let Some(file) = existing {
+            vault.decrypt(&file)?;
+        }
+        Ok(vault)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn load(&self) -> Result<Carinfos> {
+        match fs::read(&self.path).await {
+            Ok(content) => self.decrypt(&serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Carinfos::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn save(&self, carinfos: &Carinfos) -> Result<()> {
+        let file = self.seal(&serde_json::to_vec(carinfos)?)?;
+        atomic::write(&self.path, &serde_json::to_vec(&file)?).await
+    }
+
+    /// Encrypts `plaintext` like the vault itself, for private data kept in other files.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String> {
+        Ok(serde_json::to_string(&self.seal(plaintext)?)?)
+    }
+
+    /// Decrypts what [`Vault::encrypt`] returned, also on a vault with another salt as long as
+    /// the passphrase is the same.
+    pub fn decrypt_data(&self, sealed: &str) -> Result<Vec<u8>> {
+        let file: VaultFile = serde_json::from_str(sealed)?;
+        let salt = salt_of(&file)?;
+        let plaintext = if salt == self.salt {
+            open_sealed(&self.cipher, &file)?
+        } else {
+            open_sealed(&derive(&self.passphrase, &salt)?, &file)?
+        };
+        plaintext
+            .ok_or_else(|| "can't decrypt private data, wrong passphrase or damaged data".into())
+    }
+
+    fn seal(&self, plaintext: &[u8]) -> Result<VaultFile> {
+        let nonce = rand::random::<[u8; 12]>();
+        let ciphertext = self
+            .cipher
+            .encrypt(&Nonce::from(nonce), plaintext)
+            .map_err(|_| "encrypting private records failed")?;
+        Ok(VaultFile {
+            salt: STANDARD.encode(self.salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+    }
+
+    fn decrypt(&self, file: &VaultFile) -> Result<Carinfos> {
+        let plaintext = open_sealed(&self.cipher, file)?.ok_or_else(|| {
+            format!(
+                "can't decrypt {}, wrong passphrase or damaged file",
+                self.path.display()
+            )
+        })?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+/// The plaintext of `file`, None if `cipher` doesn't decrypt it.
+fn open_sealed(cipher: &ChaCha20Poly1305, file: &VaultFile) -> Result<Option<Vec<u8>>> {
+    let nonce: [u8; 12] = STANDARD
+        .decode(&file.nonce)?
+        .try_into()
+        .map_err(|_| "invalid vault nonce")?;
+    Ok(cipher
+        .decrypt(
+            &Nonce::from(nonce),
+            STANDARD.decode(&file.ciphertext)?.as_slice(),
+        )
+        .ok())
+}
+
+fn salt_of(file: &VaultFile) -> Result<[u8; 16]> {
+    Ok(STANDARD
+        .decode(&file.salt)?
+        .try_into()
+        .map_err(|_| "invalid vault salt")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("carinfo-vault-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn hidden() -> Carinfos {
+        vec![serde_json::from_value(json!({
+            "id": 3,
+            "make": "Hidden",
+            "model": "Golf",
+            "horsepower": 150,
+            "public": false,
+        }))
+        .expect("valid record")]
+    }
+
+    #[tokio::test]
+    async fn round_trips_records_encrypted() {
+        let dir = scratch("round-trip");
+        let path = dir.join("carinfo.private");
+        let vault = Vault::open(path.clone(), "correct horse").unwrap();
+        assert!(vault.load().await.unwrap().is_empty());
+        vault.save(&hidden()).await.unwrap();
+
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("Hidden"));
+        let reopened = Vault::open(path, "correct horse").unwrap();
+        let loaded = reopened.load().await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].make, "Hidden");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_a_wrong_passphrase() {
+        let dir = scratch("wrong-passphrase");
+        let path = dir.join("carinfo.private");
+        let vault = Vault::open(path.clone(), "correct horse").unwrap();
+        vault.save(&hidden()).await.unwrap();
+        let sealed = vault.encrypt(b"secret").unwrap();
+
+        assert!(Vault::open(path, "battery staple").is_err());
+        let other = Vault::open(dir.join("other.private"), "battery staple").unwrap();
+        assert!(other.decrypt_data(&sealed).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn decrypts_data_sealed_under_another_salt() {
+        let dir = scratch("other-salt");
+        let ours = Vault::open(dir.join("a.private"), "correct horse").unwrap();
+        let theirs = Vault::open(dir.join("b.private"), "correct horse").unwrap();
+        assert_ne!(ours.salt, theirs.salt);
+        let sealed = theirs.encrypt(b"secret").unwrap();
+        assert_eq!(ours.decrypt_data(&sealed).unwrap(), b"secret");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Carinfo;
+
+const KM_PER_MILE: f64 = 1.609_344;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fuel {
+    Petrol,
+    Diesel,
+    Electric,
+    Hybrid,
+    PluginHybrid,
+    Lpg,
+    Cng,
+    Hydrogen,
+    /// Anything a newer peer sends that we don't know yet.
+    #[serde(other)]
+    Other,
+}
+
+impl FromStr for Fuel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "petrol" | "gasoline" | "gas" => Ok(Fuel::Petrol),
+            "diesel" => Ok(Fuel::Diesel),
+            "electric" | "ev" => Ok(Fuel::Electric),
+            "hybrid" => Ok(Fuel::Hybrid),
+            "plugin-hybrid" | "phev" => Ok(Fuel::PluginHybrid),
+            "lpg" => Ok(Fuel::Lpg),
+            "cng" => Ok(Fuel::Cng),
+            "hydrogen" => Ok(Fuel::Hydrogen),
+            other => Err(format!(
+                "unknown fuel {}, expected petrol, diesel, electric, hybrid, plugin-hybrid, lpg, \
+                 cng or hydrogen",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Fuel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Fuel::Petrol => "petrol",
+            Fuel::Diesel => "diesel",
+            Fuel::Electric => "electric",
+            Fuel::Hybrid => "hybrid",
+            Fuel::PluginHybrid => "plug-in hybrid",
+            Fuel::Lpg => "LPG",
+            Fuel::Cng => "CNG",
+            Fuel::Hydrogen => "hydrogen",
+            Fuel::Other => "other fuel",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transmission {
+    Manual,
+    Automatic,
+    #[serde(other)]
+    Other,
+}
+
+impl FromStr for Transmission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "manual" => Ok(Transmission::Manual),
+            "automatic" | "auto" => Ok(Transmission::Automatic),
+            other => Err(format!(
+                "unknown transmission {}, expected manual or automatic",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Transmission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Transmission::Manual => "manual",
+            Transmission::Automatic => "automatic",
+            Transmission::Other => "other transmission",
+        })
+    }
+}
+
+/// Asking price in whole units of `currency`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Price {
+    pub amount: u64,
+    pub currency: String,
+}
+
+impl FromStr for Price {
+    type Err = String;
+
+    /// Reads `89000EUR` or `89000 EUR`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (amount, currency) = s.split_at(split);
+        let currency = currency.trim();
+        if amount.is_empty() || currency.len() != 3 || !currency.chars().all(|c| c.is_alphabetic())
+        {
+            return Err(format!(
+                "invalid price {}, expected an amount and currency like 89000EUR",
+                s
+            ));
+        }
+        Ok(Price {
+            amount: amount
+                .parse()
+                .map_err(|e| format!("invalid price {}, {}", s, e))?,
+            currency: currency.to_uppercase(),
+        })
+    }
+}
+
+/// The listing details of a car given to `create car` and `edit car` as `<name>=<value>`;
+/// `None` leaves a detail as it is.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Details {
+    #[serde(default)]
+    pub year: Option<u16>,
+    #[serde(default)]
+    pub mileage_km: Option<u32>,
+    #[serde(default)]
+    pub price: Option<Price>,
+    #[serde(default)]
+    pub fuel: Option<Fuel>,
+    #[serde(default)]
+    pub transmission: Option<Transmission>,
+}
+
+impl Details {
+    pub const NAMES: [&'static str; 5] = ["year", "mileage", "price", "fuel", "transmission"];
+
+    /// Sets the detail `name` from its text, e.g. `mileage` from `45000km` or `28000mi`.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match name {
+            "year" => {
+                self.year = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|year| (1885..=2100).contains(year))
+                        .ok_or_else(|| format!("invalid year {}", value))?,
+                )
+            }
+            "mileage" => {
+                let lower = value.to_lowercase();
+                let (number, km_per_unit) = match lower.strip_suffix("mi") {
+                    Some(miles) => (miles, KM_PER_MILE),
+                    None => (lower.trim_end_matches("km"), 1.0),
+                };
+                let distance: f64 = number
+                    .trim()
+                    .replace(['_', ','], "")
+                    .parse()
+                    .map_err(|_| format!("invalid mileage {}, expected like 45000km", value))?;
+                if distance < 0.0 {
+                    return Err(format!("invalid mileage {}", value));
+                }
+                self.mileage_km = Some((distance * km_per_unit).round() as u32);
+            }
+            "price" => self.price = Some(value.parse()?),
+            "fuel" => self.fuel = Some(value.parse()?),
+            "transmission" => self.transmission = Some(value.parse()?),
+            other => {
+                return Err(format!(
+                    "unknown detail {}, expected {}",
+                    other,
+                    Details::NAMES.join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Details::default()
+    }
+
+    /// Copies the details that are set onto `carinfo`.
+    pub fn apply(&self, carinfo: &mut Carinfo) {
+        if let Some(year) = self.year {
+            carinfo.year = Some(year);
+        }
+        if let Some(mileage) = self.mileage_km {
+            carinfo.mileage_km = Some(mileage);
+        }
+        if let Some(price) = &self.price {
+            carinfo.price = Some(price.clone());
+        }
+        if let Some(fuel) = self.fuel {
+            carinfo.fuel = Some(fuel);
+        }
+        if let Some(transmission) = self.transmission {
+            carinfo.transmission = Some(transmission);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+/// Weight of each position in the check digit sum, ISO 3779. Position 9 is the check digit.
+const WEIGHTS: [u32; 17] = [8, 7, 6, 5, 4, 3, 2, 10, 0, 9, 8, 7, 6, 5, 4, 3, 2];
+
+/// Normalises a vehicle identification number to upper case and checks it: 17 digits and
+/// letters other than I, O and Q, with the check digit at position 9 matching the others.
+pub fn validate(vin: &str) -> Result<String, String> {
+    let vin = vin.trim().to_ascii_uppercase();
+    if vin.len() != 17 {
+        return Err(format!(
+            "VIN {} has {} characters, expected 17",
+            vin,
+            vin.chars().count()
+        ));
+    }
+    let mut sum = 0;
+    for (c, weight) in vin.chars().zip(WEIGHTS) {
+        let value = value(c).ok_or_else(|| {
+            format!(
+                "VIN {} contains {}, only digits and letters other than I, O and Q are used",
+                vin, c
+            )
+        })?;
+        sum += value * weight;
+    }
+    let expected = match sum % 11 {
+        10 => 'X',
+        digit => char::from_digit(digit, 10).expect("less than 10"),
+    };
+    let check = vin.as_bytes()[8] as char;
+    if check != expected {
+        return Err(format!(
+            "VIN {} has check digit {}, expected {}; is it mistyped?",
+            vin, check, expected
+        ));
+    }
+    Ok(vin)
+}
+
+/// Value of a character in the check digit sum, `None` for characters not used in VINs.
+fn value(c: char) -> Option<u32> {
+    match c {
+        '0'..='9' => c.to_digit(10),
+        'A'..='H' => Some(c as u32 - 'A' as u32 + 1),
+        'J'..='N' => Some(c as u32 - 'J' as u32 + 1),
+        'P' => Some(7),
+        'R' => Some(9),
+        'S'..='Z' => Some(c as u32 - 'S' as u32 + 2),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_vins_in_upper_case() {
+        assert_eq!(
+            validate(" 1m8gdm9axkp042788 "),
+            Ok("1M8GDM9AXKP042788".to_owned())
+        );
+        assert_eq!(
+            validate("11111111111111111"),
+            Ok("11111111111111111".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_a_wrong_check_digit() {
+        assert_eq!(
+            validate("1M8GDM9A1KP042788"),
+            Err("VIN 1M8GDM9A1KP042788 has check digit 1, expected X; is it mistyped?".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_lengths_and_characters() {
+        assert_eq!(
+            validate("1M8GDM9AX"),
+            Err("VIN 1M8GDM9AX has 9 characters, expected 17".to_owned())
+        );
+        assert!(validate("1M8GDM9AXKP04278O")
+            .unwrap_err()
+            .contains("contains O"));
+        assert!(validate("IM8GDM9AXKP042788")
+            .unwrap_err()
+            .contains("contains I"));
+    }
+}
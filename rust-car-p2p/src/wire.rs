@@ -0,0 +1,336 @@
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Write};
+
+use crate::{
+    catchup::CatchUp,
+    chat::{ChatMessage, ChatResponse},
+    club::{ClubRequest, ClubResponse},
+    offers::{OfferRequest, OfferResponse},
+    photos::{PhotoChunk, PhotoRequest, ThumbnailRequest, ThumbnailResponse},
+    share::SealedShare,
+    telemetry::TelemetryUpdate,
+    CarPublished, CarinfoDeleted, Carinfos, ListRequest, ListResponse,
+};
+
+/// Version of the messages below. Bump it on changes older peers can't read; they then ignore
+/// our messages instead of misreading them, and we ignore theirs.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Everything sent to peers, on the topics and in list, photo, offer, chat and club requests.
+/// The variant is part of the encoding, so a message never has to be guessed from its fields.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Message {
+    ListRequest(ListRequest),
+    ListResponse(ListResponse),
+    /// Records of a streamed listing, see [`crate::protocol::ListCodec`].
+    ListRecords(Carinfos),
+    CarinfoDeleted(CarinfoDeleted),
+    CarPublished(CarPublished),
+    CatchUp(CatchUp),
+    Chat(ChatMessage),
+    ChatResponse(ChatResponse),
+    Share(SealedShare),
+    Telemetry(TelemetryUpdate),
+    ThumbnailRequest(ThumbnailRequest),
+    ThumbnailResponse(ThumbnailResponse),
+    PhotoRequest(PhotoRequest),
+    PhotoChunk(PhotoChunk),
+    OfferRequest(OfferRequest),
+    OfferResponse(OfferResponse),
+    ClubRequest(ClubRequest),
+    ClubResponse(ClubResponse),
+}
+
+impl Message {
+    /// Short name of the variant, used as a metrics label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::ListRequest(_) => "list_request",
+            Message::ListResponse(_) => "list_response",
+            Message::ListRecords(_) => "list_records",
+            Message::CarinfoDeleted(_) => "carinfo_deleted",
+            Message::CarPublished(_) => "car_published",
+            Message::CatchUp(_) => "catch_up",
+            Message::Chat(_) => "chat",
+            Message::ChatResponse(_) => "chat_response",
+            Message::Share(_) => "share",
+            Message::Telemetry(_) => "telemetry",
+            Message::ThumbnailRequest(_) => "thumbnail_request",
+            Message::ThumbnailResponse(_) => "thumbnail_response",
+            Message::PhotoRequest(_) => "photo_request",
+            Message::PhotoChunk(_) => "photo_chunk",
+            Message::OfferRequest(_) => "offer_request",
+            Message::OfferResponse(_) => "offer_response",
+            Message::ClubRequest(_) => "club_request",
+            Message::ClubResponse(_) => "club_response",
+        }
+    }
+}
+
+/// How the message in an envelope is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+/// What we read, in the order we prefer it, advertised in our requests.
+pub const SUPPORTED_COMPRESSION: [Compression; 2] = [Compression::Zstd, Compression::Gzip];
+
+/// Messages smaller than this are sent as they are, compressing them isn't worth it.
+pub const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+/// Largest message a compressed payload may unpack to, so a small frame can't fill memory.
+const MAX_DECOMPRESSED_BYTES: u64 = 32 * 1024 * 1024;
+const ZSTD_LEVEL: i32 = 3;
+
+/// Either `message`, or `payload` with the CBOR of the message compressed as `compression`.
+/// Peers only get a compressed message in answer to a request whose `accepts` lists how it
+/// is compressed; peers that don't know about compression ignore `accepts` and never get one.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<Compression>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    payload: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    accepts: &'a [Compression],
+}
+
+/// Read first on its own, so messages of another version are recognised even when their
+/// content doesn't parse.
+#[derive(Deserialize)]
+struct Version {
+    version: u16,
+}
+
+#[derive(Deserialize)]
+struct Received {
+    #[serde(default)]
+    message: Option<Message>,
+    #[serde(default)]
+    compression: Option<Compression>,
+    #[serde(default, with = "serde_bytes")]
+    payload: Option<Vec<u8>>,
+    #[serde(default)]
+    accepts: Vec<Compression>,
+}
+
+/// A received message, and how the sender wants its answer compressed.
+pub struct Decoded {
+    pub message: Message,
+    /// The first compression the sender accepts that we support, if any.
+    pub answer_compression: Option<Compression>,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// Sent by a peer speaking another protocol version.
+    Version(u16),
+    Invalid(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Version(version) => write!(
+                f,
+                "protocol version {} is not supported, we speak {}",
+                version, PROTOCOL_VERSION
+            ),
+            DecodeError::Invalid(e) => write!(f, "invalid message, {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// CBOR encoding of `message` in a versioned envelope.
+pub fn encode(message: &Message) -> Vec<u8> {
+    envelope(Some(message), None, None, &[])
+}
+
+/// [`encode`] for a request, asking for a compressed answer.
+pub fn encode_request(message: &Message) -> Vec<u8> {
+    envelope(Some(message), None, None, &SUPPORTED_COMPRESSION)
+}
+
+/// [`encode`] with the message compressed as `compression`, if it is large enough for that to
+/// pay off and actually gets smaller.
+pub fn encode_compressed(message: &Message, compression: Option<Compression>) -> Vec<u8> {
+    let compression = match compression {
+        Some(compression) => compression,
+        None => return encode(message),
+    };
+    let mut cbor = Vec::new();
+    ciborium::into_writer(message, &mut cbor).expect("can encode message");
+    if cbor.len() < COMPRESSION_THRESHOLD {
+        return encode(message);
+    }
+    match compress(&cbor, compression) {
+        Ok(payload) if payload.len() < cbor.len() => {
+            envelope(None, Some(compression), Some(payload), &[])
+        }
+        _ => encode(message),
+    }
+}
+
+fn envelope(
+    message: Option<&Message>,
+    compression: Option<Compression>,
+    payload: Option<Vec<u8>>,
+    accepts: &[Compression],
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    ciborium::into_writer(
+        &Envelope {
+            version: PROTOCOL_VERSION,
+            message,
+            compression,
+            payload,
+            accepts,
+        },
+        &mut data,
+    )
+    .expect("can encode message");
+    data
+}
+
+pub fn decode(data: &[u8]) -> Result<Message, DecodeError> {
+    decode_envelope(data).map(|decoded| decoded.message)
+}
+
+/// [`decode`] that also tells how the sender wants its answer compressed.
+pub fn decode_envelope(data: &[u8]) -> Result<Decoded, DecodeError> {
+    let Version { version } =
+        ciborium::from_reader(data).map_err(|e| DecodeError::Invalid(e.to_string()))?;
+    if version != PROTOCOL_VERSION {
+        return Err(DecodeError::Version(version));
+    }
+    let received: Received =
+        ciborium::from_reader(data).map_err(|e| DecodeError::Invalid(e.to_string()))?;
+    let answer_compression = received
+        .accepts
+        .iter()
+        .copied()
+        .find(|c| SUPPORTED_COMPRESSION.contains(c));
+    let message = match (received.message, received.compression, received.payload) {
+        (Some(message), None, None) => message,
+        (None, Some(compression), Some(payload)) => {
+            let cbor = decompress(&payload, compression)
+                .map_err(|e| DecodeError::Invalid(format!("can't decompress, {}", e)))?;
+            ciborium::from_reader(cbor.as_slice())
+                .map_err(|e| DecodeError::Invalid(e.to_string()))?
+        }
+        _ => {
+            return Err(DecodeError::Invalid(
+                "expected a message or a compressed payload".to_owned(),
+            ))
+        }
+    };
+    Ok(Decoded {
+        message,
+        answer_compression,
+    })
+}
+
+fn compress(data: &[u8], compression: Compression) -> std::io::Result<Vec<u8>> {
+    match compression {
+        Compression::Zstd => zstd::stream::encode_all(data, ZSTD_LEVEL),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+    }
+}
+
+fn decompress(data: &[u8], compression: Compression) -> std::io::Result<Vec<u8>> {
+    let reader: Box<dyn Read + '_> = match compression {
+        Compression::Zstd => Box::new(zstd::stream::Decoder::new(data)?),
+        Compression::Gzip => Box::new(GzDecoder::new(data)),
+    };
+    let mut out = Vec::new();
+    reader
+        .take(MAX_DECOMPRESSED_BYTES + 1)
+        .read_to_end(&mut out)?;
+    if out.len() as u64 > MAX_DECOMPRESSED_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the message is too large",
+        ));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thumbnails(n: usize) -> Message {
+        Message::ThumbnailRequest(ThumbnailRequest {
+            hashes: (0..n).map(|i| format!("{:064x}", i)).collect(),
+        })
+    }
+
+    fn hashes(message: Message) -> Vec<String> {
+        match message {
+            Message::ThumbnailRequest(req) => req.hashes,
+            other => panic!("unexpected {}", other.kind()),
+        }
+    }
+
+    #[test]
+    fn small_messages_go_uncompressed() {
+        let data = encode_compressed(&thumbnails(2), Some(Compression::Zstd));
+        assert_eq!(data, encode(&thumbnails(2)));
+        assert_eq!(hashes(decode(&data).unwrap()).len(), 2);
+    }
+
+    #[test]
+    fn large_messages_round_trip_compressed() {
+        for compression in SUPPORTED_COMPRESSION {
+            let data = encode_compressed(&thumbnails(1000), Some(compression));
+            assert!(data.len() < encode(&thumbnails(1000)).len() / 2);
+            assert_eq!(hashes(decode(&data).unwrap()), hashes(thumbnails(1000)));
+        }
+    }
+
+    #[test]
+    fn requests_ask_for_a_compressed_answer() {
+        let decoded = decode_envelope(&encode_request(&thumbnails(1))).unwrap();
+        assert_eq!(decoded.answer_compression, Some(Compression::Zstd));
+        let decoded = decode_envelope(&encode(&thumbnails(1))).unwrap();
+        assert_eq!(decoded.answer_compression, None);
+    }
+
+    #[test]
+    fn refuses_payloads_that_unpack_beyond_the_limit() {
+        let zeros = vec![0u8; MAX_DECOMPRESSED_BYTES as usize + 1];
+        for compression in SUPPORTED_COMPRESSION {
+            let payload = compress(&zeros, compression).unwrap();
+            assert!(payload.len() < 1024 * 1024);
+            let data = envelope(None, Some(compression), Some(payload), &[]);
+            match decode(&data) {
+                Err(DecodeError::Invalid(e)) => assert!(e.contains("too large"), "{}", e),
+                other => panic!("expected the payload to be refused, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn recognises_other_protocol_versions() {
+        let mut data = Vec::new();
+        ciborium::into_writer(
+            &serde_json::json!({"version": PROTOCOL_VERSION + 1, "message": "anything"}),
+            &mut data,
+        )
+        .unwrap();
+        assert!(matches!(decode(&data), Err(DecodeError::Version(v)) if v == PROTOCOL_VERSION + 1));
+    }
+}